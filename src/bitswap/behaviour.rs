@@ -1,15 +1,18 @@
-//! Handles the `/ipfs/bitswap/1.0.0` and `/ipfs/bitswap/1.1.0` protocols. This
+//! Handles the `/ipfs/bitswap/1.1.0` and `/ipfs/bitswap/1.2.0` protocols. This
 //! allows exchanging IPFS blocks.
 //!
 //! # Usage
 //!
 //! The `Bitswap` struct implements the `NetworkBehaviour` trait. When used, it
 //! will allow providing and reciving IPFS blocks.
-use crate::bitswap::ledger::{Ledger, Message, Priority, I, O};
+use crate::bitswap::ledger::{BlockPresence, Ledger, Message, Priority, I, O};
 use crate::bitswap::protocol::BitswapConfig;
+use crate::bitswap::session::Sessions;
+use crate::bitswap::stats::BitswapStats;
 use crate::bitswap::strategy::{Strategy, StrategyEvent};
 use crate::block::{Block, Cid};
 use crate::p2p::SwarmTypes;
+use crate::repo::SessionId;
 use fnv::FnvHashSet;
 use libp2p::core::swarm::{
     ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
@@ -18,7 +21,18 @@ use libp2p::core::protocols_handler::{OneShotHandler, ProtocolsHandler};
 use libp2p::{Multiaddr, PeerId};
 use std::collections::{HashMap, VecDeque};
 use std::marker::PhantomData;
+use std::time::Instant;
 use tokio::prelude::*;
+use tokio::timer::Delay;
+
+/// Peers that answered that they have a wanted block.
+#[derive(Debug, Default)]
+struct Providers {
+    /// Peer the block was requested from.
+    requested: Option<PeerId>,
+    /// Peers to request the block from next.
+    haves: VecDeque<PeerId>,
+}
 
 /// Network behaviour that handles sending and receiving IPFS blocks.
 pub struct Bitswap<TSubstream, TSwarmTypes: SwarmTypes> {
@@ -32,6 +46,14 @@ pub struct Bitswap<TSubstream, TSwarmTypes: SwarmTypes> {
     connected_peers: HashMap<PeerId, Ledger>,
     /// Wanted blocks
     wanted_blocks: HashMap<Cid, Priority>,
+    /// Peers that have the wanted blocks
+    providers: HashMap<Cid, Providers>,
+    /// Sessions of the wanted blocks
+    sessions: Sessions,
+    /// Wakes the behaviour when the oldest session want expires
+    session_timer: Option<Delay>,
+    /// Statistics shared with the node
+    stats: BitswapStats,
     /// Strategy
     strategy: TSwarmTypes::TStrategy,
 }
@@ -46,10 +68,19 @@ impl<TSubstream, TSwarmTypes: SwarmTypes> Bitswap<TSubstream, TSwarmTypes> {
             target_peers: FnvHashSet::default(),
             connected_peers: HashMap::new(),
             wanted_blocks: HashMap::new(),
+            providers: HashMap::new(),
+            sessions: Sessions::new(),
+            session_timer: None,
+            stats: BitswapStats::new(),
             strategy,
         }
     }
 
+    /// Returns the statistics of the exchange with the peers.
+    pub fn stats(&self) -> BitswapStats {
+        self.stats.clone()
+    }
+
     /// Connect to peer.
     ///
     /// Called from Kademlia behaviour.
@@ -86,7 +117,8 @@ impl<TSubstream, TSwarmTypes: SwarmTypes> Bitswap<TSubstream, TSwarmTypes> {
         if !self.wanted_blocks.is_empty() {
             let mut message = Message::new();
             for (cid, priority) in &self.wanted_blocks {
-                message.want_block(cid, *priority);
+                message.want_have(cid, *priority);
+                message.send_dont_have(cid);
             }
             debug!("  queuing wanted blocks");
             self.events.push_back(NetworkBehaviourAction::SendEvent {
@@ -96,23 +128,187 @@ impl<TSubstream, TSwarmTypes: SwarmTypes> Bitswap<TSubstream, TSwarmTypes> {
         }
     }
 
-    /// Queues the wanted block for all peers.
+    /// Asks all peers if they have the wanted block.
     ///
-    /// A user request
+    /// The block is requested from the first peer that has it, peers
+    /// speaking bitswap 1.1.0 send it right away. A user request
     pub fn want_block(&mut self, cid: Cid, priority: Priority) {
         debug!("bitswap: want_block");
         for (peer_id, ledger) in self.connected_peers.iter_mut() {
-            let message = ledger.want_block(&cid, priority);
+            let message = ledger.want_have(&cid, priority);
             debug!("  queuing want for {}", peer_id.to_base58());
             self.events.push_back(NetworkBehaviourAction::SendEvent {
                 peer_id: peer_id.to_owned(),
                 event: message,
             });
         }
+        self.stats.want(&cid);
         self.wanted_blocks.insert(cid, priority);
         debug!("");
     }
 
+    /// Queues the wanted block for the peers of the session.
+    ///
+    /// Peers that sent earlier blocks of the session are asked first,
+    /// the want is broadcast to all peers if none of them sent the block
+    /// within the session timeout or the session has no peers yet.
+    pub fn want_block_in_session(&mut self, cid: Cid, priority: Priority, session: SessionId) {
+        debug!("bitswap: want_block_in_session {}", session);
+        let connected_peers = &self.connected_peers;
+        let peers: Vec<PeerId> = self.sessions.session(session).peers()
+            .filter(|peer_id| connected_peers.contains_key(peer_id))
+            .cloned()
+            .collect();
+        self.sessions.want(session, cid.clone());
+        if peers.is_empty() {
+            self.want_block(cid, priority);
+            return;
+        }
+        for peer_id in peers {
+            let ledger = self.connected_peers.get_mut(&peer_id)
+                .expect("Peer not in ledger?!");
+            let message = ledger.want_block(&cid, priority);
+            debug!("  queuing want for {}", peer_id.to_base58());
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                event: message,
+            });
+        }
+        self.sessions.session(session).want(cid.clone(), Instant::now());
+        self.stats.want(&cid);
+        self.wanted_blocks.insert(cid, priority);
+        debug!("");
+    }
+
+    /// Tells the peer whether we have the block.
+    ///
+    /// Called from a Strategy.
+    pub fn send_presence(&mut self, peer_id: PeerId, cid: Cid, presence: BlockPresence) {
+        debug!("bitswap: send_presence");
+        let ledger = self.connected_peers.get_mut(&peer_id)
+            .expect("Peer not in ledger?!");
+        let message = ledger.send_presence(&cid, presence);
+        debug!("  queuing presence for {}", peer_id.to_base58());
+        self.events.push_back(NetworkBehaviourAction::SendEvent {
+            peer_id,
+            event: message,
+        });
+    }
+
+    /// Handles the answer of a peer whether it has a wanted block.
+    fn process_presence(&mut self, source: &PeerId, cid: &Cid, presence: BlockPresence) {
+        let priority = match self.wanted_blocks.get(cid) {
+            Some(priority) => *priority,
+            None => return,
+        };
+        let providers = self.providers.entry(cid.to_owned()).or_insert_with(Providers::default);
+        match presence {
+            BlockPresence::Have => {
+                let ledger = self.connected_peers.get(source)
+                    .expect("Peer not in ledger?!");
+                // the peer was already asked for the block
+                if ledger.wants_block(cid) || providers.requested.as_ref() == Some(source) ||
+                    providers.haves.contains(source)
+                {
+                    return;
+                }
+                providers.haves.push_back(source.to_owned());
+                self.request_block(cid, priority);
+            }
+            BlockPresence::DontHave => {
+                providers.haves.retain(|peer_id| peer_id != source);
+                if providers.requested.as_ref() == Some(source) {
+                    providers.requested = None;
+                }
+                self.request_block(cid, priority);
+                // don't wait for the session peers if one of them
+                // doesn't have the block.
+                if self.sessions.dont_have(cid) {
+                    self.want_block(cid.to_owned(), priority);
+                }
+            }
+        }
+    }
+
+    /// Requests the block from the next peer that has it, unless it is
+    /// already requested from one.
+    fn request_block(&mut self, cid: &Cid, priority: Priority) {
+        let providers = match self.providers.get_mut(cid) {
+            Some(providers) => providers,
+            None => return,
+        };
+        if providers.requested.is_some() {
+            return;
+        }
+        let peer_id = match providers.haves.pop_front() {
+            Some(peer_id) => peer_id,
+            None => return,
+        };
+        let ledger = self.connected_peers.get_mut(&peer_id)
+            .expect("Peer not in ledger?!");
+        let message = ledger.want_block(cid, priority);
+        debug!("  queuing want for {}", peer_id.to_base58());
+        self.events.push_back(NetworkBehaviourAction::SendEvent {
+            peer_id: peer_id.clone(),
+            event: message,
+        });
+        providers.requested = Some(peer_id);
+    }
+
+    /// Requests the blocks that were requested from a disconnected peer
+    /// from the next peers that have them.
+    fn remove_provider(&mut self, peer_id: &PeerId) {
+        let mut orphaned = Vec::new();
+        for (cid, providers) in self.providers.iter_mut() {
+            providers.haves.retain(|have| have != peer_id);
+            if providers.requested.as_ref() == Some(peer_id) {
+                providers.requested = None;
+                orphaned.push(cid.to_owned());
+            }
+        }
+        for cid in orphaned {
+            if let Some(priority) = self.wanted_blocks.get(&cid).cloned() {
+                self.request_block(&cid, priority);
+            }
+        }
+    }
+
+    /// Ends the session, its blocks stay wanted.
+    pub fn end_session(&mut self, session: SessionId) {
+        debug!("bitswap: end_session {}", session);
+        self.sessions.end(session);
+    }
+
+    /// Broadcasts the session wants that weren't answered in time.
+    fn broadcast_expired_wants(&mut self) {
+        for cid in self.sessions.expired(Instant::now()) {
+            if let Some(priority) = self.wanted_blocks.get(&cid).cloned() {
+                debug!("  broadcasting expired session want");
+                self.want_block(cid, priority);
+            }
+        }
+        let timeout = match self.sessions.next_timeout() {
+            Some(timeout) => timeout,
+            None => {
+                self.session_timer = None;
+                return;
+            }
+        };
+        let reset = match &self.session_timer {
+            Some(timer) => timer.deadline() != timeout,
+            None => true,
+        };
+        if reset {
+            self.session_timer = Some(Delay::new(timeout));
+        }
+        // polling the timer wakes the task at the timeout.
+        if let Some(timer) = &mut self.session_timer {
+            if let Ok(Async::Ready(())) = timer.poll() {
+                task::current().notify();
+            }
+        }
+    }
+
     /// Removes the block from our want list and updates all peers.
     ///
     /// Can be either a user request or be called when the block
@@ -130,6 +326,9 @@ impl<TSubstream, TSwarmTypes: SwarmTypes> Bitswap<TSubstream, TSwarmTypes> {
             }
         }
         self.wanted_blocks.remove(cid);
+        self.providers.remove(cid);
+        self.sessions.cancel(cid);
+        self.stats.cancel(cid);
         debug!("");
     }
 }
@@ -155,7 +354,13 @@ where
         debug!("bitswap: inject_connected");
         debug!("  peer_id: {}", peer_id.to_base58());
         debug!("  connected_point: {:?}", cp);
-        let ledger = Ledger::new();
+        // the statistics outlive the connection
+        let ledger = match self.stats.ledger(&peer_id) {
+            Some(stat) => Ledger::with_stat(stat),
+            None => Ledger::new(),
+        };
+        self.stats.set_ledger(&peer_id, ledger.stat());
+        self.stats.connected(&peer_id);
         self.connected_peers.insert(peer_id.clone(), ledger);
         self.send_want_list(peer_id);
         debug!("");
@@ -166,6 +371,9 @@ where
         debug!("  peer_id: {}", peer_id.to_base58());
         debug!("  connected_point: {:?}", cp);
         debug!("");
+        self.sessions.remove_peer(peer_id);
+        self.remove_provider(peer_id);
+        self.stats.disconnected(peer_id);
         //self.connected_peers.remove(peer_id);
     }
 
@@ -190,15 +398,29 @@ where
         let ledger = self.connected_peers.get_mut(&source)
             .expect("Peer not in ledger?!");
         ledger.update_incoming_stats(&message);
+        self.stats.set_ledger(&source, ledger.stat());
 
         // Process incoming messages.
         for block in message.blocks() {
+            if !self.wanted_blocks.contains_key(block.cid()) {
+                self.stats.duplicate_block(block.data().len());
+            }
+            // Route further wants of the session to the peer.
+            self.sessions.received(block.cid(), &source);
             // Cancel the block.
             self.cancel_block(&block.cid());
             self.strategy.process_block(source.clone(), block.to_owned());
         }
         for (cid, priority) in message.want() {
-            self.strategy.process_want(source.clone(), cid.to_owned(), *priority);
+            let send_dont_have = message.sends_dont_have(cid);
+            self.strategy.process_want(source.clone(), cid.to_owned(), *priority, send_dont_have);
+        }
+        for (cid, priority) in message.want_have_list() {
+            let send_dont_have = message.sends_dont_have(cid);
+            self.strategy.process_want_have(source.clone(), cid.to_owned(), *priority, send_dont_have);
+        }
+        for (cid, presence) in message.presences() {
+            self.process_presence(&source, cid, *presence);
         }
         // TODO: Remove cancelled `Want` events from the queue.
         // TODO: Remove cancelled blocks from `SendEvent`.
@@ -210,6 +432,8 @@ where
         _: &mut PollParameters,
     ) -> Async<NetworkBehaviourAction<
             <Self::ProtocolsHandler as ProtocolsHandler>::InEvent, Self::OutEvent>> {
+        self.broadcast_expired_wants();
+
         // TODO concat messages to same destination to reduce traffic.
         if let Some(event) = self.events.pop_front() {
             if let NetworkBehaviourAction::SendEvent { peer_id, event } = event {
@@ -221,7 +445,9 @@ where
                         event,
                     });
                 } else {
-                    ledger.unwrap().update_outgoing_stats(&event);
+                    let ledger = ledger.unwrap();
+                    ledger.update_outgoing_stats(&event);
+                    self.stats.set_ledger(&peer_id, ledger.stat());
                     debug!("  send_message to {}", peer_id.to_base58());
                     return Async::Ready(NetworkBehaviourAction::SendEvent {
                         peer_id,
@@ -240,6 +466,10 @@ where
                 self.send_block(peer_id, block);
                 task::current().notify();
             }
+            Some(StrategyEvent::Presence { peer_id, cid, presence }) => {
+                self.send_presence(peer_id, cid, presence);
+                task::current().notify();
+            }
             None => {}
         }
 
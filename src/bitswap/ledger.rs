@@ -1,21 +1,52 @@
 use crate::block::{Block, Cid};
-use crate::bitswap::bitswap_pb;
 use crate::error::Error;
-use protobuf::Message as ProtobufMessage;
+use fnv::FnvHashSet;
+use protobuf::{CodedInputStream, CodedOutputStream, ProtobufResult};
+use protobuf::wire_format::WireType;
 use std::collections::HashMap;
 use std::marker::PhantomData;
 
 pub type Priority = i32;
 
+/// Statistics of the exchange with a peer, like `ipfs bitswap ledger`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LedgerStat {
+    /// The number of blocks sent to the peer.
+    pub blocks_sent: u64,
+    /// The number of blocks received from the peer.
+    pub blocks_received: u64,
+    /// The size of the blocks sent to the peer.
+    pub bytes_sent: u64,
+    /// The size of the blocks received from the peer.
+    pub bytes_received: u64,
+}
+
+impl LedgerStat {
+    /// Returns the number of blocks sent and received.
+    pub fn exchanged(&self) -> u64 {
+        self.blocks_sent + self.blocks_received
+    }
+
+    /// Returns how much more we sent than received, computed like
+    /// go-ipfs as `bytes_sent / (bytes_received + 1)`.
+    pub fn debt_ratio(&self) -> f64 {
+        self.bytes_sent as f64 / (self.bytes_received as f64 + 1.0)
+    }
+}
+
+fn blocks_size(blocks: &[Block]) -> u64 {
+    blocks.iter().map(|block| block.data().len() as u64).sum()
+}
+
 /// The Ledger contains the history of transactions with a peer.
 #[derive(Debug)]
 pub struct Ledger {
-    /// The number of blocks sent to the peer.
-    sent_blocks: usize,
-    /// The number of blocks received from the peer.
-    received_blocks: usize,
+    /// The blocks and bytes exchanged with the peer.
+    stat: LedgerStat,
     /// The list of wanted blocks sent to the peer.
     sent_want_list: HashMap<Cid, Priority>,
+    /// The list of blocks the peer was asked to have.
+    sent_want_have_list: HashMap<Cid, Priority>,
     /// The list of wanted blocks received from the peer.
     received_want_list: HashMap<Cid, Priority>,
 }
@@ -23,10 +54,16 @@ pub struct Ledger {
 impl Ledger {
     /// Creates a new `PeerLedger`.
     pub fn new() -> Self {
+        Ledger::with_stat(LedgerStat::default())
+    }
+
+    /// Creates a ledger continuing the statistics of an earlier
+    /// connection to the peer.
+    pub fn with_stat(stat: LedgerStat) -> Self {
         Ledger {
-            sent_blocks: 0,
-            received_blocks: 0,
+            stat,
             sent_want_list: HashMap::new(),
+            sent_want_have_list: HashMap::new(),
             received_want_list: HashMap::new(),
         }
     }
@@ -40,11 +77,25 @@ impl Ledger {
     pub fn want_block(&mut self, cid: &Cid, priority: Priority) -> Message<O> {
         let mut message = Message::new();
         message.want_block(cid, priority);
+        message.send_dont_have(cid);
+        message
+    }
+
+    pub fn want_have(&mut self, cid: &Cid, priority: Priority) -> Message<O> {
+        let mut message = Message::new();
+        message.want_have(cid, priority);
+        message.send_dont_have(cid);
+        message
+    }
+
+    pub fn send_presence(&mut self, cid: &Cid, presence: BlockPresence) -> Message<O> {
+        let mut message = Message::new();
+        message.add_presence(cid, presence);
         message
     }
 
     pub fn cancel_block(&mut self, cid: &Cid) -> Option<Message<O>> {
-        if self.sent_want_list.contains_key(cid) {
+        if self.sent_want_list.contains_key(cid) || self.sent_want_have_list.contains_key(cid) {
             let mut message = Message::new();
             message.cancel_block(cid);
             Some(message)
@@ -53,24 +104,48 @@ impl Ledger {
         }
     }
 
+    /// Returns the statistics of the exchange with the peer.
+    pub fn stat(&self) -> &LedgerStat {
+        &self.stat
+    }
+
+    /// Returns whether the block was wanted from the peer.
+    pub fn wants_block(&self, cid: &Cid) -> bool {
+        self.sent_want_list.contains_key(cid)
+    }
+
     pub fn update_outgoing_stats(&mut self, message: &Message<O>) {
-        self.sent_blocks += message.blocks.len();
+        self.stat.blocks_sent += message.blocks.len() as u64;
+        self.stat.bytes_sent += blocks_size(&message.blocks);
         for cid in message.cancel() {
             self.sent_want_list.remove(cid);
+            self.sent_want_have_list.remove(cid);
         }
         for (cid, priority) in message.want() {
+            self.sent_want_have_list.remove(cid);
             self.sent_want_list.insert(cid.to_owned(), *priority);
         }
+        for (cid, priority) in message.want_have_list() {
+            self.sent_want_have_list.insert(cid.to_owned(), *priority);
+        }
     }
 
     pub fn update_incoming_stats(&mut self, message: &Message<I>) {
-        self.received_blocks += message.blocks.len();
+        self.stat.blocks_received += message.blocks.len() as u64;
+        self.stat.bytes_received += blocks_size(&message.blocks);
         for cid in message.cancel() {
             self.received_want_list.remove(cid);
         }
         for (cid, priority) in message.want() {
             self.received_want_list.insert(cid.to_owned(), *priority);
         }
+        // the peer doesn't send blocks it doesn't have
+        for (cid, presence) in message.presences() {
+            if *presence == BlockPresence::DontHave {
+                self.sent_want_list.remove(cid);
+                self.sent_want_have_list.remove(cid);
+            }
+        }
     }
 }
 
@@ -79,6 +154,14 @@ pub struct I;
 #[derive(Debug, Clone, PartialEq)]
 pub struct O;
 
+/// Whether a peer has a block, sent in answer to wants since bitswap
+/// 1.2.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockPresence {
+    Have,
+    DontHave,
+}
+
 /// A bitswap message.
 #[derive(Clone, PartialEq)]
 pub struct Message<T> {
@@ -86,12 +169,19 @@ pub struct Message<T> {
     _phantom_data: PhantomData<T>,
     /// List of wanted blocks.
     want: HashMap<Cid, Priority>,
+    /// List of blocks the peer is asked to have.
+    want_have: HashMap<Cid, Priority>,
+    /// Wants the peer should answer with `DontHave` if it doesn't have
+    /// the block.
+    send_dont_have: FnvHashSet<Cid>,
     /// List of blocks to cancel.
     cancel: Vec<Cid>,
     /// Wheather it is the full list of wanted blocks.
     full: bool,
     /// List of blocks to send.
     blocks: Vec<Block>,
+    /// Whether the sender has the wanted blocks.
+    presences: HashMap<Cid, BlockPresence>,
 }
 
 impl<T> Message<T> {
@@ -100,9 +190,12 @@ impl<T> Message<T> {
         Message {
             _phantom_data: PhantomData,
             want: HashMap::new(),
+            want_have: HashMap::new(),
+            send_dont_have: FnvHashSet::default(),
             cancel: Vec::new(),
             full: false,
             blocks: Vec::new(),
+            presences: HashMap::new(),
         }
     }
 
@@ -116,11 +209,33 @@ impl<T> Message<T> {
         &self.want
     }
 
+    /// Returns the list of blocks the peer is asked to have.
+    pub fn want_have_list(&self) -> &HashMap<Cid, Priority> {
+        &self.want_have
+    }
+
+    /// Returns whether a want should be answered with `DontHave` if the
+    /// block is missing.
+    pub fn sends_dont_have(&self, cid: &Cid) -> bool {
+        self.send_dont_have.contains(cid)
+    }
+
     /// Returns the list of cancelled blocks.
     pub fn cancel(&self) -> &Vec<Cid> {
         &self.cancel
     }
 
+    /// Returns the block presences.
+    pub fn presences(&self) -> &HashMap<Cid, BlockPresence> {
+        &self.presences
+    }
+
+    /// Returns whether the message carries anything.
+    pub fn is_empty(&self) -> bool {
+        self.want.is_empty() && self.want_have.is_empty() && self.cancel.is_empty() &&
+            self.blocks.is_empty() && self.presences.is_empty()
+    }
+
     /// Adds a `Block` to the message.
     pub fn add_block(&mut self, block: Block) {
         self.blocks.push(block);
@@ -134,73 +249,218 @@ impl<T> Message<T> {
 
     /// Adds a block to the want list.
     pub fn want_block(&mut self, cid: &Cid, priority: Priority) {
+        self.want_have.remove(cid);
         self.want.insert(cid.to_owned(), priority);
     }
 
+    /// Asks the peer to tell if it has the block, without sending it.
+    pub fn want_have(&mut self, cid: &Cid, priority: Priority) {
+        if !self.want.contains_key(cid) {
+            self.want_have.insert(cid.to_owned(), priority);
+        }
+    }
+
+    /// Asks the peer to answer the want for `cid` with `DontHave` if it
+    /// doesn't have the block.
+    pub fn send_dont_have(&mut self, cid: &Cid) {
+        self.send_dont_have.insert(cid.to_owned());
+    }
+
     /// Adds a block to the cancel list.
     pub fn cancel_block(&mut self, cid: &Cid) {
         self.cancel.push(cid.to_owned());
     }
 
+    /// Tells the peer whether we have the block.
+    pub fn add_presence(&mut self, cid: &Cid, presence: BlockPresence) {
+        self.presences.insert(cid.to_owned(), presence);
+    }
+
     /// Removes the block from the want list.
     #[allow(unused)]
     pub fn remove_want_block(&mut self, cid: &Cid) {
         self.want.remove(cid);
+        self.want_have.remove(cid);
+        self.send_dont_have.remove(cid);
+    }
+}
+
+// Field values of the messages, see `bitswap_pb.proto`.
+const WANT_TYPE_BLOCK: i32 = 0;
+const WANT_TYPE_HAVE: i32 = 1;
+const PRESENCE_HAVE: i32 = 0;
+const PRESENCE_DONT_HAVE: i32 = 1;
+
+fn encode_nested<F>(encode: F) -> ProtobufResult<Vec<u8>>
+where
+    F: FnOnce(&mut CodedOutputStream) -> ProtobufResult<()>,
+{
+    let mut bytes = Vec::new();
+    {
+        let mut os = CodedOutputStream::vec(&mut bytes);
+        encode(&mut os)?;
+        os.flush()?;
     }
+    Ok(bytes)
 }
 
 impl Message<O> {
     /// Turns this `Message` into a message that can be sent to a substream.
+    ///
+    /// Peers speaking bitswap 1.1.0 ignore the 1.2.0 fields, so they
+    /// treat `want_have` entries as wanted blocks.
     pub fn into_bytes(&self) -> Vec<u8> {
-        let mut proto = bitswap_pb::Message::new();
-        let mut wantlist = bitswap_pb::Message_Wantlist::new();
-        for (cid, priority) in self.want() {
-            let mut entry = bitswap_pb::Message_Wantlist_Entry::new();
-            entry.set_block(cid.to_bytes());
-            entry.set_priority(*priority as _);
-            wantlist.mut_entries().push(entry);
+        self.encode()
+            .expect("there is no situation in which the protobuf message can be invalid")
+    }
+
+    fn encode(&self) -> ProtobufResult<Vec<u8>> {
+        let wants = self.want.iter().map(|(cid, priority)| (cid, priority, WANT_TYPE_BLOCK))
+            .chain(self.want_have.iter().map(|(cid, priority)| (cid, priority, WANT_TYPE_HAVE)));
+        let mut entries = Vec::new();
+        for (cid, priority, want_type) in wants {
+            entries.push(encode_nested(|os| {
+                os.write_bytes(1, &cid.to_bytes())?;
+                os.write_int32(2, *priority)?;
+                os.write_int32(4, want_type)?;
+                os.write_bool(5, self.send_dont_have.contains(cid))
+            })?);
         }
         for cid in self.cancel() {
-            let mut entry = bitswap_pb::Message_Wantlist_Entry::new();
-            entry.set_block(cid.to_bytes());
-            entry.set_cancel(true);
-            wantlist.mut_entries().push(entry);
+            entries.push(encode_nested(|os| {
+                os.write_bytes(1, &cid.to_bytes())?;
+                os.write_bool(3, true)
+            })?);
         }
-        proto.set_wantlist(wantlist);
-        for block in self.blocks() {
-            let mut payload = bitswap_pb::Message_Block::new();
-            payload.set_prefix(block.cid().prefix().as_bytes());
-            payload.set_data(block.data().to_vec());
-            proto.mut_payload().push(payload);
-        }
-        proto
-            .write_to_bytes()
-            .expect("there is no situation in which the protobuf message can be invalid")
+        let wantlist = encode_nested(|os| {
+            for entry in &entries {
+                os.write_bytes(1, entry)?;
+            }
+            os.write_bool(2, self.full)
+        })?;
+        encode_nested(|os| {
+            os.write_bytes(1, &wantlist)?;
+            for block in self.blocks() {
+                let payload = encode_nested(|os| {
+                    os.write_bytes(1, &block.cid().prefix().as_bytes())?;
+                    os.write_bytes(2, block.data())
+                })?;
+                os.write_bytes(3, &payload)?;
+            }
+            for (cid, presence) in self.presences() {
+                let presence = encode_nested(|os| {
+                    os.write_bytes(1, &cid.to_bytes())?;
+                    os.write_int32(2, match presence {
+                        BlockPresence::Have => PRESENCE_HAVE,
+                        BlockPresence::DontHave => PRESENCE_DONT_HAVE,
+                    })
+                })?;
+                os.write_bytes(4, &presence)?;
+            }
+            Ok(())
+        })
     }
-
 }
 
 impl Message<I> {
     /// Creates a `Message` from bytes that were received from a substream.
     pub fn from_bytes(bytes: &Vec<u8>) -> Result<Self, Error> {
-        let proto: bitswap_pb::Message = protobuf::parse_from_bytes(bytes)?;
         let mut message = Message::new();
-        for entry in proto.get_wantlist().get_entries() {
-            let cid = Cid::from(entry.get_block())?;
-            if entry.get_cancel() {
-                message.cancel_block(&cid);
-            } else {
-                message.want_block(&cid, entry.get_priority() as _);
+        let mut is = CodedInputStream::from_bytes(bytes);
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeLengthDelimited) => message.read_wantlist(&is.read_bytes()?)?,
+                (3, WireType::WireTypeLengthDelimited) => message.read_payload(&is.read_bytes()?)?,
+                (4, WireType::WireTypeLengthDelimited) => message.read_presence(&is.read_bytes()?)?,
+                (_, wire_type) => is.skip_field(wire_type)?,
             }
         }
-        for payload in proto.get_payload() {
-            let prefix = cid::Prefix::new_from_bytes(payload.get_prefix())?;
-            let cid = cid::Cid::new_from_prefix(&prefix, payload.get_data());
-            let block = Block::new(payload.get_data().to_vec(), cid);
-            message.add_block(block);
-        }
         Ok(message)
     }
+
+    fn read_wantlist(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeLengthDelimited) => self.read_entry(&is.read_bytes()?)?,
+                (2, WireType::WireTypeVarint) => self.full = is.read_bool()?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn read_entry(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        let (mut cid, mut priority, mut cancel) = (None, 1, false);
+        let (mut want_type, mut send_dont_have) = (WANT_TYPE_BLOCK, false);
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeLengthDelimited) => cid = Some(Cid::from(is.read_bytes()?)?),
+                (2, WireType::WireTypeVarint) => priority = is.read_int32()?,
+                (3, WireType::WireTypeVarint) => cancel = is.read_bool()?,
+                (4, WireType::WireTypeVarint) => want_type = is.read_int32()?,
+                (5, WireType::WireTypeVarint) => send_dont_have = is.read_bool()?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+        let cid = match cid {
+            Some(cid) => cid,
+            None => bail!("bitswap wantlist entry without cid"),
+        };
+        if cancel {
+            self.cancel_block(&cid);
+            return Ok(());
+        }
+        match want_type {
+            WANT_TYPE_BLOCK => self.want_block(&cid, priority),
+            WANT_TYPE_HAVE => self.want_have(&cid, priority),
+            _ => bail!("unknown bitswap want type {}", want_type),
+        }
+        if send_dont_have {
+            self.send_dont_have(&cid);
+        }
+        Ok(())
+    }
+
+    fn read_payload(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        let (mut prefix, mut data) = (Vec::new(), Vec::new());
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeLengthDelimited) => prefix = is.read_bytes()?,
+                (2, WireType::WireTypeLengthDelimited) => data = is.read_bytes()?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+        let prefix = cid::Prefix::new_from_bytes(&prefix)?;
+        let cid = cid::Cid::new_from_prefix(&prefix, &data);
+        self.add_block(Block::new(data, cid));
+        Ok(())
+    }
+
+    fn read_presence(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        let (mut cid, mut presence) = (None, PRESENCE_HAVE);
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeLengthDelimited) => cid = Some(Cid::from(is.read_bytes()?)?),
+                (2, WireType::WireTypeVarint) => presence = is.read_int32()?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+        let cid = match cid {
+            Some(cid) => cid,
+            None => bail!("bitswap block presence without cid"),
+        };
+        let presence = match presence {
+            PRESENCE_HAVE => BlockPresence::Have,
+            PRESENCE_DONT_HAVE => BlockPresence::DontHave,
+            _ => bail!("unknown bitswap block presence {}", presence),
+        };
+        self.add_presence(&cid, presence);
+        Ok(())
+    }
 }
 
 impl<T> std::fmt::Debug for Message<T> {
@@ -208,18 +468,93 @@ impl<T> std::fmt::Debug for Message<T> {
         for (cid, priority) in self.want() {
             writeln!(fmt, "want: {} {}", cid.to_string(), priority)?;
         }
+        for (cid, priority) in self.want_have_list() {
+            writeln!(fmt, "want have: {} {}", cid.to_string(), priority)?;
+        }
         for cid in self.cancel() {
             writeln!(fmt, "cancel: {}", cid.to_string())?;
         }
         for block in self.blocks() {
             writeln!(fmt, "block: {}", block.cid().to_string())?;
         }
+        for (cid, presence) in self.presences() {
+            writeln!(fmt, "presence: {} {:?}", cid.to_string(), presence)?;
+        }
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_to_from_bytes() {
+        let (block_1, block_2, block_3) = (Block::from("1"), Block::from("2"), Block::from("3"));
+        let mut message = Message::<O>::new();
+        message.want_block(block_1.cid(), 2);
+        message.want_have(block_2.cid(), 1);
+        message.send_dont_have(block_2.cid());
+        message.cancel_block(block_3.cid());
+        message.add_block(block_3.clone());
+        message.add_presence(block_1.cid(), BlockPresence::DontHave);
+        message.add_presence(block_3.cid(), BlockPresence::Have);
+
+        let received = Message::<I>::from_bytes(&message.into_bytes()).unwrap();
+        assert_eq!(received.want(), message.want());
+        assert_eq!(received.want_have_list(), message.want_have_list());
+        assert!(!received.sends_dont_have(block_1.cid()));
+        assert!(received.sends_dont_have(block_2.cid()));
+        assert_eq!(received.cancel(), message.cancel());
+        assert_eq!(received.blocks(), message.blocks());
+        assert_eq!(received.presences(), message.presences());
+
+        let empty = Message::<I>::from_bytes(&Message::<O>::new().into_bytes()).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_ledger_presences() {
+        let block = Block::from("1");
+        let mut ledger = Ledger::new();
+        let message = ledger.want_have(block.cid(), 1);
+        ledger.update_outgoing_stats(&message);
+        assert!(!ledger.wants_block(block.cid()));
+        assert!(ledger.cancel_block(block.cid()).is_some());
+        let message = ledger.want_block(block.cid(), 1);
+        ledger.update_outgoing_stats(&message);
+        assert!(ledger.wants_block(block.cid()));
+
+        let mut message = Message::<I>::new();
+        message.add_presence(block.cid(), BlockPresence::DontHave);
+        ledger.update_incoming_stats(&message);
+        assert!(!ledger.wants_block(block.cid()));
+        assert!(ledger.cancel_block(block.cid()).is_none());
+    }
+
+    #[test]
+    fn test_ledger_stat() {
+        let (block_1, block_2) = (Block::from("1"), Block::from("22"));
+        let mut ledger = Ledger::new();
+        let message = ledger.send_block(block_1.clone());
+        ledger.update_outgoing_stats(&message);
+        let message = ledger.send_block(block_1);
+        ledger.update_outgoing_stats(&message);
+        let mut message = Message::<I>::new();
+        message.add_block(block_2);
+        ledger.update_incoming_stats(&message);
+
+        let stat = Ledger::with_stat(ledger.stat().to_owned()).stat().to_owned();
+        assert_eq!(stat, LedgerStat {
+            blocks_sent: 2,
+            blocks_received: 1,
+            bytes_sent: 2,
+            bytes_received: 2,
+        });
+        assert_eq!(stat.exchanged(), 3);
+        assert!((stat.debt_ratio() - 2.0 / 3.0).abs() < 1e-9);
+        assert!(LedgerStat::default().debt_ratio().abs() < 1e-9);
+    }
     /*
     use super::*;
 
@@ -1,11 +1,13 @@
 //! Bitswap protocol implementation
 pub mod behaviour;
 pub mod ledger;
-mod bitswap_pb;
+pub mod session;
+pub mod stats;
 pub mod strategy;
 pub mod protocol;
 
 pub use self::behaviour::Bitswap;
 pub use self::protocol::BitswapError;
-pub use self::ledger::Priority;
+pub use self::ledger::{BlockPresence, LedgerStat, Priority};
+pub use self::stats::{BitswapStat, BitswapStats};
 pub use self::strategy::{AltruisticStrategy, Strategy};
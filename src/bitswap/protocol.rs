@@ -8,23 +8,26 @@ use crate::bitswap::ledger::{Message, I, O};
 use crate::error::Error;
 use libp2p::core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, upgrade};
 use protobuf::ProtobufError;
-use std::{io, iter};
+use std::{io, iter, slice};
 use tokio::prelude::*;
 
 // Undocumented, but according to JS we our messages have a max size of 512*1024
 // https://github.com/ipfs/js-ipfs-bitswap/blob/d8f80408aadab94c962f6b88f343eb9f39fa0fcc/src/decision-engine/index.js#L16
 const MAX_BUF_SIZE : usize = 524288;
 
+// 1.2.0 adds fields that 1.1.0 peers ignore, so both use the same messages.
+const PROTOCOLS: &[&[u8]] = &[b"/ipfs/bitswap/1.2.0", b"/ipfs/bitswap/1.1.0"];
+
 #[derive(Clone, Debug, Default)]
 pub struct BitswapConfig {}
 
 impl UpgradeInfo for BitswapConfig {
     type Info = &'static [u8];
-    type InfoIter = iter::Once<Self::Info>;
+    type InfoIter = iter::Cloned<slice::Iter<'static, Self::Info>>;
 
     fn protocol_info(&self) -> Self::InfoIter {
         // b"/ipfs/bitswap", b"/ipfs/bitswap/1.0.0"
-        iter::once(b"/ipfs/bitswap/1.1.0")
+        PROTOCOLS.iter().cloned()
     }
 }
 
@@ -89,11 +92,11 @@ impl std::error::Error for BitswapError {
 
 impl UpgradeInfo for Message<O> {
     type Info = &'static [u8];
-    type InfoIter = iter::Once<Self::Info>;
+    type InfoIter = iter::Cloned<slice::Iter<'static, Self::Info>>;
 
     fn protocol_info(&self) -> Self::InfoIter {
         // b"/ipfs/bitswap", b"/ipfs/bitswap/1.0.0"
-        iter::once(b"/ipfs/bitswap/1.1.0")
+        PROTOCOLS.iter().cloned()
     }
 }
 
@@ -0,0 +1,207 @@
+//! Bitswap sessions
+//!
+//! A session groups the wants for related blocks, e.g. all blocks of one
+//! file. Peers that sent a block of the session are likely to have the
+//! other blocks of the dag as well, so the wants of the session are only
+//! sent to them. Wants that they don't answer in time are broadcast to
+//! all peers.
+use crate::block::Cid;
+use crate::repo::SessionId;
+use fnv::FnvHashSet;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Time the peers of a session get to send a block before the want is
+/// broadcast to all peers.
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The peers and wanted blocks of a session.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Peers that sent blocks of the session.
+    peers: FnvHashSet<PeerId>,
+    /// Blocks only wanted from the session peers, with the time they
+    /// were wanted.
+    wants: HashMap<Cid, Instant>,
+}
+
+impl Session {
+    /// Creates a session without peers.
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    /// Returns the peers that sent blocks of the session.
+    pub fn peers(&self) -> impl Iterator<Item=&PeerId> {
+        self.peers.iter()
+    }
+
+    /// Removes a peer that disconnected.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    /// Records that `cid` was wanted from the session peers at `now`.
+    pub fn want(&mut self, cid: Cid, now: Instant) {
+        self.wants.insert(cid, now);
+    }
+
+    /// Records that `peer_id` sent the block `cid`.
+    pub fn received(&mut self, cid: &Cid, peer_id: PeerId) {
+        self.wants.remove(cid);
+        self.peers.insert(peer_id);
+    }
+
+    /// Removes a want of the session.
+    pub fn cancel(&mut self, cid: &Cid) {
+        self.wants.remove(cid);
+    }
+
+    /// Returns the time the oldest want expires.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.wants.values().min().map(|wanted| *wanted + SESSION_TIMEOUT)
+    }
+
+    /// Removes and returns the wants that the session peers didn't
+    /// answer in time.
+    pub fn expired(&mut self, now: Instant) -> Vec<Cid> {
+        let expired: Vec<Cid> = self.wants.iter()
+            .filter(|(_, wanted)| **wanted + SESSION_TIMEOUT <= now)
+            .map(|(cid, _)| cid.to_owned())
+            .collect();
+        for cid in &expired {
+            self.wants.remove(cid);
+        }
+        expired
+    }
+}
+
+/// The sessions of the wanted blocks.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: HashMap<SessionId, Session>,
+    /// Session of each wanted block.
+    blocks: HashMap<Cid, SessionId>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Sessions::default()
+    }
+
+    /// Returns the session `id`, starting it if needed.
+    pub fn session(&mut self, id: SessionId) -> &mut Session {
+        self.sessions.entry(id).or_insert_with(Session::new)
+    }
+
+    /// Records that `cid` is wanted in the session `id`.
+    pub fn want(&mut self, id: SessionId, cid: Cid) {
+        self.blocks.insert(cid, id);
+    }
+
+    /// Records that `peer_id` sent the block `cid`.
+    pub fn received(&mut self, cid: &Cid, peer_id: &PeerId) {
+        if let Some(id) = self.blocks.remove(cid) {
+            if let Some(session) = self.sessions.get_mut(&id) {
+                session.received(cid, peer_id.to_owned());
+            }
+        }
+    }
+
+    /// Removes a want from its session.
+    pub fn cancel(&mut self, cid: &Cid) {
+        if let Some(id) = self.blocks.remove(cid) {
+            if let Some(session) = self.sessions.get_mut(&id) {
+                session.cancel(cid);
+            }
+        }
+    }
+
+    /// Records that a peer doesn't have the block `cid`, returning
+    /// whether the block was only wanted from the session peers.
+    ///
+    /// The want then counts as expired.
+    pub fn dont_have(&mut self, cid: &Cid) -> bool {
+        let id = match self.blocks.get(cid) {
+            Some(id) => id,
+            None => return false,
+        };
+        match self.sessions.get_mut(id) {
+            Some(session) => session.wants.remove(cid).is_some(),
+            None => false,
+        }
+    }
+
+    /// Removes a peer that disconnected from all sessions.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        for session in self.sessions.values_mut() {
+            session.remove_peer(peer_id);
+        }
+    }
+
+    /// Ends the session `id`, its wanted blocks stay wanted.
+    pub fn end(&mut self, id: SessionId) {
+        self.sessions.remove(&id);
+        self.blocks.retain(|_, session| *session != id);
+    }
+
+    /// Returns the time the oldest want of any session expires.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.sessions.values().filter_map(|session| session.next_timeout()).min()
+    }
+
+    /// Removes and returns the wants of all sessions that weren't
+    /// answered in time.
+    ///
+    /// The blocks stay in their sessions, so that the peer sending them
+    /// joins the session.
+    pub fn expired(&mut self, now: Instant) -> Vec<Cid> {
+        let mut expired = Vec::new();
+        for session in self.sessions.values_mut() {
+            expired.extend(session.expired(now));
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+
+    #[test]
+    fn test_sessions() {
+        let mut sessions = Sessions::new();
+        let peer = PeerId::random();
+        let (block_1, block_2) = (Block::from("1"), Block::from("2"));
+        let now = Instant::now();
+
+        sessions.session(1).want(block_1.cid().to_owned(), now);
+        sessions.want(1, block_1.cid().to_owned());
+        assert_eq!(sessions.next_timeout(), Some(now + SESSION_TIMEOUT));
+        sessions.received(block_1.cid(), &peer);
+        assert_eq!(sessions.session(1).peers().collect::<Vec<_>>(), vec![&peer]);
+        assert_eq!(sessions.next_timeout(), None);
+
+        sessions.session(1).want(block_2.cid().to_owned(), now);
+        sessions.want(1, block_2.cid().to_owned());
+        assert!(sessions.expired(now).is_empty());
+        assert_eq!(sessions.expired(now + SESSION_TIMEOUT), vec![block_2.cid().to_owned()]);
+        assert_eq!(sessions.next_timeout(), None);
+        let other = PeerId::random();
+        sessions.received(block_2.cid(), &other);
+        assert_eq!(sessions.session(1).peers().count(), 2);
+
+        sessions.session(1).want(block_1.cid().to_owned(), now);
+        sessions.want(1, block_1.cid().to_owned());
+        assert!(sessions.dont_have(block_1.cid()));
+        assert!(!sessions.dont_have(block_1.cid()));
+        assert_eq!(sessions.next_timeout(), None);
+
+        sessions.remove_peer(&peer);
+        assert_eq!(sessions.session(1).peers().collect::<Vec<_>>(), vec![&other]);
+        sessions.end(1);
+        assert!(sessions.sessions.is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+//! Bitswap statistics
+//!
+//! The swarm runs in the daemon, so the behaviour publishes the
+//! statistics of its ledgers to a shared `BitswapStats` that the node
+//! can read.
+use crate::bitswap::ledger::LedgerStat;
+use crate::block::Cid;
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Statistics of the exchange with all peers, like `ipfs bitswap stat`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BitswapStat {
+    /// The wanted blocks.
+    pub wantlist: Vec<Cid>,
+    /// The connected peers blocks are exchanged with.
+    pub peers: Vec<PeerId>,
+    pub blocks_sent: u64,
+    pub blocks_received: u64,
+    pub data_sent: u64,
+    pub data_received: u64,
+    /// Received blocks that weren't wanted anymore.
+    pub dup_blocks_received: u64,
+    pub dup_data_received: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    wantlist: HashSet<Cid>,
+    connected: HashSet<PeerId>,
+    /// The ledgers of all peers, including disconnected ones.
+    ledgers: HashMap<PeerId, LedgerStat>,
+    dup_blocks_received: u64,
+    dup_data_received: u64,
+}
+
+/// Shares the statistics of a `Bitswap`, clones share the statistics.
+#[derive(Clone, Debug, Default)]
+pub struct BitswapStats {
+    state: Arc<Mutex<State>>,
+}
+
+impl BitswapStats {
+    pub fn new() -> Self {
+        BitswapStats::default()
+    }
+
+    /// Returns the totals of all peers.
+    pub fn stat(&self) -> BitswapStat {
+        let state = self.state.lock().unwrap();
+        let mut stat = BitswapStat {
+            wantlist: state.wantlist.iter().cloned().collect(),
+            peers: state.connected.iter().cloned().collect(),
+            dup_blocks_received: state.dup_blocks_received,
+            dup_data_received: state.dup_data_received,
+            ..BitswapStat::default()
+        };
+        for ledger in state.ledgers.values() {
+            stat.blocks_sent += ledger.blocks_sent;
+            stat.blocks_received += ledger.blocks_received;
+            stat.data_sent += ledger.bytes_sent;
+            stat.data_received += ledger.bytes_received;
+        }
+        stat
+    }
+
+    /// Returns the statistics of the exchange with `peer_id`.
+    pub fn ledger(&self, peer_id: &PeerId) -> Option<LedgerStat> {
+        self.state.lock().unwrap().ledgers.get(peer_id).cloned()
+    }
+
+    pub(crate) fn want(&self, cid: &Cid) {
+        self.state.lock().unwrap().wantlist.insert(cid.to_owned());
+    }
+
+    pub(crate) fn cancel(&self, cid: &Cid) {
+        self.state.lock().unwrap().wantlist.remove(cid);
+    }
+
+    pub(crate) fn connected(&self, peer_id: &PeerId) {
+        self.state.lock().unwrap().connected.insert(peer_id.to_owned());
+    }
+
+    pub(crate) fn disconnected(&self, peer_id: &PeerId) {
+        self.state.lock().unwrap().connected.remove(peer_id);
+    }
+
+    pub(crate) fn set_ledger(&self, peer_id: &PeerId, stat: &LedgerStat) {
+        self.state.lock().unwrap().ledgers.insert(peer_id.to_owned(), stat.to_owned());
+    }
+
+    pub(crate) fn duplicate_block(&self, size: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.dup_blocks_received += 1;
+        state.dup_data_received += size as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+
+    #[test]
+    fn test_bitswap_stats() {
+        let stats = BitswapStats::new();
+        let (peer_1, peer_2) = (PeerId::random(), PeerId::random());
+        let block = Block::from("1");
+        let other = Block::from("2");
+        stats.want(block.cid());
+        stats.want(other.cid());
+        stats.cancel(other.cid());
+        stats.connected(&peer_1);
+        stats.connected(&peer_2);
+        stats.set_ledger(&peer_1, &LedgerStat {
+            blocks_sent: 1,
+            blocks_received: 2,
+            bytes_sent: 10,
+            bytes_received: 20,
+        });
+        stats.set_ledger(&peer_2, &LedgerStat {
+            blocks_sent: 3,
+            bytes_sent: 30,
+            ..LedgerStat::default()
+        });
+        stats.duplicate_block(5);
+        stats.disconnected(&peer_1);
+
+        let stat = stats.clone().stat();
+        assert_eq!(stat.wantlist, vec![block.cid().to_owned()]);
+        // disconnected peers keep their ledgers
+        assert_eq!(stat.peers, vec![peer_2.clone()]);
+        assert_eq!((stat.blocks_sent, stat.blocks_received), (4, 2));
+        assert_eq!((stat.data_sent, stat.data_received), (40, 20));
+        assert_eq!((stat.dup_blocks_received, stat.dup_data_received), (1, 5));
+        assert_eq!(stats.ledger(&peer_2).unwrap().blocks_sent, 3);
+        assert_eq!(stats.ledger(&PeerId::random()), None);
+    }
+}
@@ -1,12 +1,16 @@
 use crate::block::{Block, Cid};
-use crate::bitswap::Priority;
-use crate::repo::{Repo, RepoTypes};
+use crate::bitswap::{BlockPresence, Priority};
+use crate::repo::{FetchMode, Repo, RepoTypes};
 use libp2p::PeerId;
 use std::sync::mpsc::{channel, Sender, Receiver};
 
 pub trait Strategy<TRepoTypes: RepoTypes>: Send + Unpin {
     fn new(repo: Repo<TRepoTypes>) -> Self;
-    fn process_want(&mut self, source: PeerId, cid: Cid, priority: Priority);
+    /// Handles a want for a block, `send_dont_have` asks to answer with
+    /// `BlockPresence::DontHave` instead of waiting for missing blocks.
+    fn process_want(&mut self, source: PeerId, cid: Cid, priority: Priority, send_dont_have: bool);
+    /// Handles a question whether we have a block.
+    fn process_want_have(&mut self, source: PeerId, cid: Cid, priority: Priority, send_dont_have: bool);
     fn process_block(&mut self, source: PeerId, block: Block);
     fn poll(&mut self) -> Option<StrategyEvent>;
 }
@@ -15,7 +19,12 @@ pub enum StrategyEvent {
     Send {
         peer_id: PeerId,
         block: Block,
-    }
+    },
+    Presence {
+        peer_id: PeerId,
+        cid: Cid,
+        presence: BlockPresence,
+    },
 }
 
 pub struct AltruisticStrategy<TRepoTypes: RepoTypes> {
@@ -36,16 +45,60 @@ impl<TRepoTypes: RepoTypes> Strategy<TRepoTypes> for AltruisticStrategy<TRepoTyp
         source: PeerId,
         cid: Cid,
         priority: Priority,
+        send_dont_have: bool,
     ) {
         info!("Peer {} wants block {} with priority {}",
               source.to_base58(), cid.to_string(), priority);
         let events = self.events.0.clone();
-        let future = self.repo.get_block(&cid);
+        if !send_dont_have {
+            let future = self.repo.get_block(&cid);
+            tokio::spawn_async(async move {
+                let block = await!(future).unwrap();
+                events.send(StrategyEvent::Send {
+                    peer_id: source,
+                    block: block,
+                }).unwrap();
+            });
+            return;
+        }
+        let future = self.repo.get_block_with(&cid, FetchMode::LocalOnly);
+        tokio::spawn_async(async move {
+            let event = match await!(future).unwrap() {
+                Some(block) => StrategyEvent::Send {
+                    peer_id: source,
+                    block: block,
+                },
+                None => StrategyEvent::Presence {
+                    peer_id: source,
+                    cid,
+                    presence: BlockPresence::DontHave,
+                },
+            };
+            events.send(event).unwrap();
+        });
+    }
+
+    fn process_want_have(
+        &mut self,
+        source: PeerId,
+        cid: Cid,
+        priority: Priority,
+        send_dont_have: bool,
+    ) {
+        info!("Peer {} asks for block {} with priority {}",
+              source.to_base58(), cid.to_string(), priority);
+        let events = self.events.0.clone();
+        let future = self.repo.get_block_with(&cid, FetchMode::LocalOnly);
         tokio::spawn_async(async move {
-            let block = await!(future).unwrap();
-            events.send(StrategyEvent::Send {
+            let presence = match await!(future).unwrap() {
+                Some(_) => BlockPresence::Have,
+                None if send_dont_have => BlockPresence::DontHave,
+                None => return,
+            };
+            events.send(StrategyEvent::Presence {
                 peer_id: source,
-                block: block,
+                cid,
+                presence,
             }).unwrap();
         });
     }
@@ -54,7 +107,7 @@ impl<TRepoTypes: RepoTypes> Strategy<TRepoTypes> for AltruisticStrategy<TRepoTyp
         info!("Received block {} from peer {}",
               block.cid().to_string(),
               source.to_base58());
-        let future = self.repo.put_block(block);
+        let future = self.repo.put_block_fetched(block);
         tokio::spawn_async(async move {
             await!(future).unwrap();
         });
@@ -1,20 +1,55 @@
 //! Block
+pub use bytes::Bytes;
 pub use cid::Cid;
 pub use crate::error::Error;
 pub use crate::path::{IpfsPath, PathRoot};
+use crate::repo::RepoError;
+pub use multibase::Base;
+use multihash::Hash;
+
+/// Formats a cid using the multibase `base`.
+///
+/// Version 0 cids can only be represented in base58btc, so they are
+/// always formatted in base58btc and parse back to the same cid. Use
+/// `to_v1` first to format them in other bases.
+pub fn cid_to_string(cid: &Cid, base: Base) -> String {
+    match (cid.version, base) {
+        (cid::Version::V0, _) | (_, Base::Base58btc) => cid.to_string(),
+        (_, base) => multibase::encode(base, cid.to_bytes()),
+    }
+}
+
+/// Returns the version 1 cid of the same block as `cid`.
+pub fn to_v1(cid: &Cid) -> Cid {
+    Cid::new(cid.codec, cid::Version::V1, &cid.hash)
+}
+
+/// Returns whether blocks hashed with `hash` can be created and verified.
+pub(crate) fn is_supported_hash(hash: Hash) -> bool {
+    match hash {
+        Hash::SHA1 |
+        Hash::SHA2256 | Hash::SHA2512 |
+        Hash::SHA3224 | Hash::SHA3256 | Hash::SHA3384 | Hash::SHA3512 |
+        Hash::Keccak224 | Hash::Keccak256 | Hash::Keccak384 | Hash::Keccak512 => true,
+        _ => false,
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
 /// An immutable ipfs block.
+///
+/// The data is reference counted, clones of a block share the same
+/// buffer.
 pub struct Block {
-    data: Vec<u8>,
+    data: Bytes,
     cid: Cid,
 }
 
 impl Block {
     /// Creates a new immutable ipfs block.
-    pub fn new(data: Vec<u8>, cid: Cid) -> Self {
+    pub fn new<T: Into<Bytes>>(data: T, cid: Cid) -> Self {
         Block {
-            data,
+            data: data.into(),
             cid,
         }
     }
@@ -30,10 +65,31 @@ impl Block {
     }
 
     /// Returns the data of the block.
-    pub fn data(&self) -> &Vec<u8> {
+    pub fn data(&self) -> &Bytes {
         &self.data
     }
 
+    /// Checks that the data hashes to the content id of the block.
+    ///
+    /// Blocks using an unsupported hash function are never valid, use
+    /// `verify` to tell them apart from corrupted blocks.
+    pub fn is_valid(&self) -> bool {
+        self.verify().unwrap_or(false)
+    }
+
+    /// Checks that the data hashes to the content id of the block using
+    /// the hash function of the content id.
+    ///
+    /// Fails with `RepoError::UnsupportedHash` if the hash function isn't
+    /// supported.
+    pub fn verify(&self) -> Result<bool, Error> {
+        let prefix = self.cid.prefix();
+        if !is_supported_hash(prefix.mh_type) {
+            return Err(RepoError::UnsupportedHash(prefix.mh_type.code()).into());
+        }
+        Ok(Cid::new_from_prefix(&prefix, &self.data) == self.cid)
+    }
+
     /// Returns the ipfs path of the block.
     pub fn path(&self, path: &str) -> Result<IpfsPath, Error> {
         IpfsPath::new(PathRoot::Ipld(self.cid.clone())).into_sub_path(path)
@@ -64,6 +120,39 @@ impl Into<String> for Block {
 mod tests {
     use super::*;
 
+    fn block_with_hash(content: &str, mh_type: Hash) -> Block {
+        let prefix = cid::Prefix {
+            version: cid::Version::V1,
+            codec: cid::Codec::Raw,
+            mh_type,
+            mh_len: mh_type.size() as usize,
+        };
+        let data = content.as_bytes().to_vec();
+        let cid = Cid::new_from_prefix(&prefix, &data);
+        Block::new(data, cid)
+    }
+
+    #[test]
+    fn test_verify() {
+        for &hash in &[Hash::SHA2256, Hash::SHA1, Hash::SHA3256] {
+            let block = block_with_hash("hello\n", hash);
+            assert!(block.verify().unwrap());
+            let tampered = Block::new("tampered\n", block.cid().to_owned());
+            assert!(!tampered.verify().unwrap());
+        }
+
+        // multihash can't compute blake2b, so the cid is made by hand.
+        let mut hash = vec![Hash::Blake2b.code(), Hash::Blake2b.size()];
+        hash.extend(vec![0; Hash::Blake2b.size() as usize]);
+        let cid = Cid::new(cid::Codec::Raw, cid::Version::V1, &hash);
+        let block = Block::new("hello\n", cid);
+        match block.verify().unwrap_err().downcast_ref::<RepoError>() {
+            Some(RepoError::UnsupportedHash(code)) => assert_eq!(*code, Hash::Blake2b.code()),
+            _ => panic!("expected unsupported hash"),
+        }
+        assert!(!block.is_valid());
+    }
+
     #[test]
     fn test_raw_block_cid() {
         let content = "hello\n".as_bytes();
@@ -98,6 +187,21 @@ mod tests {
         assert_eq!(cid, computed_cid);
     }
 
+    #[test]
+    fn test_cid_to_string() {
+        let cid = Block::from("hello\n").cid().to_owned();
+        assert_eq!(cid_to_string(&cid, Base::Base58btc),
+                   "QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8");
+        // v0 cids stay in base58btc
+        assert_eq!(cid_to_string(&cid, Base::Base32), cid.to_string());
+
+        let v1 = to_v1(&cid);
+        let base32 = cid_to_string(&v1, Base::Base32);
+        assert!(base32.starts_with("b"));
+        assert_eq!(Cid::from(base32.as_str()).unwrap(), v1);
+        assert_eq!(v1.hash, cid.hash);
+    }
+
     #[test]
     fn test_block() {
         let block = Block::from("hello block\n");
@@ -0,0 +1,574 @@
+//! Mutable file system
+//!
+//! A namespace of unixfs files and directories that can be changed in
+//! place. Every change builds the changed directories up to the root
+//! again and stores the new root cid in the repo, where it keeps the
+//! blocks of the file system from being garbage collected.
+use crate::block::{Bytes, Cid};
+use crate::error::Error;
+use crate::ipld::{Ipld, formats::pb::PbNode};
+use crate::path::{IpfsPath, PathRoot};
+use crate::repo::{AddOptions, Metadata, Repo, RepoTypes, StoreStream};
+use crate::repo::content::unixfs_metadata;
+use crate::unixfs::{self, DirBuilder, EntryType};
+use crate::unixfs::dir::{dir_entries, directory};
+use crate::unixfs::ls::entry_type;
+use cid::Codec;
+use core::future::Future;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncRead;
+
+/// Entries of a directory by name, with their cid and cumulative size.
+type Entries = BTreeMap<String, (Cid, u64)>;
+
+/// Status of a file or directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stat {
+    pub cid: Cid,
+    pub kind: EntryType,
+    /// Number of bytes of a file, zero for directories.
+    pub size: u64,
+    /// Size of all blocks of the file or directory.
+    pub cumulative_size: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // loaded from the repo on first use
+    root: Option<Cid>,
+    // incremented with every change of the root
+    version: u64,
+}
+
+struct Dir {
+    entries: Entries,
+    metadata: Metadata,
+}
+
+/// Splits an absolute path into its names.
+fn components(path: &str) -> Result<Vec<String>, Error> {
+    if !path.starts_with('/') {
+        bail!("mfs paths must start with a slash: {:?}", path);
+    }
+    let names: Vec<String> = path.split('/')
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_owned())
+        .collect();
+    if names.iter().any(|name| name == "." || name == "..") {
+        bail!("mfs paths can't contain . or ..: {:?}", path);
+    }
+    Ok(names)
+}
+
+/// Splits an absolute path into its parent and its last name.
+fn split(path: &str) -> Result<(Vec<String>, String), Error> {
+    let mut parent = components(path)?;
+    match parent.pop() {
+        Some(name) => Ok((parent, name)),
+        None => bail!("the mfs root can't be changed"),
+    }
+}
+
+/// Returns the cumulative size of the blocks of `cid`, the dag-pb
+/// `Tsize` of a link to it.
+fn tree_size<T: RepoTypes>(repo: &Repo<T>, cid: &Cid) -> impl Future<Output=Result<u64, Error>> {
+    let get = repo.get_block(cid);
+    async move {
+        let block = await!(get)?;
+        if block.cid().prefix().codec != Codec::DagProtobuf {
+            return Ok(block.size() as u64);
+        }
+        let pb_node: PbNode = match Ipld::from(&block)?.try_into() {
+            Ok(pb_node) => pb_node,
+            Err(_) => bail!("invalid dag_pb node"),
+        };
+        Ok(block.size() as u64 + pb_node.links.iter().map(|link| link.size).sum::<u64>())
+    }
+}
+
+fn is_dir<T: RepoTypes>(repo: &Repo<T>, cid: &Cid) -> impl Future<Output=Result<bool, Error>> {
+    let get = repo.get_block(cid);
+    async move {
+        Ok(directory(&await!(get)?)?.is_some())
+    }
+}
+
+fn load_dir<T: RepoTypes>(repo: &Repo<T>, cid: &Cid) -> impl Future<Output=Result<Dir, Error>> {
+    let repo = repo.clone();
+    let cid = cid.to_owned();
+    async move {
+        let entries = await!(dir_entries(&repo, &cid))?;
+        let pb_node = match directory(&await!(repo.get_block(&cid))?)? {
+            Some(pb_node) => pb_node,
+            None => bail!("{} is not a directory", cid.to_string()),
+        };
+        let metadata = unixfs_metadata(&pb_node.data).unwrap_or_default();
+        Ok(Dir {
+            entries: entries.into_iter().map(|(name, cid, size)| (name, (cid, size))).collect(),
+            metadata,
+        })
+    }
+}
+
+fn store_dir<T: RepoTypes>(repo: &Repo<T>, dir: Dir) -> impl Future<Output=Result<(Cid, u64), Error>> {
+    let repo = repo.clone();
+    async move {
+        let mut builder = DirBuilder::new().metadata(dir.metadata);
+        for (name, (cid, size)) in dir.entries {
+            builder.insert(&name, cid, size)?;
+        }
+        let cid = await!(builder.put(&repo))?;
+        let size = await!(tree_size(&repo, &cid))?;
+        Ok((cid, size))
+    }
+}
+
+/// Applies `change` to the entries of the directory at `parent` below
+/// `root`, returning the new root. Missing directories on the way are
+/// created if `parents` is set.
+fn modify<T, F>(repo: &Repo<T>, root: Cid, parent: Vec<String>, parents: bool, change: F) ->
+impl Future<Output=Result<Cid, Error>>
+where
+    T: RepoTypes,
+    F: FnOnce(&mut Entries) -> Result<(), Error> + Send + 'static,
+{
+    let repo = repo.clone();
+    async move {
+        // the directories above the changed one with the name of the
+        // next one below them
+        let mut above = Vec::new();
+        let mut dir = await!(load_dir(&repo, &root))?;
+        for name in parent {
+            let child = match dir.entries.get(&name) {
+                Some((cid, _)) => await!(load_dir(&repo, cid))?,
+                None if parents => Dir {
+                    entries: Entries::new(),
+                    metadata: Metadata::default(),
+                },
+                None => bail!("{} doesn't exist", name),
+            };
+            above.push((name, dir));
+            dir = child;
+        }
+        change(&mut dir.entries)?;
+        let (mut cid, mut size) = await!(store_dir(&repo, dir))?;
+        while let Some((name, mut dir)) = above.pop() {
+            dir.entries.insert(name, (cid, size));
+            let stored = await!(store_dir(&repo, dir))?;
+            cid = stored.0;
+            size = stored.1;
+        }
+        Ok(cid)
+    }
+}
+
+/// Finds the entry at `path` below `root`.
+fn find<T: RepoTypes>(repo: &Repo<T>, root: Cid, path: Vec<String>) ->
+impl Future<Output=Result<Cid, Error>>
+{
+    let repo = repo.clone();
+    async move {
+        let mut cid = root;
+        for name in path {
+            let dir = await!(load_dir(&repo, &cid))?;
+            cid = match dir.entries.get(&name) {
+                Some((entry, _)) => entry.to_owned(),
+                None => bail!("{} doesn't exist", name),
+            };
+        }
+        Ok(cid)
+    }
+}
+
+/// Like `find`, but returns `None` instead of failing if `path` doesn't
+/// exist.
+fn lookup<T: RepoTypes>(repo: &Repo<T>, root: Cid, path: Vec<String>) ->
+impl Future<Output=Result<Option<Cid>, Error>>
+{
+    let repo = repo.clone();
+    async move {
+        let mut cid = root;
+        for name in path {
+            let dir = await!(load_dir(&repo, &cid))?;
+            cid = match dir.entries.get(&name) {
+                Some((entry, _)) => entry.to_owned(),
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(cid))
+    }
+}
+
+/// The mutable file system of a repo.
+///
+/// Clones share the same root, changes through one of them are seen by
+/// all others.
+#[derive(Clone)]
+pub struct Files<Types: RepoTypes> {
+    repo: Repo<Types>,
+    state: Arc<Mutex<State>>,
+}
+
+impl<Types: RepoTypes> Files<Types> {
+    pub fn new(repo: Repo<Types>) -> Self {
+        Files {
+            repo,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Returns the current root, creating an empty one for a new repo.
+    fn root(&self) -> impl Future<Output=Result<Cid, Error>> {
+        let repo = self.repo.clone();
+        let state = self.state.clone();
+        async move {
+            let loaded = state.lock().unwrap().root.clone();
+            if let Some(root) = loaded {
+                return Ok(root);
+            }
+            let root = match await!(repo.mfs_root())? {
+                Some(root) => root,
+                None => {
+                    let _guard = await!(repo.gc_guard());
+                    let root = await!(DirBuilder::new().put(&repo))?;
+                    await!(repo.set_mfs_root(&root))?;
+                    root
+                }
+            };
+            let mut state = state.lock().unwrap();
+            Ok(state.root.get_or_insert(root).to_owned())
+        }
+    }
+
+    /// Changes the root with `change` and flushes it.
+    ///
+    /// If the root was changed concurrently, `change` is applied again
+    /// to the new root so that no change is lost. Garbage collection
+    /// waits until the new root is flushed, so that the blocks stored by
+    /// `change` aren't collected before.
+    fn update<F, Fut>(&self, change: F) -> impl Future<Output=Result<(), Error>>
+    where
+        F: Fn(Cid) -> Fut + Send + 'static,
+        Fut: Future<Output=Result<Cid, Error>> + Send + 'static,
+    {
+        let files = self.clone();
+        async move {
+            let _guard = await!(files.repo.gc_guard());
+            loop {
+                let old = await!(files.root())?;
+                let new = await!(change(old.clone()))?;
+                let mut state = files.state.lock().unwrap();
+                if state.root.as_ref() == Some(&old) {
+                    state.root = Some(new);
+                    state.version += 1;
+                    break;
+                }
+            }
+            await!(files.flush_root())
+        }
+    }
+
+    /// Stores the current root in the repo.
+    fn flush_root(&self) -> impl Future<Output=Result<(), Error>> {
+        let repo = self.repo.clone();
+        let state = self.state.clone();
+        async move {
+            loop {
+                let (root, version) = {
+                    let state = state.lock().unwrap();
+                    match &state.root {
+                        Some(root) => (root.to_owned(), state.version),
+                        None => return Ok(()),
+                    }
+                };
+                await!(repo.set_mfs_root(&root))?;
+                // a newer root may have been stored before this one
+                if state.lock().unwrap().version == version {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Creates the directory `path`.
+    ///
+    /// With `parents` missing directories above it are created too and
+    /// an existing directory isn't an error.
+    pub fn mkdir(&self, path: &str, parents: bool) -> impl Future<Output=Result<(), Error>> {
+        let files = self.clone();
+        let split = split(path);
+        async move {
+            let (parent, name) = split?;
+            let _guard = await!(files.repo.gc_guard());
+            let empty = await!(DirBuilder::new().put(&files.repo))?;
+            let size = await!(tree_size(&files.repo, &empty))?;
+            let repo = files.repo.clone();
+            await!(files.update(move |root| {
+                let name = name.clone();
+                let empty = empty.clone();
+                modify(&repo, root, parent.clone(), parents, move |entries| {
+                    if entries.contains_key(&name) {
+                        if parents {
+                            return Ok(());
+                        }
+                        bail!("{} already exists", name);
+                    }
+                    entries.insert(name, (empty, size));
+                    Ok(())
+                })
+            }))
+        }
+    }
+
+    /// Writes the data of `reader` to the file `path`, replacing its
+    /// previous data. A missing file is only created with `create`.
+    pub fn write<R: AsyncRead + Send + 'static>(&self, path: &str, reader: R, create: bool) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let files = self.clone();
+        let path = path.to_owned();
+        async move {
+            let (parent, name) = split(&path)?;
+            let root = await!(files.root())?;
+            match await!(find(&files.repo, root, components(&path)?)) {
+                Ok(existing) => {
+                    if await!(is_dir(&files.repo, &existing))? {
+                        bail!("{} is a directory", path);
+                    }
+                }
+                Err(_) if create => {}
+                Err(err) => return Err(err),
+            }
+            // the file is only referenced once the new root is flushed
+            let _guard = await!(files.repo.gc_guard());
+            let (file, _) = await!(files.repo.add_with(reader, AddOptions::default()))?;
+            let size = await!(tree_size(&files.repo, &file))?;
+            let repo = files.repo.clone();
+            await!(files.update(move |root| {
+                let name = name.clone();
+                let file = file.clone();
+                modify(&repo, root, parent.clone(), false, move |entries| {
+                    if !create && !entries.contains_key(&name) {
+                        bail!("{} doesn't exist", name);
+                    }
+                    entries.insert(name, (file, size));
+                    Ok(())
+                })
+            }))
+        }
+    }
+
+    /// Streams the data of the file `path`, see `unixfs::cat` for the
+    /// `offset` and the `length`.
+    pub fn read(&self, path: &str, offset: u64, length: Option<u64>) ->
+    impl Future<Output=Result<StoreStream<Bytes>, Error>>
+    {
+        let files = self.clone();
+        let path = components(path);
+        async move {
+            let root = await!(files.root())?;
+            let cid = await!(find(&files.repo, root, path?))?;
+            let path = IpfsPath::new(PathRoot::Ipld(cid));
+            let data: StoreStream<Bytes> = Box::pin(unixfs::cat(&files.repo, path, offset, length));
+            Ok(data)
+        }
+    }
+
+    /// Moves `from` to `to`. If `to` is a directory, `from` is moved into
+    /// it under its current name.
+    pub fn mv(&self, from: &str, to: &str) -> impl Future<Output=Result<(), Error>> {
+        let files = self.clone();
+        let from = split(from);
+        let to = components(to);
+        async move {
+            let (from_parent, from_name) = from?;
+            let mut to = to?;
+            let root = await!(files.root())?;
+            if let Some(existing) = await!(lookup(&files.repo, root, to.clone()))? {
+                if !await!(is_dir(&files.repo, &existing))? {
+                    bail!("/{} already exists", to.join("/"));
+                }
+                to.push(from_name.clone());
+            }
+            let mut from = from_parent.clone();
+            from.push(from_name.clone());
+            if to.starts_with(&from) {
+                bail!("can't move /{} into itself", from.join("/"));
+            }
+            let to_name = match to.pop() {
+                Some(name) => name,
+                None => bail!("the mfs root can't be changed"),
+            };
+            let repo = files.repo.clone();
+            await!(files.update(move |root| {
+                let repo = repo.clone();
+                let from = from.clone();
+                let from_parent = from_parent.clone();
+                let from_name = from_name.clone();
+                let to = to.clone();
+                let to_name = to_name.clone();
+                async move {
+                    let moved = await!(find(&repo, root.clone(), from))?;
+                    let size = await!(tree_size(&repo, &moved))?;
+                    let root = await!(modify(&repo, root, from_parent, false, move |entries| {
+                        entries.remove(&from_name);
+                        Ok(())
+                    }))?;
+                    await!(modify(&repo, root, to, false, move |entries| {
+                        if entries.contains_key(&to_name) {
+                            bail!("{} already exists", to_name);
+                        }
+                        entries.insert(to_name, (moved, size));
+                        Ok(())
+                    }))
+                }
+            }))
+        }
+    }
+
+    /// Copies `from` to `to`. `from` is either a path of the mutable
+    /// file system or an `/ipfs/` path of a file or directory.
+    pub fn cp(&self, from: &str, to: &str) -> impl Future<Output=Result<(), Error>> {
+        let files = self.clone();
+        let from = from.to_owned();
+        let to = split(to);
+        async move {
+            let (parent, name) = to?;
+            let copied = if from.starts_with("/ipfs/") {
+                await!(unixfs::resolve(&files.repo, IpfsPath::from_str(&from)?))?
+            } else {
+                let root = await!(files.root())?;
+                await!(find(&files.repo, root, components(&from)?))?
+            };
+            let size = await!(tree_size(&files.repo, &copied))?;
+            let repo = files.repo.clone();
+            await!(files.update(move |root| {
+                let name = name.clone();
+                let copied = copied.clone();
+                modify(&repo, root, parent.clone(), false, move |entries| {
+                    if entries.contains_key(&name) {
+                        bail!("{} already exists", name);
+                    }
+                    entries.insert(name, (copied, size));
+                    Ok(())
+                })
+            }))
+        }
+    }
+
+    /// Removes `path`. Directories are only removed with `recursive`.
+    pub fn rm(&self, path: &str, recursive: bool) -> impl Future<Output=Result<(), Error>> {
+        let files = self.clone();
+        let path = path.to_owned();
+        async move {
+            let (parent, name) = split(&path)?;
+            let root = await!(files.root())?;
+            let removed = await!(find(&files.repo, root, components(&path)?))?;
+            if !recursive && await!(is_dir(&files.repo, &removed))? {
+                bail!("{} is a directory", path);
+            }
+            let repo = files.repo.clone();
+            await!(files.update(move |root| {
+                let name = name.clone();
+                modify(&repo, root, parent.clone(), false, move |entries| {
+                    if entries.remove(&name).is_none() {
+                        bail!("{} doesn't exist", name);
+                    }
+                    Ok(())
+                })
+            }))
+        }
+    }
+
+    /// Returns the status of `path`.
+    pub fn stat(&self, path: &str) -> impl Future<Output=Result<Stat, Error>> {
+        let files = self.clone();
+        let path = components(path);
+        async move {
+            let root = await!(files.root())?;
+            let cid = await!(find(&files.repo, root, path?))?;
+            let (kind, size, _) = entry_type(&await!(files.repo.get_block(&cid))?)?;
+            let cumulative_size = await!(tree_size(&files.repo, &cid))?;
+            Ok(Stat {
+                cid,
+                kind,
+                size,
+                cumulative_size,
+            })
+        }
+    }
+
+    /// Stores the root in the repo, returning the cid of `path`.
+    pub fn flush(&self, path: &str) -> impl Future<Output=Result<Cid, Error>> {
+        let files = self.clone();
+        let path = components(path);
+        async move {
+            let root = await!(files.root())?;
+            await!(files.flush_root())?;
+            await!(find(&files.repo, root, path?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::tests::create_mock_repo;
+    use futures::stream::StreamExt;
+    use std::io::Cursor;
+
+    fn read_all<T: RepoTypes>(files: &Files<T>, path: &str) ->
+    impl Future<Output=Result<Vec<u8>, Error>>
+    {
+        let read = files.read(path, 0, None);
+        async move {
+            let mut stream = await!(read)?;
+            let mut data = Vec::new();
+            while let Some(bytes) = await!(stream.next()) {
+                data.extend_from_slice(&bytes?);
+            }
+            Ok(data)
+        }
+    }
+
+    #[test]
+    fn test_files() {
+        let repo = create_mock_repo();
+        let files = Files::new(repo.clone());
+        tokio::run_async(async move {
+            await!(files.mkdir("/a/b", true)).unwrap();
+            assert!(await!(files.mkdir("/a/b", false)).is_err());
+            assert!(await!(files.write("/a/b/file", Cursor::new(b"data".to_vec()), false)).is_err());
+            await!(files.write("/a/b/file", Cursor::new(b"data".to_vec()), true)).unwrap();
+            assert_eq!(await!(read_all(&files, "/a/b/file")).unwrap(), b"data".to_vec());
+
+            let stat = await!(files.stat("/a/b/file")).unwrap();
+            assert_eq!(stat.kind, EntryType::File);
+            assert_eq!(stat.size, 4);
+            assert_eq!(await!(files.stat("/a")).unwrap().kind, EntryType::Directory);
+
+            await!(files.cp("/a/b/file", "/copy")).unwrap();
+            await!(files.mv("/copy", "/a")).unwrap();
+            assert_eq!(await!(read_all(&files, "/a/copy")).unwrap(), b"data".to_vec());
+            assert!(await!(files.stat("/copy")).is_err());
+            assert!(await!(files.mv("/a", "/a/b")).is_err());
+
+            let ipfs_path = format!("/ipfs/{}", stat.cid.to_string());
+            await!(files.cp(&ipfs_path, "/ipfs-copy")).unwrap();
+            assert_eq!(await!(files.stat("/ipfs-copy")).unwrap().cid, stat.cid);
+
+            assert!(await!(files.rm("/a", false)).is_err());
+            await!(files.rm("/a", true)).unwrap();
+            assert!(await!(files.stat("/a/b/file")).is_err());
+
+            // the flushed root is used by a new handle and kept by the gc
+            let root = await!(files.flush("/")).unwrap();
+            assert_eq!(await!(repo.mfs_root()).unwrap(), Some(root));
+            await!(repo.garbage_collect()).unwrap();
+            let reopened = Files::new(repo.clone());
+            assert_eq!(await!(read_all(&reopened, "/ipfs-copy")).unwrap(), b"data".to_vec());
+        });
+    }
+}
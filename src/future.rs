@@ -1,16 +1,18 @@
 use crate::block::{Block, Cid};
 use crate::error::Error;
-use crate::repo::BlockStore;
+use crate::repo::{BlockStore, RepoError};
 use futures::future::FutureObj;
 use futures::prelude::*;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Poll, Waker};
+use std::time::Instant;
 
 pub struct BlockFuture<TBlockStore: BlockStore> {
     block_store: TBlockStore,
     cid: Cid,
     future: FutureObj<'static, Result<Option<Block>, Error>>,
+    deadline: Option<Instant>,
 }
 
 impl<TBlockStore: BlockStore> BlockFuture<TBlockStore> {
@@ -20,8 +22,16 @@ impl<TBlockStore: BlockStore> BlockFuture<TBlockStore> {
             block_store,
             cid,
             future,
+            deadline: None,
         }
     }
+
+    /// Fails with `RepoError::FetchTimeout` if the block isn't stored by
+    /// `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
 }
 
 impl<TBlockStore: BlockStore> Future for BlockFuture<TBlockStore> {
@@ -31,6 +41,12 @@ impl<TBlockStore: BlockStore> Future for BlockFuture<TBlockStore> {
         return match self.future.poll_unpin(waker) {
             Poll::Ready(Ok(Some(block))) => Poll::Ready(Ok(block)),
             Poll::Ready(Ok(None)) => {
+                if let Some(deadline) = self.deadline {
+                    if Instant::now() >= deadline {
+                        let cid = self.cid.clone();
+                        return Poll::Ready(Err(RepoError::FetchTimeout(cid).into()));
+                    }
+                }
                 let future = self.block_store.get(&self.cid);
                 self.get_mut().future = future;
                 tokio::prelude::task::current().notify();
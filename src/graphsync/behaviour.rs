@@ -0,0 +1,379 @@
+//! Handles the `/ipfs/graphsync/1.0.0` protocol.
+//!
+//! The `Graphsync` struct implements the `NetworkBehaviour` trait. It
+//! answers requests of peers with the locally stored blocks selected by
+//! their selectors and sends requests for whole dags to peers, storing the
+//! blocks of the responses in the repo.
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::graphsync::message::{Message, Request, RequestId, Response, ResponseStatus};
+use crate::graphsync::protocol::GraphsyncConfig;
+use crate::ipld::{traverse_with, Selector};
+use crate::p2p::SwarmTypes;
+use crate::repo::{FetchMode, Repo};
+use core::future::Future;
+use fnv::FnvHashSet;
+use libp2p::core::swarm::{
+    ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
+};
+use libp2p::core::protocols_handler::{OneShotHandler, ProtocolsHandler};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use tokio::prelude::*;
+
+/// Size of the blocks sent in a single response message.
+const RESPONSE_BLOCKS_SIZE: usize = 1024 * 1024;
+
+/// Events of the graphsync behaviour.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GraphsyncEvent {
+    /// The request `id` for the dag below `root` sent to `peer_id` was
+    /// answered with the final `status`, the received blocks are being
+    /// stored in the repo.
+    Completed {
+        peer_id: PeerId,
+        id: RequestId,
+        root: Cid,
+        status: ResponseStatus,
+    },
+}
+
+/// Network behaviour that exchanges dags selected by ipld selectors.
+pub struct Graphsync<TSubstream, TSwarmTypes: SwarmTypes> {
+    /// Marker to pin the generics.
+    marker: PhantomData<TSubstream>,
+    repo: Repo<TSwarmTypes>,
+    /// Queue of events to report to the user.
+    events: VecDeque<NetworkBehaviourAction<Message, GraphsyncEvent>>,
+    connected_peers: FnvHashSet<PeerId>,
+    /// Roots of the requests sent to peers.
+    requests: HashMap<(PeerId, RequestId), Cid>,
+    /// Requests waiting for a peer to connect.
+    pending: Vec<(Cid, Selector)>,
+    next_id: RequestId,
+    /// Requests of peers being answered, with whether they were cancelled.
+    answering: HashMap<(PeerId, RequestId), bool>,
+    /// Response messages of the responder tasks.
+    responses: (Sender<(PeerId, Message)>, Receiver<(PeerId, Message)>),
+}
+
+/// Answers `request` with the locally stored blocks it selects.
+fn response_messages<TSwarmTypes: SwarmTypes>(repo: &Repo<TSwarmTypes>, request: Request) ->
+impl Future<Output=Result<Vec<Message>, Error>>
+{
+    let repo = repo.clone();
+    async move {
+        let traversal = await!(traverse_with(&repo, &request.root, &request.selector, FetchMode::LocalOnly))?;
+        let response = |status| Response {
+            id: request.id,
+            status,
+            extensions: BTreeMap::new(),
+        };
+        let mut messages = Vec::new();
+        let mut message = Message::new();
+        if traversal.blocks.is_empty() {
+            message.responses.push(response(ResponseStatus::RequestFailedContentNotFound));
+            messages.push(message);
+            return Ok(messages);
+        }
+        let mut size = 0;
+        let mut complete = traversal.missing.is_empty();
+        for cid in traversal.blocks {
+            // removed since the traversal
+            let block: Block = match await!(repo.get_block_with(&cid, FetchMode::LocalOnly))? {
+                Some(block) => block,
+                None => {
+                    complete = false;
+                    continue;
+                }
+            };
+            if size + block.size() > RESPONSE_BLOCKS_SIZE && !message.blocks.is_empty() {
+                message.responses.push(response(ResponseStatus::PartialResponse));
+                messages.push(std::mem::replace(&mut message, Message::new()));
+                size = 0;
+            }
+            size += block.size();
+            message.blocks.push(block);
+        }
+        let status = if complete {
+            ResponseStatus::RequestCompletedFull
+        } else {
+            ResponseStatus::RequestCompletedPartial
+        };
+        message.responses.push(response(status));
+        messages.push(message);
+        Ok(messages)
+    }
+}
+
+impl<TSubstream, TSwarmTypes: SwarmTypes> Graphsync<TSubstream, TSwarmTypes> {
+    /// Creates a `Graphsync`.
+    pub fn new(repo: Repo<TSwarmTypes>) -> Self {
+        Graphsync {
+            marker: PhantomData,
+            repo,
+            events: VecDeque::new(),
+            connected_peers: FnvHashSet::default(),
+            requests: HashMap::new(),
+            pending: Vec::new(),
+            next_id: 0,
+            answering: HashMap::new(),
+            responses: channel(),
+        }
+    }
+
+    /// Requests the blocks of the dag below `root` selected by `selector`
+    /// from `peer_id`.
+    pub fn request(&mut self, peer_id: PeerId, root: Cid, selector: Selector) -> RequestId {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut message = Message::new();
+        message.requests.push(Request {
+            id,
+            root: root.clone(),
+            selector,
+            priority: 1,
+            extensions: BTreeMap::new(),
+        });
+        debug!("graphsync: requesting {} from {}", root.to_string(), peer_id.to_base58());
+        self.requests.insert((peer_id.clone(), id), root);
+        self.events.push_back(NetworkBehaviourAction::SendEvent {
+            peer_id,
+            event: message,
+        });
+        id
+    }
+
+    /// Cancels the request `id` sent to `peer_id`.
+    pub fn cancel(&mut self, peer_id: PeerId, id: RequestId) {
+        if self.requests.remove(&(peer_id.clone(), id)).is_some() {
+            let mut message = Message::new();
+            message.cancel.push(id);
+            self.events.push_back(NetworkBehaviourAction::SendEvent {
+                peer_id,
+                event: message,
+            });
+        }
+    }
+
+    /// Requests the dag below `root` from a connected peer, or from the
+    /// next peer to connect.
+    pub fn want_dag(&mut self, root: Cid, selector: Selector) {
+        match self.connected_peers.iter().next().cloned() {
+            Some(peer_id) => {
+                self.request(peer_id, root, selector);
+            }
+            None => self.pending.push((root, selector)),
+        }
+    }
+
+    /// Answers `request` of `peer_id` from a spawned task.
+    fn respond(&mut self, peer_id: PeerId, request: Request) {
+        debug!("graphsync: {} requests {}", peer_id.to_base58(), request.root.to_string());
+        let id = request.id;
+        self.answering.insert((peer_id.clone(), id), false);
+        let future = response_messages(&self.repo, request);
+        let sender = self.responses.0.clone();
+        tokio::spawn_async(async move {
+            let messages = match await!(future) {
+                Ok(messages) => messages,
+                Err(err) => {
+                    debug!("graphsync: failed to answer request {}: {}", id, err);
+                    let mut message = Message::new();
+                    message.responses.push(Response {
+                        id,
+                        status: ResponseStatus::RequestFailedUnknown,
+                        extensions: BTreeMap::new(),
+                    });
+                    vec![message]
+                }
+            };
+            for message in messages {
+                // sending only fails if the behaviour is gone
+                let _ = sender.send((peer_id.clone(), message));
+            }
+        });
+    }
+}
+
+impl<TSubstream, TSwarmTypes: SwarmTypes> NetworkBehaviour for Graphsync<TSubstream, TSwarmTypes>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type ProtocolsHandler = OneShotHandler<TSubstream, GraphsyncConfig, Message, InnerMessage>;
+    type OutEvent = GraphsyncEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Default::default()
+    }
+
+    fn addresses_of_peer(&mut self, _peer_id: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, _: ConnectedPoint) {
+        self.connected_peers.insert(peer_id.clone());
+        for (root, selector) in std::mem::replace(&mut self.pending, Vec::new()) {
+            self.request(peer_id.clone(), root, selector);
+        }
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
+        self.connected_peers.remove(peer_id);
+        let failed: Vec<_> = self.requests.keys()
+            .filter(|(peer, _)| peer == peer_id)
+            .cloned()
+            .collect();
+        for key in failed {
+            let root = self.requests.remove(&key).expect("key was just listed");
+            self.events.push_back(NetworkBehaviourAction::GenerateEvent(GraphsyncEvent::Completed {
+                peer_id: key.0,
+                id: key.1,
+                root,
+                status: ResponseStatus::RequestFailedUnknown,
+            }));
+        }
+    }
+
+    fn inject_node_event(&mut self, source: PeerId, event: InnerMessage) {
+        let message = match event {
+            InnerMessage::Rx(message) => message,
+            InnerMessage::Tx => return,
+        };
+        for id in message.cancel {
+            // cancels of requests that aren't answered are ignored
+            if let Some(cancelled) = self.answering.get_mut(&(source.clone(), id)) {
+                *cancelled = true;
+            }
+        }
+        for request in message.requests {
+            self.respond(source.clone(), request);
+        }
+        // blocks of unknown requests are ignored
+        let requested = message.responses.iter()
+            .any(|response| self.requests.contains_key(&(source.clone(), response.id)));
+        if requested {
+            for block in message.blocks {
+                let future = self.repo.put_block_fetched(block);
+                tokio::spawn_async(async move {
+                    if let Err(err) = await!(future) {
+                        debug!("graphsync: failed to store block: {}", err);
+                    }
+                });
+            }
+        }
+        for response in message.responses {
+            if !response.status.is_terminal() {
+                continue;
+            }
+            if let Some(root) = self.requests.remove(&(source.clone(), response.id)) {
+                self.events.push_back(NetworkBehaviourAction::GenerateEvent(GraphsyncEvent::Completed {
+                    peer_id: source.clone(),
+                    id: response.id,
+                    root,
+                    status: response.status,
+                }));
+            }
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut PollParameters,
+    ) -> Async<NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent, Self::OutEvent>> {
+        while let Ok((peer_id, message)) = self.responses.1.try_recv() {
+            let mut dropped = false;
+            for response in &message.responses {
+                let key = (peer_id.clone(), response.id);
+                dropped |= self.answering.get(&key) == Some(&true);
+                if response.status.is_terminal() {
+                    self.answering.remove(&key);
+                }
+            }
+            if !dropped {
+                self.events.push_back(NetworkBehaviourAction::SendEvent {
+                    peer_id,
+                    event: message,
+                });
+            }
+        }
+        if let Some(event) = self.events.pop_front() {
+            task::current().notify();
+            return Async::Ready(event);
+        }
+        Async::NotReady
+    }
+}
+
+/// Transmission between the `OneShotHandler` and the `Graphsync` behaviour.
+#[derive(Debug)]
+pub enum InnerMessage {
+    /// We received a `Message` from a remote.
+    Rx(Message),
+    /// We successfully sent a `Message`.
+    Tx,
+}
+
+impl From<Message> for InnerMessage {
+    #[inline]
+    fn from(message: Message) -> InnerMessage {
+        InnerMessage::Rx(message)
+    }
+}
+
+impl From<()> for InnerMessage {
+    #[inline]
+    fn from(_: ()) -> InnerMessage {
+        InnerMessage::Tx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipld::Ipld;
+    use crate::repo::tests::create_mock_repo;
+
+    #[test]
+    fn test_response_messages() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let prefix = cid::Prefix {
+                version: cid::Version::V1,
+                codec: cid::Codec::Raw,
+                mh_type: multihash::Hash::SHA2256,
+                mh_len: 32,
+            };
+            let leaf = Cid::new_from_prefix(&prefix, b"leaf");
+            await!(repo.put_block(Block::new(b"leaf".to_vec(), leaf.clone()))).unwrap();
+            let root = Ipld::from(vec![Ipld::from(leaf.clone())]).to_dag_cbor().unwrap();
+            let root = await!(repo.put_block(root)).unwrap();
+            let request = Request {
+                id: 7,
+                root: root.clone(),
+                selector: Selector::explore_all_recursively(),
+                priority: 1,
+                extensions: BTreeMap::new(),
+            };
+
+            let messages = await!(response_messages(&repo, request.clone())).unwrap();
+            assert_eq!(messages.len(), 1);
+            let cids: Vec<_> = messages[0].blocks.iter().map(|block| block.cid().to_owned()).collect();
+            assert_eq!(cids, vec![root.clone(), leaf.clone()]);
+            assert_eq!(messages[0].responses[0].status, ResponseStatus::RequestCompletedFull);
+
+            await!(repo.remove_block(&leaf)).unwrap();
+            let messages = await!(response_messages(&repo, request.clone())).unwrap();
+            assert_eq!(messages[0].blocks.len(), 1);
+            assert_eq!(messages[0].responses[0].status, ResponseStatus::RequestCompletedPartial);
+
+            await!(repo.remove_block(&root)).unwrap();
+            let messages = await!(response_messages(&repo, request)).unwrap();
+            assert!(messages[0].blocks.is_empty());
+            assert_eq!(messages[0].responses[0].status, ResponseStatus::RequestFailedContentNotFound);
+        });
+    }
+}
@@ -0,0 +1,366 @@
+//! GraphSync messages
+//!
+//! Encoded by hand with the protobuf streams as described by the
+//! graphsync spec:
+//!
+//! ```protobuf
+//! message Message {
+//!   message Request {
+//!     int32 id = 1;
+//!     bytes root = 2;
+//!     bytes selector = 3;
+//!     map<string, bytes> extensions = 4;
+//!     int32 priority = 5;
+//!     bool cancel = 6;
+//!   }
+//!   message Response {
+//!     int32 id = 1;
+//!     int32 status = 2;
+//!     map<string, bytes> extensions = 3;
+//!   }
+//!   message Block {
+//!     bytes prefix = 1;
+//!     bytes data = 2;
+//!   }
+//!   bool completeRequestList = 1;
+//!   repeated Request requests = 2;
+//!   repeated Response responses = 3;
+//!   repeated Block data = 4;
+//! }
+//! ```
+use crate::block::{is_supported_hash, Block, Cid};
+use crate::error::Error;
+use crate::ipld::Selector;
+use crate::ipld::formats::cbor;
+use crate::repo::RepoError;
+use protobuf::{CodedInputStream, CodedOutputStream, ProtobufResult};
+use protobuf::wire_format::WireType;
+use std::collections::BTreeMap;
+
+pub type RequestId = i32;
+pub type Priority = i32;
+
+/// Status of a response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseStatus {
+    RequestAcknowledged,
+    PartialResponse,
+    RequestCompletedFull,
+    RequestCompletedPartial,
+    RequestRejected,
+    RequestFailedBusy,
+    RequestFailedUnknown,
+    RequestFailedContentNotFound,
+}
+
+impl ResponseStatus {
+    pub fn code(&self) -> i32 {
+        match self {
+            ResponseStatus::RequestAcknowledged => 10,
+            ResponseStatus::PartialResponse => 14,
+            ResponseStatus::RequestCompletedFull => 20,
+            ResponseStatus::RequestCompletedPartial => 21,
+            ResponseStatus::RequestRejected => 30,
+            ResponseStatus::RequestFailedBusy => 31,
+            ResponseStatus::RequestFailedUnknown => 32,
+            ResponseStatus::RequestFailedContentNotFound => 34,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<Self> {
+        let status = match code {
+            10 => ResponseStatus::RequestAcknowledged,
+            14 => ResponseStatus::PartialResponse,
+            20 => ResponseStatus::RequestCompletedFull,
+            21 => ResponseStatus::RequestCompletedPartial,
+            30 => ResponseStatus::RequestRejected,
+            31 => ResponseStatus::RequestFailedBusy,
+            32 => ResponseStatus::RequestFailedUnknown,
+            34 => ResponseStatus::RequestFailedContentNotFound,
+            _ => return None,
+        };
+        Some(status)
+    }
+
+    /// Returns whether no further responses follow for the request.
+    pub fn is_terminal(&self) -> bool {
+        self.code() >= 20
+    }
+}
+
+/// Request for the blocks of the dag below `root` selected by `selector`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    pub id: RequestId,
+    pub root: Cid,
+    pub selector: Selector,
+    pub priority: Priority,
+    pub extensions: BTreeMap<String, Vec<u8>>,
+}
+
+/// Response to the request `id`, the blocks are sent in the message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub id: RequestId,
+    pub status: ResponseStatus,
+    pub extensions: BTreeMap<String, Vec<u8>>,
+}
+
+/// A graphsync message.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Message {
+    /// New requests.
+    pub requests: Vec<Request>,
+    /// Ids of cancelled requests.
+    pub cancel: Vec<RequestId>,
+    pub responses: Vec<Response>,
+    pub blocks: Vec<Block>,
+}
+
+fn write_extensions(
+    os: &mut CodedOutputStream,
+    field: u32,
+    extensions: &BTreeMap<String, Vec<u8>>,
+) -> ProtobufResult<()> {
+    for (name, data) in extensions {
+        let mut entry = Vec::new();
+        {
+            let mut os = CodedOutputStream::vec(&mut entry);
+            os.write_string(1, name)?;
+            os.write_bytes(2, data)?;
+            os.flush()?;
+        }
+        os.write_bytes(field, &entry)?;
+    }
+    Ok(())
+}
+
+fn read_extension(bytes: &[u8]) -> Result<(String, Vec<u8>), Error> {
+    let mut is = CodedInputStream::from_bytes(bytes);
+    let mut name = String::new();
+    let mut data = Vec::new();
+    while !is.eof()? {
+        match is.read_tag_unpack()? {
+            (1, WireType::WireTypeLengthDelimited) => name = is.read_string()?,
+            (2, WireType::WireTypeLengthDelimited) => data = is.read_bytes()?,
+            (_, wire_type) => is.skip_field(wire_type)?,
+        }
+    }
+    Ok((name, data))
+}
+
+fn encode_nested<F>(encode: F) -> ProtobufResult<Vec<u8>>
+where
+    F: FnOnce(&mut CodedOutputStream) -> ProtobufResult<()>,
+{
+    let mut bytes = Vec::new();
+    {
+        let mut os = CodedOutputStream::vec(&mut bytes);
+        encode(&mut os)?;
+        os.flush()?;
+    }
+    Ok(bytes)
+}
+
+impl Message {
+    pub fn new() -> Self {
+        Message::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty() && self.cancel.is_empty() &&
+            self.responses.is_empty() && self.blocks.is_empty()
+    }
+
+    /// Turns this `Message` into a message that can be sent to a substream.
+    pub fn into_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        {
+            let mut os = CodedOutputStream::vec(&mut bytes);
+            for request in &self.requests {
+                let selector = cbor::encode(&request.selector.to_ipld())?;
+                let request = encode_nested(|os| {
+                    os.write_int32(1, request.id)?;
+                    os.write_bytes(2, &request.root.to_bytes())?;
+                    os.write_bytes(3, &selector)?;
+                    write_extensions(os, 4, &request.extensions)?;
+                    os.write_int32(5, request.priority)
+                })?;
+                os.write_bytes(2, &request)?;
+            }
+            for id in &self.cancel {
+                let request = encode_nested(|os| {
+                    os.write_int32(1, *id)?;
+                    os.write_bool(6, true)
+                })?;
+                os.write_bytes(2, &request)?;
+            }
+            for response in &self.responses {
+                let response = encode_nested(|os| {
+                    os.write_int32(1, response.id)?;
+                    os.write_int32(2, response.status.code())?;
+                    write_extensions(os, 3, &response.extensions)
+                })?;
+                os.write_bytes(3, &response)?;
+            }
+            for block in &self.blocks {
+                let block = encode_nested(|os| {
+                    os.write_bytes(1, &block.cid().prefix().as_bytes())?;
+                    os.write_bytes(2, block.data())
+                })?;
+                os.write_bytes(4, &block)?;
+            }
+            os.flush()?;
+        }
+        Ok(bytes)
+    }
+
+    /// Creates a `Message` from bytes that were received from a substream.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut message = Message::new();
+        let mut is = CodedInputStream::from_bytes(bytes);
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (2, WireType::WireTypeLengthDelimited) => message.read_request(&is.read_bytes()?)?,
+                (3, WireType::WireTypeLengthDelimited) => message.read_response(&is.read_bytes()?)?,
+                (4, WireType::WireTypeLengthDelimited) => message.read_block(&is.read_bytes()?)?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+        Ok(message)
+    }
+
+    fn read_request(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        let (mut id, mut priority, mut cancel) = (0, 0, false);
+        let (mut root, mut selector) = (None, None);
+        let mut extensions = BTreeMap::new();
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeVarint) => id = is.read_int32()?,
+                (2, WireType::WireTypeLengthDelimited) => root = Some(Cid::from(is.read_bytes()?)?),
+                (3, WireType::WireTypeLengthDelimited) => {
+                    selector = Some(Selector::from_ipld(&cbor::decode(is.read_bytes()?)?)?);
+                }
+                (4, WireType::WireTypeLengthDelimited) => {
+                    let (name, data) = read_extension(&is.read_bytes()?)?;
+                    extensions.insert(name, data);
+                }
+                (5, WireType::WireTypeVarint) => priority = is.read_int32()?,
+                (6, WireType::WireTypeVarint) => cancel = is.read_bool()?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+        if cancel {
+            self.cancel.push(id);
+            return Ok(());
+        }
+        match (root, selector) {
+            (Some(root), Some(selector)) => self.requests.push(Request {
+                id,
+                root,
+                selector,
+                priority,
+                extensions,
+            }),
+            _ => bail!("graphsync request {} without root or selector", id),
+        }
+        Ok(())
+    }
+
+    fn read_response(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        let (mut id, mut status) = (0, 0);
+        let mut extensions = BTreeMap::new();
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeVarint) => id = is.read_int32()?,
+                (2, WireType::WireTypeVarint) => status = is.read_int32()?,
+                (3, WireType::WireTypeLengthDelimited) => {
+                    let (name, data) = read_extension(&is.read_bytes()?)?;
+                    extensions.insert(name, data);
+                }
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+        let status = match ResponseStatus::from_code(status) {
+            Some(status) => status,
+            None => bail!("unknown graphsync response status {}", status),
+        };
+        self.responses.push(Response {
+            id,
+            status,
+            extensions,
+        });
+        Ok(())
+    }
+
+    fn read_block(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut is = CodedInputStream::from_bytes(bytes);
+        let (mut prefix, mut data) = (Vec::new(), Vec::new());
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeLengthDelimited) => prefix = is.read_bytes()?,
+                (2, WireType::WireTypeLengthDelimited) => data = is.read_bytes()?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+        let prefix = cid::Prefix::new_from_bytes(&prefix)?;
+        // hashing with other hashes panics
+        if !is_supported_hash(prefix.mh_type) {
+            return Err(RepoError::UnsupportedHash(prefix.mh_type.code()).into());
+        }
+        let cid = cid::Cid::new_from_prefix(&prefix, &data);
+        self.blocks.push(Block::new(data, cid));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_to_from_bytes() {
+        let block = Block::from("hello");
+        let mut extensions = BTreeMap::new();
+        extensions.insert("graphsync/do-not-send-cids".to_string(), vec![1, 2]);
+        let mut message = Message::new();
+        message.requests.push(Request {
+            id: 1,
+            root: block.cid().to_owned(),
+            selector: Selector::explore_all_recursively(),
+            priority: 3,
+            extensions: extensions.clone(),
+        });
+        message.cancel.push(2);
+        message.responses.push(Response {
+            id: 4,
+            status: ResponseStatus::RequestCompletedPartial,
+            extensions,
+        });
+        message.blocks.push(block);
+        let bytes = message.into_bytes().unwrap();
+        assert_eq!(Message::from_bytes(&bytes).unwrap(), message);
+
+        assert_eq!(Message::from_bytes(&Message::new().into_bytes().unwrap()).unwrap(), Message::new());
+        assert!(Message::from_bytes(&[0x12, 0x02, 0x08, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_hash() {
+        use multihash::Hash;
+        // multihash can't compute blake2b, so the cid is made by hand.
+        let mut hash = vec![Hash::Blake2b.code(), Hash::Blake2b.size()];
+        hash.extend(vec![0; Hash::Blake2b.size() as usize]);
+        let cid = Cid::new(cid::Codec::Raw, cid::Version::V1, &hash);
+        let mut message = Message::new();
+        message.blocks.push(Block::new(b"hello".to_vec(), cid));
+        let bytes = message.into_bytes().unwrap();
+        let err = Message::from_bytes(&bytes).unwrap_err();
+        match err.downcast_ref::<RepoError>() {
+            Some(RepoError::UnsupportedHash(code)) => assert_eq!(*code, Hash::Blake2b.code()),
+            _ => panic!("expected unsupported hash, got {}", err),
+        }
+    }
+}
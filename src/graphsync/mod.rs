@@ -0,0 +1,10 @@
+//! GraphSync protocol implementation
+//!
+//! Exchanges the blocks of a whole sub-dag selected by an ipld selector
+//! with a single request, instead of asking for every block with bitswap.
+pub mod behaviour;
+pub mod message;
+pub mod protocol;
+
+pub use self::behaviour::{Graphsync, GraphsyncEvent};
+pub use self::message::{Message, Request, RequestId, Response, ResponseStatus};
@@ -0,0 +1,68 @@
+//! Upgrades for the `/ipfs/graphsync/1.0.0` protocol, every substream
+//! carries a single message.
+use crate::error::Error;
+use crate::graphsync::message::Message;
+use libp2p::core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, upgrade};
+use std::{io, iter};
+use tokio::prelude::*;
+
+/// Largest message that is read, responses with more blocks are split
+/// into several messages.
+pub const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+const PROTOCOL: &[u8] = b"/ipfs/graphsync/1.0.0";
+
+#[derive(Clone, Debug, Default)]
+pub struct GraphsyncConfig {}
+
+impl UpgradeInfo for GraphsyncConfig {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PROTOCOL)
+    }
+}
+
+impl<TSocket> InboundUpgrade<TSocket> for GraphsyncConfig
+where
+    TSocket: AsyncRead + AsyncWrite,
+{
+    type Output = Message;
+    type Error = Error;
+    type Future = upgrade::ReadOneThen<TSocket, (), fn(Vec<u8>, ()) -> Result<Self::Output, Self::Error>>;
+
+    #[inline]
+    fn upgrade_inbound(self, socket: TSocket, _: Self::Info) -> Self::Future {
+        upgrade::read_one_then(socket, MAX_MESSAGE_SIZE, (), |packet, ()| {
+            let message = Message::from_bytes(&packet)?;
+            debug!("graphsync: inbound message: {:?}", message);
+            Ok(message)
+        })
+    }
+}
+
+impl UpgradeInfo for Message {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PROTOCOL)
+    }
+}
+
+impl<TSocket> OutboundUpgrade<TSocket> for Message
+where
+    TSocket: AsyncRead + AsyncWrite,
+{
+    type Output = ();
+    type Error = io::Error;
+    type Future = upgrade::WriteOne<TSocket>;
+
+    #[inline]
+    fn upgrade_outbound(self, socket: TSocket, _: Self::Info) -> Self::Future {
+        let bytes = self.into_bytes()
+            .expect("messages are encoded from valid selectors");
+        upgrade::write_one(socket, bytes)
+    }
+}
@@ -1,9 +1,12 @@
+use crate::block::{Block, Cid};
 use crate::error::Error;
 use crate::ipld::Ipld;
 use crate::path::{IpfsPath, IpfsPathError, PathRoot, SubPath};
-use crate::repo::{Repo, RepoTypes};
+use crate::repo::{Repo, RepoError, RepoTypes};
 use cid::Codec;
 use core::future::Future;
+use multihash::Hash;
+use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct IpldDag<Types: RepoTypes> {
@@ -19,15 +22,27 @@ impl<Types: RepoTypes> IpldDag<Types> {
 
     pub fn put(&self, data: Ipld, codec: Codec) ->
     impl Future<Output=Result<IpfsPath, Error>>
+    {
+        self.put_with(data, codec, Hash::SHA2256)
+    }
+
+    /// Stores `data` encoded with `codec` in a block hashed with `hash`.
+    pub fn put_with(&self, data: Ipld, codec: Codec, hash: Hash) ->
+    impl Future<Output=Result<IpfsPath, Error>>
     {
         let repo = self.repo.clone();
         async move {
-            let block = data.to_block(codec)?;
+            let block = data.to_block_with(codec, hash)?;
             let cid = await!(repo.put_block(block))?;
             Ok(IpfsPath::new(PathRoot::Ipld(cid)))
         }
     }
 
+    /// Returns the value at `path`.
+    ///
+    /// Keys resolve within dag-cbor maps and lists and to the links of the
+    /// same name of dag-pb nodes, the blocks of links on the way are read
+    /// from the repo. A path ending at a link returns the linked node.
     pub fn get(&self, path: IpfsPath) -> impl Future<Output=Result<Ipld, Error>> {
         let repo = self.repo.clone();
         async move {
@@ -35,69 +50,125 @@ impl<Types: RepoTypes> IpldDag<Types> {
                 Some(cid) => cid,
                 None => bail!("expected cid"),
             };
-            let mut ipld = Ipld::from(&await!(repo.get_block(&cid))?)?;
+            let mut ipld = decode(&await!(repo.get_block(&cid))?)?;
+            let mut dag_pb = cid.prefix().codec == Codec::DagProtobuf;
+            let mut depth = 0;
             for sub_path in path.iter() {
-                if !can_resolve(&ipld, sub_path) {
-                    let path = sub_path.to_owned();
-                    return Err(IpfsPathError::ResolveError { ipld, path }.into());
-                }
-                ipld = resolve(ipld, sub_path);
-                ipld = match ipld {
+                ipld = match resolve(ipld, sub_path, dag_pb)? {
                     Ipld::Link(root) => {
+                        depth += 1;
+                        if depth > repo.max_depth() {
+                            return Err(RepoError::DagTooDeep(repo.max_depth()).into());
+                        }
                         match root.cid() {
-                            Some(cid) => Ipld::from(&await!(repo.get_block(cid))?)?,
+                            Some(cid) => {
+                                dag_pb = cid.prefix().codec == Codec::DagProtobuf;
+                                decode(&await!(repo.get_block(cid))?)?
+                            }
                             None => bail!("expected cid"),
                         }
                     }
-                    ipld => ipld,
+                    ipld => {
+                        dag_pb = false;
+                        ipld
+                    }
                 };
             }
             Ok(ipld)
         }
     }
-}
 
-fn can_resolve(ipld: &Ipld, sub_path: &SubPath) -> bool {
-    match sub_path {
-        SubPath::Key(key) => {
-            if let Ipld::Object(ref map) = ipld {
-                if map.contains_key(key) {
-                    return true;
+    /// Resolves `path` to the cid of the block it points to.
+    ///
+    /// Fails if the path ends within a block instead of at a link.
+    pub fn resolve(&self, path: IpfsPath) -> impl Future<Output=Result<Cid, Error>> {
+        let repo = self.repo.clone();
+        async move {
+            let mut cid = match path.root().cid() {
+                Some(cid) => cid.to_owned(),
+                None => bail!("expected cid"),
+            };
+            // only decoded if the path continues, the last block may be raw
+            let mut ipld = None;
+            let mut depth = 0;
+            for sub_path in path.iter() {
+                let (current, dag_pb) = match ipld.take() {
+                    Some(ipld) => (ipld, false),
+                    None => {
+                        let block = await!(repo.get_block(&cid))?;
+                        (decode(&block)?, cid.prefix().codec == Codec::DagProtobuf)
+                    }
+                };
+                match resolve(current, sub_path, dag_pb)? {
+                    Ipld::Link(root) => {
+                        depth += 1;
+                        if depth > repo.max_depth() {
+                            return Err(RepoError::DagTooDeep(repo.max_depth()).into());
+                        }
+                        cid = match root.cid() {
+                            Some(cid) => cid.to_owned(),
+                            None => bail!("expected cid"),
+                        };
+                    }
+                    within => ipld = Some(within),
                 }
             }
-        }
-        SubPath::Index(index) => {
-            if let Ipld::Array(ref vec) = ipld {
-                if *index < vec.len() {
-                    return true;
-                }
+            if ipld.is_some() {
+                bail!("path doesn't point to a block");
             }
+            Ok(cid)
         }
     }
-    false
 }
 
-fn resolve(ipld: Ipld, sub_path: &SubPath) -> Ipld {
-    match sub_path {
-        SubPath::Key(key) => {
-            if let Ipld::Object(mut map) = ipld {
-                return map.remove(key).unwrap()
-            }
+/// Decodes `block`, the data of raw blocks are bytes.
+pub(crate) fn decode(block: &Block) -> Result<Ipld, Error> {
+    match block.cid().prefix().codec {
+        Codec::Raw => Ok(Ipld::Bytes(block.data().to_vec())),
+        _ => Ipld::from(block),
+    }
+}
+
+/// Returns the link named `name` of the dag-pb node `map`.
+fn pb_link(map: &HashMap<String, Ipld>, name: &str) -> Option<Ipld> {
+    let links = match map.get("Links") {
+        Some(Ipld::Array(links)) => links,
+        _ => return None,
+    };
+    links.iter().find_map(|link| match link {
+        Ipld::Object(link) if link.get("Name") == Some(&Ipld::String(name.to_owned())) => {
+            link.get("Hash").cloned()
         }
-        SubPath::Index(index) => {
-            if let Ipld::Array(mut vec) = ipld {
-                return vec.swap_remove(*index)
-            }
+        _ => None,
+    })
+}
+
+/// Resolves `sub_path` within `ipld`. Indices also resolve to map keys of
+/// the same digits, `dag_pb` resolves names of links of dag-pb nodes.
+fn resolve(ipld: Ipld, sub_path: &SubPath, dag_pb: bool) -> Result<Ipld, Error> {
+    let found = match (&ipld, sub_path) {
+        (Ipld::Object(map), _) => {
+            let key = sub_path.to_string();
+            let link = if dag_pb { pb_link(map, &key) } else { None };
+            link.or_else(|| map.get(&key).cloned())
+        }
+        (Ipld::Array(vec), SubPath::Index(index)) => vec.get(*index).cloned(),
+        _ => None,
+    };
+    match found {
+        Some(found) => Ok(found),
+        None => {
+            let path = sub_path.to_owned();
+            Err(IpfsPathError::ResolveError { ipld, path }.into())
         }
     }
-    panic!();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ipld::formats::pb::{PbLink, PbNode};
     use crate::repo::tests::create_mock_repo;
-    use std::collections::HashMap;
 
     #[test]
     fn test_resolve_root_cid() {
@@ -163,4 +234,79 @@ mod tests {
             assert_eq!(res, Ipld::U64(1));
         });
     }
+
+    #[test]
+    fn test_resolve_to_cid() {
+        tokio::run_async(async {
+            let repo = create_mock_repo();
+            let dag = IpldDag::new(repo);
+            let data1 = vec![1].into();
+            let path1 = await!(dag.put(data1, Codec::DagCBOR)).unwrap();
+            let data2 = vec![path1.root().to_owned()].into();
+            let path = await!(dag.put(data2, Codec::DagCBOR)).unwrap();
+            let cid = await!(dag.resolve(path.sub_path("0").unwrap())).unwrap();
+            assert_eq!(Some(&cid), path1.root().cid());
+            assert!(await!(dag.resolve(path.sub_path("0/0").unwrap())).is_err());
+        });
+    }
+
+    #[test]
+    fn test_put_with_hash() {
+        tokio::run_async(async {
+            let repo = create_mock_repo();
+            let dag = IpldDag::new(repo);
+            let data: Ipld = vec![1, 2].into();
+            let path = await!(dag.put_with(data.clone(), Codec::DagCBOR, Hash::SHA3256)).unwrap();
+            let prefix = path.root().cid().unwrap().prefix();
+            assert_eq!(prefix.mh_type, Hash::SHA3256);
+            assert_eq!(prefix.version, cid::Version::V1);
+            assert_eq!(await!(dag.get(path.sub_path("1").unwrap())).unwrap(), Ipld::U64(2));
+
+            let sha2 = await!(dag.put_with(data.clone(), Codec::DagCBOR, Hash::SHA2256)).unwrap();
+            assert_eq!(sha2, await!(dag.put(data.clone(), Codec::DagCBOR)).unwrap());
+            assert!(await!(dag.put_with(data.clone(), Codec::Raw, Hash::SHA2256)).is_err());
+            assert!(await!(dag.put_with(data, Codec::DagCBOR, Hash::Blake2b)).is_err());
+        });
+    }
+
+    #[test]
+    fn test_resolve_across_codecs() {
+        tokio::run_async(async {
+            let repo = create_mock_repo();
+            let dag = IpldDag::new(repo.clone());
+            let raw = cid::Cid::new_from_prefix(&cid::Prefix {
+                version: cid::Version::V1,
+                codec: Codec::Raw,
+                mh_type: Hash::SHA2256,
+                mh_len: 32,
+            }, b"raw");
+            await!(repo.put_block(Block::new(b"raw".to_vec(), raw.clone()))).unwrap();
+            let mut map = HashMap::new();
+            map.insert("b", Ipld::from(vec![Ipld::from(10), Ipld::from(raw)]));
+            map.insert("0", Ipld::from("zero"));
+            let cbor = await!(dag.put(map.into(), Codec::DagCBOR)).unwrap();
+            let pb: Ipld = PbNode {
+                links: vec![PbLink {
+                    cid: cbor.root().to_owned(),
+                    name: "a".to_string(),
+                    size: 0,
+                }],
+                data: vec![0x08, 0x01],
+            }.into();
+            let pb = await!(dag.put(pb, Codec::DagProtobuf)).unwrap();
+            let root = pb.to_string();
+
+            let path = IpfsPath::from_str(&format!("{}/a/b/0", root)).unwrap();
+            assert_eq!(await!(dag.get(path)).unwrap(), Ipld::U64(10));
+            let path = IpfsPath::from_str(&format!("{}/a/b/1", root)).unwrap();
+            assert_eq!(await!(dag.get(path.clone())).unwrap(), Ipld::Bytes(b"raw".to_vec()));
+            assert_eq!(await!(dag.resolve(path)).unwrap(), raw);
+            let path = IpfsPath::from_str(&format!("{}/a/0", root)).unwrap();
+            assert_eq!(await!(dag.get(path)).unwrap(), Ipld::from("zero"));
+            let path = IpfsPath::from_str(&format!("{}/Data", root)).unwrap();
+            assert_eq!(await!(dag.get(path)).unwrap(), Ipld::Bytes(vec![0x08, 0x01]));
+            let path = IpfsPath::from_str(&format!("{}/a/missing", root)).unwrap();
+            assert!(await!(dag.get(path)).is_err());
+        });
+    }
 }
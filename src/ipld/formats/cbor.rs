@@ -13,6 +13,15 @@ pub(crate) const PREFIX: Prefix = Prefix {
     mh_len: 32,
 };
 
+/// Tag of cids in dag-cbor.
+const CID_TAG: u64 = 42;
+
+/// Cids are stored as byte strings with the identity multibase prefix.
+///
+/// Earlier versions stored them without the prefix, which still decodes
+/// since cids never start with 0x00.
+const MULTIBASE_IDENTITY: u8 = 0x00;
+
 pub(crate) fn decode(bytes: Vec<u8>) -> Result<Ipld, Error> {
     let mut d = Decoder::from_bytes(bytes);
     let cbor: Cbor = d.read_data_item(None)?;
@@ -54,17 +63,21 @@ fn cbor_to_ipld(cbor: Cbor) -> Result<Ipld, Error> {
             Ipld::Object(ipld_map)
         }
         Cbor::Tag(tag) => {
-            if tag.tag == 42 {
-                if let Cbor::Bytes(bytes) = *tag.data {
-                    Ipld::Link(Cid::from(bytes.0)?.into())
-                } else {
-                    println!("{:?}", *tag.data);
+            if tag.tag != CID_TAG {
+                let err = ReadError::Other(format!("Unknown tag {}.", tag.tag));
+                return Err(CborError::Decode(err).into())
+            }
+            match *tag.data {
+                Cbor::Bytes(ref bytes) if bytes.0.first() == Some(&MULTIBASE_IDENTITY) => {
+                    Ipld::Link(Cid::from(&bytes.0[1..])?.into())
+                }
+                Cbor::Bytes(ref bytes) if !bytes.0.is_empty() => {
+                    Ipld::Link(Cid::from(&bytes.0[..])?.into())
+                }
+                _ => {
                     let err = ReadError::Other("Invalid CID.".into());
                     return Err(CborError::Decode(err).into())
                 }
-            } else {
-                let err = ReadError::Other("Unknown tag {}.".into());
-                return Err(CborError::Decode(err).into())
             }
         }
     };
@@ -90,7 +103,17 @@ impl Encodable for Ipld {
                 vec.encode(e)
             }
             Ipld::Object(ref map) => {
-                map.encode(e)
+                // canonical order, shorter keys first and keys of the same
+                // length bytewise, so that equal nodes have the same cid
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|(a, _), (b, _)| (a.len(), *a).cmp(&(b.len(), *b)));
+                e.emit_map(entries.len(), |e| {
+                    for (i, (key, value)) in entries.into_iter().enumerate() {
+                        e.emit_map_elt_key(i, |e| key.encode(e))?;
+                        e.emit_map_elt_val(i, |e| value.encode(e))?;
+                    }
+                    Ok(())
+                })
             }
             Ipld::F64(f) => {
                 f.encode(e)
@@ -102,8 +125,9 @@ impl Encodable for Ipld {
                 e.emit_nil()
             },
             Ipld::Link(ref root) => {
-                let bytes = cbor::CborBytes(root.to_bytes());
-                cbor::CborTagEncode::new(42, &bytes).encode(e)
+                let mut bytes = vec![MULTIBASE_IDENTITY];
+                bytes.extend_from_slice(&root.to_bytes());
+                cbor::CborTagEncode::new(CID_TAG, &cbor::CborBytes(bytes)).encode(e)
             }
         }
     }
@@ -130,4 +154,69 @@ mod tests {
         let data2 = decode(bytes).unwrap();
         assert_eq!(data, data2);
     }
+
+    fn object(entries: Vec<(&str, Ipld)>) -> Ipld {
+        Ipld::Object(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    // fixtures encoded as described in the dag-cbor spec
+    #[test]
+    fn test_spec_fixtures() {
+        let data = object(vec![
+            ("a", Ipld::U64(1)),
+            ("b", Ipld::Array(vec![Ipld::Bool(true), Ipld::Null])),
+        ]);
+        let bytes = vec![0xa2, 0x61, b'a', 0x01, 0x61, b'b', 0x82, 0xf5, 0xf6];
+        assert_eq!(encode(&data).unwrap(), bytes);
+        assert_eq!(decode(bytes).unwrap(), data);
+
+        let data = object(vec![("aa", Ipld::U64(1)), ("b", Ipld::U64(2))]);
+        let bytes = vec![0xa2, 0x61, b'b', 0x02, 0x62, b'a', b'a', 0x01];
+        assert_eq!(encode(&data).unwrap(), bytes);
+        assert_eq!(decode(bytes).unwrap(), data);
+
+        let cid = Block::from("hello").cid().to_owned();
+        let data = object(vec![("link", Ipld::Link(cid.clone().into()))]);
+        let mut bytes = vec![0xa1, 0x64, b'l', b'i', b'n', b'k', 0xd8, 0x2a, 0x58, 0x23, 0x00];
+        bytes.extend_from_slice(&cid.to_bytes());
+        assert_eq!(encode(&data).unwrap(), bytes);
+        assert_eq!(decode(bytes).unwrap(), data);
+        assert_eq!(decode(encode(&data).unwrap()).unwrap().links(), vec![cid]);
+    }
+
+    #[test]
+    fn test_legacy_cids() {
+        // cids written by earlier versions without the multibase prefix
+        let cid = Block::from("hello").cid().to_owned();
+        let mut bytes = vec![0xd8, 0x2a, 0x58, 0x22];
+        bytes.extend_from_slice(&cid.to_bytes());
+        assert_eq!(decode(bytes).unwrap(), Ipld::Link(cid.into()));
+    }
+
+    #[test]
+    fn test_invalid_tags() {
+        // invalid cids
+        assert!(decode(vec![0xd8, 0x2a, 0x40]).is_err());
+        assert!(decode(vec![0xd8, 0x2a, 0x42, 0x00, 0xff]).is_err());
+        assert!(decode(vec![0xd8, 0x2a, 0x01]).is_err());
+        // unknown tag
+        let bytes = vec![0xc1, 0x01];
+        assert!(decode(bytes).is_err());
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let cid = Block::from("hello").cid().to_owned();
+        let data = object(vec![
+            ("name", Ipld::String("hello".into())),
+            ("size", Ipld::I64(-5)),
+            ("data", Ipld::Bytes(vec![1, 2, 3])),
+            ("ratio", Ipld::F64(0.5)),
+            ("link", Ipld::Link(cid.into())),
+        ]);
+        let block = data.to_dag_cbor().unwrap();
+        assert_eq!(block.cid().prefix().codec, cid::Codec::DagCBOR);
+        assert_eq!(Ipld::from(&block).unwrap(), data);
+        assert_eq!(data.to_dag_cbor().unwrap().cid(), block.cid());
+    }
 }
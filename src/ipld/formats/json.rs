@@ -0,0 +1,143 @@
+//! dag-json, the json representation of ipld nodes
+//!
+//! Links are objects with a single `"/"` key holding the cid and bytes are
+//! `{"/": {"bytes": <base64>}}`, everything else maps to plain json.
+use crate::block::Cid;
+use crate::error::Error;
+use crate::ipld::Ipld;
+use crate::path::IpfsPath;
+use serde_json::{json, Map, Value};
+
+/// Key of links and bytes.
+const SLASH: &str = "/";
+
+pub(crate) fn encode(data: &Ipld) -> Result<Vec<u8>, Error> {
+    Ok(serde_json::to_vec(&to_value(data))?)
+}
+
+pub(crate) fn decode(bytes: &[u8]) -> Result<Ipld, Error> {
+    from_value(serde_json::from_slice(bytes)?)
+}
+
+pub(crate) fn to_value(data: &Ipld) -> Value {
+    match data {
+        Ipld::U64(u) => Value::from(*u),
+        Ipld::I64(i) => Value::from(*i),
+        Ipld::Bytes(bytes) => {
+            // drop the multibase prefix
+            let base64 = multibase::encode(multibase::Base::Base64, bytes)[1..].to_string();
+            json!({ "/": { "bytes": base64 } })
+        }
+        Ipld::String(string) => Value::from(string.as_str()),
+        Ipld::Array(vec) => Value::Array(vec.iter().map(to_value).collect()),
+        Ipld::Object(map) => Value::Object(map.iter()
+            .map(|(key, ipld)| (key.to_owned(), to_value(ipld)))
+            .collect()),
+        Ipld::F64(f) => Value::from(*f),
+        Ipld::Bool(b) => Value::from(*b),
+        Ipld::Null => Value::Null,
+        Ipld::Link(root) => match root.cid() {
+            Some(cid) => json!({ "/": cid.to_string() }),
+            None => json!({ "/": root.to_string() }),
+        },
+    }
+}
+
+fn decode_link(link: &str) -> Result<Ipld, Error> {
+    if link.starts_with('/') {
+        return Ok(Ipld::Link(IpfsPath::from_str(link)?.root().to_owned()));
+    }
+    Ok(Ipld::Link(Cid::from(link)?.into()))
+}
+
+fn decode_bytes(bytes: &Map<String, Value>) -> Result<Ipld, Error> {
+    match (bytes.len(), bytes.get("bytes")) {
+        (1, Some(Value::String(base64))) => match multibase::decode(format!("m{}", base64)) {
+            Ok((_, bytes)) => Ok(Ipld::Bytes(bytes)),
+            Err(_) => bail!("invalid base64 bytes {:?}", base64),
+        },
+        _ => bail!("invalid dag-json bytes"),
+    }
+}
+
+pub(crate) fn from_value(value: Value) -> Result<Ipld, Error> {
+    let ipld = match value {
+        Value::Null => Ipld::Null,
+        Value::Bool(b) => Ipld::Bool(b),
+        Value::Number(number) => {
+            if let Some(u) = number.as_u64() {
+                Ipld::U64(u)
+            } else if let Some(i) = number.as_i64() {
+                Ipld::I64(i)
+            } else {
+                match number.as_f64() {
+                    Some(f) => Ipld::F64(f),
+                    None => bail!("invalid number {}", number),
+                }
+            }
+        }
+        Value::String(string) => Ipld::String(string),
+        Value::Array(vec) => {
+            let ipld_vec = vec.into_iter()
+                .map(from_value)
+                .collect::<Result<_, _>>()?;
+            Ipld::Array(ipld_vec)
+        }
+        Value::Object(map) => {
+            if map.len() == 1 {
+                match map.get(SLASH) {
+                    Some(Value::String(link)) => return decode_link(link),
+                    Some(Value::Object(bytes)) => return decode_bytes(bytes),
+                    Some(_) => bail!("invalid dag-json link"),
+                    None => {}
+                }
+            }
+            let ipld_map = map.into_iter()
+                .map(|(k, v)| {
+                    Ok((k, from_value(v)?))
+                })
+                .collect::<Result<_, Error>>()?;
+            Ipld::Object(ipld_map)
+        }
+    };
+    Ok(ipld)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::path::PathRoot;
+
+    #[test]
+    fn test_encode_decode() {
+        let cid = Block::from("hello").cid().to_owned();
+        let mut map = std::collections::HashMap::new();
+        map.insert("name", Ipld::from("hello"));
+        map.insert("size", Ipld::from(-5));
+        map.insert("data", Ipld::from(vec![1u8, 2, 3]));
+        map.insert("ratio", Ipld::from(0.5));
+        map.insert("list", Ipld::from(vec![Ipld::Null, Ipld::Bool(true), Ipld::U64(1)]));
+        map.insert("link", Ipld::from(cid));
+        let data = Ipld::from(map);
+        let bytes = encode(&data).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn test_links_and_bytes() {
+        let cid = Block::from("hello").cid().to_owned();
+        let json = format!(r#"{{"/":"{}"}}"#, cid.to_string());
+        assert_eq!(decode(json.as_bytes()).unwrap(), Ipld::Link(PathRoot::Ipld(cid)));
+        let bytes = Ipld::Bytes(b"hi".to_vec());
+        assert_eq!(decode(&encode(&bytes).unwrap()).unwrap(), bytes);
+        assert_eq!(decode(br#"{"/":{"bytes":"aGk"}}"#).unwrap(), Ipld::Bytes(b"hi".to_vec()));
+
+        assert!(decode(br#"{"/":"not a cid"}"#).is_err());
+        assert!(decode(br#"{"/":1}"#).is_err());
+        assert!(decode(br#"{"/":{"bytes":"aGk","other":1}}"#).is_err());
+        // "/" next to other keys is a plain object
+        let data = decode(br#"{"/":"a","b":1}"#).unwrap();
+        assert_eq!(data.links(), vec![]);
+    }
+}
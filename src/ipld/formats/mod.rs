@@ -1,2 +1,3 @@
 pub mod cbor;
+pub mod json;
 pub mod pb;
@@ -1,8 +1,10 @@
-use crate::block::{Block, Cid};
+use crate::block::{is_supported_hash, Block, Cid};
 use crate::error::Error;
 use crate::ipld::{formats, IpldError};
 use crate::path::{IpfsPath, PathRoot};
+use crate::repo::RepoError;
 use cid::Codec;
+use multihash::Hash;
 use std::collections::HashMap;
 use std::convert::TryInto;
 
@@ -33,7 +35,18 @@ pub enum Ipld {
 
 impl Ipld {
     pub fn to_block(&self, codec: Codec) -> Result<Block, Error> {
-        let (prefix, bytes) = match codec {
+        self.to_block_with(codec, Hash::SHA2256)
+    }
+
+    /// Encodes this node with `codec` into a block whose cid uses `hash`.
+    ///
+    /// Nodes hashed with sha2-256 get the same cids as with `to_block`,
+    /// other hashes always result in version 1 cids.
+    pub fn to_block_with(&self, codec: Codec, hash: Hash) -> Result<Block, Error> {
+        if !is_supported_hash(hash) {
+            return Err(RepoError::UnsupportedHash(hash.code()).into());
+        }
+        let (mut prefix, bytes) = match codec {
             Codec::DagCBOR => {
                 (
                     formats::cbor::PREFIX,
@@ -48,6 +61,11 @@ impl Ipld {
             }
             codec => return Err(IpldError::UnsupportedCodec(codec).into()),
         };
+        if hash != prefix.mh_type {
+            prefix.version = cid::Version::V1;
+            prefix.mh_type = hash;
+            prefix.mh_len = hash.size() as usize;
+        }
         let cid = cid::Cid::new_from_prefix(&prefix, &bytes);
         Ok(Block::new(bytes, cid))
     }
@@ -60,10 +78,58 @@ impl Ipld {
         self.to_block(Codec::DagProtobuf)
     }
 
+    /// Returns the dag-json representation of this node.
+    pub fn to_json(&self) -> serde_json::Value {
+        formats::json::to_value(self)
+    }
+
+    /// Decodes a node from its dag-json representation.
+    pub fn from_json(value: serde_json::Value) -> Result<Self, Error> {
+        formats::json::from_value(value)
+    }
+
+    /// Encodes this node as dag-json.
+    pub fn to_json_bytes(&self) -> Result<Vec<u8>, Error> {
+        formats::json::encode(self)
+    }
+
+    /// Decodes a node from dag-json bytes.
+    pub fn from_json_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        formats::json::decode(bytes)
+    }
+
+    /// Returns the cids of all links contained in this node.
+    pub fn links(&self) -> Vec<Cid> {
+        let mut links = Vec::new();
+        self.collect_links(&mut links);
+        links
+    }
+
+    fn collect_links(&self, links: &mut Vec<Cid>) {
+        match self {
+            Ipld::Link(root) => {
+                if let Some(cid) = root.cid() {
+                    links.push(cid.to_owned());
+                }
+            }
+            Ipld::Array(vec) => {
+                for ipld in vec {
+                    ipld.collect_links(links);
+                }
+            }
+            Ipld::Object(map) => {
+                for ipld in map.values() {
+                    ipld.collect_links(links);
+                }
+            }
+            _ => {}
+        }
+    }
+
     pub fn from(block: &Block) -> Result<Self, Error> {
         let data = match block.cid().prefix().codec {
             Codec::DagCBOR => {
-                formats::cbor::decode(block.data().to_owned())?
+                formats::cbor::decode(block.data().to_vec())?
             }
             Codec::DagProtobuf => {
                 formats::pb::decode(block.data())?
@@ -2,7 +2,9 @@ pub mod dag;
 pub mod error;
 pub mod formats;
 pub mod ipld;
+pub mod selector;
 
 pub use self::dag::IpldDag;
 pub use self::error::IpldError;
 pub use self::ipld::Ipld;
+pub use self::selector::{traverse, traverse_with, RecursionLimit, Selector, Traversal};
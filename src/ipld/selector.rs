@@ -0,0 +1,516 @@
+//! IPLD selectors
+//!
+//! A selector describes which parts of a dag to visit starting from a
+//! root, so that sub-dags can be requested, exported or pinned without
+//! walking the whole dag.
+use crate::block::Cid;
+use crate::error::Error;
+use crate::ipld::Ipld;
+use crate::ipld::dag::decode;
+use crate::repo::{FetchMode, Repo, RepoError, RepoTypes};
+use core::future::Future;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Limit of the depth of a recursive selector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecursionLimit {
+    /// Recurses until there is nothing left to explore.
+    None,
+    /// Recurses at most this many times.
+    Depth(u64),
+}
+
+/// Selector as specified by the ipld selector spec.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    /// Matches the current node.
+    Matcher,
+    /// Applies `next` to all entries of a map or list.
+    ExploreAll {
+        next: Box<Selector>,
+    },
+    /// Applies the selectors to the map entries of their name.
+    ExploreFields {
+        fields: BTreeMap<String, Selector>,
+    },
+    /// Applies `next` to the list entry `index`.
+    ExploreIndex {
+        index: usize,
+        next: Box<Selector>,
+    },
+    /// Applies `next` to the list entries from `start` up to `end`.
+    ExploreRange {
+        start: usize,
+        end: usize,
+        next: Box<Selector>,
+    },
+    /// Applies `sequence`, starting it again at every edge within it.
+    ExploreRecursive {
+        limit: RecursionLimit,
+        sequence: Box<Selector>,
+    },
+    /// Marks where the closest recursive selector starts again.
+    ExploreRecursiveEdge,
+    /// Applies all selectors to the current node.
+    ExploreUnion(Vec<Selector>),
+}
+
+fn single(key: &str, value: Ipld) -> Ipld {
+    let mut map = HashMap::new();
+    map.insert(key.to_string(), value);
+    Ipld::Object(map)
+}
+
+fn empty() -> Ipld {
+    Ipld::Object(HashMap::new())
+}
+
+fn get<'a>(map: &'a HashMap<String, Ipld>, key: &str) -> Result<&'a Ipld, Error> {
+    match map.get(key) {
+        Some(value) => Ok(value),
+        None => bail!("selector is missing {:?}", key),
+    }
+}
+
+fn get_usize(map: &HashMap<String, Ipld>, key: &str) -> Result<usize, Error> {
+    match get(map, key)? {
+        Ipld::U64(u) => Ok(*u as usize),
+        _ => bail!("selector field {:?} is not an unsigned integer", key),
+    }
+}
+
+fn get_next(map: &HashMap<String, Ipld>, key: &str) -> Result<Box<Selector>, Error> {
+    Ok(Box::new(Selector::from_ipld(get(map, key)?)?))
+}
+
+impl Selector {
+    /// Selects all nodes of a dag, like `ipfs refs -r`.
+    pub fn explore_all_recursively() -> Self {
+        Selector::ExploreRecursive {
+            limit: RecursionLimit::None,
+            sequence: Box::new(Selector::ExploreUnion(vec![
+                Selector::Matcher,
+                Selector::ExploreAll {
+                    next: Box::new(Selector::ExploreRecursiveEdge),
+                },
+            ])),
+        }
+    }
+
+    /// Returns the representation of the selector in the ipld data model
+    /// with the short keys of the spec.
+    pub fn to_ipld(&self) -> Ipld {
+        match self {
+            Selector::Matcher => single(".", empty()),
+            Selector::ExploreAll { next } => single("a", single(">", next.to_ipld())),
+            Selector::ExploreFields { fields } => {
+                let fields = fields.iter()
+                    .map(|(name, selector)| (name.to_owned(), selector.to_ipld()))
+                    .collect();
+                single("f", single("f>", Ipld::Object(fields)))
+            }
+            Selector::ExploreIndex { index, next } => {
+                let mut map = HashMap::new();
+                map.insert("i".to_string(), Ipld::U64(*index as u64));
+                map.insert(">".to_string(), next.to_ipld());
+                single("i", Ipld::Object(map))
+            }
+            Selector::ExploreRange { start, end, next } => {
+                let mut map = HashMap::new();
+                map.insert("^".to_string(), Ipld::U64(*start as u64));
+                map.insert("$".to_string(), Ipld::U64(*end as u64));
+                map.insert(">".to_string(), next.to_ipld());
+                single("r", Ipld::Object(map))
+            }
+            Selector::ExploreRecursive { limit, sequence } => {
+                let limit = match limit {
+                    RecursionLimit::None => single("none", empty()),
+                    RecursionLimit::Depth(depth) => single("depth", Ipld::U64(*depth)),
+                };
+                let mut map = HashMap::new();
+                map.insert("l".to_string(), limit);
+                map.insert(":>".to_string(), sequence.to_ipld());
+                single("R", Ipld::Object(map))
+            }
+            Selector::ExploreRecursiveEdge => single("@", empty()),
+            Selector::ExploreUnion(selectors) => {
+                single("|", Ipld::Array(selectors.iter().map(Selector::to_ipld).collect()))
+            }
+        }
+    }
+
+    /// Reads a selector from its representation in the ipld data model.
+    pub fn from_ipld(ipld: &Ipld) -> Result<Self, Error> {
+        let (kind, value) = match ipld {
+            Ipld::Object(map) if map.len() == 1 => map.iter().next().unwrap(),
+            _ => bail!("selector must be a map with a single key"),
+        };
+        if kind == "|" {
+            let selectors = match value {
+                Ipld::Array(selectors) => selectors.iter()
+                    .map(Selector::from_ipld)
+                    .collect::<Result<_, _>>()?,
+                _ => bail!("selector union must be a list"),
+            };
+            return Ok(Selector::ExploreUnion(selectors));
+        }
+        let map = match value {
+            Ipld::Object(map) => map,
+            _ => bail!("invalid selector {:?}", kind),
+        };
+        let selector = match kind.as_str() {
+            "." => Selector::Matcher,
+            "a" => Selector::ExploreAll {
+                next: get_next(map, ">")?,
+            },
+            "f" => {
+                let fields = match get(map, "f>")? {
+                    Ipld::Object(fields) => fields.iter()
+                        .map(|(name, selector)| Ok((name.to_owned(), Selector::from_ipld(selector)?)))
+                        .collect::<Result<_, Error>>()?,
+                    _ => bail!("selector fields must be a map"),
+                };
+                Selector::ExploreFields { fields }
+            }
+            "i" => Selector::ExploreIndex {
+                index: get_usize(map, "i")?,
+                next: get_next(map, ">")?,
+            },
+            "r" => Selector::ExploreRange {
+                start: get_usize(map, "^")?,
+                end: get_usize(map, "$")?,
+                next: get_next(map, ">")?,
+            },
+            "R" => {
+                let limit = match get(map, "l")? {
+                    Ipld::Object(limit) if limit.contains_key("none") => RecursionLimit::None,
+                    Ipld::Object(limit) => RecursionLimit::Depth(get_usize(limit, "depth")? as u64),
+                    _ => bail!("invalid recursion limit"),
+                };
+                Selector::ExploreRecursive {
+                    limit,
+                    sequence: get_next(map, ":>")?,
+                }
+            }
+            "@" => Selector::ExploreRecursiveEdge,
+            kind => bail!("unknown selector {:?}", kind),
+        };
+        Ok(selector)
+    }
+}
+
+/// Result of a traversal.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Traversal {
+    /// Cids of the loaded blocks in the order they were first loaded.
+    pub blocks: Vec<Cid>,
+    /// Paths below the root and the nodes matched by the selector.
+    pub matches: Vec<(String, Ipld)>,
+    /// Cids of the blocks that couldn't be fetched, the parts of the dag
+    /// below them are skipped.
+    pub missing: Vec<Cid>,
+}
+
+/// A selector and the closest recursive selector around it.
+type Applied = (Selector, Option<(Box<Selector>, RecursionLimit)>);
+
+struct Frame {
+    node: Ipld,
+    selector: Selector,
+    // sequence and limit of the closest recursive selector
+    recursion: Option<(Box<Selector>, RecursionLimit)>,
+    path: Vec<String>,
+    // number of links followed to get to the node
+    depth: usize,
+}
+
+impl Frame {
+    fn child(&self, node: Ipld, selector: Selector, name: String) -> Self {
+        let mut path = self.path.clone();
+        path.push(name);
+        Frame {
+            node,
+            selector,
+            recursion: self.recursion.clone(),
+            path,
+            depth: self.depth,
+        }
+    }
+}
+
+/// Continues the closest recursive selector at a recursive edge,
+/// returning `None` once its limit is used up.
+fn follow_edge(mut frame: Frame) -> Result<Option<Frame>, Error> {
+    while let Selector::ExploreRecursiveEdge = frame.selector {
+        let (sequence, limit) = match frame.recursion.take() {
+            Some(recursion) => recursion,
+            None => bail!("recursive edge outside of a recursive selector"),
+        };
+        let limit = match limit {
+            RecursionLimit::None => RecursionLimit::None,
+            RecursionLimit::Depth(depth) if depth > 1 => RecursionLimit::Depth(depth - 1),
+            RecursionLimit::Depth(_) => return Ok(None),
+        };
+        frame.selector = (*sequence).clone();
+        frame.recursion = Some((sequence, limit));
+    }
+    Ok(Some(frame))
+}
+
+/// Returns the entries of maps in the order of their keys and of lists.
+fn children(node: &Ipld) -> Vec<(String, Ipld)> {
+    match node {
+        Ipld::Object(map) => {
+            let mut entries: Vec<_> = map.iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            entries
+        }
+        Ipld::Array(vec) => vec.iter().enumerate()
+            .map(|(index, value)| (index.to_string(), value.to_owned()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Traverses the dag below `root` with `selector`, loading the blocks
+/// of all links it explores through from `repo`.
+pub fn traverse<T: RepoTypes>(repo: &Repo<T>, root: &Cid, selector: &Selector) ->
+impl Future<Output=Result<Traversal, Error>>
+{
+    traverse_with(repo, root, selector, FetchMode::NetworkBlocking)
+}
+
+/// Traverses the dag like `traverse`, fetching missing blocks according
+/// to `mode`.
+pub fn traverse_with<T: RepoTypes>(repo: &Repo<T>, root: &Cid, selector: &Selector, mode: FetchMode) ->
+impl Future<Output=Result<Traversal, Error>>
+{
+    let repo = repo.clone();
+    let root = root.to_owned();
+    let selector = selector.to_owned();
+    async move {
+        let mut traversal = Traversal::default();
+        let mut loaded = HashSet::new();
+        // selectors already applied to the blocks, so that shared parts
+        // of the dag are only traversed once
+        let mut visited: HashMap<Cid, Vec<Applied>> = HashMap::new();
+        let mut stack = vec![Frame {
+            node: Ipld::Link(root.into()),
+            selector,
+            recursion: None,
+            path: Vec::new(),
+            depth: 0,
+        }];
+        while let Some(frame) = stack.pop() {
+            // the limit is checked before loading the block of a link
+            let mut frame = match follow_edge(frame)? {
+                Some(frame) => frame,
+                None => continue,
+            };
+            if let Ipld::Link(link) = &frame.node {
+                let cid = match link.cid() {
+                    Some(cid) => cid.to_owned(),
+                    None => bail!("expected cid"),
+                };
+                let applied = (frame.selector.clone(), frame.recursion.clone());
+                let selectors = visited.entry(cid.clone()).or_insert_with(Vec::new);
+                if selectors.contains(&applied) {
+                    continue;
+                }
+                selectors.push(applied);
+                if frame.depth > repo.max_depth() {
+                    return Err(RepoError::DagTooDeep(repo.max_depth()).into());
+                }
+                let block = match await!(repo.get_block_with(&cid, mode))? {
+                    Some(block) => block,
+                    None => {
+                        traversal.missing.push(cid);
+                        continue;
+                    }
+                };
+                if loaded.insert(cid.clone()) {
+                    traversal.blocks.push(cid);
+                }
+                frame.node = decode(&block)?;
+                frame.depth += 1;
+            }
+            // children are pushed in reverse to be visited in order
+            let mut next = Vec::new();
+            match &frame.selector {
+                Selector::Matcher => {
+                    traversal.matches.push((frame.path.join("/"), frame.node.clone()));
+                }
+                Selector::ExploreAll { next: selector } => {
+                    for (name, child) in children(&frame.node) {
+                        next.push(frame.child(child, (**selector).clone(), name));
+                    }
+                }
+                Selector::ExploreFields { fields } => {
+                    if let Ipld::Object(map) = &frame.node {
+                        for (name, selector) in fields {
+                            if let Some(child) = map.get(name) {
+                                next.push(frame.child(child.clone(), selector.clone(), name.clone()));
+                            }
+                        }
+                    }
+                }
+                Selector::ExploreIndex { index, next: selector } => {
+                    if let Ipld::Array(vec) = &frame.node {
+                        if let Some(child) = vec.get(*index) {
+                            next.push(frame.child(child.clone(), (**selector).clone(), index.to_string()));
+                        }
+                    }
+                }
+                Selector::ExploreRange { start, end, next: selector } => {
+                    if let Ipld::Array(vec) = &frame.node {
+                        for index in *start..(*end).min(vec.len()) {
+                            next.push(frame.child(vec[index].clone(), (**selector).clone(), index.to_string()));
+                        }
+                    }
+                }
+                Selector::ExploreRecursive { limit, sequence } => {
+                    if *limit != RecursionLimit::Depth(0) {
+                        next.push(Frame {
+                            node: frame.node.clone(),
+                            selector: (**sequence).clone(),
+                            recursion: Some((sequence.clone(), *limit)),
+                            path: frame.path.clone(),
+                            depth: frame.depth,
+                        });
+                    }
+                }
+                Selector::ExploreRecursiveEdge => unreachable!("edges are followed first"),
+                Selector::ExploreUnion(selectors) => {
+                    for selector in selectors {
+                        next.push(Frame {
+                            node: frame.node.clone(),
+                            selector: selector.clone(),
+                            recursion: frame.recursion.clone(),
+                            path: frame.path.clone(),
+                            depth: frame.depth,
+                        });
+                    }
+                }
+            }
+            stack.extend(next.into_iter().rev());
+        }
+        Ok(traversal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::tests::create_mock_repo;
+    use cid::Codec;
+
+    /// Stores a chain of `length` nodes `{"value": i, "next": <link>}`,
+    /// returning the cids from the root to the last node.
+    fn chain<T: RepoTypes>(repo: &Repo<T>, length: u64) -> impl Future<Output=Vec<Cid>> {
+        let repo = repo.clone();
+        async move {
+            let mut cids = Vec::new();
+            for i in (0..length).rev() {
+                let mut map = HashMap::new();
+                map.insert("value", Ipld::U64(i));
+                if let Some(next) = cids.last() {
+                    map.insert("next", Ipld::from(Cid::clone(next)));
+                }
+                let block = Ipld::from(map).to_block(Codec::DagCBOR).unwrap();
+                cids.push(await!(repo.put_block(block)).unwrap());
+            }
+            cids.reverse();
+            cids
+        }
+    }
+
+    #[test]
+    fn test_selector_ipld() {
+        let mut fields = BTreeMap::new();
+        fields.insert("next".to_string(), Selector::ExploreRecursiveEdge);
+        let selectors = vec![
+            Selector::explore_all_recursively(),
+            Selector::ExploreRecursive {
+                limit: RecursionLimit::Depth(3),
+                sequence: Box::new(Selector::ExploreFields { fields }),
+            },
+            Selector::ExploreIndex { index: 1, next: Box::new(Selector::Matcher) },
+            Selector::ExploreRange { start: 1, end: 3, next: Box::new(Selector::Matcher) },
+        ];
+        for selector in selectors {
+            let ipld = selector.to_ipld();
+            assert_eq!(Selector::from_ipld(&ipld).unwrap(), selector);
+            let block = ipld.to_dag_cbor().unwrap();
+            assert_eq!(Selector::from_ipld(&Ipld::from(&block).unwrap()).unwrap(), selector);
+        }
+        assert!(Selector::from_ipld(&single("x", empty())).is_err());
+    }
+
+    #[test]
+    fn test_traverse_all() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let cids = await!(chain(&repo, 4));
+            let selector = Selector::explore_all_recursively();
+            let traversal = await!(traverse(&repo, &cids[0], &selector)).unwrap();
+            assert_eq!(traversal.blocks, cids);
+            let paths: Vec<_> = traversal.matches.iter().map(|(path, _)| path.as_str()).collect();
+            assert_eq!(paths, vec![
+                "", "next", "next/next", "next/next/next", "next/next/next/value",
+                "next/next/value", "next/value", "value",
+            ]);
+        });
+    }
+
+    #[test]
+    fn test_traverse_limited() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let cids = await!(chain(&repo, 4));
+            let mut fields = BTreeMap::new();
+            fields.insert("next".to_string(), Selector::ExploreRecursiveEdge);
+            fields.insert("value".to_string(), Selector::Matcher);
+            let selector = Selector::ExploreRecursive {
+                limit: RecursionLimit::Depth(2),
+                sequence: Box::new(Selector::ExploreFields { fields }),
+            };
+            let traversal = await!(traverse(&repo, &cids[0], &selector)).unwrap();
+            assert_eq!(traversal.blocks, cids[..2].to_vec());
+            assert_eq!(traversal.matches, vec![
+                ("next/value".to_string(), Ipld::U64(1)),
+                ("value".to_string(), Ipld::U64(0)),
+            ]);
+
+            await!(repo.remove_block(&cids[2])).unwrap();
+            let selector = Selector::explore_all_recursively();
+            let traversal = await!(traverse_with(&repo, &cids[0], &selector, FetchMode::LocalOnly)).unwrap();
+            assert_eq!(traversal.blocks, cids[..2].to_vec());
+            assert_eq!(traversal.missing, vec![cids[2].clone()]);
+
+            let edge = Selector::ExploreAll { next: Box::new(Selector::ExploreRecursiveEdge) };
+            assert!(await!(traverse(&repo, &cids[0], &edge)).is_err());
+        });
+    }
+
+    #[test]
+    fn test_traverse_shared() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let shared = await!(chain(&repo, 2));
+            let mut map = HashMap::new();
+            map.insert("a", Ipld::from(shared[0].clone()));
+            map.insert("b", Ipld::from(shared[0].clone()));
+            let root = Ipld::from(map).to_block(Codec::DagCBOR).unwrap();
+            let root = await!(repo.put_block(root)).unwrap();
+
+            let selector = Selector::explore_all_recursively();
+            let traversal = await!(traverse(&repo, &root, &selector)).unwrap();
+            assert_eq!(traversal.blocks, vec![root, shared[0].clone(), shared[1].clone()]);
+            let paths: Vec<_> = traversal.matches.iter().map(|(path, _)| path.as_str()).collect();
+            assert!(paths.contains(&"a/next/value"));
+            assert!(!paths.contains(&"b/next/value"));
+        });
+    }
+}
@@ -2,6 +2,8 @@ use crate::error::Error;
 use crate::ipns::ipns_pb as proto;
 use crate::path::IpfsPath;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use ed25519_dalek::Keypair;
+use libp2p::PeerId;
 use libp2p::core::PublicKey;
 use libp2p::secio::SecioKeyPair;
 use protobuf::{self, ProtobufError, Message as ProtobufMessage};
@@ -34,6 +36,17 @@ impl IpnsEntry {
         self.seq
     }
 
+    /// Returns the time after which the record is no longer valid.
+    pub fn validity(&self) -> SystemTime {
+        self.validity
+    }
+
+    /// Sets the sequence number, which isn't covered by the signature.
+    pub fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = seq;
+        self
+    }
+
     pub fn from_path(path: &IpfsPath, seq: u64, key: &SecioKeyPair) -> Self {
         let value = path.to_string();
         // TODO what is a reasonable default?
@@ -46,18 +59,72 @@ impl IpnsEntry {
         Vec::new()
     }
 
+    /// Creates an entry signed with an ed25519 `keypair`.
+    pub fn new_signed(value: String, seq: u64, ttl: Duration, keypair: &Keypair) -> Self {
+        IpnsEntry::signed_until(value, seq, SystemTime::now() + ttl, keypair)
+    }
+
+    /// Creates an entry for `path` that is valid until `validity`.
+    pub fn from_path_signed(path: &IpfsPath, seq: u64, validity: SystemTime, keypair: &Keypair) -> Self {
+        IpnsEntry::signed_until(path.to_string(), seq, validity, keypair)
+    }
+
+    fn signed_until(value: String, seq: u64, validity: SystemTime, keypair: &Keypair) -> Self {
+        let public_key = PublicKey::Ed25519(keypair.public.to_bytes().to_vec());
+        let data = IpnsEntry::signature_data(&validity, &value);
+        let signature = keypair.sign(&data).to_bytes().to_vec();
+        IpnsEntry {
+            value,
+            seq,
+            validity,
+            public_key,
+            signature,
+        }
+    }
+
+    /// Returns the peer id of the key that signed the entry.
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key.clone().into_peer_id()
+    }
+
+    /// Checks that the entry was signed by `peer_id` and is still valid
+    /// at `now`.
+    pub fn verify(&self, peer_id: &PeerId, now: SystemTime) -> bool {
+        if &self.peer_id() != peer_id || self.validity <= now {
+            return false;
+        }
+        let public_key = match self.public_key {
+            PublicKey::Ed25519(ref bytes) => bytes,
+            _ => return false,
+        };
+        let public_key = match ed25519_dalek::PublicKey::from_bytes(public_key) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+        let signature = match ed25519_dalek::Signature::from_bytes(&self.signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        let data = IpnsEntry::signature_data(&self.validity, &self.value);
+        public_key.verify(&data, &signature).is_ok()
+    }
+
+    /// The signed data is the value, followed by the validity and the
+    /// validity type.
+    fn signature_data(validity: &SystemTime, value: &str) -> Vec<u8> {
+        let mut data = value.as_bytes().to_vec();
+        data.extend(validity_bytes(validity));
+        // the EOL validity type
+        data.extend(b"0");
+        data
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut proto = proto::IpnsEntry::new();
         proto.set_value(self.value.as_bytes().to_vec());
         proto.set_sequence(self.seq);
-        let nanos = self.validity
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        let mut validity = vec![];
-        validity.write_u64::<BigEndian>(nanos as u64).unwrap();
         proto.set_validityType(proto::IpnsEntry_ValidityType::EOL);
-        proto.set_validity(validity);
+        proto.set_validity(validity_bytes(&self.validity));
         proto.set_signature(self.signature.clone());
         proto.set_pubKey(self.public_key.clone().into_protobuf_encoding());
         proto
@@ -91,6 +158,16 @@ impl IpnsEntry {
     }
 }
 
+fn validity_bytes(validity: &SystemTime) -> Vec<u8> {
+    let nanos = validity
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let mut bytes = vec![];
+    bytes.write_u64::<BigEndian>(nanos as u64).unwrap();
+    bytes
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -115,6 +192,24 @@ mod tests {
         assert_eq!(ipns, ipns2);
     }
 
+    #[test]
+    fn test_verify() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let path = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8").unwrap();
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(60);
+        let ipns = IpnsEntry::from_path_signed(&path, 0, now + ttl, &keypair);
+        let ipns = IpnsEntry::from_bytes(&ipns.to_bytes()).unwrap();
+        assert!(ipns.verify(&ipns.peer_id(), now));
+
+        let mut tampered = ipns.clone();
+        tampered.value = "/ipfs/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".into();
+        assert!(!tampered.verify(&ipns.peer_id(), now));
+
+        // expired records aren't valid anymore
+        assert!(!ipns.verify(&ipns.peer_id(), now + ttl));
+    }
+
     #[test]
     fn test_from_path() {
         let key = SecioKeyPair::ed25519_generated().unwrap();
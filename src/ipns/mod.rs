@@ -1,14 +1,20 @@
 #![allow(dead_code)]
 use crate::error::Error;
 use crate::path::{IpfsPath, PathRoot};
-use crate::repo::{Repo, RepoTypes};
+use crate::repo::{Repo, RepoError, RepoTypes};
 use libp2p::PeerId;
+use std::collections::HashSet;
 use std::future::Future;
 
 mod dns;
 mod entry;
 mod ipns_pb;
 
+pub use self::entry::IpnsEntry;
+
+/// Maximum number of ipns and dns records followed by `resolve`.
+pub const MAX_RESOLVE_DEPTH: usize = 32;
+
 pub struct Ipns<Types: RepoTypes> {
     repo: Repo<Types>,
 }
@@ -21,23 +27,43 @@ impl<Types: RepoTypes> Ipns<Types> {
     }
 
     /// Resolves a ipns path to an ipld path.
+    ///
+    /// Records pointing at other ipns names are followed recursively.
+    /// Fails with `RepoError::IpnsLoop` if a name is visited twice or
+    /// more than `MAX_RESOLVE_DEPTH` records are followed.
     pub fn resolve(&self, path: &IpfsPath) ->
     impl Future<Output=Result<IpfsPath, Error>>
     {
         let repo = self.repo.clone();
-        let path = path.to_owned();
+        let mut path = path.to_owned();
         async move {
-            match path.root() {
-                PathRoot::Ipld(_) => Ok(path),
-                PathRoot::Ipns(peer_id) => {
-                    match await!(repo.get_ipns(peer_id))? {
-                        Some(path) => Ok(path),
-                        None => bail!("unimplemented"),
-                    }
-                },
-                PathRoot::Dns(domain) => {
-                    Ok(await!(dns::resolve(domain)?)?)
-                },
+            let mut visited = HashSet::new();
+            let mut depth = 0;
+            loop {
+                let mut resolved = match path.root().to_owned() {
+                    PathRoot::Ipld(_) => return Ok(path),
+                    PathRoot::Ipns(peer_id) => {
+                        depth += 1;
+                        if depth > MAX_RESOLVE_DEPTH || !visited.insert(peer_id.clone()) {
+                            return Err(RepoError::IpnsLoop(peer_id).into());
+                        }
+                        match await!(repo.get_ipns(&peer_id))? {
+                            Some(path) => path,
+                            None => bail!("unimplemented"),
+                        }
+                    },
+                    PathRoot::Dns(domain) => {
+                        depth += 1;
+                        if depth > MAX_RESOLVE_DEPTH {
+                            bail!("too many redirects resolving {}", domain);
+                        }
+                        await!(dns::resolve(&domain)?)?
+                    },
+                };
+                for sub_path in path.iter() {
+                    resolved.push(sub_path.to_owned());
+                }
+                path = resolved;
             }
         }
     }
@@ -63,3 +89,51 @@ impl<Types: RepoTypes> Ipns<Types> {
         self.repo.remove_ipns(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::repo::tests::create_mock_repo;
+
+    #[test]
+    fn test_resolve_chain() {
+        let ipns = Ipns::new(create_mock_repo());
+        let peer_a = PeerId::random();
+        let peer_b = PeerId::random();
+        let cid = Block::from("hello").cid().to_owned();
+        tokio::run_async(async move {
+            let path_b = IpfsPath::from(peer_b.clone()).sub_path("b").unwrap();
+            let path_cid = IpfsPath::from(cid.clone());
+            await!(ipns.publish(&peer_a, &path_b)).unwrap();
+            await!(ipns.publish(&peer_b, &path_cid)).unwrap();
+
+            let path = IpfsPath::from(peer_a).sub_path("a").unwrap();
+            let res = await!(ipns.resolve(&path)).unwrap();
+            assert_eq!(res, IpfsPath::from(cid).sub_path("b/a").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_resolve_loop() {
+        let ipns = Ipns::new(create_mock_repo());
+        let peer_a = PeerId::random();
+        let peer_b = PeerId::random();
+        tokio::run_async(async move {
+            let err = await!(ipns.publish(&peer_a, &IpfsPath::from(peer_a.clone())))
+                .unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::IpnsLoop(_)) => {}
+                _ => panic!("expected ipns loop"),
+            }
+
+            await!(ipns.publish(&peer_a, &IpfsPath::from(peer_b.clone()))).unwrap();
+            await!(ipns.publish(&peer_b, &IpfsPath::from(peer_a.clone()))).unwrap();
+            let err = await!(ipns.resolve(&IpfsPath::from(peer_a))).unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::IpnsLoop(_)) => {}
+                _ => panic!("expected ipns loop"),
+            }
+        });
+    }
+}
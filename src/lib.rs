@@ -6,6 +6,7 @@
 #![feature(try_trait)]
 
 #[macro_use] extern crate failure;
+#[macro_use] extern crate lazy_static;
 #[macro_use] extern crate log;
 use futures::prelude::*;
 pub use libp2p::PeerId;
@@ -20,7 +21,9 @@ pub mod bitswap;
 pub mod block;
 mod config;
 pub mod error;
+pub mod files;
 mod future;
+pub mod graphsync;
 pub mod ipld;
 pub mod ipns;
 pub mod p2p;
@@ -28,9 +31,11 @@ pub mod path;
 pub mod repo;
 pub mod unixfs;
 
+use self::bitswap::{BitswapStat, BitswapStats, LedgerStat};
 pub use self::block::{Block, Cid};
 use self::config::ConfigFile;
 pub use self::error::Error;
+use self::files::Files;
 use self::ipld::IpldDag;
 pub use self::ipld::Ipld;
 use self::ipns::Ipns;
@@ -60,6 +65,7 @@ pub struct Types;
 impl RepoTypes for Types {
     type TBlockStore = repo::fs::FsBlockStore;
     type TDataStore = repo::fs::RocksDataStore;
+    type TPinStore = repo::DataStorePinStore<repo::fs::RocksDataStore>;
 }
 
 /// Testing IPFS types
@@ -68,6 +74,7 @@ pub struct TestTypes;
 impl RepoTypes for TestTypes {
     type TBlockStore = repo::mem::MemBlockStore;
     type TDataStore = repo::mem::MemDataStore;
+    type TPinStore = repo::mem::MemPinStore;
 }
 
 /// Ipfs options
@@ -128,6 +135,8 @@ pub struct Ipfs<Types: IpfsTypes> {
     repo_events: Option<Receiver<RepoEvent>>,
     dag: IpldDag<Types>,
     ipns: Ipns<Types>,
+    files: Files<Types>,
+    bitswap_stats: BitswapStats,
     swarm: Option<TSwarm<Types>>,
     exit_events: Vec<Sender<IpfsEvent>>,
 }
@@ -143,13 +152,17 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         let (repo, repo_events) = create_repo(repo_options);
         let swarm_options = SwarmOptions::<Types>::from(&options);
         let swarm = create_swarm(swarm_options, repo.clone());
+        let bitswap_stats = swarm.bitswap_stats();
         let dag = IpldDag::new(repo.clone());
         let ipns = Ipns::new(repo.clone());
+        let files = Files::new(repo.clone());
 
         Ipfs {
             repo,
             dag,
             ipns,
+            files,
+            bitswap_stats,
             repo_events: Some(repo_events),
             swarm: Some(swarm),
             exit_events: Vec::default(),
@@ -191,6 +204,20 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         self.dag.get(path)
     }
 
+    /// Stores `ipld` encoded with `codec` in a block hashed with `hash`,
+    /// like `ipfs dag put`.
+    pub fn dag_put(&self, ipld: Ipld, codec: cid::Codec, hash: multihash::Hash) ->
+    impl Future<Output=Result<IpfsPath, Error>>
+    {
+        self.dag.put_with(ipld, codec, hash)
+    }
+
+    /// Returns the value at `path`, loading the blocks of all links on the
+    /// way, like `ipfs dag get`.
+    pub fn dag_get(&self, path: IpfsPath) -> impl Future<Output=Result<Ipld, Error>> {
+        self.dag.get(path)
+    }
+
     /// Adds a file into the ipfs repo.
     pub fn add(&self, path: PathBuf) -> impl Future<Output=Result<IpfsPath, Error>> {
         let dag = self.dag.clone();
@@ -206,6 +233,41 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         File::get_unixfs_v1(&self.dag, path)
     }
 
+    /// Streams the data of the unixfs file at `path`.
+    ///
+    /// See `unixfs::cat` for reading parts of a file.
+    pub fn cat(&self, path: IpfsPath) -> impl Stream<Item=Result<block::Bytes, Error>> {
+        unixfs::cat(&self.repo, path, 0, None)
+    }
+
+    /// Fetches the blocks of the dag below `root` selected by `selector`
+    /// from a peer with a single graphsync request.
+    pub fn want_dag(&self, root: &Cid, selector: &ipld::Selector) {
+        self.repo.want_dag(root, selector)
+    }
+
+    /// Lists the entries of the unixfs directory at `path`.
+    pub fn ls(&self, path: IpfsPath) -> impl Future<Output=Result<Vec<unixfs::Entry>, Error>> {
+        unixfs::ls(&self.repo, path)
+    }
+
+    /// Returns the mutable file system of the node.
+    pub fn files(&self) -> &Files<Types> {
+        &self.files
+    }
+
+    /// Returns the wanted blocks and the blocks and bytes exchanged with
+    /// all peers, like `ipfs bitswap stat`.
+    pub fn bitswap_stat(&self) -> BitswapStat {
+        self.bitswap_stats.stat()
+    }
+
+    /// Returns the blocks and bytes exchanged with `peer` and the debt
+    /// ratio, like `ipfs bitswap ledger`.
+    pub fn bitswap_ledger(&self, peer: &PeerId) -> Option<LedgerStat> {
+        self.bitswap_stats.ledger(peer)
+    }
+
     /// Resolves a ipns path to an ipld path.
     pub fn resolve_ipns(&self, path: &IpfsPath) ->
     impl Future<Output=Result<IpfsPath, Error>>
@@ -234,6 +296,7 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             self.exit_events.push(sender);
 
             IpfsFuture {
+                repo: self.repo.clone(),
                 repo_events,
                 exit_events: receiver,
                 swarm: Box::new(self.swarm.take().unwrap()),
@@ -250,11 +313,24 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 }
 
 pub struct IpfsFuture<Types: SwarmTypes> {
+    repo: Repo<Types>,
     swarm: Box<TSwarm<Types>>,
     repo_events: Receiver<RepoEvent>,
     exit_events: Receiver<IpfsEvent>,
 }
 
+impl<Types: SwarmTypes> IpfsFuture<Types> {
+    /// Removes a provided block from the provide queue.
+    fn provided(&self, cid: Cid) {
+        let dequeue = self.repo.provided(&cid);
+        tokio::spawn_async(async move {
+            if let Err(err) = await!(dequeue) {
+                warn!("failed to dequeue provided block {}: {}", cid.to_string(), err);
+            }
+        });
+    }
+}
+
 impl<Types: SwarmTypes> Future for IpfsFuture<Types> {
     type Output = ();
 
@@ -271,12 +347,29 @@ impl<Types: SwarmTypes> Future for IpfsFuture<Types> {
                         RepoEvent::WantBlock(cid) => {
                             _self.swarm.want_block(cid);
                         }
+                        RepoEvent::WantBlockInSession(cid, session) => {
+                            _self.swarm.want_block_in_session(cid, session);
+                        }
+                        RepoEvent::EndSession(session) => {
+                            _self.swarm.end_session(session);
+                        }
+                        RepoEvent::WantDag(root, selector) => {
+                            _self.swarm.want_dag(root, selector);
+                        }
                         RepoEvent::ProvideBlock(cid) => {
-                            _self.swarm.provide_block(cid);
+                            _self.swarm.provide_block(cid.clone());
+                            _self.provided(cid);
+                        }
+                        RepoEvent::ProvideBlocks(cids) => {
+                            for cid in cids {
+                                _self.swarm.provide_block(cid.clone());
+                                _self.provided(cid);
+                            }
                         }
                         RepoEvent::UnprovideBlock(cid) => {
                             _self.swarm.stop_providing_block(&cid);
                         }
+                        RepoEvent::GarbageCollected(_) => {}
                     }
                 } else {
                     break
@@ -336,4 +429,34 @@ mod tests {
             ipfs.exit_daemon();
         });
     }
+
+    #[test]
+    fn test_dag_put_and_get() {
+        let options = IpfsOptions::<TestTypes>::default();
+        let mut ipfs = Ipfs::new(options);
+
+        tokio::run_async(async move {
+            let fut = ipfs.start_daemon().unwrap();
+            tokio::spawn_async(fut);
+
+            let data: Ipld = vec![1, 2, 3].into();
+            let codec = cid::Codec::DagCBOR;
+            let path = await!(ipfs.dag_put(data, codec, multihash::Hash::SHA2512)).unwrap();
+            let value = await!(ipfs.dag_get(path.sub_path("2").unwrap())).unwrap();
+            assert_eq!(value, Ipld::U64(3));
+
+            ipfs.exit_daemon();
+        });
+    }
+
+    #[test]
+    fn test_bitswap_stat() {
+        let options = IpfsOptions::<TestTypes>::default();
+        let ipfs = Ipfs::new(options);
+        let stat = ipfs.bitswap_stat();
+        assert!(stat.wantlist.is_empty());
+        assert!(stat.peers.is_empty());
+        assert_eq!(stat.blocks_received, 0);
+        assert_eq!(ipfs.bitswap_ledger(&PeerId::random()), None);
+    }
 }
@@ -1,7 +1,9 @@
-use crate::bitswap::{Bitswap, Strategy};
+use crate::bitswap::{Bitswap, BitswapStats, Strategy};
 use crate::block::Cid;
+use crate::graphsync::{Graphsync, GraphsyncEvent};
+use crate::ipld::Selector;
 use crate::p2p::{SwarmOptions, SwarmTypes};
-use crate::repo::Repo;
+use crate::repo::{Repo, SessionId};
 use libp2p::{NetworkBehaviour, PeerId};
 use libp2p::core::swarm::NetworkBehaviourEventProcess;
 use libp2p::core::muxing::{StreamMuxerBox, SubstreamRef};
@@ -20,6 +22,7 @@ pub struct Behaviour<TSubstream: AsyncRead + AsyncWrite, TSwarmTypes: SwarmTypes
     mdns: Mdns<TSubstream>,
     kademlia: Kademlia<TSubstream>,
     bitswap: Bitswap<TSubstream, TSwarmTypes>,
+    graphsync: Graphsync<TSubstream, TSwarmTypes>,
     ping: Ping<TSubstream>,
     identify: Identify<TSubstream>,
     floodsub: Floodsub<TSubstream>,
@@ -93,6 +96,20 @@ impl<TSubstream: AsyncRead + AsyncWrite, TSwarmTypes: SwarmTypes>
     fn inject_event(&mut self, _event: ()) {}
 }
 
+impl<TSubstream: AsyncRead + AsyncWrite, TSwarmTypes: SwarmTypes>
+    NetworkBehaviourEventProcess<GraphsyncEvent> for
+    Behaviour<TSubstream, TSwarmTypes>
+{
+    fn inject_event(&mut self, event: GraphsyncEvent) {
+        match event {
+            GraphsyncEvent::Completed { peer_id, root, status, .. } => {
+                info!("graphsync: {} from {} completed with {:?}",
+                      root.to_string(), peer_id.to_base58(), status);
+            }
+        }
+    }
+}
+
 impl<TSubstream: AsyncRead + AsyncWrite, TSwarmTypes: SwarmTypes>
     NetworkBehaviourEventProcess<PingEvent> for
     Behaviour<TSubstream, TSwarmTypes>
@@ -137,6 +154,7 @@ impl<TSubstream: AsyncRead + AsyncWrite, TSwarmTypes: SwarmTypes> Behaviour<TSub
             kademlia.add_not_connected_address(peer_id, addr.to_owned());
         }
 
+        let graphsync = Graphsync::new(repo.clone());
         let strategy = TSwarmTypes::TStrategy::new(repo);
         let bitswap = Bitswap::new(strategy);
         let ping = Ping::new();
@@ -151,6 +169,7 @@ impl<TSubstream: AsyncRead + AsyncWrite, TSwarmTypes: SwarmTypes> Behaviour<TSub
             mdns,
             kademlia,
             bitswap,
+            graphsync,
             ping,
             identify,
             floodsub,
@@ -164,6 +183,24 @@ impl<TSubstream: AsyncRead + AsyncWrite, TSwarmTypes: SwarmTypes> Behaviour<TSub
         self.bitswap.want_block(cid, 1);
     }
 
+    pub fn bitswap_stats(&self) -> BitswapStats {
+        self.bitswap.stats()
+    }
+
+    pub fn want_block_in_session(&mut self, cid: Cid, session: SessionId) {
+        info!("Want block {} in session {}", cid.to_string(), session);
+        self.bitswap.want_block_in_session(cid, 1, session);
+    }
+
+    pub fn end_session(&mut self, session: SessionId) {
+        self.bitswap.end_session(session);
+    }
+
+    pub fn want_dag(&mut self, root: Cid, selector: Selector) {
+        info!("Want dag {}", root.to_string());
+        self.graphsync.want_dag(root, selector);
+    }
+
     pub fn provide_block(&mut self, cid: Cid) {
         info!("Providing block {}", cid.to_string());
         //let hash = Multihash::from_bytes(cid.to_bytes()).unwrap();
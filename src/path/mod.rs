@@ -1,4 +1,4 @@
-use crate::block::Cid;
+use crate::block::{cid_to_string, Base, Cid};
 use crate::error::Error;
 use libp2p::PeerId;
 use std::convert::{TryFrom, TryInto};
@@ -22,7 +22,12 @@ impl IpfsPath {
     }
 
     pub fn from_str(string: &str) -> Result<Self, Error> {
-        let mut subpath = string.split("/");
+        // allow a trailing slash like `/ipns/example.com/`
+        let trimmed = match string.len() {
+            0 | 1 => string,
+            _ => string.trim_end_matches('/'),
+        };
+        let mut subpath = trimmed.split("/");
         let empty = subpath.next();
         let root_type = subpath.next();
         let key = subpath.next();
@@ -89,7 +94,12 @@ impl IpfsPath {
     }
 
     pub fn to_string(&self) -> String {
-        let mut path = self.root.to_string();
+        self.to_string_base(Base::Base58btc)
+    }
+
+    /// Formats the path, encoding the cid in the root using `base`.
+    pub fn to_string_base(&self, base: Base) -> String {
+        let mut path = self.root.to_string_base(base);
         for sub_path in &self.path {
             path.push_str("/");
             path.push_str(&sub_path.to_string());
@@ -171,8 +181,13 @@ impl PathRoot {
     }
 
     pub fn to_string(&self) -> String {
+        self.to_string_base(Base::Base58btc)
+    }
+
+    /// Formats the root, encoding cids using `base`.
+    pub fn to_string_base(&self, base: Base) -> String {
         let (prefix, key) = match self {
-            PathRoot::Ipld(cid) => ("/ipfs/", cid.to_string()),
+            PathRoot::Ipld(cid) => ("/ipfs/", cid_to_string(cid, base)),
             PathRoot::Ipns(peer_id) => ("/ipns/", peer_id.to_base58()),
             PathRoot::Dns(domain) => ("/ipns/", domain.to_owned()),
         };
@@ -330,6 +345,37 @@ mod tests {
         assert!(IpfsPath::from_str("").is_err());
         assert!(IpfsPath::from_str("/").is_err());
         assert!(IpfsPath::from_str("/QmRN").is_err());
+        assert!(IpfsPath::from_str("/ipfs/QmRN6wdp1S2A5EtjW9A3M1vKSBuQQGcgvuhoMUoEz4iiT5//key")
+            .is_err());
+    }
+
+    #[test]
+    fn test_from_str_roots() {
+        let peer_id = PeerId::random();
+        let ipns = IpfsPath::from(peer_id.clone()).sub_path("key").unwrap();
+        assert_eq!(IpfsPath::from_str(&ipns.to_string()).unwrap(), ipns);
+        let dns = IpfsPath::from_str("/ipns/example.com/").unwrap();
+        assert_eq!(dns, IpfsPath::new(PathRoot::Dns("example.com".into())));
+        let ipfs = "/ipfs/QmRN6wdp1S2A5EtjW9A3M1vKSBuQQGcgvuhoMUoEz4iiT5/";
+        let ipld = "/ipld/QmRN6wdp1S2A5EtjW9A3M1vKSBuQQGcgvuhoMUoEz4iiT5";
+        assert_eq!(IpfsPath::from_str(ipfs).unwrap(), IpfsPath::from_str(ipld).unwrap());
+    }
+
+    #[test]
+    fn test_from_str_any_base() {
+        let prefix = cid::Prefix {
+            version: cid::Version::V1,
+            codec: cid::Codec::Raw,
+            mh_type: multihash::Hash::SHA2256,
+            mh_len: 32,
+        };
+        let cid = Cid::new_from_prefix(&prefix, b"hello");
+        let path = IpfsPath::new(PathRoot::Ipld(cid)).sub_path("key").unwrap();
+        let base32 = path.to_string_base(Base::Base32);
+        let base58 = path.to_string_base(Base::Base58btc);
+        assert_ne!(base32, base58);
+        assert_eq!(IpfsPath::from_str(&base32).unwrap(), path);
+        assert_eq!(IpfsPath::from_str(&base58).unwrap(), path);
     }
 
     #[test]
@@ -0,0 +1,655 @@
+//! Adding files from readers
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::ipld::{Ipld, formats::pb::{PbLink, PbNode}};
+use crate::repo::{BlockStore, Chunker, FixedChunker, Repo, RepoTypes};
+use core::future::Future;
+use futures::compat::*;
+use std::convert::TryInto;
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+
+/// Default size of the leaves of added files in bytes.
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Default maximum number of links of the nodes of added files, the
+/// same as in go-ipfs.
+pub const DEFAULT_MAX_LINKS: usize = 174;
+
+/// Number of subtrees of every depth in a trickle dag, the same as in
+/// go-ipfs.
+const TRICKLE_LAYER_REPEAT: usize = 4;
+
+/// Shape of the dag an added file is arranged in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DagLayout {
+    /// All subtrees are full except for the last one, which keeps the
+    /// dag shallow for seeking.
+    Balanced,
+    /// The layout of go-ipfs `add --trickle`. Nodes link to leaves
+    /// first and to subtrees of growing depth after them, so the start
+    /// of a file can be read without descending deep into the dag and
+    /// appending only changes the nodes along the right edge.
+    Trickle,
+}
+
+impl Default for DagLayout {
+    fn default() -> Self {
+        DagLayout::Balanced
+    }
+}
+
+/// Modification time of a unixfs file or directory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mtime {
+    /// Seconds since the unix epoch.
+    pub seconds: i64,
+    /// Nanoseconds within the second.
+    pub nanos: u32,
+}
+
+/// Optional UnixFS 1.5 metadata of a file or directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Metadata {
+    /// POSIX permission bits, only the lower 12 bits are stored.
+    pub mode: Option<u32>,
+    pub mtime: Option<Mtime>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.mtime.is_none()
+    }
+
+    /// Appends the metadata to a unixfs message. The metadata fields
+    /// have the highest numbers, so they are encoded last.
+    pub(crate) fn encode(&self, data: &mut Vec<u8>) {
+        if let Some(mode) = self.mode {
+            data.push(0x38);
+            encode_varint(u64::from(mode & 0o7777), data);
+        }
+        if let Some(mtime) = self.mtime {
+            let mut time = vec![0x08];
+            encode_varint(mtime.seconds as u64, &mut time);
+            // go-ipfs leaves out zero nanoseconds
+            if mtime.nanos != 0 {
+                time.push(0x15);
+                time.extend_from_slice(&mtime.nanos.to_le_bytes());
+            }
+            data.push(0x42);
+            encode_varint(time.len() as u64, data);
+            data.extend_from_slice(&time);
+        }
+    }
+
+    /// Encodes the dag-pb node `block` again with the metadata.
+    pub(crate) fn apply(&self, block: Block) -> Result<Block, Error> {
+        if self.is_empty() {
+            return Ok(block);
+        }
+        let mut pb_node: PbNode = match Ipld::from(&block)?.try_into() {
+            Ok(pb_node) => pb_node,
+            Err(_) => bail!("invalid dag_pb node"),
+        };
+        self.encode(&mut pb_node.data);
+        Ipld::from(pb_node).to_dag_pb()
+    }
+}
+
+/// How an added file is split and arranged.
+#[derive(Clone)]
+pub struct AddOptions {
+    chunker: Arc<dyn Chunker>,
+    max_links: usize,
+    layout: DagLayout,
+    metadata: Metadata,
+}
+
+impl Default for AddOptions {
+    fn default() -> Self {
+        AddOptions {
+            chunker: Arc::new(FixedChunker::new(DEFAULT_CHUNK_SIZE)),
+            max_links: DEFAULT_MAX_LINKS,
+            layout: DagLayout::default(),
+            metadata: Metadata::default(),
+        }
+    }
+}
+
+impl AddOptions {
+    /// Splits the file with `chunker`.
+    pub fn chunker<C: Chunker>(mut self, chunker: C) -> Self {
+        self.chunker = Arc::new(chunker);
+        self
+    }
+
+    /// Links at most `max_links` blocks from a node.
+    pub fn max_links(mut self, max_links: usize) -> Self {
+        self.max_links = max_links;
+        self
+    }
+
+    /// Arranges the file in the `layout` dag.
+    pub fn layout(mut self, layout: DagLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Records `metadata` in the root of the file.
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// Counts the leaves of an added file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AddStats {
+    /// Number of leaves the file was split into.
+    pub chunks: u64,
+    /// Number of leaves that were already stored.
+    pub reused: u64,
+}
+
+fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Encodes the unixfs message of a file with leaves of `sizes` bytes.
+fn unixfs_file(sizes: &[u64]) -> Vec<u8> {
+    // type file
+    let mut data = vec![0x08, 0x02];
+    data.push(0x18);
+    encode_varint(sizes.iter().sum(), &mut data);
+    for size in sizes {
+        data.push(0x20);
+        encode_varint(*size, &mut data);
+    }
+    data
+}
+
+/// Reads up to `len` bytes, returning less only at the end of the data.
+fn read_chunk<R: AsyncRead + Send + 'static>(reader: R, len: usize) ->
+impl Future<Output=Result<(R, Vec<u8>), Error>>
+{
+    async move {
+        let mut reader = reader;
+        let mut chunk = Vec::with_capacity(len);
+        while chunk.len() < len {
+            let buf = vec![0; len - chunk.len()];
+            let (next, buf, n) = await!(tokio::io::read(reader, buf).compat())?;
+            reader = next;
+            if n == 0 {
+                break;
+            }
+            chunk.extend_from_slice(&buf[..n]);
+        }
+        Ok((reader, chunk))
+    }
+}
+
+/// Link to a part of a file.
+#[derive(Clone, Debug)]
+struct FileLink {
+    cid: Cid,
+    /// Number of file bytes below the link.
+    file_size: u64,
+    /// Size of all blocks below the link, the dag-pb `Tsize`.
+    tree_size: u64,
+}
+
+/// Encodes a node of a file linking to `links` in order.
+fn file_node(links: Vec<FileLink>) -> Result<(Block, FileLink), Error> {
+    let sizes: Vec<u64> = links.iter().map(|link| link.file_size).collect();
+    let tree_size: u64 = links.iter().map(|link| link.tree_size).sum();
+    let node: Ipld = PbNode {
+        links: links.into_iter().map(|link| PbLink {
+            cid: link.cid.into(),
+            name: String::new(),
+            size: link.tree_size,
+        }).collect(),
+        data: unixfs_file(&sizes),
+    }.into();
+    let block = node.to_dag_pb()?;
+    let link = FileLink {
+        cid: block.cid().to_owned(),
+        file_size: sizes.iter().sum(),
+        tree_size: tree_size + block.size() as u64,
+    };
+    Ok((block, link))
+}
+
+/// Builds a balanced tree of file nodes from the leaves in order.
+///
+/// All subtrees of a node are full except for the last one. Nodes are
+/// returned as soon as they are complete, so only the links of one node
+/// per level are kept in memory.
+struct BalancedBuilder {
+    max_links: usize,
+    // links of the incomplete node of every level, starting at the leaves
+    levels: Vec<Vec<FileLink>>,
+}
+
+impl BalancedBuilder {
+    fn new(max_links: usize) -> Self {
+        BalancedBuilder {
+            max_links: max_links.max(2),
+            levels: vec![Vec::new()],
+        }
+    }
+
+    /// Adds a link at `level`, returning the nodes that were completed.
+    fn push_at(&mut self, level: usize, link: FileLink, nodes: &mut Vec<Block>) -> Result<(), Error> {
+        if self.levels.len() == level {
+            self.levels.push(Vec::new());
+        }
+        // only completed once the next link arrives, a full level without
+        // a next link may be the root
+        if self.levels[level].len() == self.max_links {
+            let links = std::mem::replace(&mut self.levels[level], Vec::new());
+            let (node, parent) = file_node(links)?;
+            nodes.push(node);
+            self.push_at(level + 1, parent, nodes)?;
+        }
+        self.levels[level].push(link);
+        Ok(())
+    }
+
+    fn push(&mut self, leaf: FileLink) -> Result<Vec<Block>, Error> {
+        let mut nodes = Vec::new();
+        self.push_at(0, leaf, &mut nodes)?;
+        Ok(nodes)
+    }
+
+    /// Completes the remaining nodes, the last one is the root.
+    fn finish(mut self) -> Result<Vec<Block>, Error> {
+        let mut nodes = Vec::new();
+        let mut level = 0;
+        loop {
+            let links = std::mem::replace(&mut self.levels[level], Vec::new());
+            let top = level + 1 == self.levels.len();
+            if top && level > 0 && links.len() == 1 {
+                // already stored as a node of the level below
+                return Ok(nodes);
+            }
+            let (node, parent) = file_node(links)?;
+            nodes.push(node);
+            if top {
+                return Ok(nodes);
+            }
+            self.push_at(level + 1, parent, &mut nodes)?;
+            level += 1;
+        }
+    }
+}
+
+/// Incomplete node of a trickle dag.
+struct TrickleNode {
+    links: Vec<FileLink>,
+    // depth of the subtrees below, `None` for the root
+    max_depth: Option<usize>,
+    // depth of the subtrees being added, zero while adding leaves
+    depth: usize,
+    // number of subtrees added at `depth`
+    layer: usize,
+}
+
+impl TrickleNode {
+    fn new(max_depth: Option<usize>) -> Self {
+        TrickleNode {
+            links: Vec::new(),
+            max_depth,
+            depth: 0,
+            layer: 0,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.max_depth {
+            Some(max_depth) => self.depth > 0 && self.depth >= max_depth,
+            None => false,
+        }
+    }
+
+    fn push_subtree(&mut self, link: FileLink) {
+        self.links.push(link);
+        self.layer += 1;
+        if self.layer == TRICKLE_LAYER_REPEAT {
+            self.depth += 1;
+            self.layer = 0;
+        }
+    }
+}
+
+/// Builds a trickle dag from the leaves of a file, like go-ipfs.
+///
+/// Every node links to `max_links` leaves followed by
+/// `TRICKLE_LAYER_REPEAT` subtrees of depth one, then of depth two and
+/// so on until the depth of the node is reached. Only the nodes along
+/// the right edge of the dag are kept in memory.
+struct TrickleBuilder {
+    max_links: usize,
+    // nodes along the right edge, starting at the root
+    stack: Vec<TrickleNode>,
+}
+
+impl TrickleBuilder {
+    fn new(max_links: usize) -> Self {
+        TrickleBuilder {
+            max_links: max_links.max(1),
+            stack: vec![TrickleNode::new(None)],
+        }
+    }
+
+    /// Encodes the last node on the stack and links it from its parent.
+    fn pop(&mut self, nodes: &mut Vec<Block>) -> Result<(), Error> {
+        let node = self.stack.pop().expect("the root is never popped");
+        let (block, link) = file_node(node.links)?;
+        nodes.push(block);
+        self.stack.last_mut().expect("the root is never popped").push_subtree(link);
+        Ok(())
+    }
+
+    /// Adds a leaf, returning the nodes that were completed.
+    fn push(&mut self, leaf: FileLink) -> Result<Vec<Block>, Error> {
+        let mut nodes = Vec::new();
+        loop {
+            let node = self.stack.last_mut().expect("the root is never popped");
+            if node.depth == 0 {
+                if node.links.len() < self.max_links {
+                    node.links.push(leaf);
+                    return Ok(nodes);
+                }
+                node.depth = 1;
+            }
+            if node.is_complete() {
+                self.pop(&mut nodes)?;
+            } else {
+                let depth = node.depth;
+                self.stack.push(TrickleNode::new(Some(depth)));
+            }
+        }
+    }
+
+    /// Completes the remaining nodes, the last one is the root.
+    fn finish(mut self) -> Result<Vec<Block>, Error> {
+        let mut nodes = Vec::new();
+        while self.stack.len() > 1 {
+            self.pop(&mut nodes)?;
+        }
+        let root = self.stack.pop().expect("the root is still there");
+        let (block, _) = file_node(root.links)?;
+        nodes.push(block);
+        Ok(nodes)
+    }
+}
+
+/// Builder of the selected `DagLayout`.
+enum DagBuilder {
+    Balanced(BalancedBuilder),
+    Trickle(TrickleBuilder),
+}
+
+impl DagBuilder {
+    fn new(layout: DagLayout, max_links: usize) -> Self {
+        match layout {
+            DagLayout::Balanced => DagBuilder::Balanced(BalancedBuilder::new(max_links)),
+            DagLayout::Trickle => DagBuilder::Trickle(TrickleBuilder::new(max_links)),
+        }
+    }
+
+    fn push(&mut self, leaf: FileLink) -> Result<Vec<Block>, Error> {
+        match self {
+            DagBuilder::Balanced(builder) => builder.push(leaf),
+            DagBuilder::Trickle(builder) => builder.push(leaf),
+        }
+    }
+
+    fn finish(self) -> Result<Vec<Block>, Error> {
+        match self {
+            DagBuilder::Balanced(builder) => builder.finish(),
+            DagBuilder::Trickle(builder) => builder.finish(),
+        }
+    }
+}
+
+fn raw_leaf(data: Vec<u8>) -> Block {
+    let prefix = cid::Prefix {
+        version: cid::Version::V1,
+        codec: cid::Codec::Raw,
+        mh_type: multihash::Hash::SHA2256,
+        mh_len: 32,
+    };
+    let cid = Cid::new_from_prefix(&prefix, &data);
+    Block::new(data, cid)
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Adds the data of `reader` as a unixfs file split into raw leaves
+    /// of `chunk_size` bytes, returning the cid of the file.
+    ///
+    /// The leaves are arranged in a balanced tree of nodes with at most
+    /// `DEFAULT_MAX_LINKS` links. Leaves and nodes are stored as soon as
+    /// they are complete. Leaves that are already stored aren't written
+    /// again, so adding the same data after an interrupted add only
+    /// stores the missing leaves.
+    pub fn add_reader<R: AsyncRead + Send + 'static>(&self, reader: R, chunk_size: usize) ->
+    impl Future<Output=Result<(Cid, AddStats), Error>>
+    {
+        self.add_with(reader, AddOptions::default().chunker(FixedChunker::new(chunk_size)))
+    }
+
+    /// Adds the data of `reader` like `add_reader`, splitting and
+    /// arranging it according to `options`.
+    ///
+    /// Use a content defined chunker like `BuzhashChunker` so that adding
+    /// a slightly modified file reuses most leaves of the original.
+    pub fn add_with<R: AsyncRead + Send + 'static>(&self, reader: R, options: AddOptions) ->
+    impl Future<Output=Result<(Cid, AddStats), Error>>
+    {
+        let repo = self.clone();
+        async move {
+            let chunker = options.chunker;
+            let max_size = chunker.max_size().max(1);
+            let mut reader = reader;
+            let mut stats = AddStats::default();
+            let mut builder = DagBuilder::new(options.layout, options.max_links);
+            let mut buf = Vec::new();
+            let mut eof = false;
+            loop {
+                // the chunker sees a full chunk unless the data ends
+                if !eof && buf.len() < max_size {
+                    let missing = max_size - buf.len();
+                    let (next, data) = await!(read_chunk(reader, missing))?;
+                    reader = next;
+                    eof = data.len() < missing;
+                    buf.extend(data);
+                }
+                if buf.is_empty() {
+                    break;
+                }
+                let len = chunker.cut(&buf).max(1).min(buf.len());
+                let rest = buf.split_off(len);
+                let chunk = std::mem::replace(&mut buf, rest);
+                let size = chunk.len() as u64;
+                let leaf = raw_leaf(chunk);
+                let cid = leaf.cid().to_owned();
+                if await!(repo.block_store.contains(&cid))? {
+                    stats.reused += 1;
+                } else {
+                    await!(repo.put_block(leaf))?;
+                }
+                stats.chunks += 1;
+                let leaf = FileLink {
+                    cid,
+                    file_size: size,
+                    tree_size: size,
+                };
+                for node in builder.push(leaf)? {
+                    await!(repo.put_block(node))?;
+                }
+            }
+            let mut nodes = builder.finish()?;
+            let root = nodes.pop().expect("the root is always built");
+            for node in nodes {
+                await!(repo.put_block(node))?;
+            }
+            let root = await!(repo.put_block(options.metadata.apply(root)?))?;
+            Ok((root, stats))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{block_links, BuzhashChunker};
+    use crate::repo::content::unixfs_metadata;
+    use crate::repo::tests::create_mock_repo;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use std::io::{Cursor, Read};
+
+    /// Fails after reading `limit` bytes.
+    struct InterruptedReader {
+        data: Cursor<Vec<u8>>,
+        limit: u64,
+    }
+
+    impl Read for InterruptedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.data.position() >= self.limit {
+                return Err(std::io::Error::new(std::io::ErrorKind::Other, "interrupted"));
+            }
+            let len = buf.len().min((self.limit - self.data.position()) as usize);
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    impl AsyncRead for InterruptedReader {}
+
+    #[test]
+    fn test_add_balanced() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let options = AddOptions::default().chunker(FixedChunker::new(1)).max_links(2);
+            let (root, stats) = await!(repo.add_with(Cursor::new(b"abcde".to_vec()), options.clone())).unwrap();
+            assert_eq!(stats.chunks, 5);
+            // a full subtree of four leaves and one of the last leaf
+            let root_block = await!(repo.block_store.get(&root)).unwrap().unwrap();
+            let children = block_links(&root_block).unwrap();
+            assert_eq!(children.len(), 2);
+            let last = await!(repo.block_store.get(&children[1])).unwrap().unwrap();
+            assert_eq!(block_links(&last).unwrap().len(), 1);
+            for (offset, data) in b"abcde".iter().enumerate() {
+                let (leaf, within) = await!(repo.seek(&root, offset as u64)).unwrap();
+                assert_eq!(leaf, raw_leaf(vec![*data]).cid().to_owned());
+                assert_eq!(within, 0);
+            }
+
+            // every node of a single level file links to the leaves
+            let (root, _) = await!(repo.add_with(Cursor::new(b"ab".to_vec()), options)).unwrap();
+            let root_block = await!(repo.block_store.get(&root)).unwrap().unwrap();
+            assert_eq!(block_links(&root_block).unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_add_trickle() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let options = AddOptions::default()
+                .chunker(FixedChunker::new(1))
+                .max_links(2)
+                .layout(DagLayout::Trickle);
+            let data = b"abcdefghijk".to_vec();
+            let (root, stats) = await!(repo.add_with(Cursor::new(data.clone()), options)).unwrap();
+            assert_eq!(stats.chunks, 11);
+            // two leaves followed by four subtrees of depth one, the
+            // last leaf starts a subtree of depth two
+            let root_block = await!(repo.block_store.get(&root)).unwrap().unwrap();
+            let children = block_links(&root_block).unwrap();
+            assert_eq!(children.len(), 7);
+            for child in &children[2..6] {
+                let block = await!(repo.block_store.get(child)).unwrap().unwrap();
+                assert_eq!(block_links(&block).unwrap().len(), 2);
+            }
+            let last = await!(repo.block_store.get(&children[6])).unwrap().unwrap();
+            assert_eq!(block_links(&last).unwrap().len(), 1);
+            for (offset, data) in data.iter().enumerate() {
+                let (leaf, within) = await!(repo.seek(&root, offset as u64)).unwrap();
+                assert_eq!(leaf, raw_leaf(vec![*data]).cid().to_owned());
+                assert_eq!(within, 0);
+            }
+        });
+    }
+
+    #[test]
+    fn test_add_metadata() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let metadata = Metadata {
+                mode: Some(0o100644),
+                mtime: Some(Mtime {
+                    seconds: 1_500_000_000,
+                    nanos: 42,
+                }),
+            };
+            let options = AddOptions::default().metadata(metadata);
+            let (root, _) = await!(repo.add_with(Cursor::new(b"data".to_vec()), options)).unwrap();
+            let block = await!(repo.block_store.get(&root)).unwrap().unwrap();
+            let pb_node: PbNode = Ipld::from(&block).unwrap().try_into().ok().unwrap();
+            let decoded = unixfs_metadata(&pb_node.data).unwrap();
+            assert_eq!(decoded.mode, Some(0o644));
+            assert_eq!(decoded.mtime, metadata.mtime);
+
+            // the metadata is part of the root, so the plain file has
+            // another cid and no metadata
+            let (plain, _) = await!(repo.add_reader(Cursor::new(b"data".to_vec()), DEFAULT_CHUNK_SIZE)).unwrap();
+            assert_ne!(plain, root);
+            let block = await!(repo.block_store.get(&plain)).unwrap().unwrap();
+            let pb_node: PbNode = Ipld::from(&block).unwrap().try_into().ok().unwrap();
+            assert!(unixfs_metadata(&pb_node.data).unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_add_buzhash_dedup() {
+        let repo = create_mock_repo();
+        let mut data = vec![0; 64 * 1024];
+        StdRng::seed_from_u64(2).fill(&mut data[..]);
+        let mut modified = b"prefix".to_vec();
+        modified.extend_from_slice(&data);
+        tokio::run_async(async move {
+            let options = AddOptions::default().chunker(BuzhashChunker::new(256, 4096, 10));
+            let (_, first) = await!(repo.add_with(Cursor::new(data), options.clone())).unwrap();
+            let (_, second) = await!(repo.add_with(Cursor::new(modified), options)).unwrap();
+            // only the chunks around the prefix change
+            assert!(second.reused * 2 > first.chunks);
+        });
+    }
+
+    #[test]
+    fn test_add_reader_resume() {
+        let repo = create_mock_repo();
+        let data = b"aaaabbbbcc".to_vec();
+        tokio::run_async(async move {
+            let interrupted = InterruptedReader {
+                data: Cursor::new(data.clone()),
+                limit: 8,
+            };
+            assert!(await!(repo.add_reader(interrupted, 4)).is_err());
+            assert_eq!(await!(repo.block_store.list()).unwrap().len(), 2);
+
+            let (root, stats) = await!(repo.add_reader(Cursor::new(data.clone()), 4)).unwrap();
+            assert_eq!(stats, AddStats { chunks: 3, reused: 2 });
+            let (again, stats) = await!(repo.add_reader(Cursor::new(data), 4)).unwrap();
+            assert_eq!(again, root);
+            assert_eq!(stats, AddStats { chunks: 3, reused: 3 });
+        });
+    }
+}
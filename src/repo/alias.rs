@@ -0,0 +1,164 @@
+//! Index of blocks storing identical data under different cids
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::repo::{BlockStore, Column, DataStore, Repo, RepoTypes};
+use core::future::Future;
+use multihash::Hash;
+
+const DIGEST_PREFIX: &[u8] = b"digest/";
+const ALIAS_PREFIX: &[u8] = b"alias/";
+
+fn prefixed_key(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut prefixed = prefix.to_vec();
+    prefixed.extend_from_slice(key);
+    prefixed
+}
+
+/// Returns the key of the canonical hash of the block data.
+fn digest_key(block: &Block) -> Result<Vec<u8>, Error> {
+    let digest = multihash::encode(Hash::SHA2256, block.data())?;
+    Ok(prefixed_key(DIGEST_PREFIX, &digest))
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Returns the cid the data of `block` is already stored under.
+    pub(crate) fn find_stored_copy(&self, block: &Block) ->
+    impl Future<Output=Result<Option<Cid>, Error>>
+    {
+        let repo = self.clone();
+        let key = digest_key(block);
+        async move {
+            let stored = match await!(repo.data_store.get(Column::Alias, &key?))? {
+                Some(stored) => Cid::from(stored)?,
+                None => return Ok(None),
+            };
+            // the copy may have been removed meanwhile
+            if !await!(repo.block_store.contains(&stored))? {
+                return Ok(None);
+            }
+            Ok(Some(stored))
+        }
+    }
+
+    /// Records that the data of `block` is stored under its cid.
+    pub(crate) fn index_content(&self, block: &Block) -> impl Future<Output=Result<(), Error>> {
+        let data_store = self.data_store.clone();
+        let key = digest_key(block);
+        let cid = block.cid().to_bytes();
+        async move {
+            await!(data_store.put(Column::Alias, &key?, &cid))
+        }
+    }
+
+    /// Records that the data of `alias` is stored under `stored`.
+    pub(crate) fn put_alias(&self, alias: &Cid, stored: &Cid) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let key = prefixed_key(ALIAS_PREFIX, &alias.to_bytes());
+        self.data_store.put(Column::Alias, &key, &stored.to_bytes())
+    }
+
+    /// Removes the alias `alias`, e.g. when its block is removed.
+    pub(crate) fn remove_alias(&self, alias: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let key = prefixed_key(ALIAS_PREFIX, &alias.to_bytes());
+        self.data_store.remove(Column::Alias, &key)
+    }
+
+    /// Looks up the block whose data is shared with `cid`.
+    ///
+    /// Aliases of blocks that were removed meanwhile are removed too.
+    pub(crate) fn get_aliased_block(&self, cid: &Cid) ->
+    impl Future<Output=Result<Option<Block>, Error>>
+    {
+        let repo = self.clone();
+        let cid = cid.to_owned();
+        let key = prefixed_key(ALIAS_PREFIX, &cid.to_bytes());
+        async move {
+            let stored = match await!(repo.data_store.get(Column::Alias, &key))? {
+                Some(stored) => Cid::from(stored)?,
+                None => return Ok(None),
+            };
+            match await!(repo.block_store.get(&stored))? {
+                Some(block) => Ok(Some(Block::new(block.data().clone(), cid))),
+                None => {
+                    await!(repo.data_store.remove(Column::Alias, &key))?;
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{BlockSource, RepoOptions};
+    use crate::repo::tests::Types;
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_content_index() {
+        let options = RepoOptions::<Types>::new(temp_dir()).content_index(true);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            let data = b"shared".to_vec();
+            let mut blocks = Vec::new();
+            for &mh_type in &[Hash::SHA2256, Hash::SHA3256, Hash::SHA2512] {
+                let prefix = cid::Prefix {
+                    version: cid::Version::V1,
+                    codec: cid::Codec::Raw,
+                    mh_type,
+                    mh_len: mh_type.size() as usize,
+                };
+                let cid = Cid::new_from_prefix(&prefix, &data);
+                blocks.push(Block::new(data.clone(), cid));
+            }
+            for block in &blocks {
+                await!(repo.put_block(block.clone())).unwrap();
+            }
+
+            let stored = await!(repo.block_store.list()).unwrap();
+            assert_eq!(stored, vec![blocks[0].cid().to_owned()]);
+            for block in &blocks {
+                assert_eq!(await!(repo.get_block(block.cid())).unwrap(), *block);
+            }
+
+            // removing an alias keeps the stored block
+            await!(repo.remove_block(blocks[1].cid())).unwrap();
+            let key = prefixed_key(ALIAS_PREFIX, &blocks[1].cid().to_bytes());
+            assert_eq!(await!(repo.data_store.get(Column::Alias, &key)).unwrap(), None);
+            assert_eq!(await!(repo.get_block(blocks[0].cid())).unwrap(), blocks[0]);
+
+            // removing the stored block removes the aliases of its data
+            await!(repo.remove_block(blocks[0].cid())).unwrap();
+            assert_eq!(await!(repo.get_aliased_block(blocks[2].cid())).unwrap(), None);
+            let key = prefixed_key(ALIAS_PREFIX, &blocks[2].cid().to_bytes());
+            assert_eq!(await!(repo.data_store.get(Column::Alias, &key)).unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn test_get_block_traced_alias() {
+        let options = RepoOptions::<Types>::new(temp_dir()).content_index(true);
+        let (repo, events) = Repo::new(options);
+        tokio::run_async(async move {
+            let stored = Block::from("shared");
+            let prefix = cid::Prefix {
+                version: cid::Version::V1,
+                codec: cid::Codec::Raw,
+                mh_type: Hash::SHA3256,
+                mh_len: Hash::SHA3256.size() as usize,
+            };
+            let cid = Cid::new_from_prefix(&prefix, stored.data());
+            let alias = Block::new(stored.data().to_vec(), cid);
+            await!(repo.put_block(stored.clone())).unwrap();
+            await!(repo.put_block(alias.clone())).unwrap();
+            while events.try_recv().is_ok() {}
+
+            // served from the stored block without asking the network
+            let traced = await!(repo.get_block_traced(alias.cid())).unwrap();
+            assert_eq!(traced, (alias, BlockSource::Local));
+            assert!(events.try_recv().is_err());
+        });
+    }
+}
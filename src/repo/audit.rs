@@ -0,0 +1,228 @@
+//! Durable log of repo mutations
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{Column, DataStore, Repo, RepoTypes};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use core::future::Future;
+use std::time::{Duration, SystemTime};
+
+/// Kind of a logged mutation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditOp {
+    Put,
+    Remove,
+    Pin,
+    Unpin,
+}
+
+impl AuditOp {
+    fn to_byte(self) -> u8 {
+        match self {
+            AuditOp::Put => 0,
+            AuditOp::Remove => 1,
+            AuditOp::Pin => 2,
+            AuditOp::Unpin => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, Error> {
+        match byte {
+            0 => Ok(AuditOp::Put),
+            1 => Ok(AuditOp::Remove),
+            2 => Ok(AuditOp::Pin),
+            3 => Ok(AuditOp::Unpin),
+            _ => bail!("invalid audit op {}", byte),
+        }
+    }
+}
+
+/// Entry of the audit log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// Time of the mutation.
+    pub time: SystemTime,
+    /// Kind of the mutation.
+    pub op: AuditOp,
+    /// Mutated block.
+    pub cid: Cid,
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Appends an entry to the audit log if it is enabled.
+    pub(crate) fn audit(&self, op: AuditOp, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let repo = self.clone();
+        let cid = cid.to_owned();
+        async move {
+            if !repo.audit_log {
+                return Ok(());
+            }
+            let data_store = repo.available_data_store()?;
+            let time = repo.clock.now().duration_since(SystemTime::UNIX_EPOCH)?;
+            let nanos = time.as_secs() * 1_000_000_000 + u64::from(time.subsec_nanos());
+            // the entries are ordered by the sequence number, the clock
+            // may go backwards
+            let seq = await!(repo.next_audit_seq())?;
+            let mut key = Vec::with_capacity(16);
+            key.write_u64::<BigEndian>(seq)?;
+            key.write_u64::<BigEndian>(nanos)?;
+            let mut value = vec![op.to_byte()];
+            value.extend_from_slice(&cid.to_bytes());
+            await!(data_store.put(Column::Audit, &key, &value))
+        }
+    }
+
+    /// Returns the sequence number of the next audit log entry.
+    ///
+    /// The first call continues after the last logged entry, so that the
+    /// order survives restarts.
+    fn next_audit_seq(&self) -> impl Future<Output=Result<u64, Error>> {
+        let repo = self.clone();
+        async move {
+            if repo.audit_seq.lock().unwrap().is_none() {
+                let keys = await!(repo.available_data_store()?.list_keys(Column::Audit))?;
+                let last = keys.iter()
+                    .filter(|key| key.len() == 16)
+                    .map(|key| BigEndian::read_u64(&key[..8]))
+                    .max();
+                let mut seq = repo.audit_seq.lock().unwrap();
+                if seq.is_none() {
+                    *seq = Some(last.map_or(0, |last| last + 1));
+                }
+            }
+            let mut seq = repo.audit_seq.lock().unwrap();
+            let next = seq.unwrap();
+            *seq = Some(next + 1);
+            Ok(next)
+        }
+    }
+
+    /// Returns the audit log entries of mutations at or after `since` in
+    /// the order they were logged.
+    ///
+    /// The audit log is only written if it was enabled with
+    /// `RepoOptions::audit_log`.
+    pub fn audit_log_since(&self, since: SystemTime) ->
+    impl Future<Output=Result<Vec<AuditEntry>, Error>>
+    {
+        let data_store = self.available_data_store().map(Clone::clone);
+        async move {
+            let data_store = data_store?;
+            let mut keys = await!(data_store.list_keys(Column::Audit))?;
+            keys.sort();
+            let mut entries = Vec::new();
+            for key in keys {
+                if key.len() != 16 {
+                    bail!("invalid audit log key");
+                }
+                let nanos = BigEndian::read_u64(&key[8..]);
+                let time = SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos);
+                if time < since {
+                    continue;
+                }
+                let value = match await!(data_store.get(Column::Audit, &key))? {
+                    Some(value) => value,
+                    None => bail!("audit log entry vanished"),
+                };
+                if value.is_empty() {
+                    bail!("invalid audit log entry");
+                }
+                entries.push(AuditEntry {
+                    time,
+                    op: AuditOp::from_byte(value[0])?,
+                    cid: Cid::from(&value[1..])?,
+                });
+            }
+            Ok(entries)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::repo::{Clock, RepoOptions};
+    use crate::repo::clock::FakeClock;
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_audit_log() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_audit_log");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let clock = FakeClock::new();
+        let start = clock.now();
+        let block = Block::from("1");
+        let cid = block.cid().to_owned();
+
+        let options = RepoOptions::<crate::Types>::new(tmp.clone())
+            .audit_log(true)
+            .clock(clock.clone());
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            await!(repo.put_block(block.clone())).unwrap();
+            // already stored
+            await!(repo.put_block(block)).unwrap();
+            await!(repo.pin_block(&cid)).unwrap();
+            clock.advance(Duration::from_secs(1));
+            await!(repo.unpin_block(&cid)).unwrap();
+            await!(repo.remove_block(&cid)).unwrap();
+        });
+
+        let (repo, _) = Repo::<crate::Types>::new(RepoOptions::new(tmp.clone()));
+        let cid = Block::from("1").cid().to_owned();
+        tokio::run_async(async move {
+            await!(repo.open()).unwrap();
+            let log = await!(repo.audit_log_since(start)).unwrap();
+            let ops: Vec<AuditOp> = log.iter().map(|entry| entry.op).collect();
+            assert_eq!(ops, vec![AuditOp::Put, AuditOp::Pin, AuditOp::Unpin, AuditOp::Remove]);
+            assert!(log.iter().all(|entry| entry.cid == cid));
+
+            let later = start + Duration::from_secs(1);
+            let log = await!(repo.audit_log_since(later)).unwrap();
+            assert_eq!(log.len(), 2);
+            assert_eq!(log[0].time, later);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_audit_log_order_across_restarts() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_audit_log_order");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let first = Block::from("1");
+        let second = Block::from("2");
+        let cids = vec![first.cid().to_owned(), second.cid().to_owned()];
+
+        let clock = FakeClock::new();
+        clock.advance(Duration::from_secs(10));
+        let options = RepoOptions::<crate::Types>::new(tmp.clone())
+            .audit_log(true)
+            .clock(clock);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            await!(repo.put_block(first)).unwrap();
+        });
+
+        // the clock went backwards while the repo was closed
+        let options = RepoOptions::<crate::Types>::new(tmp.clone())
+            .audit_log(true)
+            .clock(FakeClock::new());
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            await!(repo.open()).unwrap();
+            await!(repo.put_block(second)).unwrap();
+            let log = await!(repo.audit_log_since(SystemTime::UNIX_EPOCH)).unwrap();
+            let logged: Vec<Cid> = log.into_iter().map(|entry| entry.cid).collect();
+            assert_eq!(logged, cids);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
@@ -0,0 +1,308 @@
+//! Bloom filter for block stores
+use crate::block::{Cid, Block};
+use crate::error::Error;
+use crate::repo::{BlockStore, Layout, RetryPolicy, RecoveryReport, RocksTuning, Spawner, StoreStream, StoreUsage};
+use fnv::FnvHasher;
+use futures::future::{self, FutureObj};
+use futures::stream::StreamExt;
+use std::hash::Hasher;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Default size of the bloom filter in bits.
+pub const DEFAULT_BLOOM_BITS: usize = 8 * 1024 * 1024;
+
+/// Default number of hash functions of the bloom filter.
+pub const DEFAULT_BLOOM_HASHES: usize = 7;
+
+#[derive(Debug)]
+struct Bloom {
+    bits: Vec<u64>,
+    hashes: usize,
+    // set once the filter contains all stored blocks
+    ready: bool,
+}
+
+impl Bloom {
+    fn new(bits: usize, hashes: usize) -> Self {
+        Bloom {
+            bits: vec![0; (bits.max(64) + 63) / 64],
+            hashes: hashes.max(1),
+            ready: false,
+        }
+    }
+
+    /// Returns the bit positions of `cid`, using double hashing to
+    /// derive all hash functions from two hashes.
+    fn positions(&self, cid: &Cid) -> Vec<usize> {
+        let len = self.bits.len() as u64 * 64;
+        let mut hasher = FnvHasher::default();
+        hasher.write(&cid.to_bytes());
+        let h1 = hasher.finish();
+        hasher.write_u8(0xff);
+        let h2 = hasher.finish() | 1;
+        (0..self.hashes as u64)
+            .map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+            .collect()
+    }
+
+    fn insert(&mut self, cid: &Cid) {
+        for pos in self.positions(cid) {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+    }
+
+    fn might_contain(&self, cid: &Cid) -> bool {
+        self.positions(cid).into_iter().all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+
+    /// Returns `false` only if the block is certainly not stored.
+    fn check(&self, cid: &Cid) -> bool {
+        !self.ready || self.might_contain(cid)
+    }
+}
+
+/// Block store that answers `contains` for missing blocks from a bloom
+/// filter instead of asking the inner store.
+///
+/// The filter is filled with the stored blocks when the store is opened
+/// and every put block is added. Removed blocks stay in the filter, so
+/// the filter becomes less effective over time until the store is opened
+/// again. Until the filter is filled all checks go to the inner store.
+#[derive(Clone, Debug)]
+pub struct BloomBlockStore<S: BlockStore> {
+    inner: S,
+    bloom: Arc<Mutex<Bloom>>,
+}
+
+impl<S: BlockStore> BloomBlockStore<S> {
+    /// Filters checks for missing blocks of `inner`.
+    pub fn wrap(inner: S) -> Self {
+        BloomBlockStore {
+            inner,
+            bloom: Arc::new(Mutex::new(Bloom::new(DEFAULT_BLOOM_BITS, DEFAULT_BLOOM_HASHES))),
+        }
+    }
+
+    /// Uses a filter of `bits` bits with `hashes` hash functions.
+    ///
+    /// The filter should be about ten times larger than the number of
+    /// stored blocks for few false positives.
+    pub fn with_bloom_size(mut self, bits: usize, hashes: usize) -> Self {
+        self.bloom = Arc::new(Mutex::new(Bloom::new(bits, hashes)));
+        self
+    }
+
+    fn fill(&self) -> FutureObj<'static, Result<(), Error>> {
+        let mut cids = self.inner.list_stream();
+        let bloom = self.bloom.clone();
+        FutureObj::new(Box::new(async move {
+            while let Some(cid) = await!(cids.next()) {
+                bloom.lock().unwrap().insert(&cid?);
+            }
+            bloom.lock().unwrap().ready = true;
+            Ok(())
+        }))
+    }
+}
+
+impl<S: BlockStore> BlockStore for BloomBlockStore<S> {
+    fn new(path: PathBuf) -> Self {
+        BloomBlockStore::wrap(S::new(path))
+    }
+
+    fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.inner = self.inner.with_spawner(spawner);
+        self
+    }
+
+    fn with_rocks_tuning(mut self, tuning: RocksTuning) -> Self {
+        self.inner = self.inner.with_rocks_tuning(tuning);
+        self
+    }
+
+    fn with_layout(mut self, layout: Layout) -> Self {
+        self.inner = self.inner.with_layout(layout);
+        self
+    }
+
+    fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.inner = self.inner.with_retry(retry);
+        self
+    }
+
+    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.init()
+    }
+
+    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+        let open = self.inner.open();
+        let store = self.clone();
+        FutureObj::new(Box::new(async move {
+            await!(open)?;
+            // the inner store can only be listed once it is open
+            await!(store.fill())
+        }))
+    }
+
+    fn open_with_recovery(&self) -> FutureObj<'static, Result<RecoveryReport, Error>> {
+        let open = self.inner.open_with_recovery();
+        let store = self.clone();
+        FutureObj::new(Box::new(async move {
+            let report = await!(open)?;
+            await!(store.fill())?;
+            Ok(report)
+        }))
+    }
+
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.flush()
+    }
+
+    fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+        if !self.bloom.lock().unwrap().check(cid) {
+            return FutureObj::new(Box::new(future::ok(false)));
+        }
+        self.inner.contains(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+        if !self.bloom.lock().unwrap().check(cid) {
+            return FutureObj::new(Box::new(future::ok(None)));
+        }
+        self.inner.get(cid)
+    }
+
+    fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+        // added before the write, so that a concurrent check never misses
+        // the block
+        self.bloom.lock().unwrap().insert(block.cid());
+        self.inner.put(block)
+    }
+
+    fn put_many(&self, blocks: Vec<Block>) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        {
+            let mut bloom = self.bloom.lock().unwrap();
+            for block in &blocks {
+                bloom.insert(block.cid());
+            }
+        }
+        self.inner.put_many(blocks)
+    }
+
+    fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.remove(cid)
+    }
+
+    fn block_size(&self, cid: &Cid) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        if !self.bloom.lock().unwrap().check(cid) {
+            return FutureObj::new(Box::new(future::ok(None)));
+        }
+        self.inner.block_size(cid)
+    }
+
+    fn available_space(&self) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        self.inner.available_space()
+    }
+
+    fn usage(&self) -> FutureObj<'static, Result<StoreUsage, Error>> {
+        self.inner.usage()
+    }
+
+    fn snapshot(&self, dest: PathBuf) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.snapshot(dest)
+    }
+
+    fn list_stream(&self) -> StoreStream<Cid> {
+        self.inner.list_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::mem::MemBlockStore;
+    use std::env::temp_dir;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[test]
+    fn test_bloom() {
+        let mut bloom = Bloom::new(1024, 3);
+        let cid = Block::from("1").cid().to_owned();
+        assert!(bloom.check(&cid));
+        bloom.ready = true;
+        assert!(!bloom.check(&cid));
+        bloom.insert(&cid);
+        assert!(bloom.check(&cid));
+    }
+
+    #[derive(Clone)]
+    struct CountingStore {
+        inner: MemBlockStore,
+        checks: Arc<AtomicUsize>,
+        opened: Arc<AtomicBool>,
+    }
+
+    impl BlockStore for CountingStore {
+        fn new(path: PathBuf) -> Self {
+            CountingStore {
+                inner: MemBlockStore::new(path),
+                checks: Arc::new(AtomicUsize::new(0)),
+                opened: Arc::new(AtomicBool::new(false)),
+            }
+        }
+        fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.init()
+        }
+        fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.opened.store(true, Ordering::SeqCst);
+            self.inner.open()
+        }
+        fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+            self.checks.fetch_add(1, Ordering::SeqCst);
+            self.inner.contains(cid)
+        }
+        fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+            self.checks.fetch_add(1, Ordering::SeqCst);
+            self.inner.get(cid)
+        }
+        fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+            self.inner.put(block)
+        }
+        fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.remove(cid)
+        }
+        fn list_stream(&self) -> StoreStream<Cid> {
+            // like the rocks block store, listing fails until it is open
+            if !self.opened.load(Ordering::SeqCst) {
+                return Box::pin(futures::stream::iter(vec![Err(format_err!("not open"))]));
+            }
+            self.inner.list_stream()
+        }
+    }
+
+    #[test]
+    fn test_bloom_blockstore() {
+        let stored = Block::from("1");
+        let missing = Block::from("2").cid().to_owned();
+        let store = BloomBlockStore::<CountingStore>::new(temp_dir());
+        let checks = store.inner.checks.clone();
+        tokio::run_async(async move {
+            await!(store.inner.put(stored.clone())).unwrap();
+            // not filled yet
+            assert!(!await!(store.contains(&missing)).unwrap());
+            assert_eq!(checks.load(Ordering::SeqCst), 1);
+
+            await!(store.open()).unwrap();
+            assert!(await!(store.contains(stored.cid())).unwrap());
+            assert_eq!(checks.load(Ordering::SeqCst), 2);
+            assert!(!await!(store.contains(&missing)).unwrap());
+            assert_eq!(await!(store.get(&missing)).unwrap(), None);
+            assert_eq!(checks.load(Ordering::SeqCst), 2);
+
+            let block = Block::from("3");
+            await!(store.put(block.clone())).unwrap();
+            assert!(await!(store.contains(block.cid())).unwrap());
+        });
+    }
+}
@@ -0,0 +1,580 @@
+//! Write buffering for block stores
+use crate::block::{Cid, Block};
+use crate::error::Error;
+use crate::repo::{BlockStore, Layout, RetryPolicy, RocksTuning, Spawner, StoreStream};
+use core::future::Future;
+use futures::compat::*;
+use futures::future::{self, FutureObj};
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+/// Default number of bytes that may be buffered before puts are written
+/// through to the inner store.
+pub const DEFAULT_BUFFER_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Default number of blocks written to the inner store at once.
+pub const DEFAULT_FLUSH_BATCH_SIZE: usize = 32;
+
+/// Default time a block waits for its batch to fill up before it is
+/// written anyway.
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct Buffer {
+    blocks: HashMap<Cid, Block>,
+    bytes: usize,
+    /// Buffered blocks that aren't part of a batch yet.
+    pending: Vec<Cid>,
+    /// Number of running writes of each block to the inner store.
+    flushing: HashMap<Cid, usize>,
+    /// Blocks removed while they were being written, they are removed
+    /// from the inner store again once the writes are done.
+    removed: HashSet<Cid>,
+}
+
+impl Buffer {
+    fn remove(&mut self, cid: &Cid) -> Option<Block> {
+        let block = self.blocks.remove(cid)?;
+        self.bytes -= block.size();
+        Some(block)
+    }
+
+    /// Removes a block that is no longer wanted.
+    fn discard(&mut self, cid: &Cid) {
+        self.remove(cid);
+        if self.flushing.contains_key(cid) {
+            self.removed.insert(cid.to_owned());
+        }
+    }
+
+    /// Returns the buffered blocks of `cids` and records that they are
+    /// being written.
+    fn start_flush<'a, I: Iterator<Item=&'a Cid>>(&mut self, cids: I) -> Vec<Block> {
+        let blocks: Vec<Block> = cids.filter_map(|cid| self.blocks.get(cid).cloned()).collect();
+        for block in &blocks {
+            *self.flushing.entry(block.cid().to_owned()).or_insert(0) += 1;
+        }
+        blocks
+    }
+
+    /// Records that the writes of `cids` are done, returning the blocks
+    /// that were removed meanwhile.
+    fn finish_flush(&mut self, cids: &[Cid]) -> Vec<Cid> {
+        let mut removed = Vec::new();
+        for cid in cids {
+            let done = match self.flushing.get_mut(cid) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                }
+                None => false,
+            };
+            if done {
+                self.flushing.remove(cid);
+                if self.removed.remove(cid) {
+                    removed.push(cid.to_owned());
+                }
+            }
+        }
+        removed
+    }
+}
+
+/// Block store that acknowledges puts once they are buffered in memory
+/// and writes them to the inner store in the background.
+///
+/// Buffered blocks are written in batches with `put_many`, once a batch
+/// is full or the flush interval passed. Reads see buffered blocks until
+/// they are written. Once the buffer is full puts wait until the block is
+/// written to the inner store. Use `close` to write all buffered blocks
+/// before shutting down.
+#[derive(Clone, Debug)]
+pub struct BufferedBlockStore<S: BlockStore> {
+    inner: S,
+    buffer: Arc<Mutex<Buffer>>,
+    limit: usize,
+    batch_size: usize,
+    interval: Duration,
+    spawner: Spawner,
+}
+
+impl<S: BlockStore> BufferedBlockStore<S> {
+    /// Buffers puts to `inner`.
+    pub fn wrap(inner: S) -> Self {
+        BufferedBlockStore {
+            inner,
+            buffer: Arc::new(Mutex::new(Buffer::default())),
+            limit: DEFAULT_BUFFER_LIMIT,
+            batch_size: DEFAULT_FLUSH_BATCH_SIZE,
+            interval: DEFAULT_FLUSH_INTERVAL,
+            spawner: Spawner::default(),
+        }
+    }
+
+    /// Buffers at most `limit` bytes.
+    pub fn with_buffer_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Writes up to `batch_size` blocks to the inner store at once.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Writes a batch that isn't full after `interval`.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Writes all buffered blocks to the inner store before shutting down.
+    pub fn close(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.flush()
+    }
+}
+
+impl<S: BlockStore> BlockStore for BufferedBlockStore<S> {
+    fn new(path: PathBuf) -> Self {
+        BufferedBlockStore::wrap(S::new(path))
+    }
+
+    /// Writes all buffered blocks to the inner store and flushes it.
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        let inner = self.inner.clone();
+        let buffer = self.buffer.clone();
+        FutureObj::new(Box::new(async move {
+            let blocks: Vec<Block> = {
+                let mut buffer = buffer.lock().unwrap();
+                buffer.pending.clear();
+                let cids: Vec<Cid> = buffer.blocks.keys().cloned().collect();
+                buffer.start_flush(cids.iter())
+            };
+            await!(write_blocks(inner.clone(), buffer, blocks))?;
+            await!(inner.flush())
+        }))
+    }
+
+    fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.inner = self.inner.with_spawner(spawner.clone());
+        self.spawner = spawner;
+        self
+    }
+
+    fn with_rocks_tuning(mut self, tuning: RocksTuning) -> Self {
+        self.inner = self.inner.with_rocks_tuning(tuning);
+        self
+    }
+
+    fn with_layout(mut self, layout: Layout) -> Self {
+        self.inner = self.inner.with_layout(layout);
+        self
+    }
+
+    fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.inner = self.inner.with_retry(retry);
+        self
+    }
+
+    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.init()
+    }
+
+    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.open()
+    }
+
+    fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+        {
+            let buffer = self.buffer.lock().unwrap();
+            if buffer.blocks.contains_key(cid) {
+                return FutureObj::new(Box::new(future::ok(true)));
+            }
+            // a running write may still put it into the inner store
+            if buffer.removed.contains(cid) {
+                return FutureObj::new(Box::new(future::ok(false)));
+            }
+        }
+        self.inner.contains(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+        {
+            let buffer = self.buffer.lock().unwrap();
+            if let Some(block) = buffer.blocks.get(cid) {
+                return FutureObj::new(Box::new(future::ok(Some(block.to_owned()))));
+            }
+            if buffer.removed.contains(cid) {
+                return FutureObj::new(Box::new(future::ok(None)));
+            }
+        }
+        self.inner.get(cid)
+    }
+
+    fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+        let inner = self.inner.clone();
+        let shared = self.buffer.clone();
+        let limit = self.limit;
+        let batch_size = self.batch_size;
+        let interval = self.interval;
+        let spawner = self.spawner.clone();
+        FutureObj::new(Box::new(async move {
+            let cid = block.cid().to_owned();
+            {
+                let mut buffer = shared.lock().unwrap();
+                if buffer.blocks.contains_key(&cid) {
+                    return Ok(cid);
+                }
+                // put again after it was removed, the running write keeps it
+                buffer.removed.remove(&cid);
+                if buffer.bytes + block.size() <= limit {
+                    buffer.bytes += block.size();
+                    buffer.blocks.insert(cid.clone(), block);
+                    buffer.pending.push(cid.clone());
+                    if buffer.pending.len() >= batch_size {
+                        let batch = mem::replace(&mut buffer.pending, Vec::new());
+                        spawner.spawn(flush(inner, shared.clone(), batch));
+                    } else if buffer.pending.len() == 1 {
+                        spawner.spawn(flush_after(inner, shared.clone(), interval));
+                    }
+                    return Ok(cid);
+                }
+            }
+            // the buffer is full, write through to the inner store
+            await!(inner.put(block))
+        }))
+    }
+
+    fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.buffer.lock().unwrap().discard(cid);
+        self.inner.remove(cid)
+    }
+
+    fn list_stream(&self) -> StoreStream<Cid> {
+        let (buffered, removed): (HashSet<Cid>, HashSet<Cid>) = {
+            let buffer = self.buffer.lock().unwrap();
+            (buffer.blocks.keys().cloned().collect(), buffer.removed.clone())
+        };
+        let flushed = self.inner.list_stream().filter({
+            let buffered = buffered.clone();
+            move |cid| future::ready(match cid {
+                Ok(cid) => !buffered.contains(cid) && !removed.contains(cid),
+                Err(_) => true,
+            })
+        });
+        Box::pin(stream::iter(buffered.into_iter().map(Ok)).chain(flushed))
+    }
+}
+
+/// Writes a batch of buffered blocks to the inner store.
+///
+/// The blocks stay buffered until they are written, failed writes are
+/// retried on `close`.
+fn flush<S: BlockStore>(inner: S, buffer: Arc<Mutex<Buffer>>, batch: Vec<Cid>) ->
+impl Future<Output=()>
+{
+    async move {
+        // blocks may have been removed or written by `close` meanwhile
+        let blocks = buffer.lock().unwrap().start_flush(batch.iter());
+        if blocks.is_empty() {
+            return;
+        }
+        let len = blocks.len();
+        if let Err(err) = await!(write_blocks(inner, buffer, blocks)) {
+            warn!("failed to flush {} blocks: {}", len, err);
+        }
+    }
+}
+
+/// Writes blocks passed to `Buffer::start_flush` to the inner store and
+/// removes them from the buffer.
+///
+/// Blocks removed while they were written are removed from the inner
+/// store again, so that the write doesn't bring them back.
+fn write_blocks<S: BlockStore>(inner: S, buffer: Arc<Mutex<Buffer>>, blocks: Vec<Block>) ->
+impl Future<Output=Result<(), Error>>
+{
+    async move {
+        let cids: Vec<Cid> = blocks.iter().map(|block| block.cid().to_owned()).collect();
+        let res = await!(inner.put_many(blocks));
+        let removed = {
+            let mut buffer = buffer.lock().unwrap();
+            if let Ok(written) = &res {
+                for cid in written {
+                    buffer.remove(cid);
+                }
+            }
+            buffer.finish_flush(&cids)
+        };
+        for cid in removed {
+            await!(inner.remove(&cid))?;
+        }
+        res.map(|_| ())
+    }
+}
+
+/// Writes the pending blocks after `interval`, even if the batch isn't
+/// full.
+fn flush_after<S: BlockStore>(inner: S, buffer: Arc<Mutex<Buffer>>, interval: Duration) ->
+impl Future<Output=()>
+{
+    async move {
+        if let Err(err) = await!(Delay::new(Instant::now() + interval).compat()) {
+            warn!("flush timer failed: {}", err);
+        }
+        // the batch may have been filled and written meanwhile
+        let batch = mem::replace(&mut buffer.lock().unwrap().pending, Vec::new());
+        await!(flush(inner, buffer, batch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{DataStorePinStore, Repo, RepoOptions, RepoTypes};
+    use crate::repo::mem::{MemBlockStore, MemDataStore};
+    use futures::channel::oneshot;
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_buffered_blockstore() {
+        let store: BufferedBlockStore<MemBlockStore> = BlockStore::new(temp_dir());
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            let cid = await!(store.put(block.clone())).unwrap();
+            // visible whether or not the background flush has completed
+            assert!(await!(store.contains(&cid)).unwrap());
+            assert_eq!(await!(store.get(&cid)).unwrap(), Some(block.clone()));
+            assert_eq!(await!(store.list()).unwrap(), vec![cid.clone()]);
+
+            await!(store.close()).unwrap();
+            assert_eq!(store.buffer.lock().unwrap().bytes, 0);
+            assert_eq!(await!(store.inner.get(&cid)).unwrap(), Some(block));
+        });
+    }
+
+    #[test]
+    fn test_buffered_blockstore_spawner() {
+        let spawned = Arc::new(Mutex::new(0));
+        let counter = spawned.clone();
+        let spawner = Spawner::new(move |future| {
+            *counter.lock().unwrap() += 1;
+            tokio::spawn_async(future);
+        });
+        let store = BufferedBlockStore::wrap(MemBlockStore::new(temp_dir()))
+            .with_spawner(spawner);
+        tokio::run_async(async move {
+            await!(store.put(Block::from("1"))).unwrap();
+            assert_eq!(*spawned.lock().unwrap(), 1);
+        });
+    }
+
+    #[derive(Clone)]
+    struct BufferedTypes;
+
+    impl RepoTypes for BufferedTypes {
+        type TBlockStore = BufferedBlockStore<MemBlockStore>;
+        type TDataStore = MemDataStore;
+        type TPinStore = DataStorePinStore<MemDataStore>;
+    }
+
+    #[test]
+    fn test_repo_flush() {
+        // never run the background flushes
+        let options = RepoOptions::<BufferedTypes>::new(temp_dir())
+            .spawner(Spawner::new(|_| {}));
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            let cid = await!(repo.put_block(Block::from("1"))).unwrap();
+            let store = repo.block_store.clone();
+            assert!(!await!(store.inner.contains(&cid)).unwrap());
+            await!(repo.flush()).unwrap();
+            assert!(await!(store.inner.contains(&cid)).unwrap());
+
+            // the repo is still usable
+            let cid = await!(repo.put_block(Block::from("2"))).unwrap();
+            assert!(await!(repo.get_block_data(&cid)).unwrap().is_some());
+        });
+    }
+
+    /// Records the sizes of the batches written with `put_many`.
+    #[derive(Clone)]
+    struct BatchStore {
+        inner: MemBlockStore,
+        batches: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl BlockStore for BatchStore {
+        fn new(path: PathBuf) -> Self {
+            BatchStore {
+                inner: MemBlockStore::new(path),
+                batches: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.init()
+        }
+
+        fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.open()
+        }
+
+        fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+            self.inner.contains(cid)
+        }
+
+        fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+            self.inner.get(cid)
+        }
+
+        fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+            self.inner.put(block)
+        }
+
+        fn put_many(&self, blocks: Vec<Block>) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+            self.batches.lock().unwrap().push(blocks.len());
+            self.inner.put_many(blocks)
+        }
+
+        fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.remove(cid)
+        }
+
+        fn list_stream(&self) -> StoreStream<Cid> {
+            self.inner.list_stream()
+        }
+    }
+
+    #[test]
+    fn test_buffered_blockstore_batches() {
+        // run the flushes by hand
+        let tasks = Arc::new(Mutex::new(Vec::new()));
+        let spawned = tasks.clone();
+        let spawner = Spawner::new(move |future| spawned.lock().unwrap().push(future));
+        let store = BufferedBlockStore::wrap(BatchStore::new(temp_dir()))
+            .with_batch_size(4)
+            .with_flush_interval(Duration::from_millis(0))
+            .with_spawner(spawner);
+        tokio::run_async(async move {
+            let mut cids = Vec::new();
+            for i in 0..10 {
+                cids.push(await!(store.put(Block::from(i.to_string().as_str()))).unwrap());
+            }
+            let tasks = mem::replace(&mut *tasks.lock().unwrap(), Vec::new());
+            for task in tasks {
+                for cid in &cids {
+                    assert!(await!(store.get(cid)).unwrap().is_some());
+                }
+                await!(task);
+            }
+            for cid in &cids {
+                assert!(await!(store.inner.inner.contains(cid)).unwrap());
+            }
+            assert!(store.buffer.lock().unwrap().blocks.is_empty());
+            let mut batches = store.inner.batches.lock().unwrap().clone();
+            batches.sort();
+            assert_eq!(batches, vec![2, 4, 4]);
+        });
+    }
+
+    #[test]
+    fn test_buffered_blockstore_full() {
+        let store = BufferedBlockStore::wrap(MemBlockStore::new(temp_dir()))
+            .with_buffer_limit(0);
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            let cid = await!(store.put(block.clone())).unwrap();
+            assert!(store.buffer.lock().unwrap().blocks.is_empty());
+            assert_eq!(await!(store.inner.get(&cid)).unwrap(), Some(block));
+        });
+    }
+    /// Mem block store whose batch writes wait until the gate opens.
+    #[derive(Clone)]
+    struct GatedStore {
+        inner: MemBlockStore,
+        gate: Arc<Mutex<Option<oneshot::Receiver<()>>>>,
+    }
+
+    impl BlockStore for GatedStore {
+        fn new(path: PathBuf) -> Self {
+            GatedStore {
+                inner: MemBlockStore::new(path),
+                gate: Arc::new(Mutex::new(None)),
+            }
+        }
+
+        fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.init()
+        }
+
+        fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.open()
+        }
+
+        fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+            self.inner.contains(cid)
+        }
+
+        fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+            self.inner.get(cid)
+        }
+
+        fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+            self.inner.put(block)
+        }
+
+        fn put_many(&self, blocks: Vec<Block>) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+            let inner = self.inner.clone();
+            let gate = self.gate.lock().unwrap().take();
+            FutureObj::new(Box::new(async move {
+                if let Some(gate) = gate {
+                    await!(gate).unwrap();
+                }
+                await!(inner.put_many(blocks))
+            }))
+        }
+
+        fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.remove(cid)
+        }
+
+        fn list_stream(&self) -> StoreStream<Cid> {
+            self.inner.list_stream()
+        }
+    }
+
+    #[test]
+    fn test_buffered_blockstore_remove_while_flushing() {
+        let tasks = Arc::new(Mutex::new(Vec::new()));
+        let spawned = tasks.clone();
+        let spawner = Spawner::new(move |future| spawned.lock().unwrap().push(future));
+        let (open, gate) = oneshot::channel();
+        let inner = GatedStore::new(temp_dir());
+        *inner.gate.lock().unwrap() = Some(gate);
+        let store = BufferedBlockStore::wrap(inner)
+            .with_batch_size(1)
+            .with_spawner(spawner);
+        tokio::run_async(async move {
+            let cid = await!(store.put(Block::from("1"))).unwrap();
+            let mut flush = Box::pin(tasks.lock().unwrap().pop().unwrap());
+            // the write waits for the gate
+            assert!(futures::poll!(flush.as_mut()).is_pending());
+
+            await!(store.remove(&cid)).unwrap();
+            assert!(!await!(store.contains(&cid)).unwrap());
+            open.send(()).unwrap();
+            await!(flush);
+            // the write didn't bring the block back
+            assert!(!await!(store.inner.inner.contains(&cid)).unwrap());
+            assert!(!await!(store.contains(&cid)).unwrap());
+            assert!(store.buffer.lock().unwrap().removed.is_empty());
+        });
+    }
+}
@@ -0,0 +1,331 @@
+//! Read caching for block stores
+use crate::block::{Cid, Block};
+use crate::error::Error;
+use crate::repo::{BlockStore, Layout, RetryPolicy, RecoveryReport, RocksTuning, Spawner, StoreStream, StoreUsage};
+use futures::future::{self, FutureObj};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Default number of bytes that may be cached.
+pub const DEFAULT_CACHE_LIMIT: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Default)]
+struct Cache {
+    // block and the tick of the last use
+    blocks: HashMap<Cid, (Block, u64)>,
+    // tick of the last use to block
+    uses: BTreeMap<u64, Cid>,
+    bytes: usize,
+    tick: u64,
+    // incremented with every removal, blocks read before it aren't cached
+    generation: u64,
+}
+
+impl Cache {
+    fn get(&mut self, cid: &Cid) -> Option<Block> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (block, used) = self.blocks.get_mut(cid)?;
+        self.uses.remove(used);
+        self.uses.insert(tick, cid.to_owned());
+        *used = tick;
+        Some(block.to_owned())
+    }
+
+    fn insert(&mut self, block: Block, limit: usize) {
+        if block.size() > limit || self.blocks.contains_key(block.cid()) {
+            return;
+        }
+        while self.bytes + block.size() > limit {
+            let oldest = *self.uses.keys().next().expect("cache isn't empty");
+            let cid = self.uses.remove(&oldest).unwrap();
+            self.remove(&cid);
+        }
+        self.tick += 1;
+        self.bytes += block.size();
+        self.uses.insert(self.tick, block.cid().to_owned());
+        self.blocks.insert(block.cid().to_owned(), (block, self.tick));
+    }
+
+    /// Removes a block that was removed from the store.
+    fn invalidate(&mut self, cid: &Cid) {
+        self.generation += 1;
+        self.remove(cid);
+    }
+
+    fn remove(&mut self, cid: &Cid) {
+        if let Some((block, used)) = self.blocks.remove(cid) {
+            self.uses.remove(&used);
+            self.bytes -= block.size();
+        }
+    }
+}
+
+/// Block store that keeps the most recently read blocks in memory.
+///
+/// Blocks are cached when they are read, the least recently used
+/// blocks are evicted once the cache is full.
+#[derive(Clone, Debug)]
+pub struct CachedBlockStore<S: BlockStore> {
+    inner: S,
+    cache: Arc<Mutex<Cache>>,
+    limit: usize,
+}
+
+impl<S: BlockStore> CachedBlockStore<S> {
+    /// Caches reads from `inner`.
+    pub fn wrap(inner: S) -> Self {
+        CachedBlockStore {
+            inner,
+            cache: Arc::new(Mutex::new(Cache::default())),
+            limit: DEFAULT_CACHE_LIMIT,
+        }
+    }
+
+    /// Caches at most `limit` bytes.
+    pub fn with_cache_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+impl<S: BlockStore> BlockStore for CachedBlockStore<S> {
+    fn new(path: PathBuf) -> Self {
+        CachedBlockStore::wrap(S::new(path))
+    }
+
+    fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.inner = self.inner.with_spawner(spawner);
+        self
+    }
+
+    fn with_rocks_tuning(mut self, tuning: RocksTuning) -> Self {
+        self.inner = self.inner.with_rocks_tuning(tuning);
+        self
+    }
+
+    fn with_layout(mut self, layout: Layout) -> Self {
+        self.inner = self.inner.with_layout(layout);
+        self
+    }
+
+    fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.inner = self.inner.with_retry(retry);
+        self
+    }
+
+    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.init()
+    }
+
+    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.open()
+    }
+
+    fn open_with_recovery(&self) -> FutureObj<'static, Result<RecoveryReport, Error>> {
+        self.inner.open_with_recovery()
+    }
+
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.flush()
+    }
+
+    fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+        if self.cache.lock().unwrap().blocks.contains_key(cid) {
+            return FutureObj::new(Box::new(future::ok(true)));
+        }
+        self.inner.contains(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(block) = cache.get(cid) {
+                return FutureObj::new(Box::new(future::ok(Some(block))));
+            }
+            cache.generation
+        };
+        let get = self.inner.get(cid);
+        let cache = self.cache.clone();
+        let limit = self.limit;
+        FutureObj::new(Box::new(async move {
+            let block = await!(get)?;
+            if let Some(block) = &block {
+                let mut cache = cache.lock().unwrap();
+                // the block may have been removed while it was read
+                if cache.generation == generation {
+                    cache.insert(block.to_owned(), limit);
+                }
+            }
+            Ok(block)
+        }))
+    }
+
+    fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+        self.inner.put(block)
+    }
+
+    fn put_many(&self, blocks: Vec<Block>) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        self.inner.put_many(blocks)
+    }
+
+    fn block_size(&self, cid: &Cid) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        let size = self.cache.lock().unwrap()
+            .blocks
+            .get(cid)
+            .map(|(block, _)| block.size() as u64);
+        match size {
+            Some(size) => FutureObj::new(Box::new(future::ok(Some(size)))),
+            // doesn't load the block like the default implementation
+            None => self.inner.block_size(cid),
+        }
+    }
+
+    fn available_space(&self) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        self.inner.available_space()
+    }
+
+    fn usage(&self) -> FutureObj<'static, Result<StoreUsage, Error>> {
+        self.inner.usage()
+    }
+
+    fn snapshot(&self, dest: PathBuf) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.snapshot(dest)
+    }
+
+    fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.cache.lock().unwrap().invalidate(cid);
+        let remove = self.inner.remove(cid);
+        let cache = self.cache.clone();
+        let cid = cid.to_owned();
+        FutureObj::new(Box::new(async move {
+            await!(remove)?;
+            // also drops blocks read since the invalidation above
+            cache.lock().unwrap().invalidate(&cid);
+            Ok(())
+        }))
+    }
+
+    fn list_stream(&self) -> StoreStream<Cid> {
+        self.inner.list_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{DataStorePinStore, Repo, RepoOptions, RepoTypes};
+    use crate::repo::mem::{MemBlockStore, MemDataStore};
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_cached_blockstore_eviction() {
+        let store = CachedBlockStore::wrap(MemBlockStore::new(temp_dir()))
+            .with_cache_limit(2);
+        tokio::run_async(async move {
+            let blocks = vec![Block::from("1"), Block::from("2"), Block::from("3")];
+            for block in &blocks {
+                await!(store.put(block.clone())).unwrap();
+            }
+            await!(store.get(blocks[0].cid())).unwrap();
+            await!(store.get(blocks[1].cid())).unwrap();
+            // "1" was used more recently than "2"
+            await!(store.get(blocks[0].cid())).unwrap();
+            await!(store.get(blocks[2].cid())).unwrap();
+
+            let cache = store.cache.lock().unwrap();
+            assert_eq!(cache.bytes, 2);
+            assert!(cache.blocks.contains_key(blocks[0].cid()));
+            assert!(!cache.blocks.contains_key(blocks[1].cid()));
+            assert!(cache.blocks.contains_key(blocks[2].cid()));
+        });
+    }
+
+    #[test]
+    fn test_cached_blockstore_shares_data() {
+        use crate::repo::fs::FsBlockStore;
+
+        let mut tmp = temp_dir();
+        tmp.push("cached-blockstore-shares-data");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        // every read of the fs store allocates a new buffer
+        let store = CachedBlockStore::wrap(FsBlockStore::new(tmp.clone()));
+        tokio::run_async(async move {
+            let data = vec![0; 1024 * 1024];
+            let block = Block::new(data, Block::from("1").cid().to_owned());
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+            await!(store.put(block.clone())).unwrap();
+            let get = await!(store.get(block.cid())).unwrap().unwrap();
+            let other = await!(store.get(block.cid())).unwrap().unwrap();
+            // both getters share the cached buffer
+            assert_eq!(get, block);
+            assert_eq!(other.data().as_ptr(), get.data().as_ptr());
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_cached_blockstore_remove_while_reading() {
+        let store = CachedBlockStore::wrap(MemBlockStore::new(temp_dir()));
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            await!(store.put(block.clone())).unwrap();
+            // read from the inner store before the block is removed
+            let get = store.get(block.cid());
+            await!(store.remove(block.cid())).unwrap();
+            assert_eq!(await!(get).unwrap(), Some(block.clone()));
+            assert!(!store.cache.lock().unwrap().blocks.contains_key(block.cid()));
+            assert!(!await!(store.contains(block.cid())).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_cached_blockstore_short_circuits() {
+        let store = CachedBlockStore::wrap(MemBlockStore::new(temp_dir()));
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            await!(store.put(block.clone())).unwrap();
+            assert_eq!(await!(store.get(block.cid())).unwrap(), Some(block.clone()));
+
+            // answered from the cache without asking the inner store
+            await!(store.inner.remove(block.cid())).unwrap();
+            assert!(await!(store.contains(block.cid())).unwrap());
+            assert_eq!(await!(store.block_size(block.cid())).unwrap(), Some(1));
+
+            await!(store.remove(block.cid())).unwrap();
+            assert!(!await!(store.contains(block.cid())).unwrap());
+            assert_eq!(await!(store.block_size(block.cid())).unwrap(), None);
+        });
+    }
+
+    #[derive(Clone)]
+    struct CachedTypes;
+
+    impl RepoTypes for CachedTypes {
+        type TBlockStore = CachedBlockStore<MemBlockStore>;
+        type TDataStore = MemDataStore;
+        type TPinStore = DataStorePinStore<MemDataStore>;
+    }
+
+    #[test]
+    fn test_repo_warm() {
+        let (repo, events) = Repo::new(RepoOptions::<CachedTypes>::new(temp_dir()));
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            await!(repo.put_block_quiet(block.clone())).unwrap();
+            let missing = Block::from("2").cid().to_owned();
+
+            let warmed = await!(repo.warm(&[block.cid().to_owned(), missing])).unwrap();
+            assert_eq!(warmed, 1);
+            assert!(events.try_recv().is_err());
+
+            // served from the cache
+            let store = repo.block_store.clone();
+            await!(store.inner.remove(block.cid())).unwrap();
+            assert_eq!(await!(repo.get_block(block.cid())).unwrap(), block);
+        });
+    }
+}
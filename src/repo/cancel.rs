@@ -0,0 +1,40 @@
+//! Cooperative cancellation of long running operations
+use crate::error::Error;
+use crate::repo::RepoError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Signals a long running operation to stop.
+///
+/// Operations check the token between steps and fail with
+/// `RepoError::Cancelled` once it was cancelled, so no step is left half
+/// done. Clones share the cancellation.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that isn't cancelled.
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Cancels the operations using the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `RepoError::Cancelled` if the token was cancelled.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(RepoError::Cancelled.into());
+        }
+        Ok(())
+    }
+}
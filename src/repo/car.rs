@@ -0,0 +1,345 @@
+//! Importing and exporting of CAR (content addressable archive) files
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::ipld::Ipld;
+use crate::ipld::formats::cbor;
+use crate::path::PathRoot;
+use crate::repo::{block_links, BlockStore, Repo, RepoError, RepoTypes};
+use core::future::Future;
+use futures::compat::*;
+use std::collections::{HashMap, HashSet};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Sections larger than this are rejected instead of being read into
+/// memory.
+const MAX_SECTION_SIZE: u64 = 4 * 1024 * 1024;
+
+/// What to do with blocks whose data doesn't match their cid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BadBlockPolicy {
+    /// Fail with `RepoError::BlockCorrupted`.
+    Abort,
+    /// Skip the block and continue with the next one.
+    Skip,
+}
+
+/// Counts the blocks of an import.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImportStats {
+    /// Number of blocks put into the repo.
+    pub imported: u64,
+    /// Number of corrupted blocks that were skipped.
+    pub skipped: u64,
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Imports all blocks of a CARv1 archive.
+    ///
+    /// Every block is verified against its cid while reading. When the
+    /// import is aborted, the blocks imported up to that point are kept.
+    pub fn import_car<R: AsyncRead + Send + 'static>(&self, reader: R, on_bad_block: BadBlockPolicy) ->
+    impl Future<Output=Result<ImportStats, Error>>
+    {
+        let import = self.import_car_roots(reader, on_bad_block);
+        async move {
+            Ok(await!(import)?.0)
+        }
+    }
+
+    /// Imports all blocks of a CARv1 archive like `import_car` and pins
+    /// the roots of the archive recursively.
+    ///
+    /// The roots are pinned after all blocks were imported.
+    pub fn import_car_pinned<R: AsyncRead + Send + 'static>(&self, reader: R, on_bad_block: BadBlockPolicy) ->
+    impl Future<Output=Result<ImportStats, Error>>
+    {
+        let repo = self.clone();
+        let import = self.import_car_roots(reader, on_bad_block);
+        async move {
+            let (stats, roots) = await!(import)?;
+            for root in &roots {
+                await!(repo.pin_add(root, true))?;
+            }
+            Ok(stats)
+        }
+    }
+
+    /// Writes the DAG below `root` into a CARv1 archive with `root` as its
+    /// only root, returning the writer and the number of written blocks.
+    ///
+    /// Blocks are written in depth-first order, every block once. Fails
+    /// with `RepoError::BlockNotFound` if a block of the DAG isn't stored
+    /// locally.
+    pub fn export_car<W: AsyncWrite + Send + 'static>(&self, root: &Cid, writer: W) ->
+    impl Future<Output=Result<(W, u64), Error>>
+    {
+        let repo = self.clone();
+        let root = root.to_owned();
+        async move {
+            let mut header = HashMap::new();
+            header.insert("version", Ipld::from(1u64));
+            header.insert("roots", Ipld::from(vec![Ipld::from(root.clone())]));
+            let header = Ipld::from(header).to_dag_cbor()?;
+            let mut writer = await!(write_section(writer, &[], header.data()))?;
+
+            let mut written = HashSet::new();
+            let mut stack = vec![(root, 0)];
+            while let Some((cid, depth)) = stack.pop() {
+                if depth > repo.max_depth {
+                    return Err(RepoError::DagTooDeep(repo.max_depth).into());
+                }
+                if written.contains(&cid) {
+                    continue;
+                }
+                let block = match await!(repo.block_store.get(&cid))? {
+                    Some(block) => block,
+                    None => return Err(RepoError::BlockNotFound(cid).into()),
+                };
+                // reversed so that the links are written in order
+                stack.extend(block_links(&block)?.into_iter().rev().map(|link| (link, depth + 1)));
+                writer = await!(write_section(writer, &cid.to_bytes(), block.data()))?;
+                written.insert(cid);
+            }
+            Ok((writer, written.len() as u64))
+        }
+    }
+
+    fn import_car_roots<R: AsyncRead + Send + 'static>(&self, reader: R, on_bad_block: BadBlockPolicy) ->
+    impl Future<Output=Result<(ImportStats, Vec<Cid>), Error>>
+    {
+        let repo = self.clone();
+        async move {
+            let mut stats = ImportStats::default();
+            let (reader, header_len) = match await!(read_varint(reader))? {
+                Some(header) => header,
+                None => bail!("car file is empty"),
+            };
+            let (mut reader, header) = await!(read_section(reader, header_len))?;
+            let roots = parse_roots(header)?;
+            loop {
+                let (next, len) = match await!(read_varint(reader))? {
+                    Some(section) => section,
+                    None => break,
+                };
+                let (next, section) = await!(read_section(next, len))?;
+                reader = next;
+                let (block, valid) = await!(repo.verify_block(parse_block(section)?))?;
+                if !valid {
+                    let cid = block.cid().to_owned();
+                    match on_bad_block {
+                        BadBlockPolicy::Abort => return Err(RepoError::BlockCorrupted(cid).into()),
+                        BadBlockPolicy::Skip => {
+                            warn!("skipping corrupted block {}", cid.to_string());
+                            stats.skipped += 1;
+                            continue;
+                        }
+                    }
+                }
+                await!(repo.put_block(block))?;
+                stats.imported += 1;
+            }
+            Ok((stats, roots))
+        }
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Writes a section made of `prefix` and `data`.
+fn write_section<W: AsyncWrite + Send + 'static>(writer: W, prefix: &[u8], data: &[u8]) ->
+impl Future<Output=Result<W, Error>>
+{
+    let mut section = Vec::with_capacity(prefix.len() + data.len() + 9);
+    write_varint(&mut section, (prefix.len() + data.len()) as u64);
+    section.extend_from_slice(prefix);
+    section.extend_from_slice(data);
+    async move {
+        let (writer, _) = await!(tokio::io::write_all(writer, section).compat())?;
+        Ok(writer)
+    }
+}
+
+/// Returns the roots listed in the header of an archive.
+fn parse_roots(header: Vec<u8>) -> Result<Vec<Cid>, Error> {
+    let header = match cbor::decode(header)? {
+        Ipld::Object(header) => header,
+        _ => bail!("car header isn't a map"),
+    };
+    match header.get("version") {
+        Some(Ipld::U64(1)) => {}
+        _ => bail!("unsupported car version"),
+    }
+    match header.get("roots") {
+        Some(Ipld::Array(roots)) => roots.iter().map(|root| match root {
+            Ipld::Link(PathRoot::Ipld(cid)) => Ok(cid.to_owned()),
+            _ => bail!("car root isn't a cid"),
+        }).collect(),
+        _ => bail!("car header has no roots"),
+    }
+}
+
+/// Reads an unsigned varint, returns `None` at the end of the reader.
+fn read_varint<R: AsyncRead + Send + 'static>(reader: R) ->
+impl Future<Output=Result<Option<(R, u64)>, Error>>
+{
+    async move {
+        let mut reader = reader;
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let (next, byte) = match await!(tokio::io::read_exact(reader, [0u8; 1]).compat()) {
+                Ok(read) => read,
+                Err(ref err) if shift == 0 && err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                Err(err) => return Err(err.into()),
+            };
+            reader = next;
+            value |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some((reader, value)));
+            }
+            shift += 7;
+            if shift >= 64 {
+                bail!("varint is too long");
+            }
+        }
+    }
+}
+
+fn read_section<R: AsyncRead + Send + 'static>(reader: R, len: u64) ->
+impl Future<Output=Result<(R, Vec<u8>), Error>>
+{
+    async move {
+        if len > MAX_SECTION_SIZE {
+            bail!("car section of {} bytes is too large", len);
+        }
+        let buf = vec![0; len as usize];
+        Ok(await!(tokio::io::read_exact(reader, buf).compat())?)
+    }
+}
+
+/// Decodes an unsigned varint, returning the value and its length.
+fn decode_varint(bytes: &[u8]) -> Result<(u64, usize), Error> {
+    let mut value = 0;
+    for (i, byte) in bytes.iter().enumerate().take(9) {
+        value |= u64::from(byte & 0x7f) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    bail!("invalid varint")
+}
+
+/// Splits a section into the cid and the block data.
+fn parse_block(mut section: Vec<u8>) -> Result<Block, Error> {
+    let cid_len = if section.len() >= 34 && section[0] == 0x12 && section[1] == 0x20 {
+        // version 0 cids are bare sha2-256 multihashes
+        34
+    } else {
+        let mut pos = 0;
+        // version, codec and hash function
+        for _ in 0..3 {
+            pos += decode_varint(&section[pos..])?.1;
+        }
+        let (hash_len, n) = decode_varint(&section[pos..])?;
+        pos + n + hash_len as usize
+    };
+    if cid_len > section.len() {
+        bail!("car section is truncated");
+    }
+    let data = section.split_off(cid_len);
+    let cid = Cid::from(section)?;
+    Ok(Block::new(data, cid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::tests::create_mock_repo;
+    use std::io::Cursor;
+
+    fn write_car(blocks: &[Block]) -> Vec<u8> {
+        let mut header = HashMap::new();
+        header.insert("version", Ipld::from(1u64));
+        header.insert("roots", Ipld::from(vec![Ipld::from(blocks[0].cid().to_owned())]));
+        let header = Ipld::from(header).to_dag_cbor().unwrap();
+        let mut car = Vec::new();
+        write_varint(&mut car, header.size() as u64);
+        car.extend_from_slice(header.data());
+        for block in blocks {
+            let cid = block.cid().to_bytes();
+            write_varint(&mut car, (cid.len() + block.size()) as u64);
+            car.extend(cid);
+            car.extend_from_slice(block.data());
+        }
+        car
+    }
+
+    fn corrupted_car() -> (Vec<u8>, Vec<Block>) {
+        let good = vec![Block::from("1"), Block::from("3")];
+        let bad = Block::new(b"bad".to_vec(), Block::from("2").cid().to_owned());
+        let car = write_car(&[good[0].clone(), bad, good[1].clone()]);
+        (car, good)
+    }
+
+    #[test]
+    fn test_import_car_skip() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let (car, good) = corrupted_car();
+            let stats = await!(repo.import_car(Cursor::new(car), BadBlockPolicy::Skip)).unwrap();
+            assert_eq!(stats, ImportStats { imported: 2, skipped: 1 });
+            for block in good {
+                assert!(await!(repo.block_store.contains(block.cid())).unwrap());
+            }
+            assert!(!await!(repo.block_store.contains(Block::from("2").cid())).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_import_car_abort() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let (car, good) = corrupted_car();
+            let res = await!(repo.import_car(Cursor::new(car), BadBlockPolicy::Abort));
+            assert!(res.is_err());
+            assert!(await!(repo.block_store.contains(good[0].cid())).unwrap());
+            assert!(!await!(repo.block_store.contains(Block::from("2").cid())).unwrap());
+            assert!(!await!(repo.block_store.contains(good[1].cid())).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_export_import_car() {
+        let repo = create_mock_repo();
+        let imported = create_mock_repo();
+        tokio::run_async(async move {
+            let leaf = Block::from("1");
+            let mut node = HashMap::new();
+            node.insert("a", Ipld::from(leaf.cid().to_owned()));
+            node.insert("b", Ipld::from(leaf.cid().to_owned()));
+            let root = Ipld::from(node).to_dag_cbor().unwrap();
+            await!(repo.put_block(leaf.clone())).unwrap();
+            await!(repo.put_block(root.clone())).unwrap();
+
+            let (car, written) = await!(repo.export_car(root.cid(), Cursor::new(Vec::new()))).unwrap();
+            let car = car.into_inner();
+            assert_eq!(written, 2);
+
+            let stats = await!(imported.import_car_pinned(Cursor::new(car), BadBlockPolicy::Abort)).unwrap();
+            assert_eq!(stats, ImportStats { imported: 2, skipped: 0 });
+            assert!(await!(imported.is_pinned(root.cid())).unwrap());
+            assert!(await!(imported.block_store.contains(leaf.cid())).unwrap());
+
+            let missing = Block::from("2").cid().to_owned();
+            assert!(await!(repo.export_car(&missing, Cursor::new(Vec::new()))).is_err());
+        });
+    }
+}
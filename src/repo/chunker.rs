@@ -0,0 +1,165 @@
+//! Splitting of added files into chunks
+use std::sync::Arc;
+
+/// Splits the data of added files into chunks.
+pub trait Chunker: Send + Sync + 'static {
+    /// Returns the size of the largest chunk. The data passed to `cut` is
+    /// at least this long unless the end of the file is reached.
+    fn max_size(&self) -> usize;
+
+    /// Returns the length of the first chunk of `data`, between one and
+    /// `max_size` bytes.
+    fn cut(&self, data: &[u8]) -> usize;
+}
+
+/// Splits files into chunks of the same size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedChunker {
+    size: usize,
+}
+
+impl FixedChunker {
+    /// Creates a chunker cutting chunks of `size` bytes.
+    pub fn new(size: usize) -> Self {
+        FixedChunker {
+            size: size.max(1),
+        }
+    }
+}
+
+impl Chunker for FixedChunker {
+    fn max_size(&self) -> usize {
+        self.size
+    }
+
+    fn cut(&self, data: &[u8]) -> usize {
+        data.len().min(self.size)
+    }
+}
+
+/// Size of the window of the rolling hash.
+const WINDOW: usize = 32;
+
+/// Default smallest chunk of the buzhash chunker, the same as in go-ipfs.
+pub const DEFAULT_BUZHASH_MIN: usize = 128 * 1024;
+
+/// Default largest chunk of the buzhash chunker, the same as in go-ipfs.
+pub const DEFAULT_BUZHASH_MAX: usize = 512 * 1024;
+
+/// Default number of hash bits that need to be zero for a cut, resulting
+/// in chunks of 256KiB on average.
+pub const DEFAULT_BUZHASH_BITS: u32 = 17;
+
+/// Splits files at the positions where a rolling hash of the last 32
+/// bytes matches a mask, so that inserting data into a file only changes
+/// the chunks around the insertion.
+#[derive(Clone, Debug)]
+pub struct BuzhashChunker {
+    min: usize,
+    max: usize,
+    mask: u32,
+    table: Arc<[u32; 256]>,
+}
+
+/// Returns the hashes of the bytes, derived from a fixed seed so that the
+/// cuts are the same on every run.
+fn byte_hashes() -> [u32; 256] {
+    let mut table = [0; 256];
+    // splitmix64
+    let mut state: u64 = 0x6275_7a68_6173_6821;
+    for hash in table.iter_mut() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        *hash = (z ^ (z >> 31)) as u32;
+    }
+    table
+}
+
+impl Default for BuzhashChunker {
+    fn default() -> Self {
+        BuzhashChunker::new(DEFAULT_BUZHASH_MIN, DEFAULT_BUZHASH_MAX, DEFAULT_BUZHASH_BITS)
+    }
+}
+
+impl BuzhashChunker {
+    /// Creates a chunker cutting chunks between `min` and `max` bytes
+    /// where the lowest `bits` bits of the hash are zero.
+    pub fn new(min: usize, max: usize, bits: u32) -> Self {
+        let min = min.max(WINDOW);
+        BuzhashChunker {
+            min,
+            max: max.max(min),
+            mask: (1u32 << bits.min(31)) - 1,
+            table: Arc::new(byte_hashes()),
+        }
+    }
+}
+
+impl Chunker for BuzhashChunker {
+    fn max_size(&self) -> usize {
+        self.max
+    }
+
+    fn cut(&self, data: &[u8]) -> usize {
+        let end = data.len().min(self.max);
+        if end <= self.min {
+            return end;
+        }
+        let table = &self.table;
+        let mut hash = 0u32;
+        for &byte in &data[self.min - WINDOW..self.min] {
+            hash = hash.rotate_left(1) ^ table[byte as usize];
+        }
+        for i in self.min..end {
+            if hash & self.mask == 0 {
+                return i;
+            }
+            // the byte leaving the window was rotated 32 times, which is
+            // its original hash
+            hash = hash.rotate_left(1) ^ table[data[i - WINDOW] as usize] ^ table[data[i] as usize];
+        }
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    fn chunks<C: Chunker>(chunker: &C, mut data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        while !data.is_empty() {
+            let len = chunker.cut(data);
+            assert!(len > 0 && len <= chunker.max_size());
+            chunks.push(data[..len].to_vec());
+            data = &data[len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_fixed_chunker() {
+        let chunker = FixedChunker::new(4);
+        assert_eq!(chunks(&chunker, b"aaaabbbbcc"), vec![b"aaaa".to_vec(), b"bbbb".to_vec(), b"cc".to_vec()]);
+    }
+
+    #[test]
+    fn test_buzhash_chunker_resyncs() {
+        let mut data = vec![0; 64 * 1024];
+        StdRng::seed_from_u64(1).fill(&mut data[..]);
+        let chunker = BuzhashChunker::new(256, 4096, 10);
+        let original = chunks(&chunker, &data);
+
+        let mut modified = b"inserted".to_vec();
+        modified.extend_from_slice(&data);
+        let modified = chunks(&chunker, &modified);
+        assert_eq!(original.concat(), data);
+        // only the first chunks differ
+        let shared = modified.iter().filter(|chunk| original.contains(chunk)).count();
+        assert!(shared + 2 >= original.len());
+    }
+}
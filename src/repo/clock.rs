@@ -0,0 +1,55 @@
+//! Time source for expiring repo state
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+/// Returns the current time.
+///
+/// Tombstones and cached ipns records read the time through the clock of
+/// the repo, so that tests can control it.
+pub trait Clock: Debug + Send + Sync + 'static {
+    fn now(&self) -> SystemTime;
+}
+
+/// Clock returning the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+#[cfg(test)]
+pub(crate) use self::fake::FakeClock;
+
+#[cfg(test)]
+mod fake {
+    use super::Clock;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+
+    /// Clock that only moves when it is advanced.
+    #[derive(Clone, Debug)]
+    pub(crate) struct FakeClock {
+        now: Arc<Mutex<SystemTime>>,
+    }
+
+    impl FakeClock {
+        pub(crate) fn new() -> Self {
+            FakeClock {
+                now: Arc::new(Mutex::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000))),
+            }
+        }
+
+        pub(crate) fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            *self.now.lock().unwrap()
+        }
+    }
+}
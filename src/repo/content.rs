@@ -0,0 +1,315 @@
+//! Content type detection for stored files
+use crate::block::Cid;
+use crate::error::Error;
+use crate::ipld::{Ipld, formats::pb::PbNode};
+use crate::repo::{BlockStore, Metadata, Mtime, Repo, RepoError, RepoTypes};
+use cid::Codec;
+use core::future::Future;
+use std::convert::TryInto;
+
+/// Metadata key the detected content type is cached under.
+const CONTENT_TYPE_KEY: &str = "content-type";
+
+/// Number of bytes considered when sniffing the content type.
+const SNIFF_LEN: usize = 512;
+
+/// Content type of unixfs directories.
+const DIRECTORY: &str = "inode/directory";
+
+/// Content type of unixfs symlinks.
+const SYMLINK: &str = "inode/symlink";
+
+/// Magic bytes of common formats.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b\x08", "application/gzip"),
+    (b"OggS\x00", "application/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x00asm", "application/wasm"),
+];
+
+/// Guesses the content type of `data` from its first bytes.
+fn sniff(data: &[u8]) -> &'static str {
+    let data = &data[..data.len().min(SNIFF_LEN)];
+    for &(magic, mime) in SIGNATURES {
+        if data.starts_with(magic) {
+            return mime;
+        }
+    }
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    let text = data.iter().skip_while(|byte| byte.is_ascii_whitespace());
+    let start: Vec<u8> = text.take(14).map(|byte| byte.to_ascii_lowercase()).collect();
+    if start.starts_with(b"<!doctype html") || start.starts_with(b"<html") {
+        return "text/html; charset=utf-8";
+    }
+    if start.starts_with(b"<?xml") {
+        return "text/xml; charset=utf-8";
+    }
+    // a multi-byte character may have been cut off at the end
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&data[..err.valid_up_to()]).unwrap()
+        }
+        Err(_) => return "application/octet-stream",
+    };
+    if text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return "application/octet-stream";
+    }
+    "text/plain; charset=utf-8"
+}
+
+/// Kind of a unixfs node.
+pub(crate) enum Node<'a> {
+    Directory,
+    /// A HAMT shard of a sharded directory, placing its entries by the
+    /// `hash_type` multihash of their names into `fanout` buckets.
+    HamtShard {
+        hash_type: u64,
+        fanout: u64,
+    },
+    /// A file with its own data and the sizes of the data of its
+    /// children.
+    File(&'a [u8], Vec<u64>),
+    /// A symlink to the path in its data.
+    Symlink(&'a [u8]),
+}
+
+/// Reads a protobuf varint, returning it and the remaining bytes.
+fn read_varint(data: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value = 0;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, &data[i + 1..]));
+        }
+    }
+    None
+}
+
+/// Reads the fields of a protobuf message, returning the number, the wire
+/// type and the value or the bytes of every field.
+fn read_fields(mut data: &[u8]) -> Option<Vec<(u64, u64, u64, &[u8])>> {
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let (tag, rest) = read_varint(data)?;
+        let (field, wire_type) = (tag >> 3, tag & 0x7);
+        data = match wire_type {
+            0 => {
+                let (value, rest) = read_varint(rest)?;
+                fields.push((field, wire_type, value, &[][..]));
+                rest
+            }
+            2 => {
+                let (len, rest) = read_varint(rest)?;
+                let len = len as usize;
+                if rest.len() < len {
+                    return None;
+                }
+                fields.push((field, wire_type, 0, &rest[..len]));
+                &rest[len..]
+            }
+            5 => {
+                if rest.len() < 4 {
+                    return None;
+                }
+                let mut value = 0;
+                for (i, byte) in rest[..4].iter().enumerate() {
+                    value |= u64::from(*byte) << (8 * i);
+                }
+                fields.push((field, wire_type, value, &[][..]));
+                &rest[4..]
+            }
+            _ => return None,
+        };
+    }
+    Some(fields)
+}
+
+/// Reads the UnixFS 1.5 metadata of the unixfs message in a dag-pb node.
+pub(crate) fn unixfs_metadata(data: &[u8]) -> Option<Metadata> {
+    let mut metadata = Metadata::default();
+    for (field, wire_type, value, bytes) in read_fields(data)? {
+        match (field, wire_type) {
+            (7, 0) => metadata.mode = Some(value as u32),
+            (8, 2) => {
+                let mut mtime = Mtime {
+                    seconds: 0,
+                    nanos: 0,
+                };
+                for (field, wire_type, value, _) in read_fields(bytes)? {
+                    match (field, wire_type) {
+                        (1, 0) => mtime.seconds = value as i64,
+                        (2, 5) => mtime.nanos = value as u32,
+                        _ => {}
+                    }
+                }
+                metadata.mtime = Some(mtime);
+            }
+            _ => {}
+        }
+    }
+    Some(metadata)
+}
+
+/// Reads the type and the data of the unixfs message in a dag-pb node.
+pub(crate) fn unixfs_node(mut data: &[u8]) -> Option<Node> {
+    let mut kind = None;
+    let mut content: &[u8] = &[];
+    let mut blocksizes = Vec::new();
+    let mut hash_type = 0;
+    let mut fanout = 0;
+    while !data.is_empty() {
+        let (tag, rest) = read_varint(data)?;
+        let (value, rest) = read_varint(rest)?;
+        data = match (tag >> 3, tag & 0x7) {
+            (1, 0) => {
+                kind = Some(value);
+                rest
+            }
+            (4, 0) => {
+                blocksizes.push(value);
+                rest
+            }
+            (5, 0) => {
+                hash_type = value;
+                rest
+            }
+            (6, 0) => {
+                fanout = value;
+                rest
+            }
+            (_, 0) => rest,
+            (field, 2) => {
+                let len = value as usize;
+                if rest.len() < len {
+                    return None;
+                }
+                if field == 2 {
+                    content = &rest[..len];
+                }
+                if field == 4 {
+                    // packed blocksizes
+                    let mut packed = &rest[..len];
+                    while !packed.is_empty() {
+                        let (size, next) = read_varint(packed)?;
+                        blocksizes.push(size);
+                        packed = next;
+                    }
+                }
+                &rest[len..]
+            }
+            _ => return None,
+        };
+    }
+    match kind? {
+        1 => Some(Node::Directory),
+        5 => Some(Node::HamtShard { hash_type, fanout }),
+        4 => Some(Node::Symlink(content)),
+        _ => Some(Node::File(content, blocksizes)),
+    }
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Detects the content type of the file or directory `cid`.
+    ///
+    /// Files are sniffed from the data of their first leaf, directories
+    /// are `inode/directory`. Returns `None` if the blocks aren't stored
+    /// locally. The result is cached in the block metadata.
+    pub fn detect_content_type(&self, cid: &Cid) ->
+    impl Future<Output=Result<Option<String>, Error>>
+    {
+        let repo = self.clone();
+        let root = cid.to_owned();
+        async move {
+            if let Some(cached) = await!(repo.get_block_meta(&root, CONTENT_TYPE_KEY))? {
+                return Ok(Some(String::from_utf8(cached)?));
+            }
+            let mut cid = root.clone();
+            let mut depth = 0;
+            let mime = loop {
+                if depth > repo.max_depth {
+                    return Err(RepoError::DagTooDeep(repo.max_depth).into());
+                }
+                let block = match await!(repo.block_store.get(&cid))? {
+                    Some(block) => block,
+                    None => return Ok(None),
+                };
+                if cid.prefix().codec != Codec::DagProtobuf {
+                    break sniff(block.data());
+                }
+                let pb_node: PbNode = match Ipld::from(&block)?.try_into() {
+                    Ok(pb_node) => pb_node,
+                    Err(_) => bail!("invalid dag_pb node"),
+                };
+                let content = match unixfs_node(&pb_node.data) {
+                    Some(Node::Directory) | Some(Node::HamtShard { .. }) => break DIRECTORY,
+                    Some(Node::Symlink(_)) => break SYMLINK,
+                    Some(Node::File(content, _)) => content,
+                    None => bail!("invalid unixfs node"),
+                };
+                match pb_node.links.first().and_then(|link| link.cid.cid()) {
+                    // the data of the node comes before its children
+                    Some(first) if content.is_empty() => cid = first.to_owned(),
+                    _ => break sniff(content),
+                }
+                depth += 1;
+            };
+            await!(repo.set_block_meta(&root, CONTENT_TYPE_KEY, mime.as_bytes()))?;
+            Ok(Some(mime.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::repo::tests::create_mock_repo;
+
+    fn raw_block(data: &[u8]) -> Block {
+        let prefix = cid::Prefix {
+            version: cid::Version::V1,
+            codec: Codec::Raw,
+            mh_type: multihash::Hash::SHA2256,
+            mh_len: 32,
+        };
+        Block::new(data.to_vec(), Cid::new_from_prefix(&prefix, data))
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"), "image/png");
+        assert_eq!(sniff(b"  <!DOCTYPE html><html>"), "text/html; charset=utf-8");
+        assert_eq!(sniff("h\u{e9}llo\n".as_bytes()), "text/plain; charset=utf-8");
+        assert_eq!(sniff(b"\x00\x01\x02"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_detect_content_type() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let png = raw_block(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR");
+            let text = raw_block(b"Here is some data\n");
+            await!(repo.put_block(png.clone())).unwrap();
+            await!(repo.put_block(text.clone())).unwrap();
+
+            let mime = await!(repo.detect_content_type(png.cid())).unwrap();
+            assert_eq!(mime, Some("image/png".to_string()));
+            let mime = await!(repo.detect_content_type(text.cid())).unwrap();
+            assert_eq!(mime, Some("text/plain; charset=utf-8".to_string()));
+            let cached = await!(repo.get_block_meta(png.cid(), CONTENT_TYPE_KEY)).unwrap();
+            assert_eq!(cached, Some(b"image/png".to_vec()));
+
+            let missing = raw_block(b"missing");
+            assert_eq!(await!(repo.detect_content_type(missing.cid())).unwrap(), None);
+        });
+    }
+}
@@ -0,0 +1,147 @@
+//! Copying of blocks between block stores
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{BlockStore, Repo, RepoError, RepoTypes};
+use core::future::Future;
+use futures::future::FutureObj;
+use futures::stream::StreamExt;
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Copies all blocks to `dest`, returning the number of copied blocks.
+    ///
+    /// Up to `concurrency` blocks are read, verified and written at the
+    /// same time. `progress` is called with the number of blocks copied
+    /// so far after every block.
+    pub fn copy_all_to<S, F>(&self, dest: S, concurrency: usize, mut progress: F) ->
+    impl Future<Output=Result<u64, Error>>
+    where S: BlockStore, F: FnMut(u64) + Send + 'static
+    {
+        let cids = self.block_store.list_stream();
+        let repo = self.clone();
+        let mut copies = cids.map(move |cid| {
+            FutureObj::new(Box::new(copy_block(repo.clone(), dest.clone(), cid)))
+        }).buffer_unordered(concurrency.max(1));
+        async move {
+            let mut copied = 0;
+            while let Some(res) = await!(copies.next()) {
+                if res? {
+                    copied += 1;
+                    progress(copied);
+                }
+            }
+            Ok(copied)
+        }
+    }
+}
+
+/// Copies a block from the repo to `dest`, returning false if it was
+/// removed meanwhile.
+fn copy_block<T: RepoTypes, D: BlockStore>(repo: Repo<T>, dest: D, cid: Result<Cid, Error>) ->
+impl Future<Output=Result<bool, Error>>
+{
+    async move {
+        let cid = cid?;
+        let block = match await!(repo.block_store.get(&cid))? {
+            Some(block) => block,
+            None => return Ok(false),
+        };
+        let (block, valid) = await!(repo.verify_block(block))?;
+        if !valid {
+            return Err(RepoError::BlockCorrupted(cid).into());
+        }
+        await!(dest.put(block))?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::repo::StoreStream;
+    use crate::repo::mem::MemBlockStore;
+    use crate::repo::tests::create_mock_repo;
+    use futures::compat::Future01CompatExt;
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tokio::timer::Delay;
+
+    /// Mem block store recording the maximum number of concurrent puts.
+    #[derive(Clone)]
+    struct CountingStore {
+        inner: MemBlockStore,
+        // (in flight, max in flight)
+        puts: Arc<Mutex<(usize, usize)>>,
+    }
+
+    impl BlockStore for CountingStore {
+        fn new(path: PathBuf) -> Self {
+            CountingStore {
+                inner: MemBlockStore::new(path),
+                puts: Arc::new(Mutex::new((0, 0))),
+            }
+        }
+        fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.init()
+        }
+        fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.open()
+        }
+        fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+            self.inner.contains(cid)
+        }
+        fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+            self.inner.get(cid)
+        }
+        fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+            let inner = self.inner.clone();
+            let puts = self.puts.clone();
+            FutureObj::new(Box::new(async move {
+                {
+                    let mut puts = puts.lock().unwrap();
+                    puts.0 += 1;
+                    puts.1 = puts.1.max(puts.0);
+                }
+                let delay = Instant::now() + Duration::from_millis(5);
+                await!(Delay::new(delay).compat()).unwrap();
+                let res = await!(inner.put(block));
+                puts.lock().unwrap().0 -= 1;
+                res
+            }))
+        }
+        fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.remove(cid)
+        }
+        fn list_stream(&self) -> StoreStream<Cid> {
+            self.inner.list_stream()
+        }
+    }
+
+    #[test]
+    fn test_copy_all_to() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            for i in 0..10 {
+                await!(repo.put_block(Block::from(&*i.to_string()))).unwrap();
+            }
+            let expected: HashSet<Cid> = await!(repo.block_store.list())
+                .unwrap().into_iter().collect();
+            for &concurrency in &[1, 4] {
+                let dest = CountingStore::new(std::env::temp_dir());
+                let reported = Arc::new(Mutex::new(0));
+                let last = reported.clone();
+                let copied = await!(repo.copy_all_to(dest.clone(), concurrency, move |n| {
+                    *last.lock().unwrap() = n;
+                })).unwrap();
+                assert_eq!(copied, 10);
+                assert_eq!(*reported.lock().unwrap(), 10);
+                let copy: HashSet<Cid> = await!(dest.list())
+                    .unwrap().into_iter().collect();
+                assert_eq!(copy, expected);
+                assert!(dest.puts.lock().unwrap().1 <= concurrency);
+            }
+        });
+    }
+}
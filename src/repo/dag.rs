@@ -0,0 +1,313 @@
+//! Traversal of the DAGs stored in the repo
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::ipld::{Ipld, formats::pb::PbNode};
+use crate::repo::{BlockStore, Repo, RepoError, RepoTypes};
+use crate::repo::content::{unixfs_node, Node};
+use cid::Codec;
+use core::future::Future;
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+/// What to do when a traversal reaches a block that isn't stored locally.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissingBlocks {
+    /// Fail with `RepoError::BlockNotFound`.
+    Error,
+    /// Skip the block and its children.
+    Skip,
+}
+
+/// Returns the cids linked to from `block`.
+pub fn block_links(block: &Block) -> Result<Vec<Cid>, Error> {
+    match block.cid().prefix().codec {
+        Codec::Raw => Ok(Vec::new()),
+        _ => Ok(Ipld::from(block)?.links()),
+    }
+}
+
+/// Renders the data of a raw block as a hex dump.
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = line.iter().map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                *byte as char
+            } else {
+                '.'
+            }
+        }).collect();
+        dump.push_str(&format!("{:08x}  {:<47}  |{}|\n", i * 16, hex.join(" "), ascii));
+    }
+    dump
+}
+
+/// Renders the links of a dag-pb node.
+fn links_summary(ipld: &Ipld) -> String {
+    let mut summary = String::new();
+    if let Ipld::Object(map) = ipld {
+        if let Some(Ipld::Bytes(data)) = map.get("Data") {
+            summary.push_str(&format!("data: {} bytes\n", data.len()));
+        }
+        if let Some(Ipld::Array(links)) = map.get("Links") {
+            summary.push_str(&format!("links: {}\n", links.len()));
+            for link in links {
+                let field = |name: &str| match link {
+                    Ipld::Object(link) => link.get(name),
+                    _ => None,
+                };
+                let name = match field("Name") {
+                    Some(Ipld::String(name)) => name.as_str(),
+                    _ => "",
+                };
+                let hash = match field("Hash") {
+                    Some(Ipld::Link(root)) => root.to_string(),
+                    _ => String::new(),
+                };
+                let size = match field("Tsize") {
+                    Some(Ipld::U64(size)) => *size,
+                    _ => 0,
+                };
+                summary.push_str(&format!("  {} {} {}\n", hash, size, name));
+            }
+        }
+    }
+    summary
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Renders the content of a locally stored block for inspection.
+    ///
+    /// Raw blocks are rendered as a hex dump, dag-pb blocks as a summary
+    /// of their links and all other blocks as json.
+    pub fn inspect(&self, cid: &Cid) -> impl Future<Output=Result<String, Error>> {
+        let block_store = self.block_store.clone();
+        let cid = cid.to_owned();
+        async move {
+            let block = match await!(block_store.get(&cid))? {
+                Some(block) => block,
+                None => return Err(RepoError::BlockNotFound(cid).into()),
+            };
+            match cid.prefix().codec {
+                Codec::Raw => Ok(hex_dump(block.data())),
+                Codec::DagProtobuf => Ok(links_summary(&Ipld::from(&block)?)),
+                _ => Ok(serde_json::to_string_pretty(&Ipld::from(&block)?.to_json())?),
+            }
+        }
+    }
+
+    /// Returns the cumulative size of all blocks reachable from `root`.
+    ///
+    /// Blocks linked to multiple times are only counted once. Only
+    /// blocks in the local block store are considered. Fails with
+    /// `RepoError::DagTooDeep` for DAGs deeper than `max_depth`.
+    pub fn dag_size(&self, root: &Cid, missing: MissingBlocks) ->
+    impl Future<Output=Result<u64, Error>>
+    {
+        let block_store = self.block_store.clone();
+        let max_depth = self.max_depth;
+        let root = root.to_owned();
+        async move {
+            let mut size = 0;
+            let mut visited = HashSet::new();
+            let mut stack = vec![(root, 0)];
+            while let Some((cid, depth)) = stack.pop() {
+                if depth > max_depth {
+                    return Err(RepoError::DagTooDeep(max_depth).into());
+                }
+                if !visited.insert(cid.clone()) {
+                    continue;
+                }
+                let block = match await!(block_store.get(&cid))? {
+                    Some(block) => block,
+                    None => match missing {
+                        MissingBlocks::Error => return Err(RepoError::BlockNotFound(cid).into()),
+                        MissingBlocks::Skip => continue,
+                    },
+                };
+                size += block.size() as u64;
+                stack.extend(block_links(&block)?.into_iter().map(|link| (link, depth + 1)));
+            }
+            Ok(size)
+        }
+    }
+
+    /// Finds the block of the unixfs file `root` that contains the byte
+    /// at `offset`, returning its cid and the offset within its data.
+    ///
+    /// Only the blocks on the path to the leaf are read. The sizes of the
+    /// children are taken from the unixfs blocksizes of a node, or from
+    /// the dag-pb `Tsize` of its links if they are missing.
+    pub fn seek(&self, root: &Cid, offset: u64) ->
+    impl Future<Output=Result<(Cid, u64), Error>>
+    {
+        let block_store = self.block_store.clone();
+        let max_depth = self.max_depth;
+        let mut cid = root.to_owned();
+        let mut offset = offset;
+        async move {
+            for _ in 0..=max_depth {
+                let block = match await!(block_store.get(&cid))? {
+                    Some(block) => block,
+                    None => return Err(RepoError::BlockNotFound(cid).into()),
+                };
+                if cid.prefix().codec != Codec::DagProtobuf {
+                    if offset < block.data().len() as u64 {
+                        return Ok((cid, offset));
+                    }
+                    bail!("offset is beyond the end of the file");
+                }
+                let pb_node: PbNode = match Ipld::from(&block)?.try_into() {
+                    Ok(pb_node) => pb_node,
+                    Err(_) => bail!("invalid dag_pb node"),
+                };
+                let (content, blocksizes) = match unixfs_node(&pb_node.data) {
+                    Some(Node::File(content, blocksizes)) => (content, blocksizes),
+                    Some(Node::Directory) | Some(Node::HamtShard { .. }) => {
+                        bail!("can't seek in a directory")
+                    }
+                    Some(Node::Symlink(_)) => bail!("can't seek in a symlink"),
+                    None => bail!("invalid unixfs node"),
+                };
+                // the data of the node comes before its children
+                if offset < content.len() as u64 {
+                    return Ok((cid, offset));
+                }
+                offset -= content.len() as u64;
+                let sizes: Vec<u64> = if blocksizes.len() == pb_node.links.len() {
+                    blocksizes
+                } else {
+                    pb_node.links.iter().map(|link| link.size).collect()
+                };
+                let mut child = None;
+                for (link, size) in pb_node.links.iter().zip(sizes) {
+                    if offset < size {
+                        child = link.cid.cid().map(|cid| cid.to_owned());
+                        break;
+                    }
+                    offset -= size;
+                }
+                cid = match child {
+                    Some(child) => child,
+                    None => bail!("offset is beyond the end of the file"),
+                };
+            }
+            Err(RepoError::DagTooDeep(max_depth).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipld::formats::pb::PbLink;
+    use crate::repo::RepoOptions;
+    use crate::repo::tests::{create_mock_repo, Types};
+    use std::env::temp_dir;
+    use std::io::Cursor;
+
+    fn raw_cid(data: &[u8]) -> Cid {
+        let prefix = cid::Prefix {
+            version: cid::Version::V1,
+            codec: Codec::Raw,
+            mh_type: multihash::Hash::SHA2256,
+            mh_len: 32,
+        };
+        Cid::new_from_prefix(&prefix, data)
+    }
+
+    #[test]
+    fn test_seek() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let (first, _) = await!(repo.add_reader(Cursor::new(b"aaaabbbbcc".to_vec()), 4)).unwrap();
+            let (second, _) = await!(repo.add_reader(Cursor::new(b"ddddee".to_vec()), 4)).unwrap();
+            assert_eq!(await!(repo.seek(&first, 5)).unwrap(), (raw_cid(b"bbbb"), 1));
+            assert_eq!(await!(repo.seek(&first, 9)).unwrap(), (raw_cid(b"cc"), 1));
+            assert!(await!(repo.seek(&first, 10)).is_err());
+
+            // without blocksizes the sizes of the links are used
+            let link = |cid: Cid, size| PbLink {
+                cid: cid.into(),
+                name: String::new(),
+                size,
+            };
+            let root: Ipld = PbNode {
+                links: vec![link(first, 10), link(second, 6)],
+                data: vec![0x08, 0x02],
+            }.into();
+            let root = await!(repo.put_block(root.to_dag_pb().unwrap())).unwrap();
+            assert_eq!(await!(repo.seek(&root, 0)).unwrap(), (raw_cid(b"aaaa"), 0));
+            assert_eq!(await!(repo.seek(&root, 12)).unwrap(), (raw_cid(b"dddd"), 2));
+            assert_eq!(await!(repo.seek(&root, 15)).unwrap(), (raw_cid(b"ee"), 1));
+            assert!(await!(repo.seek(&root, 16)).is_err());
+        });
+    }
+
+    #[test]
+    fn test_inspect_dag_cbor() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let link = Block::from("1").cid().to_owned();
+            let mut map = std::collections::HashMap::new();
+            map.insert("name", Ipld::from("inspect"));
+            map.insert("link", Ipld::from(link.clone()));
+            let block = Ipld::from(map).to_dag_cbor().unwrap();
+            let cid = await!(repo.put_block(block)).unwrap();
+
+            let json = await!(repo.inspect(&cid)).unwrap();
+            let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(value, serde_json::json!({
+                "link": { "/": link.to_string() },
+                "name": "inspect",
+            }));
+        });
+    }
+
+    #[test]
+    fn test_dag_size_max_depth() {
+        let options = RepoOptions::<Types>::new(temp_dir()).max_depth(3);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            let mut cid = await!(repo.put_block(Block::from("leaf"))).unwrap();
+            for i in 0..3 {
+                let block = Ipld::from(vec![Ipld::from(cid), Ipld::from(i)]).to_dag_cbor().unwrap();
+                cid = await!(repo.put_block(block)).unwrap();
+            }
+            assert!(await!(repo.dag_size(&cid, MissingBlocks::Error)).is_ok());
+
+            let block = Ipld::from(vec![Ipld::from(cid)]).to_dag_cbor().unwrap();
+            let cid = await!(repo.put_block(block)).unwrap();
+            let err = await!(repo.dag_size(&cid, MissingBlocks::Error)).unwrap_err();
+            assert_eq!(err.to_string(), "Dag is deeper than 3 links");
+        });
+    }
+
+    #[test]
+    fn test_dag_size_shared_child() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let child = Ipld::from("child").to_dag_cbor().unwrap();
+            let child_cid = await!(repo.put_block(child.clone())).unwrap();
+            let left = Ipld::from(vec![Ipld::from(child_cid.clone()), Ipld::from(1)])
+                .to_dag_cbor().unwrap();
+            let right = Ipld::from(vec![Ipld::from(child_cid.clone()), Ipld::from(2)])
+                .to_dag_cbor().unwrap();
+            let left_cid = await!(repo.put_block(left.clone())).unwrap();
+            let right_cid = await!(repo.put_block(right.clone())).unwrap();
+            let root = Ipld::from(vec![Ipld::from(left_cid), Ipld::from(right_cid)])
+                .to_dag_cbor().unwrap();
+            let root_cid = await!(repo.put_block(root.clone())).unwrap();
+
+            let expected = root.size() + left.size() + right.size() + child.size();
+            let size = await!(repo.dag_size(&root_cid, MissingBlocks::Error)).unwrap();
+            assert_eq!(size, expected as u64);
+
+            await!(repo.remove_block(&child_cid)).unwrap();
+            assert!(await!(repo.dag_size(&root_cid, MissingBlocks::Error)).is_err());
+            let size = await!(repo.dag_size(&root_cid, MissingBlocks::Skip)).unwrap();
+            assert_eq!(size, (expected - child.size()) as u64);
+        });
+    }
+}
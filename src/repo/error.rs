@@ -0,0 +1,112 @@
+use crate::block::Cid;
+use libp2p::PeerId;
+
+#[derive(Debug)]
+pub enum RepoError {
+    BlockNotFound(Cid),
+    BlockPinned(Cid),
+    BlockCorrupted(Cid),
+    InvalidSignature(PeerId),
+    IpnsExpired(PeerId),
+    DagTooDeep(usize),
+    IpnsLoop(PeerId),
+    OutOfSpace,
+    FetchTimeout(Cid),
+    UnsupportedHash(u8),
+    Cancelled,
+    DatastoreUnavailable,
+    InvalidCid(String),
+    NotPinned(Cid),
+    PinnedRecursively(Cid),
+    UnsupportedVersion(u32),
+    MigrationRequired(u32),
+    RepoLocked(u32),
+}
+
+impl std::error::Error for RepoError {
+    fn description(&self) -> &str {
+        match *self {
+            RepoError::BlockNotFound(_) => "block not found",
+            RepoError::BlockPinned(_) => "block is pinned",
+            RepoError::BlockCorrupted(_) => "block is corrupted",
+            RepoError::InvalidSignature(_) => "invalid signature",
+            RepoError::IpnsExpired(_) => "ipns record expired",
+            RepoError::DagTooDeep(_) => "dag is too deep",
+            RepoError::IpnsLoop(_) => "ipns loop",
+            RepoError::OutOfSpace => "out of space",
+            RepoError::FetchTimeout(_) => "fetch timed out",
+            RepoError::UnsupportedHash(_) => "unsupported hash",
+            RepoError::Cancelled => "cancelled",
+            RepoError::DatastoreUnavailable => "datastore unavailable",
+            RepoError::InvalidCid(_) => "invalid cid",
+            RepoError::NotPinned(_) => "block is not pinned",
+            RepoError::PinnedRecursively(_) => "block is pinned recursively",
+            RepoError::UnsupportedVersion(_) => "unsupported repo version",
+            RepoError::MigrationRequired(_) => "repo needs to be migrated",
+            RepoError::RepoLocked(_) => "repo is locked",
+        }
+    }
+}
+
+impl std::fmt::Display for RepoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            RepoError::BlockNotFound(ref cid) => {
+                write!(f, "Block {} not found", cid.to_string())
+            }
+            RepoError::BlockPinned(ref cid) => {
+                write!(f, "Block {} is pinned", cid.to_string())
+            }
+            RepoError::BlockCorrupted(ref cid) => {
+                write!(f, "Block {} doesn't match its cid", cid.to_string())
+            }
+            RepoError::InvalidSignature(ref peer_id) => {
+                write!(f, "Record for {} has an invalid signature", peer_id.to_base58())
+            }
+            RepoError::IpnsExpired(ref peer_id) => {
+                write!(f, "Record for {} is no longer valid", peer_id.to_base58())
+            }
+            RepoError::DagTooDeep(max_depth) => {
+                write!(f, "Dag is deeper than {} links", max_depth)
+            }
+            RepoError::IpnsLoop(ref peer_id) => {
+                write!(f, "Record for {} resolves to itself", peer_id.to_base58())
+            }
+            RepoError::OutOfSpace => {
+                write!(f, "No space left on device")
+            }
+            RepoError::FetchTimeout(ref cid) => {
+                write!(f, "Timed out fetching block {}", cid.to_string())
+            }
+            RepoError::UnsupportedHash(code) => {
+                write!(f, "Unsupported multihash code {:#x}", code)
+            }
+            RepoError::Cancelled => {
+                write!(f, "Operation was cancelled")
+            }
+            RepoError::DatastoreUnavailable => {
+                write!(f, "Datastore failed to open")
+            }
+            RepoError::InvalidCid(ref cid) => {
+                write!(f, "Invalid cid {:?}", cid)
+            }
+            RepoError::NotPinned(ref cid) => {
+                write!(f, "Block {} is not pinned", cid.to_string())
+            }
+            RepoError::PinnedRecursively(ref cid) => {
+                write!(f, "Block {} is already pinned recursively", cid.to_string())
+            }
+            RepoError::UnsupportedVersion(version) => {
+                write!(f, "Repo version {} is newer than the supported version {}",
+                       version, crate::repo::REPO_VERSION)
+            }
+            RepoError::MigrationRequired(version) => {
+                write!(f, "Repo version {} needs to be migrated to version {}",
+                       version, crate::repo::REPO_VERSION)
+            }
+            RepoError::RepoLocked(pid) => {
+                write!(f, "Repo is used by the running process {}", pid)
+            }
+        }
+    }
+}
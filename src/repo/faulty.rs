@@ -0,0 +1,208 @@
+//! Failure injection for testing error handling
+use crate::block::{Cid, Block};
+use crate::error::Error;
+use crate::repo::{BlockStore, Layout, RetryPolicy, RocksTuning, Spawner, StoreStream};
+use futures::future::{self, FutureObj};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Decides which operations of a `FaultyBlockStore` fail.
+#[derive(Clone)]
+pub enum FaultPolicy {
+    /// Never fail.
+    Never,
+    /// Fail every `n`th operation.
+    EveryNth(usize),
+    /// Fail all operations on cids matching the predicate.
+    Matching(Arc<dyn Fn(&Cid) -> bool + Send + Sync>),
+    /// Fail operations with `probability`, using a random number
+    /// generator seeded with `seed`.
+    Random {
+        seed: u64,
+        probability: f64,
+    },
+}
+
+/// Block store that fails operations according to a `FaultPolicy` and
+/// delegates all other operations to the inner store.
+///
+/// The `contains`, `get`, `put` and `remove` operations are counted and
+/// can fail. Failures are `std::io::Error`s of kind `Interrupted` unless
+/// configured otherwise, which the retry policies treat as transient.
+#[derive(Clone)]
+pub struct FaultyBlockStore<S: BlockStore> {
+    inner: S,
+    policy: FaultPolicy,
+    kind: ErrorKind,
+    ops: Arc<AtomicUsize>,
+    injected: Arc<AtomicUsize>,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl<S: BlockStore> FaultyBlockStore<S> {
+    /// Fails operations of `inner` according to `policy`.
+    pub fn wrap(inner: S, policy: FaultPolicy) -> Self {
+        let seed = match policy {
+            FaultPolicy::Random { seed, .. } => seed,
+            _ => 0,
+        };
+        FaultyBlockStore {
+            inner,
+            policy,
+            kind: ErrorKind::Interrupted,
+            ops: Arc::new(AtomicUsize::new(0)),
+            injected: Arc::new(AtomicUsize::new(0)),
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    /// Fails with errors of `kind`.
+    pub fn with_error_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Returns the number of injected failures.
+    pub fn injected(&self) -> usize {
+        self.injected.load(Ordering::SeqCst)
+    }
+
+    /// Counts an operation on `cid`, returning an error if it fails.
+    fn fault(&self, cid: &Cid) -> Option<Error> {
+        let op = self.ops.fetch_add(1, Ordering::SeqCst) + 1;
+        let fail = match &self.policy {
+            FaultPolicy::Never => false,
+            FaultPolicy::EveryNth(n) => *n > 0 && op % n == 0,
+            FaultPolicy::Matching(predicate) => predicate(cid),
+            FaultPolicy::Random { probability, .. } => {
+                self.rng.lock().unwrap().gen_bool(*probability)
+            }
+        };
+        if !fail {
+            return None;
+        }
+        self.injected.fetch_add(1, Ordering::SeqCst);
+        Some(std::io::Error::new(self.kind, "injected fault").into())
+    }
+}
+
+impl<S: BlockStore> BlockStore for FaultyBlockStore<S> {
+    fn new(path: PathBuf) -> Self {
+        FaultyBlockStore::wrap(S::new(path), FaultPolicy::Never)
+    }
+
+    fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.inner = self.inner.with_spawner(spawner);
+        self
+    }
+
+    fn with_rocks_tuning(mut self, tuning: RocksTuning) -> Self {
+        self.inner = self.inner.with_rocks_tuning(tuning);
+        self
+    }
+
+    fn with_layout(mut self, layout: Layout) -> Self {
+        self.inner = self.inner.with_layout(layout);
+        self
+    }
+
+    fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.inner = self.inner.with_retry(retry);
+        self
+    }
+
+    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.init()
+    }
+
+    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.open()
+    }
+
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.inner.flush()
+    }
+
+    fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+        match self.fault(cid) {
+            Some(err) => FutureObj::new(Box::new(future::err(err))),
+            None => self.inner.contains(cid),
+        }
+    }
+
+    fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+        match self.fault(cid) {
+            Some(err) => FutureObj::new(Box::new(future::err(err))),
+            None => self.inner.get(cid),
+        }
+    }
+
+    fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+        match self.fault(block.cid()) {
+            Some(err) => FutureObj::new(Box::new(future::err(err))),
+            None => self.inner.put(block),
+        }
+    }
+
+    fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        match self.fault(cid) {
+            Some(err) => FutureObj::new(Box::new(future::err(err))),
+            None => self.inner.remove(cid),
+        }
+    }
+
+    fn list_stream(&self) -> StoreStream<Cid> {
+        self.inner.list_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::mem::MemBlockStore;
+    use crate::repo::retry::{is_transient, retry, RetryPolicy};
+    use std::env::temp_dir;
+    use std::time::Duration;
+
+    #[test]
+    fn test_retry_faulty_blockstore() {
+        let store = FaultyBlockStore::wrap(MemBlockStore::new(temp_dir()), FaultPolicy::EveryNth(2));
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            assert!(!await!(store.contains(block.cid())).unwrap());
+            let policy = RetryPolicy::new(3, Duration::from_millis(1));
+            let cid = await!(retry(policy, || store.put(block.clone()))).unwrap();
+            assert_eq!(store.injected(), 1);
+            assert_eq!(await!(store.inner.get(&cid)).unwrap(), Some(block));
+        });
+    }
+
+    #[test]
+    fn test_faulty_blockstore_policies() {
+        let bad = Block::from("bad").cid().to_owned();
+        let matching = {
+            let bad = bad.clone();
+            FaultPolicy::Matching(Arc::new(move |cid: &Cid| cid == &bad))
+        };
+        let store = FaultyBlockStore::wrap(MemBlockStore::new(temp_dir()), matching);
+        tokio::run_async(async move {
+            let err = await!(store.get(&bad)).unwrap_err();
+            assert!(is_transient(&err));
+            assert!(await!(store.get(Block::from("good").cid())).is_ok());
+        });
+
+        // the same seed fails the same operations
+        let random = FaultPolicy::Random { seed: 7, probability: 0.5 };
+        let failures = || {
+            let store = FaultyBlockStore::wrap(MemBlockStore::new(temp_dir()), random.clone());
+            (0..32).map(|_| store.fault(&bad).is_some()).collect::<Vec<_>>()
+        };
+        let first = failures();
+        assert!(first.contains(&true) && first.contains(&false));
+        assert_eq!(first, failures());
+    }
+}
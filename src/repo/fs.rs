@@ -1,52 +1,177 @@
 //! Persistent fs backed repo
-use crate::block::{Cid, Block};
+use crate::block::{Base, Bytes, Cid, Block};
 use crate::error::Error;
-use crate::repo::{BlockStore, Column, DataStore};
+use crate::repo::{init_columns, BlockStore, Column, DataStore, PutResult, RecoveryReport, RocksTuning, Spawner, StoreStream};
+use crate::repo::retry::{out_of_space, retry, RetryPolicy};
+#[cfg(feature = "metrics")]
+use crate::repo::OpStats;
+use core::future::Future;
 use futures::compat::*;
-use futures::future::FutureObj;
+use futures::future::{self, FutureObj};
+use futures::stream::StreamExt;
+use rustc_serialize::hex::ToHex;
 use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+use tokio::io::AsyncRead;
 use tokio::prelude::{Future as OldFuture, Stream as OldStream};
 use tokio::fs;
 
+/// How the block files are laid out on disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// All blocks are stored in one directory, named by their cid.
+    Flat,
+    /// The layout of the go-ipfs flatfs datastore. Blocks are named by
+    /// the base32 encoding of their cid and sharded with `next-to-last/2`.
+    GoFlatfs,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Flat
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct FsBlockStore {
     path: PathBuf,
     cids: Arc<Mutex<HashSet<Cid>>>,
+    retry: RetryPolicy,
+    layout: Layout,
+}
+
+impl FsBlockStore {
+    /// Rebuilds the index and the manifest by scanning the block files.
+    pub fn reindex(&self) -> FutureObj<'static, Result<(), Error>> {
+        let path = self.path.clone();
+        let cids = self.cids.clone();
+        let layout = self.layout;
+        FutureObj::new(Box::new(async move {
+            let mut scanned = HashSet::new();
+            await!(block_entries(path.clone(), layout).for_each(|entry| {
+                let path = entry.path();
+                if is_temp_file(&path) {
+                    // left over from an interrupted `put`
+                    debug!("removing stale temp file {:?}", path);
+                    std::fs::remove_file(&path)?;
+                } else if let Some(cid) = block_cid(&path, layout) {
+                    scanned.insert(cid);
+                }
+                Ok(())
+            }).compat())?;
+            await!(write_manifest(path, &scanned))?;
+            *cids.lock().unwrap() = scanned;
+            Ok(())
+        }))
+    }
 }
 
 impl BlockStore for FsBlockStore {
     fn new(path: PathBuf) -> Self {
         FsBlockStore {
             path,
-            cids: Arc::new(Mutex::new(HashSet::new()))
+            cids: Arc::new(Mutex::new(HashSet::new())),
+            retry: RetryPolicy::default(),
+            layout: Layout::default(),
         }
     }
 
+    fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
     fn init(&self) -> FutureObj<'static, Result<(), Error>> {
         let path = self.path.clone();
+        let layout = self.layout;
         FutureObj::new(Box::new(async move {
-            await!(fs::create_dir_all(path).compat())?;
-            Ok(())
+            await!(fs::create_dir_all(path.clone()).compat())?;
+            check_sharding(&path, layout)
         }))
     }
 
     fn open(&self) -> FutureObj<'static, Result<(), Error>> {
-        let path = self.path.clone();
-        let cids = self.cids.clone();
+        let store = self.clone();
         FutureObj::new(Box::new(async move {
-            await!(fs::read_dir(path).flatten_stream().for_each(|dir| {
-                let path = dir.path();
-                if path.extension() == Some(OsStr::new("data")) {
-                    let cid_str = path.file_stem().unwrap();
-                    let cid = Cid::from(cid_str.to_str().unwrap()).unwrap();
-                    cids.lock().unwrap().insert(cid);
+            check_sharding(&store.path, store.layout)?;
+            let mut temp_dir = store.path.clone();
+            temp_dir.push(TEMP_DIR);
+            if temp_dir.exists() {
+                // left over from interrupted `put`s
+                debug!("removing stale temp files in {:?}", temp_dir);
+                std::fs::remove_dir_all(&temp_dir)?;
+            }
+            if let Some(cids) = await!(read_manifest(store.path.clone()))? {
+                let valid = cids.iter().take(MANIFEST_SAMPLES).all(|cid| {
+                    block_path(store.path.clone(), store.layout, cid).exists()
+                });
+                if valid {
+                    *store.cids.lock().unwrap() = cids;
+                    return Ok(());
                 }
-                Ok(())
-            }).compat())?;
-            Ok(())
+                debug!("manifest in {:?} is stale", store.path);
+            }
+            await!(store.reindex())
+        }))
+    }
+
+    /// Verifies every block file instead of trusting the manifest.
+    ///
+    /// Files that don't match their cid are moved to the `quarantine`
+    /// directory and blocks stored in another layout are moved to the
+    /// layout of the store.
+    fn open_with_recovery(&self) -> FutureObj<'static, Result<RecoveryReport, Error>> {
+        let store = self.clone();
+        FutureObj::new(Box::new(async move {
+            let mut temp_dir = store.path.clone();
+            temp_dir.push(TEMP_DIR);
+            if temp_dir.exists() {
+                std::fs::remove_dir_all(&temp_dir)?;
+            }
+            let mut report = RecoveryReport::default();
+            let mut cids = HashSet::new();
+            for (path, layout) in layout_files(&store.path)? {
+                if path.extension() != Some(OsStr::new("data")) {
+                    continue;
+                }
+                let cid = match block_cid(&path, layout) {
+                    Some(cid) => cid,
+                    None => {
+                        report.quarantined.push(quarantine(&store.path, &path)?);
+                        continue;
+                    }
+                };
+                let block = match await!(read_block(path.clone(), cid.clone()))? {
+                    Some(block) => block,
+                    None => continue,
+                };
+                if !block.is_valid() {
+                    report.quarantined.push(quarantine(&store.path, &path)?);
+                    continue;
+                }
+                if layout != store.layout {
+                    // left over from an interrupted migration
+                    let dest = block_path(store.path.clone(), store.layout, &cid);
+                    std::fs::create_dir_all(dest.parent().unwrap())?;
+                    std::fs::rename(&path, &dest)?;
+                    report.migrated += 1;
+                }
+                report.recovered += 1;
+                cids.insert(cid);
+            }
+            await!(write_manifest(store.path.clone(), &cids))?;
+            *store.cids.lock().unwrap() = cids;
+            Ok(report)
         }))
     }
 
@@ -54,75 +179,251 @@ impl BlockStore for FsBlockStore {
         let contains = self.cids.lock().unwrap().contains(cid);
         FutureObj::new(Box::new(async move {
             Ok(contains)
-        })
+        }))
     }
 
     fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
-        let path = block_path(self.path.clone(), cid);
+        let path = block_path(self.path.clone(), self.layout, cid);
         let cid = cid.to_owned();
+        let policy = self.retry;
         FutureObj::new(Box::new(async move {
-            let file = match await!(fs::File::open(path).compat()) {
-                Ok(file) => file,
-                Err(err) => {
-                    if err.kind() == std::io::ErrorKind::NotFound {
-                        return Ok(None);
-                    } else {
-                        return Err(err.into());
-                    }
-                }
-            };
-            let (_, data) = await!(tokio::io::read_to_end(file, Vec::new()).compat())?;
-            let block = Block::new(data, cid);
-            Ok(Some(block))
+            await!(retry(policy, || read_block(path.clone(), cid.clone())))
+        }))
+    }
+
+    fn get_data(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Bytes>, Error>> {
+        let path = block_path(self.path.clone(), self.layout, cid);
+        let policy = self.retry;
+        FutureObj::new(Box::new(async move {
+            let data = await!(retry(policy, || read_data(path.clone())))?;
+            Ok(data.map(Bytes::from))
         }))
     }
 
     fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
-        let path = block_path(self.path.clone(), &block.cid());
+        let path = block_path(self.path.clone(), self.layout, &block.cid());
+        let tmp_path = temp_path(self.path.clone(), &path);
+        let base = self.path.clone();
         let cids = self.cids.clone();
+        let policy = self.retry;
         FutureObj::new(Box::new(async move {
-            let file = await!(fs::File::create(path).compat())?;
-            let data = block.data();
-            await!(tokio::io::write_all(file, &*data).compat())?;
-            cids.lock().unwrap().insert(block.cid().to_owned());
+            await!(retry(policy, || {
+                write_block(path.clone(), tmp_path.clone(), block.clone())
+            })).map_err(out_of_space)?;
+            let inserted = cids.lock().unwrap().insert(block.cid().to_owned());
+            if inserted {
+                await!(append_manifest(base, '+', block.cid())).map_err(out_of_space)?;
+            }
             Ok(block.cid().to_owned())
         }))
     }
 
+    fn put_checked(&self, block: Block) -> FutureObj<'static, Result<PutResult, Error>> {
+        if self.cids.lock().unwrap().contains(block.cid()) {
+            let put = PutResult::new(block.cid().to_owned(), false);
+            return FutureObj::new(Box::new(future::ok(put)));
+        }
+        let put = self.put(block);
+        FutureObj::new(Box::new(async move {
+            Ok(PutResult::new(await!(put)?, true))
+        }))
+    }
+
     fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
-        let path = block_path(self.path.clone(), cid);
+        let path = block_path(self.path.clone(), self.layout, cid);
         let cid = cid.to_owned();
+        let base = self.path.clone();
         let cids = self.cids.clone();
         let contains = self.contains(&cid);
+        let policy = self.retry;
         FutureObj::new(Box::new(async move {
             if await!(contains)? {
-                await!(fs::remove_file(path).compat())?;
+                await!(retry(policy, || remove_file(path.clone())))?;
                 cids.lock().unwrap().remove(&cid);
+                await!(append_manifest(base, '-', &cid))?;
+            }
+            Ok(())
+        }))
+    }
+
+    fn snapshot(&self, dest: PathBuf) -> FutureObj<'static, Result<(), Error>> {
+        // blocks are immutable, so hard linking the blocks that are
+        // currently stored gives a consistent copy.
+        let cids: Vec<Cid> = self.cids.lock().unwrap().iter().cloned().collect();
+        let path = self.path.clone();
+        let layout = self.layout;
+        FutureObj::new(Box::new(async move {
+            await!(fs::create_dir_all(dest.clone()).compat())?;
+            for cid in cids {
+                let src = block_path(path.clone(), layout, &cid);
+                let dst = block_path(dest.clone(), layout, &cid);
+                await!(fs::create_dir_all(dst.parent().unwrap().to_owned()).compat())?;
+                match await!(fs::hard_link(src, dst).compat()) {
+                    Ok(()) => {}
+                    // removed since taking the snapshot
+                    Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
             }
             Ok(())
         }))
     }
+
+    fn list_stream(&self) -> StoreStream<Cid> {
+        let layout = self.layout;
+        let stream = block_entries(self.path.clone(), layout)
+            .compat()
+            .filter_map(move |entry| {
+                future::ready(match entry {
+                    Ok(entry) => block_cid(&entry.path(), layout).map(Ok),
+                    Err(err) => Some(Err(err.into())),
+                })
+            });
+        Box::pin(stream)
+    }
+
+    fn block_size(&self, cid: &Cid) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        let path = block_path(self.path.clone(), self.layout, cid);
+        FutureObj::new(Box::new(async move {
+            match await!(fs::metadata(path).compat()) {
+                Ok(metadata) => Ok(Some(metadata.len())),
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        }))
+    }
+
+    fn available_space(&self) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        let path = self.path.clone();
+        FutureObj::new(Box::new(async move {
+            Ok(available_space(&path)?)
+        }))
+    }
+
+    #[cfg(feature = "metrics")]
+    fn get_stat(&self, cid: &Cid) ->
+        FutureObj<'static, Result<(Option<Block>, OpStats), Error>>
+    {
+        let start = Instant::now();
+        let future = self.get(cid);
+        FutureObj::new(Box::new(async move {
+            let block = await!(future)?;
+            let mut stats = OpStats::default();
+            stats.disk_reads = 1;
+            match block {
+                Some(ref block) => {
+                    stats.hits = 1;
+                    stats.bytes_read = block.size() as u64;
+                }
+                None => stats.misses = 1,
+            }
+            stats.elapsed = start.elapsed();
+            Ok((block, stats))
+        }))
+    }
+}
+
+/// Controls how writes to the `RocksDataStore` are persisted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Durability {
+    /// Appends writes to the write-ahead log, which is replayed on `open`
+    /// after a crash.
+    pub wal: bool,
+    /// Syncs the write-ahead log to disk on every write.
+    pub sync: bool,
+    /// Checkpoints the write-ahead log once this many bytes were written
+    /// to it since the last checkpoint. This bounds the size of the log
+    /// and the time it takes to replay it.
+    pub max_wal_size: usize,
+}
+
+/// Default of `Durability::max_wal_size`.
+pub const DEFAULT_MAX_WAL_SIZE: usize = 64 * 1024 * 1024;
+
+impl Default for Durability {
+    fn default() -> Self {
+        Durability {
+            wal: true,
+            sync: false,
+            max_wal_size: DEFAULT_MAX_WAL_SIZE,
+        }
+    }
+}
+
+impl Durability {
+    fn write_options(&self) -> rocksdb::WriteOptions {
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.disable_wal(!self.wal);
+        opts.set_sync(self.sync);
+        opts
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct RocksDataStore {
     path: PathBuf,
     db: Arc<Mutex<Option<rocksdb::DB>>>,
+    retry: RetryPolicy,
+    durability: Durability,
+    // bytes written to the write-ahead log since the last checkpoint
+    wal_size: Arc<AtomicUsize>,
+    tuning: RocksTuning,
+    spawner: Spawner,
 }
 
 impl RocksDataStore {
+    /// Moves all writes from the write-ahead log into the main store,
+    /// allowing the log to be truncated.
+    pub fn checkpoint_wal(&self) -> FutureObj<'static, Result<(), Error>> {
+        let db = self.db.clone();
+        let wal_size = self.wal_size.clone();
+        FutureObj::new(Box::new(async move {
+            wal_size.store(0, Ordering::SeqCst);
+            let db = db.lock().unwrap();
+            let db = db.as_ref().unwrap();
+            // `flush` only flushes the default column family
+            for col in Column::all() {
+                let cf = db.cf_handle(col.name())
+                    .ok_or_else(|| format_err!("missing column family {}", col.name()))?;
+                db.flush_cf(cf)?;
+            }
+            Ok(())
+        }))
+    }
+
+    /// Counts a write of `size` bytes to the write-ahead log, returning a
+    /// checkpoint if the log grew beyond `Durability::max_wal_size`.
+    fn log_write(&self, size: usize) -> Option<FutureObj<'static, Result<(), Error>>> {
+        if !self.durability.wal {
+            return None;
+        }
+        let logged = self.wal_size.fetch_add(size, Ordering::SeqCst) + size;
+        if logged > self.durability.max_wal_size {
+            Some(self.checkpoint_wal())
+        } else {
+            None
+        }
+    }
+
     fn get_cf(&self, col: Column) -> rocksdb::ColumnFamily {
-        let cf_name = match col {
-            Column::Ipns => "ipns"
-        };
         self.db.lock()
             .unwrap()
             .as_ref()
             .unwrap()
-            .cf_handle(cf_name)
+            .cf_handle(col.name())
             // TODO safe to unwrap
             .unwrap()
     }
+
+    /// Streamed values are too large for rocksdb and are stored as
+    /// files in the `streams` directory instead.
+    fn stream_path(&self, col: Column, key: &[u8]) -> PathBuf {
+        let mut path = self.path.clone();
+        path.push("streams");
+        path.push(col.name());
+        path.push(key.to_hex());
+        path
+    }
 }
 
 impl DataStore for RocksDataStore {
@@ -130,27 +431,67 @@ impl DataStore for RocksDataStore {
         RocksDataStore {
             path,
             db: Arc::new(Mutex::new(None)),
+            retry: RetryPolicy::default(),
+            durability: Durability::default(),
+            wal_size: Arc::new(AtomicUsize::new(0)),
+            tuning: RocksTuning::default(),
+            spawner: Spawner::default(),
         }
     }
 
+    fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    fn with_rocks_tuning(mut self, tuning: RocksTuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+
+    fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
     fn init(&self) -> FutureObj<'static, Result<(), Error>> {
-        FutureObj::new(Box::new(futures::future::ok(())))
+        let store = self.clone();
+        let spawner = self.spawner.clone();
+        FutureObj::new(Box::new(init_columns(spawner, move |col| store.init_column(col))))
+    }
+
+    fn init_column(&self, col: Column) -> FutureObj<'static, Result<(), Error>> {
+        let mut path = self.path.clone();
+        path.push("streams");
+        path.push(col.name());
+        FutureObj::new(Box::new(async move {
+            await!(fs::create_dir_all(path).compat())?;
+            Ok(())
+        }))
     }
 
     fn open(&self) -> FutureObj<'static, Result<(), Error>> {
         let db = self.db.clone();
         let path = self.path.clone();
+        let tuning = self.tuning;
         FutureObj::new(Box::new(async move {
-            let mut db_opts = rocksdb::Options::default();
+            let mut db_opts = tuning.options();
             db_opts.create_missing_column_families(true);
             db_opts.create_if_missing(true);
 
-            let ipns_opts = rocksdb::Options::default();
-            let ipns_cf = rocksdb::ColumnFamilyDescriptor::new("ipns", ipns_opts);
+            let cfs = Column::all().iter().map(|col| {
+                let cf_opts = tuning.options();
+                rocksdb::ColumnFamilyDescriptor::new(col.name(), cf_opts)
+            }).collect();
             let rdb = rocksdb::DB::open_cf_descriptors(
                 &db_opts,
                 &path,
-                vec![ipns_cf],
+                cfs,
             )?;
             *db.lock().unwrap() = Some(rdb);
             Ok(())
@@ -162,12 +503,21 @@ impl DataStore for RocksDataStore {
     {
         let cf = self.get_cf(col);
         let db = self.db.clone();
+        let stream = self.stream_path(col, key);
         let key = key.to_owned();
-        FutureObj::new(Box::new(async move {
+        let policy = self.retry;
+        let contains = retry(policy, move || {
             let db = db.lock().unwrap();
             let db = db.as_ref().unwrap();
-            let contains = db.get_cf(cf, &key)?.is_some();
-            Ok(contains)
+            future::ready(db.get_cf(cf, &key)
+                .map(|value| value.is_some())
+                .map_err(Into::into))
+        });
+        FutureObj::new(Box::new(async move {
+            if await!(contains)? {
+                return Ok(true);
+            }
+            await!(file_exists(stream))
         }))
     }
 
@@ -177,12 +527,14 @@ impl DataStore for RocksDataStore {
         let cf = self.get_cf(col);
         let db = self.db.clone();
         let key = key.to_owned();
-        FutureObj::new(Box::new(async move {
+        let policy = self.retry;
+        FutureObj::new(Box::new(retry(policy, move || {
             let db = db.lock().unwrap();
             let db = db.as_ref().unwrap();
-            let get = db.get_cf(cf, &key)?.map(|value| value.to_vec());
-            Ok(get)
-        }))
+            future::ready(db.get_cf(cf, &key)
+                .map(|value| value.map(|value| value.to_vec()))
+                .map_err(Into::into))
+        })))
     }
 
     fn put(&self, col: Column, key: &[u8], value: &[u8]) ->
@@ -190,12 +542,21 @@ impl DataStore for RocksDataStore {
     {
         let cf = self.get_cf(col);
         let db = self.db.clone();
+        let checkpoint = self.log_write(key.len() + value.len());
         let key = key.to_owned();
         let value = value.to_owned();
+        let policy = self.retry;
+        let durability = self.durability;
         FutureObj::new(Box::new(async move {
-            let db = db.lock().unwrap();
-            let db = db.as_ref().unwrap();
-            db.put_cf(cf, &key, &value)?;
+            await!(retry(policy, move || {
+                let db = db.lock().unwrap();
+                let db = db.as_ref().unwrap();
+                let opts = durability.write_options();
+                future::ready(db.put_cf_opt(cf, &key, &value, &opts).map_err(Into::into))
+            })).map_err(out_of_space)?;
+            if let Some(checkpoint) = checkpoint {
+                await!(checkpoint)?;
+            }
             Ok(())
         }))
     }
@@ -205,80 +566,569 @@ impl DataStore for RocksDataStore {
     {
         let cf = self.get_cf(col);
         let db = self.db.clone();
+        let stream = self.stream_path(col, key);
+        let checkpoint = self.log_write(key.len());
         let key = key.to_owned();
-        FutureObj::new(Box::new(async move {
+        let policy = self.retry;
+        let durability = self.durability;
+        let remove = retry(policy, move || {
             let db = db.lock().unwrap();
             let db = db.as_ref().unwrap();
-            db.delete_cf(cf, &key)?;
+            let opts = durability.write_options();
+            future::ready(db.delete_cf_opt(cf, &key, &opts).map_err(Into::into))
+        });
+        FutureObj::new(Box::new(async move {
+            await!(remove)?;
+            await!(remove_stream(stream))?;
+            if let Some(checkpoint) = checkpoint {
+                await!(checkpoint)?;
+            }
             Ok(())
         }))
     }
-}
 
-fn block_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
-    let mut file = cid.to_string();
-    file.push_str(".data");
-    base.push(file);
-    base
-}
+    fn remove_many(&self, col: Column, keys: Vec<Vec<u8>>) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        let cf = self.get_cf(col);
+        let db = self.db.clone();
+        let streams: Vec<PathBuf> = keys.iter().map(|key| self.stream_path(col, key)).collect();
+        let checkpoint = self.log_write(keys.iter().map(Vec::len).sum());
+        let policy = self.retry;
+        let durability = self.durability;
+        let remove = retry(policy, move || {
+            let db = db.lock().unwrap();
+            let db = db.as_ref().unwrap();
+            let opts = durability.write_options();
+            let mut batch = rocksdb::WriteBatch::default();
+            for key in &keys {
+                if let Err(err) = batch.delete_cf(cf, key) {
+                    return future::ready(Err(err.into()));
+                }
+            }
+            future::ready(db.write_opt(batch, &opts).map_err(Into::into))
+        });
+        FutureObj::new(Box::new(async move {
+            await!(remove)?;
+            for stream in streams {
+                await!(remove_stream(stream))?;
+            }
+            if let Some(checkpoint) = checkpoint {
+                await!(checkpoint)?;
+            }
+            Ok(())
+        }))
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::env::temp_dir;
+    fn list_keys(&self, col: Column) ->
+        FutureObj<'static, Result<Vec<Vec<u8>>, Error>>
+    {
+        let cf = self.get_cf(col);
+        let db = self.db.clone();
+        let policy = self.retry;
+        FutureObj::new(Box::new(retry(policy, move || {
+            let db = db.lock().unwrap();
+            let db = db.as_ref().unwrap();
+            future::ready(db.iterator_cf(cf, rocksdb::IteratorMode::Start)
+                .map(|iter| iter.map(|(key, _)| key.to_vec()).collect())
+                .map_err(Into::into))
+        })))
+    }
 
-    #[test]
-    fn test_fs_blockstore() {
-        let mut tmp = temp_dir();
-        tmp.push("blockstore1");
-        std::fs::remove_dir_all(tmp.clone()).ok();
-        let store = FsBlockStore::new(tmp.clone());
+    fn snapshot(&self, dest: PathBuf) -> FutureObj<'static, Result<(), Error>> {
+        let db = self.db.clone();
+        let mut streams = self.path.clone();
+        streams.push("streams");
+        FutureObj::new(Box::new(async move {
+            {
+                let db = db.lock().unwrap();
+                let db = db.as_ref().unwrap();
+                let checkpoint = rocksdb::checkpoint::Checkpoint::new(db)?;
+                checkpoint.create_checkpoint(&dest)?;
+            }
+            if streams.exists() {
+                let mut dest = dest;
+                dest.push("streams");
+                copy_dir(&streams, &dest)?;
+            }
+            Ok(())
+        }))
+    }
 
-        tokio::run_async(async move {
-            let block = Block::from("1");
-            let cid = block.cid();
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        self.checkpoint_wal()
+    }
 
-            assert_eq!(await!(store.init()).unwrap(), ());
-            assert_eq!(await!(store.open()).unwrap(), ());
+    fn compact(&self, col: Column) -> FutureObj<'static, Result<(), Error>> {
+        let cf = self.get_cf(col);
+        let db = self.db.clone();
+        FutureObj::new(Box::new(async move {
+            let db = db.lock().unwrap();
+            let db = db.as_ref().unwrap();
+            // move the memtable into sst files, so that the compaction
+            // sees all writes and the write-ahead log can be truncated
+            db.flush_cf(cf)?;
+            db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+            Ok(())
+        }))
+    }
 
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), None);
-            let remove = store.remove(cid);
-            assert_eq!(await!(remove).unwrap(), ());
+    fn get_stream(&self, col: Column, key: &[u8]) ->
+        FutureObj<'static, Result<Option<Box<dyn AsyncRead + Send>>, Error>>
+    {
+        let path = self.stream_path(col, key);
+        FutureObj::new(Box::new(async move {
+            match await!(fs::File::open(path).compat()) {
+                Ok(file) => {
+                    let reader: Box<dyn AsyncRead + Send> = Box::new(file);
+                    Ok(Some(reader))
+                }
+                Err(err) => {
+                    if err.kind() == std::io::ErrorKind::NotFound {
+                        Ok(None)
+                    } else {
+                        Err(err.into())
+                    }
+                }
+            }
+        }))
+    }
 
-            let put = store.put(block.clone());
-            assert_eq!(await!(put).unwrap(), cid.to_owned());
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), true);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), Some(block.clone()));
+    fn put_stream(&self, col: Column, key: &[u8], value: Box<dyn AsyncRead + Send>) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        let path = self.stream_path(col, key);
+        let mut tmp_path = path.clone();
+        // concurrent writes of the same key need their own temp files
+        tmp_path.set_extension(format!("{:x}.tmp", rand::random::<u64>()));
+        FutureObj::new(Box::new(async move {
+            await!(write_stream(path, tmp_path, value)).map_err(out_of_space)
+        }))
+    }
+}
 
-            let remove = store.remove(cid);
-            assert_eq!(await!(remove).unwrap(), ());
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), None);
-        });
+pub(crate) fn write_stream(path: PathBuf, tmp_path: PathBuf, value: Box<dyn AsyncRead + Send>) ->
+impl Future<Output=Result<(), Error>>
+{
+    async move {
+        await!(fs::create_dir_all(path.parent().unwrap().to_owned()).compat())?;
+        let file = await!(fs::File::create(tmp_path.clone()).compat())?;
+        await!(tokio::io::copy(value, file).compat())?;
+        await!(fs::rename(tmp_path, path).compat())?;
+        Ok(())
+    }
+}
 
-        std::fs::remove_dir_all(tmp).ok();
+/// Returns `true` if there is a file at `path`.
+pub(crate) fn file_exists(path: PathBuf) -> impl Future<Output=Result<bool, Error>> {
+    async move {
+        match await!(fs::metadata(path).compat()) {
+            Ok(_) => Ok(true),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
     }
+}
 
-    #[test]
-    fn test_fs_blockstore_open() {
-        let mut tmp = temp_dir();
-        tmp.push("blockstore2");
-        std::fs::remove_dir_all(tmp.clone()).ok();
+/// Removes the file of a streamed value, if there is one.
+pub(crate) fn remove_stream(path: PathBuf) -> impl Future<Output=Result<(), Error>> {
+    async move {
+        match await!(fs::remove_file(path).compat()) {
+            Ok(()) => Ok(()),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
 
-        let blockstore_path = tmp.clone();
-        tokio::run_async(async move {
-            let block = Block::from("1");
+fn read_block(path: PathBuf, cid: Cid) ->
+impl Future<Output=Result<Option<Block>, Error>>
+{
+    async move {
+        Ok(await!(read_data(path))?.map(|data| Block::new(data, cid)))
+    }
+}
 
-            let block_store = FsBlockStore::new(blockstore_path.clone());
-            await!(block_store.init()).unwrap();
-            await!(block_store.open()).unwrap();
+fn read_data(path: PathBuf) -> impl Future<Output=Result<Option<Vec<u8>>, Error>> {
+    async move {
+        let file = match await!(fs::File::open(path).compat()) {
+            Ok(file) => file,
+            Err(err) => {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    return Ok(None);
+                } else {
+                    return Err(err.into());
+                }
+            }
+        };
+        let (_, data) = await!(tokio::io::read_to_end(file, Vec::new()).compat())?;
+        Ok(Some(data))
+    }
+}
+
+fn write_block(path: PathBuf, tmp_path: PathBuf, block: Block) ->
+impl Future<Output=Result<(), Error>>
+{
+    async move {
+        // sharded layouts store blocks in subdirectories
+        await!(fs::create_dir_all(path.parent().unwrap().to_owned()).compat())?;
+        await!(fs::create_dir_all(tmp_path.parent().unwrap().to_owned()).compat())?;
+        // write to a temp file first so that a crash never leaves a
+        // truncated block behind.
+        let file = await!(fs::File::create(tmp_path.clone()).compat())?;
+        let data = block.data();
+        await!(tokio::io::write_all(file, &*data).compat())?;
+        await!(fs::rename(tmp_path, path).compat())?;
+        Ok(())
+    }
+}
+
+fn remove_file(path: PathBuf) -> impl Future<Output=Result<(), Error>> {
+    async move {
+        await!(fs::remove_file(path).compat())?;
+        Ok(())
+    }
+}
+
+/// Returns the space available to unprivileged users on the filesystem
+/// containing `path`.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Result<Option<u64>, std::io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Result<Option<u64>, std::io::Error> {
+    Ok(None)
+}
+
+/// Recursively copies the directory `src` to `dest`.
+fn copy_dir(src: &Path, dest: &Path) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(dest)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let mut to = dest.to_owned();
+        to.push(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to)?;
+        } else if !is_temp_file(&entry.path()) {
+            std::fs::copy(entry.path(), to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prefix reserved for temp files, no block file name starts with it.
+const TEMP_PREFIX: &str = ".tmp-";
+
+/// Directory for temp files, it is cleared on `open`.
+const TEMP_DIR: &str = ".tmp";
+
+/// Directory for damaged block files found by `open_with_recovery`.
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// File listing the stored cids, so that `open` doesn't have to scan all
+/// block files.
+///
+/// Each line adds (`+<cid>`) or removes (`-<cid>`) a cid.
+const MANIFEST_FILE: &str = "manifest";
+
+/// Number of manifest entries checked against the block files on `open`.
+const MANIFEST_SAMPLES: usize = 16;
+
+/// File go-ipfs flatfs reads the sharding function from.
+const SHARDING_FILE: &str = "SHARDING";
+
+/// Sharding function of `Layout::GoFlatfs`.
+const SHARDING: &str = "/repo/flatfs/shard/v1/next-to-last/2";
+
+/// Writes the sharding spec of a go-ipfs flatfs store or checks that an
+/// existing one matches the layout.
+fn check_sharding(base: &Path, layout: Layout) -> Result<(), Error> {
+    if layout != Layout::GoFlatfs {
+        return Ok(());
+    }
+    let mut path = base.to_owned();
+    path.push(SHARDING_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(ref spec) if spec.trim() == SHARDING => Ok(()),
+        Ok(spec) => bail!("unsupported flatfs sharding {}", spec.trim()),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+            std::fs::write(&path, format!("{}\n", SHARDING))?;
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn manifest_path(mut base: PathBuf) -> PathBuf {
+    base.push(MANIFEST_FILE);
+    base
+}
+
+fn read_manifest(base: PathBuf) ->
+impl Future<Output=Result<Option<HashSet<Cid>>, Error>>
+{
+    async move {
+        let file = match await!(fs::File::open(manifest_path(base)).compat()) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let (_, data) = await!(tokio::io::read_to_end(file, Vec::new()).compat())?;
+        let mut cids = HashSet::new();
+        for line in String::from_utf8_lossy(&data).lines() {
+            if line.is_empty() {
+                continue;
+            }
+            let add = line.starts_with('+');
+            if !add && !line.starts_with('-') {
+                return Ok(None);
+            }
+            // a truncated last line fails to parse, the manifest is
+            // rebuilt in that case
+            let cid = match Cid::from(&line[1..]) {
+                Ok(cid) => cid,
+                Err(_) => return Ok(None),
+            };
+            if add {
+                cids.insert(cid);
+            } else {
+                cids.remove(&cid);
+            }
+        }
+        Ok(Some(cids))
+    }
+}
+
+fn write_manifest(base: PathBuf, cids: &HashSet<Cid>) ->
+impl Future<Output=Result<(), Error>>
+{
+    let mut data = String::new();
+    for cid in cids {
+        data.push('+');
+        data.push_str(&cid.to_string());
+        data.push('\n');
+    }
+    let path = manifest_path(base);
+    let mut tmp_path = path.clone();
+    tmp_path.set_extension("tmp");
+    async move {
+        let file = await!(fs::File::create(tmp_path.clone()).compat())?;
+        await!(tokio::io::write_all(file, data).compat())?;
+        await!(fs::rename(tmp_path, path).compat())?;
+        Ok(())
+    }
+}
+
+fn append_manifest(base: PathBuf, op: char, cid: &Cid) ->
+impl Future<Output=Result<(), Error>>
+{
+    let line = format!("{}{}\n", op, cid.to_string());
+    async move {
+        let file = await!(fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(manifest_path(base))
+            .compat())?;
+        await!(tokio::io::write_all(file, line).compat())?;
+        Ok(())
+    }
+}
+
+type DirEntries = Box<dyn OldStream<Item=fs::DirEntry, Error=std::io::Error> + Send>;
+
+/// Returns the entries of the directories that contain blocks.
+fn block_entries(path: PathBuf, layout: Layout) -> DirEntries {
+    let entries = fs::read_dir(path).flatten_stream();
+    match layout {
+        Layout::Flat => Box::new(entries),
+        Layout::GoFlatfs => Box::new(entries
+            .filter(|entry| entry.path().is_dir() && !is_reserved_dir(&entry.path()))
+            .map(|entry| fs::read_dir(entry.path()).flatten_stream())
+            .flatten()),
+    }
+}
+
+/// Returns all files that may be blocks together with the layout they
+/// are stored in.
+fn layout_files(base: &Path) -> Result<Vec<(PathBuf, Layout)>, std::io::Error> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(base)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            files.push((path, Layout::Flat));
+            continue;
+        }
+        if is_reserved_dir(&path) {
+            continue;
+        }
+        for entry in std::fs::read_dir(&path)? {
+            files.push((entry?.path(), Layout::GoFlatfs));
+        }
+    }
+    Ok(files)
+}
+
+/// Returns `true` for the directories of the store that are not shards.
+fn is_reserved_dir(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str());
+    name == Some(TEMP_DIR) || name == Some(QUARANTINE_DIR)
+}
+
+/// Moves a damaged block file to the quarantine directory.
+fn quarantine(base: &Path, path: &Path) -> Result<PathBuf, std::io::Error> {
+    let mut dest = base.to_owned();
+    dest.push(QUARANTINE_DIR);
+    std::fs::create_dir_all(&dest)?;
+    dest.push(path.file_name().unwrap());
+    std::fs::rename(path, &dest)?;
+    Ok(dest)
+}
+
+fn block_path(mut base: PathBuf, layout: Layout, cid: &Cid) -> PathBuf {
+    match layout {
+        Layout::Flat => {
+            let mut file = cid.to_string();
+            file.push_str(".data");
+            base.push(file);
+        }
+        Layout::GoFlatfs => {
+            // drop the multibase prefix
+            let mut file = multibase::encode(Base::Base32Upper, cid.to_bytes())[1..].to_string();
+            // `next-to-last/2` shards by the two characters before the last
+            let shard = file[file.len() - 3..file.len() - 1].to_string();
+            file.push_str(".data");
+            base.push(shard);
+            base.push(file);
+        }
+    }
+    base
+}
+
+fn block_cid(path: &Path, layout: Layout) -> Option<Cid> {
+    if is_temp_file(path) || path.extension() != Some(OsStr::new("data")) {
+        return None;
+    }
+    let name = path.file_stem()?.to_str()?;
+    match layout {
+        Layout::Flat => Cid::from(name).ok(),
+        Layout::GoFlatfs => {
+            let (_, bytes) = multibase::decode(format!("B{}", name)).ok()?;
+            Cid::from(bytes).ok()
+        }
+    }
+}
+
+/// Returns a new temp file path for writing the block file `block_path`.
+///
+/// The random suffix keeps concurrent writes of the same block apart.
+fn temp_path(mut base: PathBuf, block_path: &Path) -> PathBuf {
+    let mut file = TEMP_PREFIX.to_string();
+    file.push_str(block_path.file_name().unwrap().to_str().unwrap());
+    file.push_str(&format!(".{:x}", rand::random::<u64>()));
+    base.push(TEMP_DIR);
+    base.push(file);
+    base
+}
+
+fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with(TEMP_PREFIX))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::testsuite::{run_block_store_tests, run_data_store_tests};
+    use std::env::temp_dir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns a function creating new empty directories named `name-<n>`.
+    fn temp_dirs(name: &'static str) -> impl Fn() -> PathBuf {
+        let count = AtomicUsize::new(0);
+        move || {
+            let mut tmp = temp_dir();
+            tmp.push(format!("{}-{}", name, count.fetch_add(1, Ordering::SeqCst)));
+            std::fs::remove_dir_all(tmp.clone()).ok();
+            tmp
+        }
+    }
+
+    #[test]
+    fn test_fs_blockstore_suite() {
+        let dirs = temp_dirs("blockstore-suite");
+        run_block_store_tests(|| FsBlockStore::new(dirs()));
+    }
+
+    #[test]
+    fn test_rocks_datastore_suite() {
+        let dirs = temp_dirs("datastore-suite");
+        run_data_store_tests(|| RocksDataStore::new(dirs()));
+    }
+
+    #[test]
+    fn test_fs_blockstore() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore1");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = FsBlockStore::new(tmp.clone());
+
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            let cid = block.cid();
+
+            assert_eq!(await!(store.init()).unwrap(), ());
+            assert_eq!(await!(store.open()).unwrap(), ());
+
+            let contains = store.contains(cid);
+            assert_eq!(await!(contains).unwrap(), false);
+            let get = store.get(cid);
+            assert_eq!(await!(get).unwrap(), None);
+            let remove = store.remove(cid);
+            assert_eq!(await!(remove).unwrap(), ());
+
+            let put = store.put(block.clone());
+            assert_eq!(await!(put).unwrap(), cid.to_owned());
+            let contains = store.contains(cid);
+            assert_eq!(await!(contains).unwrap(), true);
+            let get = store.get(cid);
+            assert_eq!(await!(get).unwrap(), Some(block.clone()));
+
+            let remove = store.remove(cid);
+            assert_eq!(await!(remove).unwrap(), ());
+            let contains = store.contains(cid);
+            assert_eq!(await!(contains).unwrap(), false);
+            let get = store.get(cid);
+            assert_eq!(await!(get).unwrap(), None);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_fs_blockstore_open() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore2");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let blockstore_path = tmp.clone();
+        tokio::run_async(async move {
+            let block = Block::from("1");
+
+            let block_store = FsBlockStore::new(blockstore_path.clone());
+            await!(block_store.init()).unwrap();
+            await!(block_store.open()).unwrap();
 
             assert!(!await!(block_store.contains(block.cid())).unwrap());
             await!(block_store.put(block.clone())).unwrap();
@@ -292,6 +1142,486 @@ mod tests {
         std::fs::remove_dir_all(tmp).ok();
     }
 
+    #[test]
+    fn test_fs_blockstore_get_data() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore7");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = FsBlockStore::new(tmp.clone());
+
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+            assert_eq!(await!(store.get_data(block.cid())).unwrap(), None);
+            await!(store.put(block.clone())).unwrap();
+            let data = await!(store.get_data(block.cid())).unwrap();
+            assert_eq!(data.as_ref(), Some(block.data()));
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_fs_blockstore_open_with_recovery() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore8");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = FsBlockStore::new(tmp.clone());
+        let path = tmp.clone();
+
+        tokio::run_async(async move {
+            let good = Block::from("good");
+            let corrupt = Block::from("corrupt");
+            let moved = Block::from("moved");
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+            await!(store.put(good.clone())).unwrap();
+            await!(store.put(corrupt.clone())).unwrap();
+
+            let corrupt_path = block_path(path.clone(), Layout::Flat, corrupt.cid());
+            std::fs::write(&corrupt_path, b"garbage").unwrap();
+            std::fs::write(manifest_path(path.clone()), b"garbage").unwrap();
+            // a block in the old layout of a partial migration
+            let moved_path = block_path(path.clone(), Layout::GoFlatfs, moved.cid());
+            std::fs::create_dir_all(moved_path.parent().unwrap()).unwrap();
+            std::fs::write(&moved_path, moved.data()).unwrap();
+
+            let store = FsBlockStore::new(path.clone());
+            let report = await!(store.open_with_recovery()).unwrap();
+            assert_eq!(report.recovered, 2);
+            assert_eq!(report.migrated, 1);
+            assert_eq!(report.quarantined.len(), 1);
+            assert!(report.quarantined[0].exists());
+
+            assert_eq!(await!(store.get(good.cid())).unwrap(), Some(good));
+            assert_eq!(await!(store.get(moved.cid())).unwrap(), Some(moved));
+            assert!(!await!(store.contains(corrupt.cid())).unwrap());
+            assert!(!corrupt_path.exists());
+
+            // the rebuilt manifest is used by the next open
+            let store = FsBlockStore::new(path.clone());
+            await!(store.open()).unwrap();
+            assert_eq!(await!(store.list()).unwrap().len(), 2);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_fs_blockstore_go_flatfs_recovery() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore9");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let path = tmp.clone();
+        tokio::run_async(async move {
+            let good = Block::from("good");
+            let corrupt = Block::from("corrupt");
+            let store = FsBlockStore::new(path.clone()).with_layout(Layout::GoFlatfs);
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+            await!(store.put(good.clone())).unwrap();
+            await!(store.put(corrupt.clone())).unwrap();
+            std::fs::write(block_path(path.clone(), Layout::GoFlatfs, corrupt.cid()), b"garbage").unwrap();
+
+            let store = FsBlockStore::new(path.clone()).with_layout(Layout::GoFlatfs);
+            let report = await!(store.open_with_recovery()).unwrap();
+            assert_eq!(report.recovered, 1);
+            assert_eq!(report.quarantined.len(), 1);
+            assert_eq!(await!(store.list()).unwrap(), vec![good.cid().to_owned()]);
+
+            // the quarantine directory is not mistaken for a shard
+            await!(store.reindex()).unwrap();
+            assert!(!await!(store.contains(corrupt.cid())).unwrap());
+            assert_eq!(await!(store.list()).unwrap(), vec![good.cid().to_owned()]);
+            assert_eq!(await!(store.get(good.cid())).unwrap(), Some(good));
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_fs_blockstore_manifest() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore6");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let blockstore_path = tmp.clone();
+        tokio::run_async(async move {
+            let block_store = FsBlockStore::new(blockstore_path.clone());
+            await!(block_store.init()).unwrap();
+            await!(block_store.open()).unwrap();
+            for data in &["1", "2", "3"] {
+                await!(block_store.put(Block::from(*data))).unwrap();
+            }
+            await!(block_store.remove(Block::from("2").cid())).unwrap();
+
+            let from_manifest = FsBlockStore::new(blockstore_path.clone());
+            await!(from_manifest.open()).unwrap();
+            let scanned = FsBlockStore::new(blockstore_path.clone());
+            await!(scanned.reindex()).unwrap();
+            assert_eq!(*from_manifest.cids.lock().unwrap(), *scanned.cids.lock().unwrap());
+            assert_eq!(scanned.cids.lock().unwrap().len(), 2);
+
+            // removing a block behind the store's back makes the manifest stale
+            std::fs::remove_file(block_path(blockstore_path.clone(), Layout::Flat,
+                                            Block::from("1").cid())).unwrap();
+            let reopened = FsBlockStore::new(blockstore_path);
+            await!(reopened.open()).unwrap();
+            assert!(!await!(reopened.contains(Block::from("1").cid())).unwrap());
+            assert!(await!(reopened.contains(Block::from("3").cid())).unwrap());
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_fs_blockstore_open_removes_temp_files() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore3");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let blockstore_path = tmp.clone();
+        tokio::run_async(async move {
+            let block = Block::from("1");
+
+            let block_store = FsBlockStore::new(blockstore_path.clone());
+            await!(block_store.init()).unwrap();
+            await!(block_store.open()).unwrap();
+            await!(block_store.put(block.clone())).unwrap();
+
+            let temp_file = temp_path(blockstore_path.clone(),
+                                      &block_path(blockstore_path.clone(), Layout::Flat, block.cid()));
+            std::fs::write(&temp_file, "partial").unwrap();
+
+            let block_store = FsBlockStore::new(blockstore_path);
+            await!(block_store.open()).unwrap();
+            assert!(!temp_file.exists());
+            assert_eq!(await!(block_store.get(block.cid())).unwrap().unwrap(), block);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_fs_blockstore_put_out_of_space() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore-full");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let blockstore_path = tmp.clone();
+        tokio::run_async(async move {
+            let block_store = FsBlockStore::new(blockstore_path.clone());
+            await!(block_store.init()).unwrap();
+            await!(block_store.open()).unwrap();
+
+            // writes to /dev/full fail with ENOSPC
+            let manifest = manifest_path(blockstore_path);
+            std::fs::remove_file(&manifest).ok();
+            std::os::unix::fs::symlink("/dev/full", &manifest).unwrap();
+
+            let err = await!(block_store.put(Block::from("1"))).unwrap_err();
+            match err.downcast_ref::<crate::repo::RepoError>() {
+                Some(crate::repo::RepoError::OutOfSpace) => {}
+                _ => panic!("expected out of space, got {}", err),
+            }
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_fs_blockstore_go_flatfs() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore5");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        // the empty unixfs directory as stored by go-ipfs
+        let mut shard = tmp.clone();
+        shard.push("X3");
+        std::fs::create_dir_all(&shard).unwrap();
+        let mut file = shard.clone();
+        file.push("CIQFTFEEHEDF6KLBT32BFAGLXEZL4UWFNWM4LFTLMXQBCERZ6CMLX3Y.data");
+        std::fs::write(&file, [0x0a, 0x02, 0x08, 0x01]).unwrap();
+        let mut sharding = tmp.clone();
+        sharding.push("SHARDING");
+        std::fs::write(&sharding, "/repo/flatfs/shard/v1/next-to-last/2\n").unwrap();
+
+        let store = FsBlockStore::new(tmp.clone()).with_layout(Layout::GoFlatfs);
+        let path = tmp.clone();
+        tokio::run_async(async move {
+            let cid = Cid::from("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+            await!(store.open()).unwrap();
+            assert!(await!(store.contains(&cid)).unwrap());
+            let block = await!(store.get(&cid)).unwrap().unwrap();
+            assert_eq!(block.data(), &vec![0x0a, 0x02, 0x08, 0x01]);
+            assert_eq!(await!(store.list()).unwrap(), vec![cid.clone()]);
+            assert_eq!(block_path(path.clone(), Layout::GoFlatfs, &cid), file);
+
+            let block = Block::from("1");
+            await!(store.put(block.clone())).unwrap();
+            assert!(block_path(path.clone(), Layout::GoFlatfs, block.cid()).exists());
+            assert_eq!(await!(store.get(block.cid())).unwrap(), Some(block));
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_fs_blockstore_sharding() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore_sharding");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let mut sharding = tmp.clone();
+        sharding.push(SHARDING_FILE);
+
+        let store = FsBlockStore::new(tmp.clone()).with_layout(Layout::GoFlatfs);
+        let spec = sharding.clone();
+        tokio::run_async(async move {
+            await!(store.init()).unwrap();
+            assert_eq!(std::fs::read_to_string(&spec).unwrap(), format!("{}\n", SHARDING));
+            await!(store.open()).unwrap();
+
+            std::fs::write(&spec, "/repo/flatfs/shard/v1/prefix/2\n").unwrap();
+            let err = await!(store.open()).unwrap_err();
+            assert_eq!(err.to_string(), "unsupported flatfs sharding /repo/flatfs/shard/v1/prefix/2");
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_fs_blockstore_list() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore4");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = FsBlockStore::new(tmp.clone());
+
+        tokio::run_async(async move {
+            let block1 = Block::from("1");
+            let block2 = Block::from("2");
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+            await!(store.put(block1.clone())).unwrap();
+            await!(store.put(block2.clone())).unwrap();
+
+            let mut list = await!(store.list()).unwrap();
+            let mut stream = Vec::new();
+            let mut cids = store.list_stream();
+            while let Some(cid) = await!(cids.next()) {
+                stream.push(cid.unwrap());
+            }
+            list.sort_by_key(|cid| cid.to_bytes());
+            stream.sort_by_key(|cid| cid.to_bytes());
+            assert_eq!(list, stream);
+            assert_eq!(list.len(), 2);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_repo_store_paths() {
+        use crate::repo::{Repo, RepoOptions};
+
+        let mut tmp = temp_dir();
+        tmp.push("repo_store_paths");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let mut repo_path = tmp.clone();
+        repo_path.push("repo");
+        let mut hdd = tmp.clone();
+        hdd.push("hdd");
+        let mut ssd = tmp.clone();
+        ssd.push("ssd");
+
+        let options = RepoOptions::new(repo_path.clone())
+            .blockstore_path(hdd.clone())
+            .datastore_path(ssd.clone());
+        let (repo, _) = Repo::<crate::Types>::new(options);
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            let block = Block::from("split");
+            await!(repo.put_block(block.clone())).unwrap();
+
+            assert!(block_path(hdd, Layout::Flat, block.cid()).exists());
+            assert!(std::fs::read_dir(ssd).unwrap().next().is_some());
+            assert!(!repo_path.join("blockstore").exists());
+            assert!(!repo_path.join("datastore").exists());
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_repo_go_flatfs() {
+        use crate::repo::{Repo, RepoOptions};
+
+        let mut tmp = temp_dir();
+        tmp.push("repo_go_flatfs");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let mut repo_path = tmp.clone();
+        repo_path.push("repo");
+        // the `blocks` directory of a go-ipfs repo
+        let mut blocks = tmp.clone();
+        blocks.push("go-ipfs");
+        blocks.push("blocks");
+        let mut file = blocks.clone();
+        file.push("X3");
+        std::fs::create_dir_all(&file).unwrap();
+        file.push("CIQFTFEEHEDF6KLBT32BFAGLXEZL4UWFNWM4LFTLMXQBCERZ6CMLX3Y.data");
+        std::fs::write(&file, [0x0a, 0x02, 0x08, 0x01]).unwrap();
+        let mut sharding = blocks.clone();
+        sharding.push(SHARDING_FILE);
+        std::fs::write(&sharding, format!("{}\n", SHARDING)).unwrap();
+
+        let options = RepoOptions::new(repo_path)
+            .blockstore_path(blocks)
+            .blockstore_layout(Layout::GoFlatfs);
+        let (repo, _) = Repo::<crate::Types>::new(options);
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            let cid = Cid::from("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+            let block = await!(repo.get_block(&cid)).unwrap();
+            assert_eq!(block.data(), &vec![0x0a, 0x02, 0x08, 0x01]);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_repo_go_flatfs_round_trip() {
+        use crate::repo::{Repo, RepoOptions};
+
+        let mut tmp = temp_dir();
+        tmp.push("repo_go_flatfs_round_trip");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let mut repo_path = tmp.clone();
+        repo_path.push("repo");
+        let mut blocks = tmp.clone();
+        blocks.push("blocks");
+
+        tokio::run_async(async move {
+            let options = || RepoOptions::new(repo_path.clone())
+                .blockstore_path(blocks.clone())
+                .blockstore_layout(Layout::GoFlatfs);
+            let block = Block::from("go-ipfs");
+            let removed = Block::from("removed");
+
+            let (repo, _) = Repo::<crate::Types>::new(options());
+            await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            await!(repo.put_block(block.clone())).unwrap();
+            await!(repo.put_block(removed.clone())).unwrap();
+            await!(repo.remove_block(removed.cid())).unwrap();
+            assert!(block_path(blocks.clone(), Layout::GoFlatfs, block.cid()).exists());
+            assert!(!block_path(blocks.clone(), Layout::GoFlatfs, removed.cid()).exists());
+            await!(repo.close()).unwrap();
+
+            let (repo, _) = Repo::<crate::Types>::new(options());
+            await!(repo.open()).unwrap();
+            assert_eq!(await!(repo.get_block(block.cid())).unwrap(), block);
+            assert_eq!(await!(repo.block_store.list()).unwrap(), vec![block.cid().to_owned()]);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_repo_retry() {
+        use crate::repo::{Repo, RepoOptions};
+        use std::time::Duration;
+
+        let retry = RetryPolicy::new(3, Duration::from_millis(1));
+        let options = RepoOptions::new(temp_dir().join("repo_retry")).retry(retry);
+        let (repo, _) = Repo::<crate::Types>::new(options);
+        assert_eq!(repo.block_store.retry, retry);
+        assert_eq!(repo.data_store.retry, retry);
+    }
+
+    #[test]
+    fn test_repo_snapshot() {
+        use crate::repo::{Repo, RepoOptions};
+        use futures::join;
+
+        let mut tmp = temp_dir();
+        tmp.push("repo_snapshot");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let mut repo_path = tmp.clone();
+        repo_path.push("repo");
+        let mut snapshot_path = tmp.clone();
+        snapshot_path.push("snapshot");
+
+        let (repo, _) = Repo::<crate::Types>::new(RepoOptions::new(repo_path));
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            await!(repo.put_block(Block::from("before"))).unwrap();
+
+            let puts = repo.clone();
+            let puts = async move {
+                for i in 0..100 {
+                    await!(puts.put_block(Block::from(i.to_string().as_str()))).unwrap();
+                }
+            };
+            let (_, res) = join!(puts, repo.snapshot(&snapshot_path));
+            res.unwrap();
+
+            let (snapshot, _) = Repo::<crate::Types>::new(RepoOptions::new(snapshot_path));
+            await!(snapshot.open()).unwrap();
+            let cids = await!(snapshot.block_store.list()).unwrap();
+            assert!(cids.contains(Block::from("before").cid()));
+            for cid in cids {
+                let block = await!(snapshot.block_store.get(&cid)).unwrap().unwrap();
+                let hashed = Cid::new_from_prefix(&cid.prefix(), block.data());
+                assert_eq!(hashed, cid);
+            }
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    /// Returns the total size of the files in `path`.
+    fn dir_size(path: &Path) -> u64 {
+        std::fs::read_dir(path).unwrap().map(|entry| {
+            let entry = entry.unwrap();
+            if entry.path().is_dir() {
+                dir_size(&entry.path())
+            } else {
+                entry.metadata().unwrap().len()
+            }
+        }).sum()
+    }
+
+    #[test]
+    fn test_rocks_datastore_compact() {
+        let mut tmp = temp_dir();
+        tmp.push("datastore_compact");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = RocksDataStore::new(tmp.clone());
+        let path = tmp.clone();
+
+        tokio::run_async(async move {
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+            let value = vec![0; 1024];
+            for i in 0..1000u32 {
+                let key = i.to_be_bytes();
+                await!(store.put(Column::Tombstone, &key, &value)).unwrap();
+                await!(store.remove(Column::Tombstone, &key)).unwrap();
+            }
+            let before = dir_size(&path);
+            await!(store.compact(Column::Tombstone)).unwrap();
+            assert!(dir_size(&path) < before);
+            assert!(await!(store.list_keys(Column::Tombstone)).unwrap().is_empty());
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
     #[test]
     fn test_rocks_datastore() {
         let mut tmp = temp_dir();
@@ -331,4 +1661,125 @@ mod tests {
 
         std::fs::remove_dir_all(tmp).ok();
     }
+
+    /// Copies the files of the open store at `path` to `crashed`, like
+    /// a crash leaves them behind. Nothing is flushed or closed.
+    fn crash_copy(path: &Path, crashed: &Path) {
+        std::fs::remove_dir_all(crashed).ok();
+        copy_dir(path, crashed).unwrap();
+    }
+
+    #[test]
+    fn test_rocks_datastore_wal_recovery() {
+        let mut tmp = temp_dir();
+        tmp.push("datastore3");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let base = tmp.clone();
+        tokio::run_async(async move {
+            let col = Column::Ipns;
+            let key = [1, 2, 3, 4];
+            let value = [5, 6, 7, 8];
+            let datastore_path = base.join("datastore");
+            let crashed_path = base.join("crashed");
+
+            for &wal in &[true, false] {
+                // rocksdb only syncs writes to the wal
+                let durability = Durability { wal, sync: wal, ..Durability::default() };
+                std::fs::remove_dir_all(&datastore_path).ok();
+                let store = RocksDataStore::new(datastore_path.clone())
+                    .with_durability(durability);
+                await!(store.init()).unwrap();
+                await!(store.open()).unwrap();
+                await!(store.put(col, &key, &value)).unwrap();
+                // crash without checkpointing the wal
+                crash_copy(&datastore_path, &crashed_path);
+                drop(store);
+
+                let store = RocksDataStore::new(crashed_path.clone());
+                await!(store.open()).unwrap();
+                let recovered = await!(store.get(col, &key)).unwrap();
+                if wal {
+                    assert_eq!(recovered, Some(value.to_vec()));
+                } else {
+                    // only the wal keeps writes that weren't flushed
+                    assert_eq!(recovered, None);
+                }
+            }
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    /// Returns the number of sst files of the rocksdb store at `path`.
+    fn sst_files(path: &Path) -> usize {
+        std::fs::read_dir(path).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some(OsStr::new("sst")))
+            .count()
+    }
+
+    #[test]
+    fn test_rocks_datastore_wal_checkpoint() {
+        let mut tmp = temp_dir();
+        tmp.push("datastore4");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let datastore_path = tmp.clone();
+        tokio::run_async(async move {
+            let col = Column::Ipns;
+            let durability = Durability { max_wal_size: 1024, ..Durability::default() };
+            let store = RocksDataStore::new(datastore_path.clone())
+                .with_durability(durability);
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+
+            await!(store.put(col, &[1], &[0; 512])).unwrap();
+            assert_eq!(sst_files(&datastore_path), 0);
+            // the log grows beyond its limit and is checkpointed
+            await!(store.put(col, &[2], &[0; 512])).unwrap();
+            assert!(sst_files(&datastore_path) > 0);
+            assert_eq!(store.wal_size.load(Ordering::SeqCst), 0);
+            assert_eq!(await!(store.get(col, &[2])).unwrap(), Some(vec![0; 512]));
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_repo_durability() {
+        use crate::repo::{Repo, RepoOptions};
+
+        let durability = Durability { wal: true, sync: true, max_wal_size: 1024 };
+        let options = RepoOptions::new(temp_dir().join("repo_durability")).durability(durability);
+        let (repo, _) = Repo::<crate::Types>::new(options);
+        assert_eq!(repo.data_store.durability, durability);
+    }
+
+    #[test]
+    fn test_rocks_datastore_stream() {
+        let mut tmp = temp_dir();
+        tmp.push("datastore2");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = RocksDataStore::new(tmp.clone());
+
+        tokio::run_async(async move {
+            let col = Column::Ipns;
+            let key = [1, 2, 3, 4];
+            // larger than the buffers used by `tokio::io::copy`
+            let value: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+
+            assert!(await!(store.get_stream(col, &key)).unwrap().is_none());
+            let reader = Box::new(std::io::Cursor::new(value.clone()));
+            await!(store.put_stream(col, &key, reader)).unwrap();
+
+            let reader = await!(store.get_stream(col, &key)).unwrap().unwrap();
+            let (_, read) = await!(tokio::io::read_to_end(reader, Vec::new()).compat()).unwrap();
+            assert_eq!(read, value);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
 }
@@ -0,0 +1,498 @@
+//! Garbage collection of unpinned blocks
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{block_links, BlockStore, CancellationToken, Repo, RepoError, RepoEvent, RepoTypes};
+use core::future::Future;
+use futures::channel::{mpsc, oneshot};
+use futures::compat::*;
+use futures::stream::{Stream, StreamExt};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+/// Result of a garbage collection.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// Number of removed blocks.
+    pub removed: u64,
+    /// Total size of the removed blocks in bytes.
+    pub bytes_freed: u64,
+}
+
+/// Handle of a garbage collection scheduler started with
+/// `Repo::start_gc_scheduler`.
+#[derive(Clone, Debug)]
+pub struct GcHandle {
+    stopped: Arc<AtomicBool>,
+}
+
+impl GcHandle {
+    /// Stops the scheduler. A collection that is already running is
+    /// completed.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Default)]
+struct GcLockState {
+    writers: usize,
+    collecting: bool,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+/// Keeps garbage collections from running while blocks are written and
+/// pinned.
+///
+/// Writers share the lock, a collection holds it alone. Writers only wait
+/// for a running collection, so that a writer holding the lock can take
+/// it again.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GcLock {
+    state: Arc<Mutex<GcLockState>>,
+}
+
+impl GcLock {
+    fn lock(&self, collect: bool) -> impl Future<Output=GcGuard> {
+        let state = self.state.clone();
+        async move {
+            loop {
+                let waiter = {
+                    let mut state = state.lock().unwrap();
+                    if !state.collecting && (!collect || state.writers == 0) {
+                        if collect {
+                            state.collecting = true;
+                        } else {
+                            state.writers += 1;
+                        }
+                        break;
+                    }
+                    let (tx, rx) = oneshot::channel();
+                    state.waiters.push(tx);
+                    rx
+                };
+                // canceled only if the guard is dropped, check again then
+                let _ = await!(waiter);
+            }
+            GcGuard { state, collect }
+        }
+    }
+}
+
+/// Keeps garbage collections from running until it is dropped, see
+/// `Repo::gc_guard`.
+#[derive(Debug)]
+pub struct GcGuard {
+    state: Arc<Mutex<GcLockState>>,
+    collect: bool,
+}
+
+impl Drop for GcGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if self.collect {
+            state.collecting = false;
+        } else {
+            state.writers -= 1;
+        }
+        // the waiters check whether they can take the lock now
+        for waiter in state.waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Waits for a running garbage collection and keeps new ones from
+    /// starting until the guard is dropped.
+    ///
+    /// Putting and pinning blocks takes the guard on its own. Hold it
+    /// across putting blocks and pinning them, so that the blocks aren't
+    /// collected in between.
+    pub fn gc_guard(&self) -> impl Future<Output=GcGuard> {
+        self.gc_lock.lock(false)
+    }
+
+    /// Returns the pinned blocks and the local blocks reachable from
+    /// recursively pinned blocks or from the root of the mutable file
+    /// system.
+    pub(crate) fn live_blocks(&self, cancel: &CancellationToken) ->
+    impl Future<Output=Result<HashSet<Cid>, Error>>
+    {
+        let pins = self.list_recursive_pins();
+        let direct = self.list_direct_pins();
+        let mfs_root = self.mfs_root();
+        let block_store = self.block_store.clone();
+        let max_depth = self.max_depth;
+        let cancel = cancel.clone();
+        async move {
+            let direct = await!(direct)?;
+            let mut live = HashSet::new();
+            let mut stack: Vec<(Cid, usize)> = await!(pins)?
+                .into_iter().map(|pin| (pin, 0)).collect();
+            stack.extend(await!(mfs_root)?.map(|root| (root, 0)));
+            while let Some((cid, depth)) = stack.pop() {
+                cancel.check()?;
+                if depth > max_depth {
+                    return Err(RepoError::DagTooDeep(max_depth).into());
+                }
+                if !live.insert(cid.clone()) {
+                    continue;
+                }
+                if let Some(block) = await!(block_store.get(&cid))? {
+                    stack.extend(block_links(&block)?.into_iter().map(|link| (link, depth + 1)));
+                }
+            }
+            live.extend(direct);
+            Ok(live)
+        }
+    }
+
+    /// Removes all blocks that are neither pinned nor reachable from a
+    /// pinned block.
+    pub fn garbage_collect(&self) -> impl Future<Output=Result<GcStats, Error>> {
+        self.garbage_collect_with(&CancellationToken::new())
+    }
+
+    /// Runs `garbage_collect` until `cancel` is cancelled.
+    ///
+    /// Fails with `RepoError::Cancelled` once cancelled. Blocks removed
+    /// before that stay removed, the remaining blocks are kept.
+    pub fn garbage_collect_with(&self, cancel: &CancellationToken) ->
+    impl Future<Output=Result<GcStats, Error>>
+    {
+        self.run_gc(cancel, None)
+    }
+
+    /// Runs `garbage_collect` in the background, streaming the cids of
+    /// the removed blocks.
+    ///
+    /// The stream ends with the collection. If it fails the error is the
+    /// last item.
+    pub fn gc(&self) -> impl Stream<Item=Result<Cid, Error>> {
+        let (sender, removed) = mpsc::unbounded();
+        let gc = self.run_gc(&CancellationToken::new(), Some(sender.clone()));
+        self.spawner.spawn(async move {
+            if let Err(err) = await!(gc) {
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = sender.unbounded_send(Err(err));
+            }
+        });
+        removed
+    }
+
+    fn run_gc(&self, cancel: &CancellationToken, progress: Option<mpsc::UnboundedSender<Result<Cid, Error>>>) ->
+    impl Future<Output=Result<GcStats, Error>>
+    {
+        let repo = self.clone();
+        let cancel = cancel.clone();
+        async move {
+            repo.gc_runs.fetch_add(1, Ordering::SeqCst);
+            let _guard = await!(repo.gc_lock.lock(true));
+            let res = await!(repo.collect_garbage(cancel, progress));
+            repo.gc_runs.fetch_sub(1, Ordering::SeqCst);
+            res
+        }
+    }
+
+    fn collect_garbage(&self, cancel: CancellationToken, progress: Option<mpsc::UnboundedSender<Result<Cid, Error>>>) ->
+    impl Future<Output=Result<GcStats, Error>>
+    {
+        let repo = self.clone();
+        async move {
+            let live = await!(repo.live_blocks(&cancel))?;
+            let mut stats = GcStats::default();
+            let mut swept = Vec::new();
+            let mut cids = repo.block_store.list_stream();
+            let res = loop {
+                let cid = match await!(cids.next()) {
+                    Some(Ok(cid)) => cid,
+                    Some(Err(err)) => break Err(err),
+                    None => break Ok(()),
+                };
+                if let Err(err) = cancel.check() {
+                    break Err(err);
+                }
+                if live.contains(&cid) {
+                    continue;
+                }
+                // read through a view, collected after the view is dropped
+                if !repo.holds.lock().unwrap().start_sweep(&cid) {
+                    continue;
+                }
+                let removed = match await!(repo.block_store.block_size(&cid)) {
+                    Ok(Some(size)) => await!(repo.remove_block_keep_meta(&cid)).map(|()| Some(size)),
+                    // removed while collecting
+                    Ok(None) => Ok(None),
+                    Err(err) => Err(err),
+                };
+                repo.holds.lock().unwrap().finish_sweep(&cid);
+                let size = match removed {
+                    Ok(Some(size)) => size,
+                    Ok(None) => continue,
+                    Err(err) => break Err(err),
+                };
+                if let Some(progress) = &progress {
+                    // sending only fails if no one is listening anymore
+                    // and that is okay with us.
+                    let _ = progress.unbounded_send(Ok(cid.clone()));
+                }
+                swept.push(cid);
+                stats.removed += 1;
+                stats.bytes_freed += size;
+            };
+            // also when the collection failed, so that the metadata of the
+            // blocks removed so far doesn't leak
+            await!(repo.clear_blocks_meta(&swept))?;
+            res.map(|()| stats)
+        }
+    }
+
+    /// Runs `garbage_collect` every `interval` until the returned handle
+    /// is stopped.
+    ///
+    /// The results are sent as `RepoEvent::GarbageCollected`. A run is
+    /// skipped if a collection is still in progress.
+    pub fn start_gc_scheduler(&self, interval: Duration) -> GcHandle {
+        let handle = GcHandle {
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+        let stopped = handle.stopped.clone();
+        let repo = self.clone();
+        self.spawner.spawn(async move {
+            loop {
+                if let Err(err) = await!(Delay::new(Instant::now() + interval).compat()) {
+                    warn!("stopping gc scheduler: {}", err);
+                    return;
+                }
+                if stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                if repo.gc_runs.load(Ordering::SeqCst) > 0 {
+                    continue;
+                }
+                match await!(repo.garbage_collect()) {
+                    Ok(stats) => {
+                        // sending only fails if no one is listening anymore
+                        // and that is okay with us.
+                        let _ = repo.events.send(RepoEvent::GarbageCollected(stats));
+                    }
+                    Err(err) => warn!("garbage collection failed: {}", err),
+                }
+            }
+        });
+        handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::ipld::Ipld;
+    use crate::repo::{Column, DataStore, DataStorePinStore, RepoOptions, StoreStream};
+    use crate::repo::mem::{MemBlockStore, MemDataStore};
+    use crate::repo::tests::{create_mock_repo, create_mock_repo_with_events};
+    use futures::future::FutureObj;
+    use std::env::temp_dir;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_garbage_collect() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let child = await!(repo.put_block(Block::from("child"))).unwrap();
+            let parent = Ipld::from(vec![Ipld::from(child.clone())]).to_dag_cbor().unwrap();
+            let parent = await!(repo.put_block(parent)).unwrap();
+            let unpinned = await!(repo.put_block(Block::from("unpinned"))).unwrap();
+            await!(repo.pin_block(&parent)).unwrap();
+
+            let stats = await!(repo.garbage_collect()).unwrap();
+            assert_eq!(stats, GcStats { removed: 1, bytes_freed: 8 });
+            assert!(!await!(repo.block_store.contains(&unpinned)).unwrap());
+            assert!(await!(repo.block_store.contains(&parent)).unwrap());
+            assert!(await!(repo.block_store.contains(&child)).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_garbage_collect_mfs_root() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let child = await!(repo.put_block(Block::from("child"))).unwrap();
+            let root = Ipld::from(vec![Ipld::from(child.clone())]).to_dag_cbor().unwrap();
+            let root = await!(repo.put_block(root)).unwrap();
+            await!(repo.set_mfs_root(&root)).unwrap();
+            assert_eq!(await!(repo.mfs_root()).unwrap(), Some(root.clone()));
+
+            let stats = await!(repo.garbage_collect()).unwrap();
+            assert_eq!(stats.removed, 0);
+            assert!(await!(repo.block_store.contains(&child)).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_gc_stream() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let pinned = await!(repo.put_block(Block::from("pinned"))).unwrap();
+            await!(repo.pin_block(&pinned)).unwrap();
+            let mut unpinned = Vec::new();
+            for data in &["1", "2", "3"] {
+                unpinned.push(await!(repo.put_block(Block::from(*data))).unwrap());
+            }
+
+            let mut removed: Vec<Cid> = await!(repo.gc().collect::<Vec<_>>())
+                .into_iter().map(Result::unwrap).collect();
+            removed.sort_by_key(|cid| cid.to_bytes());
+            unpinned.sort_by_key(|cid| cid.to_bytes());
+            assert_eq!(removed, unpinned);
+            assert!(await!(repo.block_store.contains(&pinned)).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_garbage_collect_meta() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let pinned = await!(repo.put_block(Block::from("pinned"))).unwrap();
+            await!(repo.pin_block(&pinned)).unwrap();
+            let unpinned = await!(repo.put_block(Block::from("unpinned"))).unwrap();
+            for cid in &[&pinned, &unpinned] {
+                await!(repo.set_block_meta(cid, "content-type", b"text/plain")).unwrap();
+                await!(repo.set_block_meta(cid, "source", b"test")).unwrap();
+            }
+
+            let stats = await!(repo.garbage_collect()).unwrap();
+            assert_eq!(stats.removed, 1);
+            assert_eq!(await!(repo.get_block_meta(&unpinned, "content-type")).unwrap(), None);
+            assert_eq!(await!(repo.get_block_meta(&unpinned, "source")).unwrap(), None);
+            // only the metadata of the pinned block is left
+            assert_eq!(await!(repo.data_store.list_keys(Column::Meta)).unwrap().len(), 2);
+            assert!(await!(repo.get_block_meta(&pinned, "source")).unwrap().is_some());
+        });
+    }
+
+    #[test]
+    fn test_gc_scheduler() {
+        let (repo, events) = create_mock_repo_with_events();
+        tokio::run_async(async move {
+            let pinned = await!(repo.put_block(Block::from("pinned"))).unwrap();
+            await!(repo.pin_block(&pinned)).unwrap();
+            let unpinned = await!(repo.put_block(Block::from("1"))).unwrap();
+
+            let handle = repo.start_gc_scheduler(Duration::from_millis(10));
+            let wait = || Delay::new(Instant::now() + Duration::from_millis(100)).compat();
+            await!(wait()).unwrap();
+            assert!(!await!(repo.block_store.contains(&unpinned)).unwrap());
+            let unpinned = await!(repo.put_block(Block::from("2"))).unwrap();
+            await!(wait()).unwrap();
+            assert!(!await!(repo.block_store.contains(&unpinned)).unwrap());
+            assert!(await!(repo.block_store.contains(&pinned)).unwrap());
+            handle.stop();
+
+            let runs = events.try_iter().filter(|event| match event {
+                RepoEvent::GarbageCollected(_) => true,
+                _ => false,
+            }).count();
+            assert!(runs >= 2);
+        });
+    }
+
+    #[test]
+    fn test_gc_guard() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let guard = await!(repo.gc_guard());
+            let cid = await!(repo.put_block(Block::from("1"))).unwrap();
+            let mut gc = Box::pin(repo.garbage_collect());
+            // waits for the guard
+            assert!(futures::poll!(gc.as_mut()).is_pending());
+            await!(repo.pin_block(&cid)).unwrap();
+            drop(guard);
+            assert_eq!(await!(gc).unwrap().removed, 0);
+            assert!(await!(repo.block_store.contains(&cid)).unwrap());
+
+            // writers wait for a running collection
+            let gc = repo.gc_lock.lock(true);
+            let gc = await!(gc);
+            let mut put = Box::pin(repo.put_block(Block::from("2")));
+            assert!(futures::poll!(put.as_mut()).is_pending());
+            drop(gc);
+            await!(put).unwrap();
+        });
+    }
+
+    /// Cancels its token when the first block is removed.
+    #[derive(Clone, Debug)]
+    struct CancellingStore {
+        inner: MemBlockStore,
+        cancel: CancellationToken,
+    }
+
+    impl BlockStore for CancellingStore {
+        fn new(path: PathBuf) -> Self {
+            CancellingStore {
+                inner: MemBlockStore::new(path),
+                cancel: CancellationToken::new(),
+            }
+        }
+        fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.init()
+        }
+        fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.open()
+        }
+        fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+            self.inner.contains(cid)
+        }
+        fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+            self.inner.get(cid)
+        }
+        fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+            self.inner.put(block)
+        }
+        fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+            self.cancel.cancel();
+            self.inner.remove(cid)
+        }
+        fn list_stream(&self) -> StoreStream<Cid> {
+            self.inner.list_stream()
+        }
+    }
+
+    #[derive(Clone)]
+    struct CancellingTypes;
+
+    impl RepoTypes for CancellingTypes {
+        type TBlockStore = CancellingStore;
+        type TDataStore = MemDataStore;
+        type TPinStore = DataStorePinStore<MemDataStore>;
+    }
+
+    #[test]
+    fn test_garbage_collect_cancelled() {
+        let mut tmp = temp_dir();
+        tmp.push("rust-ipfs-repo-gc-cancelled");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let (repo, _) = Repo::new(RepoOptions::<CancellingTypes>::new(tmp.clone()));
+        tokio::run_async(async move {
+            for data in &["1", "2", "3"] {
+                await!(repo.put_block(Block::from(*data))).unwrap();
+            }
+            let cancel = repo.block_store.cancel.clone();
+            let err = await!(repo.garbage_collect_with(&cancel)).unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::Cancelled) => {}
+                _ => panic!("expected cancellation, got {}", err),
+            }
+            assert_eq!(await!(repo.block_store.list()).unwrap().len(), 2);
+            assert_eq!(repo.gc_runs.load(Ordering::SeqCst), 0);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
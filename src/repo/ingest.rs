@@ -0,0 +1,80 @@
+//! Writing blocks handed over through a channel
+use crate::block::Block;
+use crate::error::Error;
+use crate::repo::{Repo, RepoTypes};
+use futures::channel::mpsc;
+use futures::stream::StreamExt;
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Returns a channel for handing blocks to a background writer,
+    /// together with a stream of the errors of the writer.
+    ///
+    /// The channel holds up to `capacity` blocks, once it is full sending
+    /// waits until the writer caught up. The writer puts the blocks that
+    /// are waiting with `put_many` and runs until all senders are dropped,
+    /// then the error stream ends. A failed batch doesn't stop the writer.
+    pub fn ingest_sender(&self, capacity: usize) ->
+    (mpsc::Sender<Block>, mpsc::UnboundedReceiver<Error>)
+    {
+        let (sender, mut blocks) = mpsc::channel(capacity);
+        let (errors, error_stream) = mpsc::unbounded();
+        let repo = self.clone();
+        self.spawner.spawn(async move {
+            while let Some(block) = await!(blocks.next()) {
+                let mut batch = vec![block];
+                while batch.len() < capacity.max(1) {
+                    match blocks.try_next() {
+                        Ok(Some(block)) => batch.push(block),
+                        _ => break,
+                    }
+                }
+                if let Err(err) = await!(repo.put_many(batch)) {
+                    // sending only fails if no one is listening anymore
+                    // and that is okay with us.
+                    let _ = errors.unbounded_send(err);
+                }
+            }
+        });
+        (sender, error_stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{BlockStore, RepoOptions, Spawner};
+    use crate::repo::tests::{create_mock_repo, Types};
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_ingest_backpressure() {
+        // the writer never runs, like a store that can't keep up
+        let options = RepoOptions::<Types>::new(temp_dir()).spawner(Spawner::new(|_| {}));
+        let (repo, _) = Repo::new(options);
+        let (mut sender, _errors) = repo.ingest_sender(2);
+        // one extra slot is reserved for every sender
+        for i in 0..3 {
+            sender.try_send(Block::from(i.to_string().as_str())).unwrap();
+        }
+        let err = sender.try_send(Block::from("3")).unwrap_err();
+        assert!(err.is_full());
+    }
+
+    #[test]
+    fn test_ingest_sender() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let (mut sender, mut errors) = repo.ingest_sender(4);
+            let blocks: Vec<Block> = (0..3).map(|i| Block::from(i.to_string().as_str())).collect();
+            for block in &blocks {
+                sender.try_send(block.clone()).unwrap();
+            }
+            drop(sender);
+            // ends once the writer is done
+            assert!(await!(errors.next()).is_none());
+            for block in blocks {
+                assert!(await!(repo.block_store.contains(block.cid())).unwrap());
+            }
+        });
+    }
+}
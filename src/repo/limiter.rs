@@ -0,0 +1,277 @@
+//! Limiting of concurrent store operations
+use futures::channel::oneshot;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// The kind of a store operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Access {
+    /// A local read.
+    Read,
+    /// A write of a block received from the network.
+    Write,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: usize,
+    reads: usize,
+    writes: usize,
+    waiting_reads: VecDeque<oneshot::Sender<Permit>>,
+    waiting_writes: VecDeque<oneshot::Sender<Permit>>,
+}
+
+impl State {
+    fn can_start(&self, access: Access) -> bool {
+        let free = self.reads + self.writes < self.limit;
+        match access {
+            // reads are only held back by writes
+            Access::Read => free || self.writes == 0,
+            Access::Write => free && self.waiting_reads.is_empty(),
+        }
+    }
+
+    fn running(&mut self, access: Access) -> &mut usize {
+        match access {
+            Access::Read => &mut self.reads,
+            Access::Write => &mut self.writes,
+        }
+    }
+
+    fn waiting(&mut self, access: Access) -> &mut VecDeque<oneshot::Sender<Permit>> {
+        match access {
+            Access::Read => &mut self.waiting_reads,
+            Access::Write => &mut self.waiting_writes,
+        }
+    }
+
+    /// Hands the free slots to the waiting operations, reads first.
+    fn schedule(&mut self, state: &Arc<Mutex<State>>) {
+        loop {
+            let access = if !self.waiting_reads.is_empty() && self.can_start(Access::Read) {
+                Access::Read
+            } else if !self.waiting_writes.is_empty() && self.can_start(Access::Write) {
+                Access::Write
+            } else {
+                return;
+            };
+            let tx = self.waiting(access).pop_front().unwrap();
+            *self.running(access) += 1;
+            let permit = Permit { state: Some(state.clone()), access };
+            if let Err(mut permit) = tx.send(permit) {
+                // the waiting operation was cancelled, disarm the permit
+                // so that dropping it doesn't release the slot twice
+                permit.state = None;
+                *self.running(access) -= 1;
+            }
+        }
+    }
+}
+
+/// Lets at most `limit` network writes run at the same time.
+///
+/// Reads and writes share the `limit` slots. Reads are only held back
+/// while writes are running and get the next free slot before any
+/// waiting write, so a flood of writes can't starve them. Waiting
+/// operations of the same kind are started in the order they arrived.
+#[derive(Clone, Debug)]
+pub(crate) struct Limiter {
+    state: Arc<Mutex<State>>,
+}
+
+/// Allows an operation to run until it is dropped.
+#[derive(Debug)]
+pub(crate) struct Permit {
+    state: Option<Arc<Mutex<State>>>,
+    access: Access,
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: usize) -> Self {
+        Limiter {
+            state: Arc::new(Mutex::new(State {
+                limit: limit.max(1),
+                reads: 0,
+                writes: 0,
+                waiting_reads: VecDeque::new(),
+                waiting_writes: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Waits until the operation may run.
+    pub(crate) fn acquire(&self, access: Access) -> impl Future<Output=Permit> {
+        let state = self.state.clone();
+        async move {
+            let rx = {
+                let mut guard = state.lock().unwrap();
+                if guard.waiting(access).is_empty() && guard.can_start(access) {
+                    *guard.running(access) += 1;
+                    return Permit { state: Some(state.clone()), access };
+                }
+                let (tx, rx) = oneshot::channel();
+                guard.waiting(access).push_back(tx);
+                rx
+            };
+            // the sender is only dropped after handing over the permit
+            await!(rx).unwrap()
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let state = match self.state.take() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut guard = state.lock().unwrap();
+        *guard.running(self.access) -= 1;
+        guard.schedule(&state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Block, Cid};
+    use crate::error::Error;
+    use crate::repo::{BlockStore, DataStorePinStore, Repo, RepoOptions, RepoTypes, StoreStream};
+    use crate::repo::mem::{MemBlockStore, MemDataStore};
+    use futures::compat::Future01CompatExt;
+    use futures::future::FutureObj;
+    use std::env::temp_dir;
+    use std::path::PathBuf;
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
+    use tokio::timer::Delay;
+
+    #[derive(Debug, Default)]
+    struct Load {
+        puts: usize,
+        max_puts: usize,
+        done_puts: usize,
+        /// Puts that were done before the first get.
+        done_puts_before_get: Option<usize>,
+    }
+
+    /// Mem block store with slow puts that records how many puts were
+    /// done before a get.
+    #[derive(Clone)]
+    struct TimingStore {
+        inner: MemBlockStore,
+        load: Arc<Mutex<Load>>,
+    }
+
+    impl BlockStore for TimingStore {
+        fn new(path: PathBuf) -> Self {
+            TimingStore {
+                inner: MemBlockStore::new(path),
+                load: Arc::new(Mutex::new(Load::default())),
+            }
+        }
+        fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.init()
+        }
+        fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.open()
+        }
+        fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+            self.inner.contains(cid)
+        }
+        fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+            let mut load = self.load.lock().unwrap();
+            if load.done_puts_before_get.is_none() {
+                load.done_puts_before_get = Some(load.done_puts);
+            }
+            self.inner.get(cid)
+        }
+        fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+            let inner = self.inner.clone();
+            let load = self.load.clone();
+            FutureObj::new(Box::new(async move {
+                {
+                    let mut load = load.lock().unwrap();
+                    load.puts += 1;
+                    load.max_puts = load.max_puts.max(load.puts);
+                }
+                let delay = Instant::now() + Duration::from_millis(5);
+                await!(Delay::new(delay).compat()).unwrap();
+                let res = await!(inner.put(block));
+                let mut load = load.lock().unwrap();
+                load.puts -= 1;
+                load.done_puts += 1;
+                res
+            }))
+        }
+        fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+            self.inner.remove(cid)
+        }
+        fn list_stream(&self) -> StoreStream<Cid> {
+            self.inner.list_stream()
+        }
+    }
+
+    #[derive(Clone)]
+    struct TimingTypes;
+
+    impl RepoTypes for TimingTypes {
+        type TBlockStore = TimingStore;
+        type TDataStore = MemDataStore;
+        type TPinStore = DataStorePinStore<MemDataStore>;
+    }
+
+    #[test]
+    fn test_limiter_prefers_reads() {
+        tokio::run_async(async {
+            let limiter = Limiter::new(1);
+            let write = await!(limiter.acquire(Access::Write));
+            let mut queued_write = Box::pin(limiter.acquire(Access::Write));
+            let mut read = Box::pin(limiter.acquire(Access::Read));
+            assert!(futures::poll!(queued_write.as_mut()).is_pending());
+            assert!(futures::poll!(read.as_mut()).is_pending());
+
+            // the read gets the slot before the write that waited longer
+            drop(write);
+            assert!(futures::poll!(queued_write.as_mut()).is_pending());
+            let read = match futures::poll!(read.as_mut()) {
+                Poll::Ready(permit) => permit,
+                Poll::Pending => panic!("the queued read didn't run"),
+            };
+            // reads don't wait for other reads
+            let other = await!(limiter.acquire(Access::Read));
+            drop(read);
+            assert!(futures::poll!(queued_write.as_mut()).is_pending());
+            drop(other);
+            await!(queued_write);
+        });
+    }
+
+    #[test]
+    fn test_network_writes_leave_room_for_reads() {
+        let options = RepoOptions::<TimingTypes>::new(temp_dir()).max_network_writes(1);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            let local = await!(repo.put_block(Block::from("local"))).unwrap();
+            // the local put isn't a network write
+            *repo.block_store.load.lock().unwrap() = Load::default();
+            for i in 0..20 {
+                let put = repo.put_block_fetched(Block::from(&*i.to_string()));
+                tokio::spawn_async(async move {
+                    await!(put).unwrap();
+                });
+            }
+            // let the writes start and queue up
+            let delay = Instant::now() + Duration::from_millis(2);
+            await!(Delay::new(delay).compat()).unwrap();
+
+            await!(repo.get_block(&local)).unwrap();
+            let load = repo.block_store.load.lock().unwrap();
+            assert!(load.max_puts <= 1);
+            // the read only waited for the running write
+            assert!(load.done_puts_before_get.unwrap() <= 1);
+            assert!(load.done_puts < 20);
+        });
+    }
+}
@@ -0,0 +1,216 @@
+//! Lock file keeping other processes from opening the repo
+use crate::error::Error;
+use crate::repo::RepoError;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Name of the lock file in the repo directory.
+pub const LOCK_FILE: &str = "repo.lock";
+
+/// Lock file locked with `flock` on unix.
+///
+/// The kernel releases the lock when the file is closed, also when the
+/// process dies, so a lock file left behind by a crash is never held.
+///
+/// Other platforms lock the repo by creating the file, a lock file left
+/// behind by a crash has to be removed by hand there.
+#[derive(Debug)]
+struct LockFile {
+    path: PathBuf,
+    // closing the file releases the lock
+    #[cfg(unix)]
+    _file: File,
+}
+
+impl LockFile {
+    #[cfg(unix)]
+    fn lock(path: &Path) -> Result<Self, Error> {
+        let pid = std::process::id();
+        loop {
+            let mut file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let err = std::io::Error::last_os_error();
+                if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                    return Err(err.into());
+                }
+                let mut holder = String::new();
+                file.read_to_string(&mut holder)?;
+                match holder.trim().parse::<u32>() {
+                    // released by another repo of this process meanwhile
+                    Ok(holder) if holder == pid => std::thread::yield_now(),
+                    Ok(holder) => return Err(RepoError::RepoLocked(holder).into()),
+                    // the holder hasn't written its pid yet
+                    Err(_) => return Err(format_err!(
+                        "repo lock {:?} is being acquired by another process", path)),
+                }
+                continue;
+            }
+            // the previous holder removes the file before releasing it, a
+            // file opened before that isn't the lock file anymore
+            let locked = file.metadata()?;
+            match std::fs::metadata(path) {
+                Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {}
+                Ok(_) => continue,
+                Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
+            // a stale file holds the pid of a crashed process or nothing
+            file.set_len(0)?;
+            write!(file, "{}", pid)?;
+            file.sync_all()?;
+            return Ok(LockFile {
+                path: path.to_owned(),
+                _file: file,
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn lock(path: &Path) -> Result<Self, Error> {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                write!(file, "{}", std::process::id())?;
+                file.sync_all()?;
+                Ok(LockFile { path: path.to_owned() })
+            }
+            Err(ref err) if err.kind() == ErrorKind::AlreadyExists => {
+                let mut holder = String::new();
+                File::open(path)?.read_to_string(&mut holder)?;
+                match holder.trim().parse::<u32>() {
+                    Ok(holder) => Err(RepoError::RepoLocked(holder).into()),
+                    Err(_) => Err(format_err!(
+                        "repo lock {:?} is being acquired by another process", path)),
+                }
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // removed while it is still locked
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!("failed to release repo lock {:?}: {}", self.path, err);
+        }
+    }
+}
+
+lazy_static! {
+    /// The lock files held by this process by path.
+    static ref HELD_LOCKS: Mutex<HashMap<PathBuf, Weak<LockFile>>> = Mutex::new(HashMap::new());
+}
+
+/// Lock on a repo directory, released when dropped.
+///
+/// The lock file holds the pid of the locking process. The lock only
+/// keeps other processes out: opening the same path again in this
+/// process, also with another `Repo`, succeeds and shares the lock. It is
+/// released when the last of them is closed.
+#[derive(Debug)]
+pub(crate) struct RepoLock {
+    // the file is unlocked once no repo shares it anymore
+    _file: Arc<LockFile>,
+}
+
+impl RepoLock {
+    /// Locks the repo at `path`, failing with `RepoError::RepoLocked` if
+    /// another running process holds the lock.
+    pub(crate) fn acquire(path: &Path) -> Result<Self, Error> {
+        std::fs::create_dir_all(path)?;
+        let lock_path = std::fs::canonicalize(path)?.join(LOCK_FILE);
+        let mut held = HELD_LOCKS.lock().unwrap();
+        if let Some(file) = held.get(&lock_path).and_then(Weak::upgrade) {
+            return Ok(RepoLock { _file: file });
+        }
+        held.retain(|_, file| file.upgrade().is_some());
+        let file = Arc::new(LockFile::lock(&lock_path)?);
+        held.insert(lock_path, Arc::downgrade(&file));
+        Ok(RepoLock { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    fn lock_dir(name: &str) -> PathBuf {
+        let mut tmp = temp_dir();
+        tmp.push(name);
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        tmp
+    }
+
+    fn is_locked_by_other(err: Error) -> bool {
+        match err.downcast_ref::<RepoError>() {
+            Some(RepoError::RepoLocked(1)) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_repo_lock() {
+        let tmp = lock_dir("repo_lock");
+        let lock = RepoLock::acquire(&tmp).unwrap();
+        let lock_path = tmp.join(LOCK_FILE);
+        assert_eq!(std::fs::read_to_string(&lock_path).unwrap(), std::process::id().to_string());
+
+        // shared within the process, released with the last repo
+        let shared = RepoLock::acquire(&tmp).unwrap();
+        drop(lock);
+        assert!(lock_path.exists());
+        drop(shared);
+        assert!(!lock_path.exists());
+
+        // can be locked again
+        let lock = RepoLock::acquire(&tmp).unwrap();
+        assert!(lock_path.exists());
+        drop(lock);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_repo_lock_held() {
+        let tmp = lock_dir("repo_lock_held");
+        std::fs::create_dir_all(&tmp).unwrap();
+        // locked like by the process with pid 1
+        let path = tmp.join(LOCK_FILE);
+        std::fs::write(&path, "1").unwrap();
+        let file = File::open(&path).unwrap();
+        assert_eq!(unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) }, 0);
+        assert!(is_locked_by_other(RepoLock::acquire(&tmp).unwrap_err()));
+    }
+
+    #[test]
+    #[cfg(not(unix))]
+    fn test_repo_lock_held() {
+        let tmp = lock_dir("repo_lock_held");
+        std::fs::create_dir_all(&tmp).unwrap();
+        // created by the process with pid 1
+        std::fs::write(tmp.join(LOCK_FILE), "1").unwrap();
+        assert!(is_locked_by_other(RepoLock::acquire(&tmp).unwrap_err()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_repo_lock_stale() {
+        let tmp = lock_dir("repo_lock_stale");
+        std::fs::create_dir_all(&tmp).unwrap();
+        let path = tmp.join(LOCK_FILE);
+        // left behind by a crashed process, also before it wrote its pid
+        for stale in &["4194305", ""] {
+            std::fs::write(&path, stale).unwrap();
+            let lock = RepoLock::acquire(&tmp).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), std::process::id().to_string());
+            drop(lock);
+        }
+    }
+}
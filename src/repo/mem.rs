@@ -1,11 +1,14 @@
 //! Volatile memory backed repo
 use crate::block::{Cid, Block};
 use crate::error::Error;
-use crate::repo::{BlockStore, DataStore, Column};
+use crate::repo::{BlockStore, DataStore, Column, PinStore, PutResult, StoreStream};
+use futures::compat::*;
 use futures::future::FutureObj;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use tokio::io::AsyncRead;
 
 #[derive(Clone, Debug)]
 pub struct MemBlockStore {
@@ -39,6 +42,13 @@ impl BlockStore for MemBlockStore {
         FutureObj::new(Box::new(futures::future::ok(block)))
     }
 
+    fn block_size(&self, cid: &Cid) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        let size = self.blocks.lock().unwrap()
+            .get(cid)
+            .map(|block| block.size() as u64);
+        FutureObj::new(Box::new(futures::future::ok(size)))
+    }
+
     fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
         let cid = block.cid().to_owned();
         self.blocks.lock().unwrap()
@@ -46,21 +56,67 @@ impl BlockStore for MemBlockStore {
         FutureObj::new(Box::new(futures::future::ok(cid)))
     }
 
+    fn put_checked(&self, block: Block) -> FutureObj<'static, Result<PutResult, Error>> {
+        let cid = block.cid().to_owned();
+        let mut blocks = self.blocks.lock().unwrap();
+        let written = !blocks.contains_key(&cid);
+        if written {
+            blocks.insert(cid.clone(), block);
+        }
+        FutureObj::new(Box::new(futures::future::ok(PutResult::new(cid, written))))
+    }
+
     fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
         self.blocks.lock().unwrap().remove(cid);
         FutureObj::new(Box::new(futures::future::ok(())))
     }
+
+    fn list_stream(&self) -> StoreStream<Cid> {
+        let cids: Vec<Cid> = self.blocks.lock().unwrap().keys().cloned().collect();
+        Box::pin(futures::stream::iter(cids.into_iter().map(Ok)))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct MemDataStore {
     ipns: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    pin: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    tombstone: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    meta: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    config: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    alias: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    audit: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    mfs: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    streams: Arc<Mutex<HashMap<(Column, Vec<u8>), Vec<u8>>>>,
+}
+
+impl MemDataStore {
+    fn column(&self, col: Column) -> &Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>> {
+        match col {
+            Column::Ipns => &self.ipns,
+            Column::Pin => &self.pin,
+            Column::Tombstone => &self.tombstone,
+            Column::Meta => &self.meta,
+            Column::Config => &self.config,
+            Column::Alias => &self.alias,
+            Column::Audit => &self.audit,
+            Column::Mfs => &self.mfs,
+        }
+    }
 }
 
 impl DataStore for MemDataStore {
     fn new(_path: PathBuf) -> Self {
         MemDataStore {
             ipns: Arc::new(Mutex::new(HashMap::new())),
+            pin: Arc::new(Mutex::new(HashMap::new())),
+            tombstone: Arc::new(Mutex::new(HashMap::new())),
+            meta: Arc::new(Mutex::new(HashMap::new())),
+            config: Arc::new(Mutex::new(HashMap::new())),
+            alias: Arc::new(Mutex::new(HashMap::new())),
+            audit: Arc::new(Mutex::new(HashMap::new())),
+            mfs: Arc::new(Mutex::new(HashMap::new())),
+            streams: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -75,19 +131,16 @@ impl DataStore for MemDataStore {
     fn contains(&self, col: Column, key: &[u8]) ->
         FutureObj<'static, Result<bool, Error>>
     {
-        let map = match col {
-            Column::Ipns => &self.ipns
-        };
-        let contains = map.lock().unwrap().contains_key(key);
+        let map = self.column(col);
+        let contains = map.lock().unwrap().contains_key(key) ||
+            self.streams.lock().unwrap().contains_key(&(col, key.to_owned()));
         FutureObj::new(Box::new(futures::future::ok(contains)))
     }
 
     fn get(&self, col: Column, key: &[u8]) ->
         FutureObj<'static, Result<Option<Vec<u8>>, Error>>
     {
-        let map = match col {
-            Column::Ipns => &self.ipns
-        };
+        let map = self.column(col);
         let value = map.lock().unwrap().get(key).map(|value| value.to_owned());
         FutureObj::new(Box::new(futures::future::ok(value)))
     }
@@ -95,9 +148,7 @@ impl DataStore for MemDataStore {
     fn put(&self, col: Column, key: &[u8], value: &[u8]) ->
         FutureObj<'static, Result<(), Error>>
     {
-        let map = match col {
-            Column::Ipns => &self.ipns
-        };
+        let map = self.column(col);
         map.lock().unwrap().insert(key.to_owned(), value.to_owned());
         FutureObj::new(Box::new(futures::future::ok(())))
     }
@@ -105,19 +156,128 @@ impl DataStore for MemDataStore {
     fn remove(&self, col: Column, key: &[u8]) ->
         FutureObj<'static, Result<(), Error>>
     {
-        let map = match col {
-            Column::Ipns => &self.ipns
-        };
+        let map = self.column(col);
         map.lock().unwrap().remove(key);
+        self.streams.lock().unwrap().remove(&(col, key.to_owned()));
+        FutureObj::new(Box::new(futures::future::ok(())))
+    }
+
+    fn list_keys(&self, col: Column) ->
+        FutureObj<'static, Result<Vec<Vec<u8>>, Error>>
+    {
+        let keys = self.column(col).lock().unwrap().keys().cloned().collect();
+        FutureObj::new(Box::new(futures::future::ok(keys)))
+    }
+
+    fn remove_many(&self, col: Column, keys: Vec<Vec<u8>>) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        let map = self.column(col);
+        let mut map = map.lock().unwrap();
+        let mut streams = self.streams.lock().unwrap();
+        for key in keys {
+            map.remove(&key);
+            streams.remove(&(col, key));
+        }
+        FutureObj::new(Box::new(futures::future::ok(())))
+    }
+
+    fn get_stream(&self, col: Column, key: &[u8]) ->
+        FutureObj<'static, Result<Option<Box<dyn AsyncRead + Send>>, Error>>
+    {
+        let value = self.streams.lock().unwrap()
+            .get(&(col, key.to_owned()))
+            .map(|value| {
+                let reader: Box<dyn AsyncRead + Send> = Box::new(Cursor::new(value.to_owned()));
+                reader
+            });
+        FutureObj::new(Box::new(futures::future::ok(value)))
+    }
+
+    fn put_stream(&self, col: Column, key: &[u8], value: Box<dyn AsyncRead + Send>) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        let streams = self.streams.clone();
+        let key = key.to_owned();
+        FutureObj::new(Box::new(async move {
+            let (_, value) = await!(tokio::io::read_to_end(value, Vec::new()).compat())?;
+            streams.lock().unwrap().insert((col, key), value);
+            Ok(())
+        }))
+    }
+}
+
+/// Pin store that keeps the pins in memory instead of the data store.
+#[derive(Clone, Debug)]
+pub struct MemPinStore {
+    pins: Arc<Mutex<HashSet<Cid>>>,
+    direct: Arc<Mutex<HashSet<Cid>>>,
+}
+
+impl<TDataStore: DataStore> PinStore<TDataStore> for MemPinStore {
+    fn new(_data_store: TDataStore) -> Self {
+        MemPinStore {
+            pins: Arc::new(Mutex::new(HashSet::new())),
+            direct: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    fn pin(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.pins.lock().unwrap().insert(cid.to_owned());
+        self.direct.lock().unwrap().remove(cid);
+        FutureObj::new(Box::new(futures::future::ok(())))
+    }
+
+    fn pin_direct(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.pins.lock().unwrap().insert(cid.to_owned());
+        self.direct.lock().unwrap().insert(cid.to_owned());
         FutureObj::new(Box::new(futures::future::ok(())))
     }
+
+    fn unpin(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.pins.lock().unwrap().remove(cid);
+        self.direct.lock().unwrap().remove(cid);
+        FutureObj::new(Box::new(futures::future::ok(())))
+    }
+
+    fn is_pinned(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+        let pinned = self.pins.lock().unwrap().contains(cid);
+        FutureObj::new(Box::new(futures::future::ok(pinned)))
+    }
+
+    fn list(&self) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        let pins: Vec<Cid> = self.pins.lock().unwrap().iter().cloned().collect();
+        FutureObj::new(Box::new(futures::future::ok(pins)))
+    }
+
+    fn list_direct(&self) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        let pins: Vec<Cid> = self.direct.lock().unwrap().iter().cloned().collect();
+        FutureObj::new(Box::new(futures::future::ok(pins)))
+    }
+
+    fn pin_count(&self) -> FutureObj<'static, Result<u64, Error>> {
+        let count = self.pins.lock().unwrap().len() as u64;
+        FutureObj::new(Box::new(futures::future::ok(count)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::repo::testsuite::{run_block_store_tests, run_data_store_tests};
+    use futures::stream::StreamExt;
     use std::env::temp_dir;
 
+    #[test]
+    fn test_mem_blockstore_suite() {
+        run_block_store_tests(|| MemBlockStore::new(temp_dir()));
+    }
+
+    #[test]
+    fn test_mem_datastore_suite() {
+        run_data_store_tests(|| MemDataStore::new(temp_dir()));
+    }
+
     #[test]
     fn test_mem_blockstore() {
         let tmp = temp_dir();
@@ -152,6 +312,68 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_mem_blockstore_list() {
+        let tmp = temp_dir();
+        let store = MemBlockStore::new(tmp);
+        tokio::run_async(async move {
+            let block1 = Block::from("1");
+            let block2 = Block::from("2");
+            await!(store.put(block1.clone())).unwrap();
+            await!(store.put(block2.clone())).unwrap();
+
+            let mut list = await!(store.list()).unwrap();
+            let mut stream = Vec::new();
+            let mut cids = store.list_stream();
+            while let Some(cid) = await!(cids.next()) {
+                stream.push(cid.unwrap());
+            }
+            list.sort_by_key(|cid| cid.to_bytes());
+            stream.sort_by_key(|cid| cid.to_bytes());
+            assert_eq!(list, stream);
+            assert_eq!(list.len(), 2);
+            assert!(list.contains(block1.cid()));
+            assert!(list.contains(block2.cid()));
+        });
+    }
+
+    #[test]
+    fn test_mem_blockstore_shares_data() {
+        let tmp = temp_dir();
+        let store = MemBlockStore::new(tmp);
+        tokio::run_async(async move {
+            let data = vec![0; 1024 * 1024];
+            let block = Block::new(data, Block::from("1").cid().to_owned());
+            await!(store.put(block.clone())).unwrap();
+            let get = await!(store.get(block.cid())).unwrap().unwrap();
+            let other = await!(store.get(block.cid())).unwrap().unwrap();
+            // neither putting nor getting the block copies the data
+            assert_eq!(get.data().as_ptr(), block.data().as_ptr());
+            assert_eq!(other.data().as_ptr(), block.data().as_ptr());
+        });
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_mem_blockstore_get_stat() {
+        let tmp = temp_dir();
+        let store = MemBlockStore::new(tmp);
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            let cid = block.cid();
+
+            let (get, stats) = await!(store.get_stat(cid)).unwrap();
+            assert_eq!(get, None);
+            assert_eq!((stats.hits, stats.misses), (0, 1));
+
+            await!(store.put(block.clone())).unwrap();
+            let (get, stats) = await!(store.get_stat(cid)).unwrap();
+            assert_eq!(get, Some(block.clone()));
+            assert_eq!((stats.hits, stats.misses), (1, 0));
+            assert_eq!(stats.bytes_read, block.size() as u64);
+        });
+    }
+
     #[test]
     fn test_mem_datastore() {
         let tmp = temp_dir();
@@ -0,0 +1,177 @@
+//! Metadata attached to blocks
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{Column, DataStore, Repo, RepoTypes};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use core::future::Future;
+use std::io::Read;
+
+/// Ends the key of the index of a block's metadata keys.
+///
+/// Metadata keys are utf-8, so they never contain this byte.
+const INDEX_MARKER: u8 = 0xff;
+
+/// Returns the prefix of all metadata keys of `cid`.
+///
+/// The cid is prefixed with its length, so that the prefix of one cid
+/// never starts with the prefix of another.
+fn meta_prefix(cid: &Cid) -> Vec<u8> {
+    let cid = cid.to_bytes();
+    let mut prefix = Vec::with_capacity(cid.len() + 2);
+    prefix.write_u16::<BigEndian>(cid.len() as u16).unwrap();
+    prefix.extend_from_slice(&cid);
+    prefix
+}
+
+fn meta_key(cid: &Cid, key: &str) -> Vec<u8> {
+    let mut meta_key = meta_prefix(cid);
+    meta_key.extend_from_slice(key.as_bytes());
+    meta_key
+}
+
+/// Returns the key of the list of metadata keys of `cid`.
+fn index_key(cid: &Cid) -> Vec<u8> {
+    let mut index_key = meta_prefix(cid);
+    index_key.push(INDEX_MARKER);
+    index_key
+}
+
+fn encode_index(keys: &[String]) -> Vec<u8> {
+    let mut index = Vec::new();
+    for key in keys {
+        index.write_u16::<BigEndian>(key.len() as u16).unwrap();
+        index.extend_from_slice(key.as_bytes());
+    }
+    index
+}
+
+fn decode_index(mut index: &[u8]) -> Result<Vec<String>, Error> {
+    let mut keys = Vec::new();
+    while !index.is_empty() {
+        let mut key = vec![0; usize::from(index.read_u16::<BigEndian>()?)];
+        index.read_exact(&mut key)?;
+        keys.push(String::from_utf8(key)?);
+    }
+    Ok(keys)
+}
+
+/// Returns the stored keys of all metadata of `cid`, including the index.
+fn stored_keys<D: DataStore>(data_store: D, cid: Cid) ->
+impl Future<Output=Result<Vec<Vec<u8>>, Error>>
+{
+    async move {
+        let index_key = index_key(&cid);
+        let keys = match await!(data_store.get(Column::Meta, &index_key))? {
+            Some(index) => decode_index(&index)?,
+            None => return Ok(Vec::new()),
+        };
+        let mut stored: Vec<Vec<u8>> = keys.iter().map(|key| meta_key(&cid, key)).collect();
+        stored.push(index_key);
+        Ok(stored)
+    }
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Attaches the metadata `key` with `value` to a block.
+    pub fn set_block_meta(&self, cid: &Cid, key: &str, value: &[u8]) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let data_store = self.available_data_store().map(Clone::clone);
+        let cid = cid.to_owned();
+        let key = key.to_owned();
+        let value = value.to_owned();
+        async move {
+            let data_store = data_store?;
+            // the metadata keys of a block are indexed, so that removing
+            // the block doesn't need to scan all metadata
+            let index_key = index_key(&cid);
+            let mut keys = match await!(data_store.get(Column::Meta, &index_key))? {
+                Some(index) => decode_index(&index)?,
+                None => Vec::new(),
+            };
+            if !keys.contains(&key) {
+                keys.push(key.clone());
+                await!(data_store.put(Column::Meta, &index_key, &encode_index(&keys)))?;
+            }
+            await!(data_store.put(Column::Meta, &meta_key(&cid, &key), &value))
+        }
+    }
+
+    /// Returns the metadata `key` of a block.
+    pub fn get_block_meta(&self, cid: &Cid, key: &str) ->
+    impl Future<Output=Result<Option<Vec<u8>>, Error>>
+    {
+        let get = self.available_data_store()
+            .map(|data_store| data_store.get(Column::Meta, &meta_key(cid, key)));
+        async move {
+            await!(get?)
+        }
+    }
+
+    /// Removes all metadata of a block.
+    pub(crate) fn clear_block_meta(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        self.clear_blocks_meta(&[cid.to_owned()])
+    }
+
+    /// Removes all metadata of multiple blocks in a single batch.
+    pub(crate) fn clear_blocks_meta(&self, cids: &[Cid]) -> impl Future<Output=Result<(), Error>> {
+        let data_store = self.available_data_store().map(Clone::clone);
+        let cids = cids.to_owned();
+        async move {
+            let data_store = data_store?;
+            let mut stored = Vec::new();
+            for cid in cids {
+                stored.extend(await!(stored_keys(data_store.clone(), cid))?);
+            }
+            if stored.is_empty() {
+                return Ok(());
+            }
+            await!(data_store.remove_many(Column::Meta, stored))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::repo::{Repo, RepoOptions};
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_block_meta() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_block_meta");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let block = Block::from("1");
+        let cid = block.cid().to_owned();
+
+        let (repo, _) = Repo::<crate::Types>::new(RepoOptions::new(tmp.clone()));
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            await!(repo.put_block(block)).unwrap();
+            await!(repo.set_block_meta(&cid, "content-type", b"text/plain")).unwrap();
+        });
+
+        let (repo, _) = Repo::<crate::Types>::new(RepoOptions::new(tmp.clone()));
+        let cid = Block::from("1").cid().to_owned();
+        tokio::run_async(async move {
+            await!(repo.open()).unwrap();
+            let meta = await!(repo.get_block_meta(&cid, "content-type")).unwrap();
+            assert_eq!(meta, Some(b"text/plain".to_vec()));
+            assert_eq!(await!(repo.get_block_meta(&cid, "source")).unwrap(), None);
+
+            let other = await!(repo.put_block(Block::from("2"))).unwrap();
+            await!(repo.set_block_meta(&other, "source", b"1")).unwrap();
+            await!(repo.set_block_meta(&other, "source", b"2")).unwrap();
+
+            await!(repo.remove_block(&cid)).unwrap();
+            assert_eq!(await!(repo.get_block_meta(&cid, "content-type")).unwrap(), None);
+            assert_eq!(await!(repo.get_block_meta(&other, "source")).unwrap(), Some(b"2".to_vec()));
+            await!(repo.remove_block(&other)).unwrap();
+            assert_eq!(await!(repo.get_block_meta(&other, "source")).unwrap(), None);
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
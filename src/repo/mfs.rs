@@ -0,0 +1,35 @@
+//! Root of the mutable file system
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{Column, DataStore, Repo, RepoTypes};
+use core::future::Future;
+
+/// Key of the root cid in the mfs column.
+const ROOT_KEY: &[u8] = b"root";
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Returns the flushed root directory of the mutable file system.
+    pub fn mfs_root(&self) -> impl Future<Output=Result<Option<Cid>, Error>> {
+        let get = self.available_data_store()
+            .map(|data_store| data_store.get(Column::Mfs, ROOT_KEY));
+        async move {
+            match await!(get?)? {
+                Some(root) => Ok(Some(Cid::from(root)?)),
+                None => Ok(None),
+            }
+        }
+    }
+
+    /// Stores `root` as the root directory of the mutable file system.
+    ///
+    /// The blocks reachable from the root are kept by the garbage
+    /// collection like those of recursive pins.
+    pub fn set_mfs_root(&self, root: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let repo = self.clone();
+        let root = root.to_bytes();
+        async move {
+            let _guard = await!(repo.gc_guard());
+            await!(repo.available_data_store()?.put(Column::Mfs, ROOT_KEY, &root))
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Upgrades of the on-disk repo layout
+use crate::error::Error;
+use crate::repo::{RepoError, REPO_VERSION};
+use std::path::{Path, PathBuf};
+
+/// Name of the file holding the version of the repo layout.
+pub const VERSION_FILE: &str = "version";
+
+/// Upgrades the layout of a repo from version `from` to `from + 1`.
+#[derive(Clone, Copy)]
+pub struct Migration {
+    /// Version the migration starts from.
+    pub from: u32,
+    /// What the migration changes, for logging.
+    pub description: &'static str,
+    /// Rewrites the repo at the given path.
+    pub run: fn(&Path) -> Result<(), Error>,
+}
+
+/// Migrations of repos written by earlier versions, ordered by `from`.
+pub const MIGRATIONS: &[Migration] = &[];
+
+fn version_path(path: &Path) -> PathBuf {
+    path.join(VERSION_FILE)
+}
+
+/// Reads the version of the repo at `path`, returning `None` for repos
+/// without a version file.
+pub fn read_version(path: &Path) -> Result<Option<u32>, Error> {
+    match std::fs::read_to_string(version_path(path)) {
+        Ok(version) => match version.trim().parse() {
+            Ok(version) => Ok(Some(version)),
+            Err(_) => bail!("invalid repo version {:?}", version.trim()),
+        },
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes the version of the repo at `path`.
+///
+/// The file is replaced atomically so that a concurrent open never reads
+/// a partial version.
+pub fn write_version(path: &Path, version: u32) -> Result<(), Error> {
+    std::fs::create_dir_all(path)?;
+    let tmp_path = path.join(format!("{}.{:x}.tmp", VERSION_FILE, rand::random::<u64>()));
+    std::fs::write(&tmp_path, format!("{}\n", version))?;
+    std::fs::rename(tmp_path, version_path(path))?;
+    Ok(())
+}
+
+/// Writes the current version if the repo doesn't have a version file.
+pub(crate) fn init_version(path: &Path) -> Result<(), Error> {
+    if read_version(path)?.is_none() {
+        write_version(path, REPO_VERSION)?;
+    }
+    Ok(())
+}
+
+/// Checks that the repo can be opened without migrating it.
+///
+/// Repos without a version file were written before the version file
+/// was introduced and use the layout of version 1.
+pub(crate) fn check_version(path: &Path) -> Result<(), Error> {
+    match read_version(path)? {
+        None => Ok(()),
+        Some(version) if version == REPO_VERSION => Ok(()),
+        Some(version) if version > REPO_VERSION => Err(RepoError::UnsupportedVersion(version).into()),
+        Some(version) => Err(RepoError::MigrationRequired(version).into()),
+    }
+}
+
+/// Runs the `migrations` needed to bring the repo at `path` to `target`,
+/// returning the version of the repo afterwards.
+///
+/// The version is written after every migration, so that a failed run
+/// can be resumed.
+pub(crate) fn run_migrations(path: &Path, migrations: &[Migration], target: u32) ->
+    Result<u32, Error>
+{
+    let mut version = read_version(path)?.unwrap_or(1);
+    if version > target {
+        return Err(RepoError::UnsupportedVersion(version).into());
+    }
+    while version < target {
+        let migration = match migrations.iter().find(|migration| migration.from == version) {
+            Some(migration) => migration,
+            None => bail!("no migration from repo version {}", version),
+        };
+        info!("migrating repo from version {}: {}", version, migration.description);
+        (migration.run)(path)?;
+        version += 1;
+        write_version(path, version)?;
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    fn rename_marker(path: &Path) -> Result<(), Error> {
+        std::fs::rename(path.join("marker-1"), path.join("marker-2"))?;
+        Ok(())
+    }
+
+    fn fail(_path: &Path) -> Result<(), Error> {
+        bail!("failed")
+    }
+
+    #[test]
+    fn test_version_file() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_version_file");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        assert_eq!(read_version(&tmp).unwrap(), None);
+        check_version(&tmp).unwrap();
+
+        init_version(&tmp).unwrap();
+        assert_eq!(read_version(&tmp).unwrap(), Some(REPO_VERSION));
+
+        write_version(&tmp, REPO_VERSION + 1).unwrap();
+        match check_version(&tmp).unwrap_err().downcast_ref::<RepoError>() {
+            Some(RepoError::UnsupportedVersion(version)) => assert_eq!(*version, REPO_VERSION + 1),
+            _ => panic!("expected unsupported version"),
+        }
+        // never downgraded
+        init_version(&tmp).unwrap();
+        assert_eq!(read_version(&tmp).unwrap(), Some(REPO_VERSION + 1));
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_run_migrations() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_run_migrations");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        write_version(&tmp, 1).unwrap();
+        std::fs::write(tmp.join("marker-1"), b"").unwrap();
+        let migrations = [
+            Migration { from: 2, description: "fails", run: fail },
+            Migration { from: 1, description: "renames the marker", run: rename_marker },
+        ];
+
+        assert!(run_migrations(&tmp, &migrations, 3).is_err());
+        // the first migration is kept
+        assert_eq!(read_version(&tmp).unwrap(), Some(2));
+        assert!(tmp.join("marker-2").exists());
+
+        assert_eq!(run_migrations(&tmp, &migrations[1..], 2).unwrap(), 2);
+        assert!(run_migrations(&tmp, &migrations, 1).is_err());
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
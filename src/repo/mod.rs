@@ -1,44 +1,334 @@
 //! IPFS repo
-use crate::block::{Cid, Block};
+use crate::block::{cid_to_string, Base, Bytes, Cid, Block};
 use crate::error::Error;
 use crate::future::BlockFuture;
-use crate::path::IpfsPath;
+use crate::ipld::Selector;
+use crate::ipns::IpnsEntry;
+use crate::path::{IpfsPath, IpfsPathError};
 use crate::IpfsOptions;
 use core::future::Future;
+use ed25519_dalek::Keypair;
+use futures::channel::{mpsc, oneshot};
 use futures::future::FutureObj;
 use futures::join;
+use futures::stream::{Stream, StreamExt};
 use libp2p::PeerId;
 use std::marker::PhantomData;
-use std::path::PathBuf;
-use std::sync::mpsc::{channel, Sender, Receiver};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, SendError, Receiver};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::AsyncRead;
 
 pub mod mem;
 pub mod fs;
+pub mod bloom;
+pub mod buffered;
+pub mod cached;
+mod add;
+mod alias;
+mod audit;
+mod cancel;
+mod car;
+mod chunker;
+mod clock;
+pub(crate) mod content;
+mod copy;
+mod dag;
+pub mod error;
+#[cfg(any(test, feature = "testing"))]
+pub mod faulty;
+mod gc;
+mod ingest;
+mod limiter;
+mod lock;
+mod meta;
+mod mfs;
+pub mod migrations;
+mod pin;
+mod provide;
+pub mod retry;
+pub mod rocks;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "sled")]
+pub mod sled;
+mod session;
+mod spawner;
+#[cfg(test)]
+pub(crate) mod testsuite;
+mod tombstone;
+mod verify;
+mod view;
+#[cfg(feature = "metrics")]
+pub mod stats;
+
+pub use self::add::{
+    AddOptions, AddStats, DagLayout, Metadata, Mtime, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_LINKS,
+};
+pub use self::audit::{AuditEntry, AuditOp};
+pub use self::cancel::CancellationToken;
+pub use self::car::{BadBlockPolicy, ImportStats};
+pub use self::chunker::{
+    BuzhashChunker, Chunker, FixedChunker,
+    DEFAULT_BUZHASH_BITS, DEFAULT_BUZHASH_MAX, DEFAULT_BUZHASH_MIN,
+};
+pub use self::clock::{Clock, SystemClock};
+pub use self::dag::{block_links, MissingBlocks};
+pub use self::error::RepoError;
+pub use self::fs::{Durability, Layout};
+pub use self::gc::{GcGuard, GcHandle, GcStats};
+use self::gc::GcLock;
+use self::limiter::{Access, Limiter};
+use self::lock::RepoLock;
+pub use self::pin::{DataStorePinStore, PinMode, PinStat};
+pub use self::retry::RetryPolicy;
+use self::pin::PinCache;
+pub use self::rocks::{CompactionStyle, RocksTuning};
+pub use self::session::{Session, SessionId};
+pub use self::spawner::Spawner;
+pub use self::verify::RepairPolicy;
+pub use self::view::ReadView;
+use self::view::Holds;
+#[cfg(feature = "metrics")]
+pub use self::stats::OpStats;
 
 pub trait RepoTypes: Clone + Send + Sync + 'static {
     type TBlockStore: BlockStore;
     type TDataStore: DataStore;
+    type TPinStore: PinStore<Self::TDataStore>;
 }
 
 #[derive(Clone, Debug)]
 pub struct RepoOptions<TRepoTypes: RepoTypes> {
     _marker: PhantomData<TRepoTypes>,
     path: PathBuf,
+    blockstore_path: Option<PathBuf>,
+    datastore_path: Option<PathBuf>,
+    cid_base: Base,
+    max_depth: usize,
+    max_storage: Option<u64>,
+    tombstones: bool,
+    content_index: bool,
+    audit_log: bool,
+    degraded: bool,
+    ipns_cache_ttl: Duration,
+    ipns_record_ttl: Duration,
+    clock: Arc<dyn Clock>,
+    max_network_writes: usize,
+    hash_offload_threshold: usize,
+    rocks_tuning: RocksTuning,
+    blockstore_layout: Layout,
+    retry: RetryPolicy,
+    durability: Durability,
+    spawner: Spawner,
 }
 
-impl<TRepoTypes: RepoTypes> From<&IpfsOptions<TRepoTypes>> for RepoOptions<TRepoTypes> {
-    fn from(options: &IpfsOptions<TRepoTypes>) -> Self {
+/// Default limit for the depth of DAG traversals.
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
+/// Default number of blocks received from the network that are written
+/// at the same time.
+pub const DEFAULT_MAX_NETWORK_WRITES: usize = 4;
+
+/// Default time resolved ipns records are cached for.
+pub const DEFAULT_IPNS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Default time records written with `put_ipns_signed` are valid for.
+pub const DEFAULT_IPNS_RECORD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default size from which blocks are hashed off the async executor.
+pub const DEFAULT_HASH_OFFLOAD_THRESHOLD: usize = 1024 * 1024;
+
+/// Version of the repo layout.
+pub const REPO_VERSION: u32 = 1;
+
+impl<TRepoTypes: RepoTypes> RepoOptions<TRepoTypes> {
+    /// Creates `RepoOptions` for a repo at `path`.
+    pub fn new(path: PathBuf) -> Self {
         RepoOptions {
             _marker: PhantomData,
-            path: options.ipfs_path.clone(),
+            path,
+            blockstore_path: None,
+            datastore_path: None,
+            cid_base: Base::Base58btc,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_storage: None,
+            tombstones: false,
+            content_index: false,
+            audit_log: false,
+            degraded: false,
+            ipns_cache_ttl: DEFAULT_IPNS_CACHE_TTL,
+            ipns_record_ttl: DEFAULT_IPNS_RECORD_TTL,
+            clock: Arc::new(SystemClock),
+            max_network_writes: DEFAULT_MAX_NETWORK_WRITES,
+            hash_offload_threshold: DEFAULT_HASH_OFFLOAD_THRESHOLD,
+            rocks_tuning: RocksTuning::default(),
+            blockstore_layout: Layout::default(),
+            retry: RetryPolicy::default(),
+            durability: Durability::default(),
+            spawner: Spawner::default(),
         }
     }
+
+    /// Caches resolved ipns records for at most `ttl`. Signed records are
+    /// cached until their validity runs out if that is earlier. A zero
+    /// `ttl` disables the cache.
+    pub fn ipns_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.ipns_cache_ttl = ttl;
+        self
+    }
+
+    /// Signs records written with `put_ipns_signed` as valid for `ttl`.
+    pub fn ipns_record_ttl(mut self, ttl: Duration) -> Self {
+        self.ipns_record_ttl = ttl;
+        self
+    }
+
+    /// Writes at most `max` blocks received from the network at the same
+    /// time, so that they can't starve local reads.
+    ///
+    /// Local reads waiting for a running write get the next free slot
+    /// before any waiting write.
+    pub fn max_network_writes(mut self, max: usize) -> Self {
+        self.max_network_writes = max;
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system time.
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Records a tombstone for every removed block.
+    ///
+    /// Tombstones are kept until they are reaped with `reap_tombstones`.
+    pub fn tombstones(mut self, tombstones: bool) -> Self {
+        self.tombstones = tombstones;
+        self
+    }
+
+    /// Stores identical data put under different cids only once.
+    ///
+    /// The data is indexed by its sha2-256 digest, blocks with data that
+    /// is already stored are kept as aliases of the stored block.
+    pub fn content_index(mut self, content_index: bool) -> Self {
+        self.content_index = content_index;
+        self
+    }
+
+    /// Appends every put, removal, pin and unpin to a durable audit log.
+    ///
+    /// The log is read with `audit_log_since` and never truncated.
+    pub fn audit_log(mut self, audit_log: bool) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Opens the repo even if the data store fails to open.
+    ///
+    /// Getting and putting blocks keeps working. Ipns, pins, block metadata
+    /// and the audit log fail with `RepoError::DatastoreUnavailable`, and so
+    /// does removing blocks as their pins can't be checked. Useful for
+    /// read-only gateways.
+    pub fn degraded(mut self, degraded: bool) -> Self {
+        self.degraded = degraded;
+        self
+    }
+
+    /// Limits the total size of the stored blocks to `max_storage` bytes.
+    pub fn max_storage(mut self, max_storage: u64) -> Self {
+        self.max_storage = Some(max_storage);
+        self
+    }
+
+    /// Verifies blocks of at least `threshold` bytes with
+    /// `Spawner::spawn_blocking` instead of on the async executor.
+    ///
+    /// For small blocks handing the work to another thread costs more
+    /// than hashing them.
+    pub fn hash_offload_threshold(mut self, threshold: usize) -> Self {
+        self.hash_offload_threshold = threshold;
+        self
+    }
+
+    /// Tunes the write buffers and compaction of rocksdb backed stores.
+    pub fn rocks_tuning(mut self, tuning: RocksTuning) -> Self {
+        self.rocks_tuning = tuning;
+        self
+    }
+
+    /// Retries store operations failing with a transient error according
+    /// to `retry`. Operations are not retried by default.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Persists the writes of the data store according to `durability`.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Spawns the background tasks of the stores with `spawner`.
+    pub fn spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    /// Limits DAG traversals to `max_depth` links from the root.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Stores the blocks at `path` instead of `blockstore` in the repo.
+    pub fn blockstore_path(mut self, path: PathBuf) -> Self {
+        self.blockstore_path = Some(path);
+        self
+    }
+
+    /// Lays out the block files of file backed block stores according to
+    /// `layout`.
+    ///
+    /// Use `Layout::GoFlatfs` together with `blockstore_path` to open the
+    /// `blocks` directory of a go-ipfs repo.
+    pub fn blockstore_layout(mut self, layout: Layout) -> Self {
+        self.blockstore_layout = layout;
+        self
+    }
+
+    /// Stores the data at `path` instead of `datastore` in the repo.
+    pub fn datastore_path(mut self, path: PathBuf) -> Self {
+        self.datastore_path = Some(path);
+        self
+    }
+
+    /// Sets the multibase used for displaying cids.
+    pub fn cid_base(mut self, base: Base) -> Self {
+        self.cid_base = base;
+        self
+    }
+}
+
+impl<TRepoTypes: RepoTypes> From<&IpfsOptions<TRepoTypes>> for RepoOptions<TRepoTypes> {
+    fn from(options: &IpfsOptions<TRepoTypes>) -> Self {
+        RepoOptions::new(options.ipfs_path.clone())
+    }
 }
 
 pub fn create_repo<TRepoTypes: RepoTypes>(options: RepoOptions<TRepoTypes>) -> (Repo<TRepoTypes>, Receiver<RepoEvent>) {
     Repo::new(options)
 }
 
+/// A stream of items read from a store.
+pub type StoreStream<T> = Pin<Box<dyn Stream<Item=Result<T, Error>> + Send>>;
+
 pub trait BlockStore: Clone + Send + Sync + Unpin + 'static {
     fn new(path: PathBuf) -> Self;
     fn init(&self) ->
@@ -53,6 +343,207 @@ pub trait BlockStore: Clone + Send + Sync + Unpin + 'static {
         FutureObj<'static, Result<Cid, Error>>;
     fn remove(&self, cid: &Cid) ->
         FutureObj<'static, Result<(), Error>>;
+    /// Puts multiple blocks into the store, returning their cids in the
+    /// same order.
+    ///
+    /// Stores that can write batches more efficiently than single blocks
+    /// should override this.
+    fn put_many(&self, blocks: Vec<Block>) ->
+        FutureObj<'static, Result<Vec<Cid>, Error>>
+    {
+        let store = self.clone();
+        FutureObj::new(Box::new(async move {
+            let mut cids = Vec::with_capacity(blocks.len());
+            for block in blocks {
+                cids.push(await!(store.put(block))?);
+            }
+            Ok(cids)
+        }))
+    }
+    /// Puts a block into the store, also returning if the block was
+    /// written or already stored.
+    fn put_checked(&self, block: Block) ->
+        FutureObj<'static, Result<PutResult, Error>>
+    {
+        let store = self.clone();
+        FutureObj::new(Box::new(async move {
+            if await!(store.contains(block.cid()))? {
+                return Ok(PutResult::new(block.cid().to_owned(), false));
+            }
+            let cid = await!(store.put(block))?;
+            Ok(PutResult::new(cid, true))
+        }))
+    }
+    /// Returns the cids of all stored blocks without loading them into
+    /// memory at once.
+    fn list_stream(&self) -> StoreStream<Cid>;
+
+    /// Spawns background tasks with `spawner`.
+    fn with_spawner(self, _spawner: Spawner) -> Self {
+        self
+    }
+
+    /// Tunes the store if it is backed by rocksdb.
+    fn with_rocks_tuning(self, _tuning: RocksTuning) -> Self {
+        self
+    }
+
+    /// Lays out the block files according to `layout` if the store is
+    /// backed by files.
+    fn with_layout(self, _layout: Layout) -> Self {
+        self
+    }
+
+    /// Retries operations failing with a transient error according to
+    /// `retry`, if the store supports it.
+    fn with_retry(self, _retry: RetryPolicy) -> Self {
+        self
+    }
+
+    /// Persists pending writes.
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        FutureObj::new(Box::new(futures::future::ok(())))
+    }
+
+    /// Opens a store that fails to `open`, skipping damaged data.
+    fn open_with_recovery(&self) -> FutureObj<'static, Result<RecoveryReport, Error>> {
+        let open = self.open();
+        FutureObj::new(Box::new(async move {
+            await!(open)?;
+            Ok(RecoveryReport::default())
+        }))
+    }
+
+    /// Returns the data of a stored block.
+    fn get_data(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Bytes>, Error>> {
+        let get = self.get(cid);
+        FutureObj::new(Box::new(async move {
+            Ok(await!(get)?.map(|block| block.data().clone()))
+        }))
+    }
+
+    /// Returns the size of a stored block in bytes.
+    fn block_size(&self, cid: &Cid) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        let get = self.get(cid);
+        FutureObj::new(Box::new(async move {
+            Ok(await!(get)?.map(|block| block.size() as u64))
+        }))
+    }
+
+    /// Returns the space left for storing blocks in bytes, if the store
+    /// knows about it.
+    fn available_space(&self) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        FutureObj::new(Box::new(futures::future::ok(None)))
+    }
+
+    /// Returns the number and total size of the stored blocks.
+    ///
+    /// Lists all blocks by default, stores that keep count should
+    /// override this.
+    fn usage(&self) -> FutureObj<'static, Result<StoreUsage, Error>> {
+        let store = self.clone();
+        FutureObj::new(Box::new(async move {
+            let mut usage = StoreUsage::default();
+            let mut cids = store.list_stream();
+            while let Some(cid) = await!(cids.next()) {
+                // removed while listing
+                if let Some(size) = await!(store.block_size(&cid?))? {
+                    usage.objects += 1;
+                    usage.bytes += size;
+                }
+            }
+            Ok(usage)
+        }))
+    }
+
+    /// Writes a consistent copy of the store to `dest`.
+    fn snapshot(&self, _dest: PathBuf) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        FutureObj::new(Box::new(futures::future::err(format_err!(
+            "block store doesn't support snapshots"))))
+    }
+
+    /// Returns the cids of all stored blocks.
+    fn list(&self) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        let mut stream = self.list_stream();
+        FutureObj::new(Box::new(async move {
+            let mut cids = Vec::new();
+            while let Some(cid) = await!(stream.next()) {
+                cids.push(cid?);
+            }
+            Ok(cids)
+        }))
+    }
+    /// Returns the cids of all stored blocks sorted by their bytes.
+    ///
+    /// Unlike `list` the order is the same for every call and backend.
+    /// All cids are loaded into memory to sort them, which is expensive
+    /// for large stores.
+    fn list_sorted(&self) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        let list = self.list();
+        FutureObj::new(Box::new(async move {
+            let mut cids = await!(list)?;
+            cids.sort_by_key(|cid| cid.to_bytes());
+            Ok(cids)
+        }))
+    }
+
+    #[cfg(feature = "metrics")]
+    fn contains_stat(&self, cid: &Cid) ->
+        FutureObj<'static, Result<(bool, OpStats), Error>>
+    {
+        let start = Instant::now();
+        let future = self.contains(cid);
+        FutureObj::new(Box::new(async move {
+            let contains = await!(future)?;
+            let mut stats = OpStats::default();
+            if contains {
+                stats.hits = 1;
+            } else {
+                stats.misses = 1;
+            }
+            stats.elapsed = start.elapsed();
+            Ok((contains, stats))
+        }))
+    }
+
+    #[cfg(feature = "metrics")]
+    fn get_stat(&self, cid: &Cid) ->
+        FutureObj<'static, Result<(Option<Block>, OpStats), Error>>
+    {
+        let start = Instant::now();
+        let future = self.get(cid);
+        FutureObj::new(Box::new(async move {
+            let block = await!(future)?;
+            let mut stats = OpStats::default();
+            match block {
+                Some(ref block) => {
+                    stats.hits = 1;
+                    stats.bytes_read = block.size() as u64;
+                }
+                None => stats.misses = 1,
+            }
+            stats.elapsed = start.elapsed();
+            Ok((block, stats))
+        }))
+    }
+
+    #[cfg(feature = "metrics")]
+    fn put_stat(&self, block: Block) ->
+        FutureObj<'static, Result<(Cid, OpStats), Error>>
+    {
+        let start = Instant::now();
+        let size = block.size() as u64;
+        let future = self.put(block);
+        FutureObj::new(Box::new(async move {
+            let cid = await!(future)?;
+            let mut stats = OpStats::default();
+            stats.bytes_written = size;
+            stats.elapsed = start.elapsed();
+            Ok((cid, stats))
+        }))
+    }
 }
 
 pub trait DataStore: Clone + Send + Sync + Unpin + 'static {
@@ -69,47 +560,664 @@ pub trait DataStore: Clone + Send + Sync + Unpin + 'static {
         FutureObj<'static, Result<(), Error>>;
     fn remove(&self, col: Column, key: &[u8]) ->
         FutureObj<'static, Result<(), Error>>;
+    fn list_keys(&self, col: Column) ->
+        FutureObj<'static, Result<Vec<Vec<u8>>, Error>>;
+    /// Removes multiple keys from `col`.
+    ///
+    /// Stores that can write batches should override this.
+    fn remove_many(&self, col: Column, keys: Vec<Vec<u8>>) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        let store = self.clone();
+        FutureObj::new(Box::new(async move {
+            for key in keys {
+                await!(store.remove(col, &key))?;
+            }
+            Ok(())
+        }))
+    }
+    /// Spawns background tasks with `spawner`.
+    fn with_spawner(self, _spawner: Spawner) -> Self {
+        self
+    }
+    /// Tunes the store if it is backed by rocksdb.
+    fn with_rocks_tuning(self, _tuning: RocksTuning) -> Self {
+        self
+    }
+    /// Retries operations failing with a transient error according to
+    /// `retry`, if the store supports it.
+    fn with_retry(self, _retry: RetryPolicy) -> Self {
+        self
+    }
+    /// Persists writes according to `durability`, if the store has a
+    /// write-ahead log.
+    fn with_durability(self, _durability: Durability) -> Self {
+        self
+    }
+    /// Persists pending writes.
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        FutureObj::new(Box::new(futures::future::ok(())))
+    }
+    /// Returns the number and total size of the keys and values in all
+    /// columns, not counting streamed values.
+    ///
+    /// Reads all values by default, stores that keep count should
+    /// override this.
+    fn usage(&self) -> FutureObj<'static, Result<StoreUsage, Error>> {
+        let store = self.clone();
+        FutureObj::new(Box::new(async move {
+            let mut usage = StoreUsage::default();
+            for &col in Column::all() {
+                for key in await!(store.list_keys(col))? {
+                    // removed while listing
+                    if let Some(value) = await!(store.get(col, &key))? {
+                        usage.objects += 1;
+                        usage.bytes += (key.len() + value.len()) as u64;
+                    }
+                }
+            }
+            Ok(usage)
+        }))
+    }
+    /// Reclaims the space of removed and overwritten values in `col`.
+    fn compact(&self, _col: Column) -> FutureObj<'static, Result<(), Error>> {
+        FutureObj::new(Box::new(futures::future::ok(())))
+    }
+    /// Sets up the storage for a single column. Stores that need per
+    /// column setup can implement `init` with `init_columns`.
+    fn init_column(&self, _col: Column) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        FutureObj::new(Box::new(futures::future::ok(())))
+    }
+    /// Writes a consistent copy of the store to `dest`.
+    fn snapshot(&self, _dest: PathBuf) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        FutureObj::new(Box::new(futures::future::err(format_err!(
+            "data store doesn't support snapshots"))))
+    }
+    /// Returns a reader for a value written with `put_stream`.
+    fn get_stream(&self, col: Column, key: &[u8]) ->
+        FutureObj<'static, Result<Option<Box<dyn AsyncRead + Send>>, Error>>;
+    /// Writes a value that may be too large to keep in memory.
+    ///
+    /// Streamed values live next to the values written with `put`
+    /// and can only be read with `get_stream`. `contains`, `remove` and
+    /// `remove_many` see both kinds of values.
+    fn put_stream(&self, col: Column, key: &[u8], value: Box<dyn AsyncRead + Send>) ->
+        FutureObj<'static, Result<(), Error>>;
+}
+
+/// Runs `init` for all columns concurrently.
+///
+/// All columns are attempted even if some fail, the errors are combined
+/// into a single error.
+pub fn init_columns<F>(spawner: Spawner, init: F) -> impl Future<Output=Result<(), Error>>
+where F: Fn(Column) -> FutureObj<'static, Result<(), Error>> + Send + 'static
+{
+    async move {
+        let pending: Vec<_> = Column::all().iter().map(|col| {
+            let (tx, rx) = oneshot::channel();
+            let future = init(*col);
+            spawner.spawn(async move {
+                // sending only fails if the init was abandoned
+                let _ = tx.send(await!(future));
+            });
+            (*col, rx)
+        }).collect();
+        let mut errors = Vec::new();
+        for (col, rx) in pending {
+            match await!(rx) {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => errors.push(format!("{}: {}", col.name(), err)),
+                Err(_) => errors.push(format!("{}: init was cancelled", col.name())),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format_err!("failed to initialize columns: {}", errors.join(", ")))
+        }
+    }
+}
+
+/// Keeps track of the pinned blocks.
+///
+/// Pin stores are created from the repo's data store, but are free to
+/// keep their index elsewhere.
+pub trait PinStore<TDataStore: DataStore>: Clone + Send + Sync + Unpin + 'static {
+    fn new(data_store: TDataStore) -> Self;
+    fn pin(&self, cid: &Cid) ->
+        FutureObj<'static, Result<(), Error>>;
+    /// Pins a block without the blocks it links to.
+    fn pin_direct(&self, cid: &Cid) ->
+        FutureObj<'static, Result<(), Error>>;
+    fn unpin(&self, cid: &Cid) ->
+        FutureObj<'static, Result<(), Error>>;
+    fn is_pinned(&self, cid: &Cid) ->
+        FutureObj<'static, Result<bool, Error>>;
+    fn list(&self) ->
+        FutureObj<'static, Result<Vec<Cid>, Error>>;
+    /// Lists the blocks pinned with `pin_direct`.
+    fn list_direct(&self) ->
+        FutureObj<'static, Result<Vec<Cid>, Error>>;
+
+    /// Returns the number of pinned blocks.
+    fn pin_count(&self) -> FutureObj<'static, Result<u64, Error>> {
+        let list = self.list();
+        FutureObj::new(Box::new(async move {
+            Ok(await!(list)?.len() as u64)
+        }))
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum Column {
-    Ipns
+    Ipns,
+    Pin,
+    Tombstone,
+    Meta,
+    Config,
+    Alias,
+    Audit,
+    Mfs,
+}
+
+impl Column {
+    /// Returns all columns.
+    pub fn all() -> &'static [Column] {
+        &[
+            Column::Ipns, Column::Pin, Column::Tombstone, Column::Meta, Column::Config,
+            Column::Alias, Column::Audit, Column::Mfs,
+        ]
+    }
+
+    /// Returns the name of the column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Ipns => "ipns",
+            Column::Pin => "pin",
+            Column::Tombstone => "tombstone",
+            Column::Meta => "meta",
+            Column::Config => "config",
+            Column::Alias => "alias",
+            Column::Audit => "audit",
+            Column::Mfs => "mfs",
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Repo<TRepoTypes: RepoTypes> {
+    path: PathBuf,
     block_store: TRepoTypes::TBlockStore,
     data_store: TRepoTypes::TDataStore,
-    events: Sender<RepoEvent>,
+    pin_store: TRepoTypes::TPinStore,
+    pin_cache: Arc<Mutex<PinCache>>,
+    events: RepoEvents,
+    dedup: Arc<Mutex<DedupStats>>,
+    cid_base: Base,
+    max_depth: usize,
+    max_storage: Option<u64>,
+    tombstones: bool,
+    content_index: bool,
+    audit_log: bool,
+    audit_seq: Arc<Mutex<Option<u64>>>,
+    degraded: bool,
+    data_store_unavailable: Arc<AtomicBool>,
+    ipns_cache: Arc<Mutex<IpnsCache>>,
+    ipns_cache_ttl: Duration,
+    ipns_record_ttl: Duration,
+    clock: Arc<dyn Clock>,
+    store_access: Limiter,
+    hash_offload_threshold: usize,
+    spawner: Spawner,
+    gc_runs: Arc<AtomicUsize>,
+    gc_lock: GcLock,
+    sessions: Arc<AtomicUsize>,
+    holds: Arc<Mutex<Holds>>,
+    initialized: Once,
+    opened: Once,
+    lock: Arc<Mutex<Option<RepoLock>>>,
 }
 
 #[derive(Clone, Debug)]
 pub enum RepoEvent {
     WantBlock(Cid),
+    /// A block is wanted for the session, see `Session`.
+    WantBlockInSession(Cid, SessionId),
+    /// All clones of the session were dropped.
+    EndSession(SessionId),
+    WantDag(Cid, Selector),
     ProvideBlock(Cid),
+    ProvideBlocks(Vec<Cid>),
     UnprovideBlock(Cid),
+    GarbageCollected(GcStats),
+}
+
+/// Outcome of `BlockStore::put_checked`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PutResult {
+    /// Content id of the block.
+    pub cid: Cid,
+    /// `false` if the block was already stored.
+    pub written: bool,
+}
+
+impl PutResult {
+    /// Creates a new `PutResult`.
+    pub fn new(cid: Cid, written: bool) -> Self {
+        PutResult {
+            cid,
+            written,
+        }
+    }
+}
+
+/// Statistics about the stored blocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RepoStat {
+    /// Number of stored blocks.
+    pub num_blocks: u64,
+    /// Total size of the stored blocks in bytes.
+    pub total_size: u64,
+}
+
+/// Number and total size of the entries of a store.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StoreUsage {
+    /// Number of stored blocks or values.
+    pub objects: u64,
+    /// Total size in bytes.
+    pub bytes: u64,
+}
+
+/// Statistics about the whole repo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepoSummary {
+    /// Number of stored blocks.
+    pub num_objects: u64,
+    /// Total size of the blocks and the data store in bytes.
+    pub repo_size: u64,
+    /// Path of the repo.
+    pub repo_path: PathBuf,
+    /// Version of the repo layout.
+    pub version: u32,
+}
+
+/// What `open_with_recovery` found in the block store.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecoveryReport {
+    /// Number of intact blocks.
+    pub recovered: u64,
+    /// Number of intact blocks that were moved to the current layout.
+    pub migrated: u64,
+    /// Damaged block files, moved out of the way for inspection.
+    pub quarantined: Vec<PathBuf>,
+}
+
+/// Counts how many of the blocks put into the repo were already stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DedupStats {
+    /// Number of newly stored blocks.
+    pub unique: u64,
+    /// Number of blocks that were already stored.
+    pub duplicate: u64,
+    /// Total size of the blocks that were already stored.
+    pub bytes_saved: u64,
+}
+
+/// What `get_block_with` does if a block isn't stored locally.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FetchMode {
+    /// Return `None` without asking the network.
+    LocalOnly,
+    /// Ask the network and fail with `RepoError::FetchTimeout` if the
+    /// block didn't arrive in time.
+    NetworkWithTimeout(Duration),
+    /// Ask the network and wait until the block arrives.
+    NetworkBlocking,
+}
+
+/// Where a block was retrived from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockSource {
+    /// The block was in the block store.
+    Local,
+    /// The block was requested from the network.
+    Network,
+}
+
+/// Resolved ipns records.
+#[derive(Debug, Default)]
+struct IpnsCache {
+    entries: HashMap<PeerId, (IpfsPath, SystemTime)>,
+    /// Incremented on every invalidation, so that lookups that started
+    /// before a record was updated don't cache the old record.
+    generation: u64,
+}
+
+impl IpnsCache {
+    fn get(&mut self, peer_id: &PeerId, now: SystemTime) -> Option<IpfsPath> {
+        let expired = match self.entries.get(peer_id) {
+            Some((path, expires)) if *expires > now => return Some(path.clone()),
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            self.entries.remove(peer_id);
+        }
+        None
+    }
+
+    fn insert(&mut self, peer_id: PeerId, path: IpfsPath, expires: SystemTime, generation: u64) {
+        if generation == self.generation {
+            self.entries.insert(peer_id, (path, expires));
+        }
+    }
+
+    fn invalidate(&mut self, peer_id: &PeerId) {
+        self.generation += 1;
+        self.entries.remove(peer_id);
+    }
+}
+
+#[derive(Debug)]
+enum OnceState {
+    Pending,
+    Running(Vec<oneshot::Sender<Result<(), String>>>),
+    Done,
+}
+
+/// Runs an operation until it succeeds once.
+///
+/// Callers arriving while the operation runs wait for its result instead
+/// of running it again. If the running caller is dropped before it
+/// finishes, a waiting caller runs its own operation instead.
+#[derive(Clone, Debug)]
+struct Once {
+    state: Arc<Mutex<OnceState>>,
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Once {
+            state: Arc::new(Mutex::new(OnceState::Pending)),
+        }
+    }
+}
+
+/// Resets a running `Once` whose operation was dropped unfinished.
+struct RunningGuard {
+    state: Arc<Mutex<OnceState>>,
+    finished: bool,
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            // dropping the waiters wakes them to run the operation.
+            *self.state.lock().unwrap() = OnceState::Pending;
+        }
+    }
+}
+
+impl Once {
+    fn run<F: Future<Output=Result<(), Error>>>(&self, future: F) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let state = self.state.clone();
+        async move {
+            loop {
+                let waiting = {
+                    let mut state = state.lock().unwrap();
+                    match *state {
+                        OnceState::Done => return Ok(()),
+                        OnceState::Running(ref mut waiters) => {
+                            let (tx, rx) = oneshot::channel();
+                            waiters.push(tx);
+                            Some(rx)
+                        }
+                        OnceState::Pending => {
+                            *state = OnceState::Running(Vec::new());
+                            None
+                        }
+                    }
+                };
+                match waiting {
+                    Some(rx) => match await!(rx) {
+                        Ok(res) => return res.map_err(|err| format_err!("{}", err)),
+                        // the running caller was dropped
+                        Err(_) => continue,
+                    },
+                    None => break,
+                }
+            }
+            let mut guard = RunningGuard {
+                state: state.clone(),
+                finished: false,
+            };
+            let res = await!(future);
+            guard.finished = true;
+            let next = if res.is_ok() {
+                OnceState::Done
+            } else {
+                // allow retrying failed operations
+                OnceState::Pending
+            };
+            let waiters = match std::mem::replace(&mut *state.lock().unwrap(), next) {
+                OnceState::Running(waiters) => waiters,
+                _ => Vec::new(),
+            };
+            for waiter in waiters {
+                // sending only fails if the waiter is gone
+                let _ = waiter.send(res.as_ref().map(|_| ()).map_err(|err| err.to_string()));
+            }
+            res
+        }
+    }
+
+    /// Allows running the operation again, e.g. after closing the repo.
+    fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let OnceState::Done = *state {
+            *state = OnceState::Pending;
+        }
+    }
+}
+
+type EventFilter = Box<dyn Fn(&RepoEvent) -> bool + Send>;
+
+/// Receiving end of a subscription.
+enum Subscriber {
+    Receiver(Sender<RepoEvent>),
+    Stream(mpsc::UnboundedSender<RepoEvent>),
+}
+
+impl Subscriber {
+    /// Returns `false` if the subscriber went away.
+    fn send(&self, event: RepoEvent) -> bool {
+        match self {
+            Subscriber::Receiver(sender) => sender.send(event).is_ok(),
+            Subscriber::Stream(sender) => sender.unbounded_send(event).is_ok(),
+        }
+    }
+}
+
+/// Sends repo events to the daemon and to all subscribers.
+#[derive(Clone)]
+struct RepoEvents {
+    sender: Sender<RepoEvent>,
+    subscribers: Arc<Mutex<Vec<(EventFilter, Subscriber)>>>,
+}
+
+impl RepoEvents {
+    fn new(sender: Sender<RepoEvent>) -> Self {
+        RepoEvents {
+            sender,
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn subscribe(&self, filter: EventFilter) -> Receiver<RepoEvent> {
+        let (sender, receiver) = channel::<RepoEvent>();
+        self.subscribers.lock().unwrap().push((filter, Subscriber::Receiver(sender)));
+        receiver
+    }
+
+    fn subscribe_stream(&self, filter: EventFilter) -> mpsc::UnboundedReceiver<RepoEvent> {
+        let (sender, receiver) = mpsc::unbounded::<RepoEvent>();
+        self.subscribers.lock().unwrap().push((filter, Subscriber::Stream(sender)));
+        receiver
+    }
+
+    fn send(&self, event: RepoEvent) -> Result<(), SendError<RepoEvent>> {
+        // subscribers that went away are dropped.
+        self.subscribers.lock().unwrap().retain(|(filter, sender)| {
+            !filter(&event) || sender.send(event.clone()).is_ok()
+        });
+        self.sender.send(event)
+    }
+}
+
+impl std::fmt::Debug for RepoEvents {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RepoEvents")
+            .field("subscribers", &self.subscribers.lock().unwrap().len())
+            .finish()
+    }
 }
 
 impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     pub fn new(options: RepoOptions<TRepoTypes>) -> (Self, Receiver<RepoEvent>) {
-        let mut blockstore_path = options.path.clone();
-        let mut datastore_path = options.path;
-        blockstore_path.push("blockstore");
-        datastore_path.push("datastore");
-        let block_store = TRepoTypes::TBlockStore::new(blockstore_path);
-        let data_store = TRepoTypes::TDataStore::new(datastore_path);
+        let blockstore_path = options.blockstore_path.clone().unwrap_or_else(|| {
+            let mut path = options.path.clone();
+            path.push("blockstore");
+            path
+        });
+        let datastore_path = options.datastore_path.clone().unwrap_or_else(|| {
+            let mut path = options.path.clone();
+            path.push("datastore");
+            path
+        });
+        let block_store = TRepoTypes::TBlockStore::new(blockstore_path)
+            .with_spawner(options.spawner.clone())
+            .with_rocks_tuning(options.rocks_tuning)
+            .with_layout(options.blockstore_layout)
+            .with_retry(options.retry);
+        let data_store = TRepoTypes::TDataStore::new(datastore_path)
+            .with_spawner(options.spawner.clone())
+            .with_rocks_tuning(options.rocks_tuning)
+            .with_retry(options.retry)
+            .with_durability(options.durability);
+        let pin_store = TRepoTypes::TPinStore::new(data_store.clone());
         let (sender, receiver) = channel::<RepoEvent>();
         (Repo {
+            path: options.path.clone(),
             block_store,
             data_store,
-            events: sender,
+            pin_store,
+            pin_cache: Arc::new(Mutex::new(PinCache::default())),
+            events: RepoEvents::new(sender),
+            dedup: Arc::new(Mutex::new(DedupStats::default())),
+            cid_base: options.cid_base,
+            max_depth: options.max_depth,
+            max_storage: options.max_storage,
+            tombstones: options.tombstones,
+            content_index: options.content_index,
+            audit_log: options.audit_log,
+            audit_seq: Arc::new(Mutex::new(None)),
+            degraded: options.degraded,
+            data_store_unavailable: Arc::new(AtomicBool::new(false)),
+            ipns_cache: Arc::new(Mutex::new(IpnsCache::default())),
+            ipns_cache_ttl: options.ipns_cache_ttl,
+            ipns_record_ttl: options.ipns_record_ttl,
+            clock: options.clock,
+            store_access: Limiter::new(options.max_network_writes),
+            hash_offload_threshold: options.hash_offload_threshold,
+            spawner: options.spawner,
+            gc_runs: Arc::new(AtomicUsize::new(0)),
+            gc_lock: GcLock::default(),
+            sessions: Arc::new(AtomicUsize::new(0)),
+            holds: Arc::new(Mutex::new(Holds::default())),
+            initialized: Once::default(),
+            opened: Once::default(),
+            lock: Arc::new(Mutex::new(None)),
         }, receiver)
     }
 
+    /// Returns the multibase used for displaying cids.
+    pub fn cid_base(&self) -> Base {
+        self.cid_base
+    }
+
+    /// Returns the maximum depth of DAG traversals.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Formats a cid using the configured multibase.
+    pub fn format_cid(&self, cid: &Cid) -> String {
+        cid_to_string(cid, self.cid_base)
+    }
+
+    /// Formats a path using the configured multibase.
+    ///
+    /// Paths are always stored in their canonical form, this only affects
+    /// how they are displayed.
+    pub fn format_path(&self, path: &IpfsPath) -> String {
+        path.to_string_base(self.cid_base)
+    }
+
+    /// Parses a cid in any multibase, so that the output of `format_cid`
+    /// can always be parsed.
+    ///
+    /// Fails with `RepoError::InvalidCid`.
+    pub fn parse_cid(&self, string: &str) -> Result<Cid, Error> {
+        Cid::from(string).map_err(|_| RepoError::InvalidCid(string.to_owned()).into())
+    }
+
+    /// Parses a path in any multibase, so that the output of
+    /// `format_path` can always be parsed.
+    ///
+    /// Fails with `IpfsPathError::InvalidPath`, also if the cid of the
+    /// path is invalid.
+    pub fn parse_path(&self, string: &str) -> Result<IpfsPath, Error> {
+        IpfsPath::from_str(string)
+            .map_err(|_| IpfsPathError::InvalidPath(string.to_owned()).into())
+    }
+
+    /// Subscribes to all repo events.
+    pub fn subscribe_events(&self) -> Receiver<RepoEvent> {
+        self.events.subscribe(Box::new(|_| true))
+    }
+
+    /// Subscribes to the repo events matching `filter`.
+    pub fn subscribe_events_filtered<F>(&self, filter: F) -> Receiver<RepoEvent>
+    where
+        F: Fn(&RepoEvent) -> bool + Send + 'static,
+    {
+        self.events.subscribe(Box::new(filter))
+    }
+
+    /// Subscribes to the repo events as a stream.
+    ///
+    /// The stream ends once all clones of the repo are dropped.
+    pub fn event_stream(&self) -> impl Stream<Item=RepoEvent> {
+        self.events.subscribe_stream(Box::new(|_| true))
+    }
+
+    /// Initializes the repo.
+    ///
+    /// Concurrent calls wait for the first one, calls after a successful
+    /// init do nothing.
     pub fn init(&self) -> impl Future<Output=Result<(), Error>> {
         let block_store = self.block_store.clone();
         let data_store = self.data_store.clone();
-        async move {
+        let path = self.path.clone();
+        self.initialized.run(async move {
+            migrations::init_version(&path)?;
             let f1 = block_store.init();
             let f2 = data_store.init();
             let (r1, r2) = join!(f1, f2);
@@ -118,78 +1226,717 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
             } else {
                 r2
             }
-        }
+        })
     }
 
+    /// Opens the repo.
+    ///
+    /// Concurrent calls wait for the first one, calls after a successful
+    /// open do nothing. Fails with `RepoError::UnsupportedVersion` for
+    /// repos written by newer versions and with
+    /// `RepoError::MigrationRequired` for repos that need to be migrated
+    /// first.
+    ///
+    /// The repo is locked until it is closed or dropped, opening it from
+    /// another process fails with `RepoError::RepoLocked`. Opening the
+    /// same path with another `Repo` of this process succeeds, the repos
+    /// share the lock.
     pub fn open(&self) -> impl Future<Output=Result<(), Error>> {
         let block_store = self.block_store.clone();
         let data_store = self.data_store.clone();
-        async move {
+        let degraded = self.degraded;
+        let unavailable = self.data_store_unavailable.clone();
+        let path = self.path.clone();
+        let repo_lock = self.lock.clone();
+        self.opened.run(async move {
+            let lock = RepoLock::acquire(&path)?;
+            migrations::check_version(&path)?;
             let f1 = block_store.open();
             let f2 = data_store.open();
             let (r1, r2) = join!(f1, f2);
-            if r1.is_err() {
-                r1
-            } else {
-                r2
+            match (r1, r2) {
+                (Err(err), _) => return Err(err),
+                (Ok(()), Err(ref err)) if degraded => {
+                    warn!("opening without datastore: {}", err);
+                    unavailable.store(true, Ordering::SeqCst);
+                }
+                (Ok(()), r2) => r2?,
             }
+            *repo_lock.lock().unwrap() = Some(lock);
+            Ok(())
+        })
+    }
+
+    /// Flushes the stores and releases the lock of the repo.
+    ///
+    /// The repo can be opened again afterwards.
+    pub fn close(&self) -> impl Future<Output=Result<(), Error>> {
+        let flush = self.flush();
+        let repo = self.clone();
+        async move {
+            let res = await!(flush);
+            repo.lock.lock().unwrap().take();
+            repo.opened.reset();
+            res
+        }
+    }
+
+    /// Upgrades the layout of a repo written by an earlier version,
+    /// returning the version of the repo afterwards.
+    ///
+    /// Call this before opening the repo.
+    pub fn migrate(&self) -> impl Future<Output=Result<u32, Error>> {
+        let path = self.path.clone();
+        async move {
+            migrations::run_migrations(&path, migrations::MIGRATIONS, REPO_VERSION)
+        }
+    }
+
+    /// Returns the data store unless the repo was opened without it.
+    pub(crate) fn available_data_store(&self) -> Result<&TRepoTypes::TDataStore, Error> {
+        if self.data_store_unavailable.load(Ordering::SeqCst) {
+            return Err(RepoError::DatastoreUnavailable.into());
+        }
+        Ok(&self.data_store)
+    }
+
+    /// Opens the repo, recovering from damaged block files and indices.
+    ///
+    /// Damaged block files are quarantined and the indices are rebuilt
+    /// from the intact blocks. Use this if `open` fails.
+    pub fn open_with_recovery(&self) -> impl Future<Output=Result<RecoveryReport, Error>> {
+        let block_store = self.block_store.clone();
+        let data_store = self.data_store.clone();
+        let path = self.path.clone();
+        let repo_lock = self.lock.clone();
+        async move {
+            let lock = RepoLock::acquire(&path)?;
+            migrations::check_version(&path)?;
+            let report = await!(block_store.open_with_recovery())?;
+            for path in &report.quarantined {
+                warn!("quarantined damaged block file {:?}", path);
+            }
+            await!(data_store.open())?;
+            *repo_lock.lock().unwrap() = Some(lock);
+            Ok(report)
+        }
+    }
+
+    /// Writes a point-in-time copy of the repo to `dest`.
+    ///
+    /// The copy can be opened like any other repo.
+    pub fn snapshot(&self, dest: &Path) -> impl Future<Output=Result<(), Error>> {
+        let mut blockstore_path = dest.to_owned();
+        let mut datastore_path = dest.to_owned();
+        blockstore_path.push("blockstore");
+        datastore_path.push("datastore");
+        let f1 = self.block_store.snapshot(blockstore_path);
+        let f2 = self.data_store.snapshot(datastore_path);
+        async move {
+            let (r1, r2) = join!(f1, f2);
+            r1?;
+            r2
+        }
+    }
+
+    /// Persists pending writes of the block and data stores while
+    /// keeping the repo open.
+    pub fn flush(&self) -> impl Future<Output=Result<(), Error>> {
+        let block_store = self.block_store.flush();
+        let data_store = self.data_store.flush();
+        async move {
+            let (res1, res2) = join!(block_store, data_store);
+            res1?;
+            res2?;
+            Ok(())
+        }
+    }
+
+    /// Reclaims the space of removed and overwritten values in all
+    /// columns of the data store. The repo stays usable meanwhile.
+    pub fn compact_datastore(&self) -> impl Future<Output=Result<(), Error>> {
+        let data_store = self.data_store.clone();
+        async move {
+            for &col in Column::all() {
+                await!(data_store.compact(col))?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns the number and total size of the stored blocks.
+    pub fn repo_stat(&self) -> impl Future<Output=Result<RepoStat, Error>> {
+        let usage = self.block_store.usage();
+        async move {
+            let usage = await!(usage)?;
+            Ok(RepoStat {
+                num_blocks: usage.objects,
+                total_size: usage.bytes,
+            })
+        }
+    }
+
+    /// Returns the path of the repo.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of stored blocks, the size of the repo, its
+    /// path and its version.
+    ///
+    /// Without the data store only the blocks are counted.
+    pub fn stat(&self) -> impl Future<Output=Result<RepoSummary, Error>> {
+        let blocks = self.block_store.usage();
+        let data = self.available_data_store().ok().map(|data_store| data_store.usage());
+        let repo_path = self.path.clone();
+        async move {
+            let blocks = await!(blocks)?;
+            let data = match data {
+                Some(data) => await!(data)?,
+                None => StoreUsage::default(),
+            };
+            Ok(RepoSummary {
+                num_objects: blocks.objects,
+                repo_size: blocks.bytes + data.bytes,
+                repo_path,
+                version: REPO_VERSION,
+            })
+        }
+    }
+
+    /// Reads the locally stored blocks of `cids` so that caching block
+    /// stores keep them in memory, returning the number of found blocks.
+    ///
+    /// Blocks that aren't stored locally are skipped.
+    pub fn warm(&self, cids: &[Cid]) -> impl Future<Output=Result<usize, Error>> {
+        let block_store = self.block_store.clone();
+        let cids = cids.to_vec();
+        async move {
+            let mut found = 0;
+            for cid in cids {
+                if await!(block_store.get(&cid))?.is_some() {
+                    found += 1;
+                }
+            }
+            Ok(found)
+        }
+    }
+
+    /// Streams the cids of the locally stored blocks in no particular
+    /// order, without loading all of them into memory.
+    pub fn refs_local(&self) -> StoreStream<Cid> {
+        self.block_store.list_stream()
+    }
+
+    /// Lists the cids of the stored blocks sorted by their bytes.
+    ///
+    /// See `BlockStore::list_sorted` for the cost of sorting.
+    pub fn list_blocks_sorted(&self) -> impl Future<Output=Result<Vec<Cid>, Error>> {
+        self.block_store.list_sorted()
+    }
+
+    /// Lists the stored blocks with a size between `min` and `max` bytes,
+    /// inclusive, together with their size.
+    pub fn blocks_by_size(&self, min: u64, max: u64) ->
+    impl Future<Output=Result<Vec<(Cid, u64)>, Error>>
+    {
+        let block_store = self.block_store.clone();
+        async move {
+            let mut blocks = Vec::new();
+            let mut cids = block_store.list_stream();
+            while let Some(cid) = await!(cids.next()) {
+                let cid = cid?;
+                // removed while listing
+                if let Some(size) = await!(block_store.block_size(&cid))? {
+                    if min <= size && size <= max {
+                        blocks.push((cid, size));
+                    }
+                }
+            }
+            Ok(blocks)
+        }
+    }
+
+    /// Checks if `additional_bytes` of blocks can be stored without
+    /// exceeding the configured `max_storage` or the available space.
+    pub fn can_store(&self, additional_bytes: u64) -> impl Future<Output=Result<bool, Error>> {
+        let stat = self.repo_stat();
+        let available = self.block_store.available_space();
+        let max_storage = self.max_storage;
+        async move {
+            if let Some(max_storage) = max_storage {
+                if await!(stat)?.total_size + additional_bytes > max_storage {
+                    return Ok(false);
+                }
+            }
+            if let Some(available) = await!(available)? {
+                if additional_bytes > available {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+    }
+
+    /// Returns the deduplication stats of all blocks put since the repo
+    /// was created.
+    pub fn dedup_stats(&self) -> DedupStats {
+        *self.dedup.lock().unwrap()
+    }
+
+    fn insert_block(&self, block: Block) ->
+    impl Future<Output=Result<PutResult, Error>>
+    {
+        let repo = self.clone();
+        let dedup = self.dedup.clone();
+        let size = block.size() as u64;
+        async move {
+            let _guard = await!(repo.gc_guard());
+            let indexed = repo.content_index && repo.available_data_store().is_ok();
+            if indexed && !await!(repo.block_store.contains(block.cid()))? {
+                if let Some(stored) = await!(repo.find_stored_copy(&block))? {
+                    await!(repo.put_alias(block.cid(), &stored))?;
+                    repo.touch_indirect_pin(block.cid());
+                    await!(repo.audit(AuditOp::Put, block.cid()))?;
+                    let mut dedup = dedup.lock().unwrap();
+                    dedup.duplicate += 1;
+                    dedup.bytes_saved += size;
+                    // new under this cid even though the data isn't written
+                    return Ok(PutResult::new(block.cid().to_owned(), true));
+                }
+                await!(repo.index_content(&block))?;
+            }
+            let put = await!(repo.block_store.put_checked(block))?;
+            await!(repo.record_put(&put, size))?;
+            Ok(put)
+        }
+    }
+
+    /// Clears the tombstone and audits the put of a written block and
+    /// updates the deduplication stats with a put of `size` bytes.
+    fn record_put(&self, put: &PutResult, size: u64) -> impl Future<Output=Result<(), Error>> {
+        let repo = self.clone();
+        let PutResult { cid, written } = put.to_owned();
+        async move {
+            if written {
+                repo.touch_indirect_pin(&cid);
+                // without the data store there is no tombstone to clear
+                if repo.tombstones && repo.available_data_store().is_ok() {
+                    await!(repo.clear_tombstone(&cid))?;
+                }
+                await!(repo.audit(AuditOp::Put, &cid))?;
+            }
+            let mut dedup = repo.dedup.lock().unwrap();
+            if written {
+                dedup.unique += 1;
+            } else {
+                dedup.duplicate += 1;
+                dedup.bytes_saved += size;
+            }
+            Ok(())
         }
     }
 
     /// Puts a block into the block store.
+    ///
+    /// Blocks that weren't stored before are announced, they stay in the
+    /// provide queue until then.
     pub fn put_block(&self, block: Block) ->
     impl Future<Output=Result<Cid, Error>>
     {
-        let events = self.events.clone();
-        let block_store = self.block_store.clone();
+        let repo = self.clone();
+        let insert = self.insert_block(block);
         async move {
-            let cid = await!(block_store.put(block))?;
+            let PutResult { cid, written } = await!(insert)?;
+            if !written {
+                return Ok(cid);
+            }
+            // the queue can't be kept without the data store
+            if repo.available_data_store().is_ok() {
+                await!(repo.enqueue_provide(&cid))?;
+            }
             // sending only fails if no one is listening anymore
             // and that is okay with us.
-            let _ = events.send(RepoEvent::ProvideBlock(cid.clone()));
+            let _ = repo.events.send(RepoEvent::ProvideBlock(cid.clone()));
             Ok(cid)
         }
     }
 
+    /// Puts multiple blocks like `put_block`, returning their cids in the
+    /// same order.
+    ///
+    /// Stops at the first block that fails, the blocks before it stay
+    /// stored.
+    pub fn put_many(&self, blocks: Vec<Block>) ->
+    impl Future<Output=Result<Vec<Cid>, Error>>
+    {
+        let repo = self.clone();
+        async move {
+            let mut cids = Vec::with_capacity(blocks.len());
+            for block in blocks {
+                cids.push(await!(repo.put_block(block))?);
+            }
+            Ok(cids)
+        }
+    }
+
+    /// Puts multiple blocks with a single `BlockStore::put_many` call,
+    /// returning their cids in the same order.
+    ///
+    /// Unlike `put_many` the new blocks are announced with a single
+    /// `RepoEvent::ProvideBlocks`. With the content index enabled every
+    /// block needs its own lookup, so the blocks are put one by one.
+    pub fn put_blocks(&self, blocks: Vec<Block>) ->
+    impl Future<Output=Result<Vec<Cid>, Error>>
+    {
+        let repo = self.clone();
+        async move {
+            if repo.content_index && repo.available_data_store().is_ok() {
+                return await!(repo.put_many(blocks));
+            }
+            let _guard = await!(repo.gc_guard());
+            let cids: Vec<Cid> = blocks.iter().map(|block| block.cid().to_owned()).collect();
+            let mut seen = HashSet::new();
+            let mut new = Vec::new();
+            for block in blocks {
+                if !seen.insert(block.cid().to_owned())
+                    || await!(repo.block_store.contains(block.cid()))?
+                {
+                    let put = PutResult::new(block.cid().to_owned(), false);
+                    await!(repo.record_put(&put, block.size() as u64))?;
+                    continue;
+                }
+                new.push(block);
+            }
+            let sizes: Vec<u64> = new.iter().map(|block| block.size() as u64).collect();
+            let written = await!(repo.block_store.put_many(new))?;
+            // the queue can't be kept without the data store
+            let available = repo.available_data_store().is_ok();
+            for (cid, size) in written.iter().zip(sizes) {
+                await!(repo.record_put(&PutResult::new(cid.to_owned(), true), size))?;
+                if available {
+                    await!(repo.enqueue_provide(cid))?;
+                }
+            }
+            if !written.is_empty() {
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = repo.events.send(RepoEvent::ProvideBlocks(written));
+            }
+            Ok(cids)
+        }
+    }
+
+    /// Puts a block received from the network into the block store.
+    ///
+    /// Waits while `max_network_writes` other received blocks are being
+    /// written.
+    pub fn put_block_fetched(&self, block: Block) ->
+    impl Future<Output=Result<Cid, Error>>
+    {
+        let repo = self.clone();
+        async move {
+            let _permit = await!(repo.store_access.acquire(Access::Write));
+            await!(repo.put_block(block))
+        }
+    }
+
+    /// Puts a block into the block store after checking that the data
+    /// hashes to its cid.
+    pub fn put_block_verified(&self, block: Block) ->
+    impl Future<Output=Result<Cid, Error>>
+    {
+        let repo = self.clone();
+        async move {
+            let (block, valid) = await!(repo.verify_block(block))?;
+            if !valid {
+                return Err(RepoError::BlockCorrupted(block.cid().to_owned()).into());
+            }
+            await!(repo.put_block(block))
+        }
+    }
+
+    /// Checks that the data of a block hashes to its cid, returning the
+    /// block with the result.
+    ///
+    /// Blocks above the hash offload threshold are hashed with the
+    /// spawner's blocking runner to keep the executor responsive.
+    pub(crate) fn verify_block(&self, block: Block) ->
+    impl Future<Output=Result<(Block, bool), Error>>
+    {
+        let offload = if block.size() >= self.hash_offload_threshold {
+            Some(self.spawner.clone())
+        } else {
+            None
+        };
+        async move {
+            match offload {
+                Some(spawner) => await!(spawner.spawn_blocking(move || -> Result<_, Error> {
+                    let valid = block.verify()?;
+                    Ok((block, valid))
+                }))?,
+                None => {
+                    let valid = block.verify()?;
+                    Ok((block, valid))
+                }
+            }
+        }
+    }
+
+    /// Puts a block into the block store without announcing it.
+    ///
+    /// Use `provide` to announce the block later on.
+    pub fn put_block_quiet(&self, block: Block) ->
+    impl Future<Output=Result<Cid, Error>>
+    {
+        let insert = self.insert_block(block);
+        async move {
+            Ok(await!(insert)?.cid)
+        }
+    }
+
+    /// Asks a peer for the blocks of the dag below `root` selected by
+    /// `selector` with a single graphsync request.
+    ///
+    /// The received blocks are stored like blocks fetched with bitswap,
+    /// `get_block` returns them once they arrived.
+    pub fn want_dag(&self, root: &Cid, selector: &Selector) {
+        // sending only fails if no one is listening anymore
+        // and that is okay with us.
+        let _ = self.events.send(RepoEvent::WantDag(root.to_owned(), selector.to_owned()));
+    }
+
+    /// Announces a block that is stored in the block store.
+    pub fn provide(&self, cid: &Cid) {
+        // sending only fails if no one is listening anymore
+        // and that is okay with us.
+        let _ = self.events.send(RepoEvent::ProvideBlock(cid.to_owned()));
+    }
+
+    /// Announces all blocks in the block store, returning the number
+    /// of announced blocks.
+    pub fn reprovide_all(&self) -> impl Future<Output=Result<u64, Error>> {
+        let events = self.events.clone();
+        let mut cids = self.block_store.list_stream();
+        async move {
+            let mut count = 0;
+            while let Some(cid) = await!(cids.next()) {
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = events.send(RepoEvent::ProvideBlock(cid?));
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+
     /// Retrives a block from the block store.
     pub fn get_block(&self, cid: &Cid) ->
     impl Future<Output=Result<Block, Error>>
+    {
+        let get = self.get_block_with(cid, FetchMode::NetworkBlocking);
+        async move {
+            Ok(await!(get)?.expect("blocking gets wait for the block"))
+        }
+    }
+
+    /// Retrives a block from the block store, fetching missing blocks
+    /// from the network according to `mode`.
+    pub fn get_block_with(&self, cid: &Cid, mode: FetchMode) ->
+    impl Future<Output=Result<Option<Block>, Error>>
+    {
+        self.fetch_block(cid, mode, None)
+    }
+
+    /// Starts a session for fetching related blocks, see `Session`.
+    pub fn session(&self) -> Session<TRepoTypes> {
+        let id = self.sessions.fetch_add(1, Ordering::SeqCst);
+        Session::new(self.clone(), id)
+    }
+
+    /// Retrives a block, wanting missing blocks in `session` if given.
+    fn fetch_block(&self, cid: &Cid, mode: FetchMode, session: Option<SessionId>) ->
+    impl Future<Output=Result<Option<Block>, Error>>
+    {
+        let fetch = self.fetch_block_traced(cid, mode, session);
+        async move {
+            Ok(await!(fetch)?.map(|(block, _)| block))
+        }
+    }
+
+    /// Retrives a block like `fetch_block`, also returning if it had to
+    /// be wanted from the network.
+    fn fetch_block_traced(&self, cid: &Cid, mode: FetchMode, session: Option<SessionId>) ->
+    impl Future<Output=Result<Option<(Block, BlockSource)>, Error>>
     {
         let cid = cid.to_owned();
+        let repo = self.clone();
         let events = self.events.clone();
         let block_store = self.block_store.clone();
         async move {
+            let permit = await!(repo.store_access.acquire(Access::Read));
+            let indexed = repo.content_index && repo.available_data_store().is_ok();
+            if indexed && !await!(block_store.contains(&cid))? {
+                if let Some(block) = await!(repo.get_aliased_block(&cid))? {
+                    return Ok(Some((block, BlockSource::Local)));
+                }
+            }
+            let deadline = match mode {
+                FetchMode::LocalOnly => {
+                    let block = await!(block_store.get(&cid))?;
+                    return Ok(block.map(|block| (block, BlockSource::Local)));
+                }
+                FetchMode::NetworkWithTimeout(timeout) => Some(Instant::now() + timeout),
+                FetchMode::NetworkBlocking => None,
+            };
+            if let Some(block) = await!(block_store.get(&cid))? {
+                return Ok(Some((block, BlockSource::Local)));
+            }
+            // the block is written by a network write, which needs the slot
+            drop(permit);
+            // written meanwhile by a fetch of another get
+            let mut source = BlockSource::Local;
             if !await!(block_store.contains(&cid))? {
                 // sending only fails if no one is listening anymore
                 // and that is okay with us.
-                let _ = events.send(RepoEvent::WantBlock(cid.clone()));
+                let _ = match session {
+                    Some(session) => events.send(RepoEvent::WantBlockInSession(cid.clone(), session)),
+                    None => events.send(RepoEvent::WantBlock(cid.clone())),
+                };
+                source = BlockSource::Network;
             }
-            await!(BlockFuture::new(block_store, cid))
+            let future = BlockFuture::new(block_store, cid);
+            let block = match deadline {
+                Some(deadline) => await!(future.with_deadline(deadline))?,
+                None => await!(future)?,
+            };
+            Ok(Some((block, source)))
         }
     }
 
+    /// Retrives a block from the block store and reports if it had to
+    /// be fetched from the network.
+    ///
+    /// Works like `get_block`, the block is only `BlockSource::Network` if
+    /// it was wanted from the network.
+    pub fn get_block_traced(&self, cid: &Cid) ->
+    impl Future<Output=Result<(Block, BlockSource), Error>>
+    {
+        let fetch = self.fetch_block_traced(cid, FetchMode::NetworkBlocking, None);
+        async move {
+            Ok(await!(fetch)?.expect("blocking gets wait for the block"))
+        }
+    }
+
+    /// Retrieves the data of a locally stored block.
+    ///
+    /// Unlike `get_block` this doesn't ask the network for missing blocks.
+    pub fn get_block_data(&self, cid: &Cid) ->
+    impl Future<Output=Result<Option<Bytes>, Error>>
+    {
+        self.block_store.get_data(cid)
+    }
+
     /// Remove block from the block store.
+    ///
+    /// Fails with `RepoError::BlockPinned` if the block is pinned directly
+    /// or indirectly.
     pub fn remove_block(&self, cid: &Cid)
         -> impl Future<Output=Result<(), Error>>
     {
+        let repo = self.clone();
+        let cid = cid.to_owned();
+        async move {
+            if await!(repo.is_pinned(&cid))? ||
+                await!(repo.is_pinned_indirectly(&cid))?
+            {
+                return Err(RepoError::BlockPinned(cid).into());
+            }
+            await!(repo.remove_block_force(&cid))
+        }
+    }
+
+    /// Remove block from the block store even if it is pinned.
+    ///
+    /// Removes the metadata of the block and leaves a tombstone if
+    /// tombstones are enabled.
+    pub fn remove_block_force(&self, cid: &Cid)
+        -> impl Future<Output=Result<(), Error>>
+    {
+        let repo = self.clone();
+        let cid = cid.to_owned();
+        let remove = self.remove_block_keep_meta(&cid);
+        async move {
+            await!(repo.clear_block_meta(&cid))?;
+            await!(remove)
+        }
+    }
+
+    /// Removes a block like `remove_block_force` except for its metadata.
+    pub(crate) fn remove_block_keep_meta(&self, cid: &Cid)
+        -> impl Future<Output=Result<(), Error>>
+    {
+        let repo = self.clone();
+        let cid = cid.to_owned();
         // sending only fails if no one is listening anymore
         // and that is okay with us.
-        let _ = self.events.send(RepoEvent::UnprovideBlock(cid.to_owned()));
-        self.block_store.remove(cid)
+        let _ = self.events.send(RepoEvent::UnprovideBlock(cid.clone()));
+        async move {
+            if repo.tombstones {
+                await!(repo.put_tombstone(&cid, repo.clock.now()))?;
+            }
+            if repo.content_index && repo.available_data_store().is_ok() {
+                await!(repo.remove_alias(&cid))?;
+            }
+            await!(repo.block_store.remove(&cid))?;
+            repo.touch_indirect_pin(&cid);
+            await!(repo.audit(AuditOp::Remove, &cid))
+        }
     }
 
     /// Get an ipld path from the datastore.
+    ///
+    /// Records written with `put_ipns_signed` fail with
+    /// `RepoError::InvalidSignature` unless they are signed by `ipns`, and
+    /// with `RepoError::IpnsExpired` once their validity ran out.
+    /// Resolved records are cached according to `ipns_cache_ttl`.
     pub fn get_ipns(&self, ipns: &PeerId) ->
     impl Future<Output=Result<Option<IpfsPath>, Error>>
     {
-        let data_store = self.data_store.clone();
+        let data_store = self.available_data_store().map(Clone::clone);
+        let cache = self.ipns_cache.clone();
+        let ttl = self.ipns_cache_ttl;
+        let now = self.clock.now();
         let key = ipns.to_owned();
         async move {
+            let data_store = data_store?;
+            let generation = {
+                let mut cache = cache.lock().unwrap();
+                if let Some(path) = cache.get(&key, now) {
+                    return Ok(Some(path));
+                }
+                cache.generation
+            };
+            let signed = await!(data_store.get(Column::Ipns, &signed_ipns_key(&key)))?;
+            if let Some(bytes) = signed {
+                let entry = IpnsEntry::from_bytes(&bytes)?;
+                if !entry.verify(&key, now) {
+                    if entry.validity() <= now {
+                        return Err(RepoError::IpnsExpired(key).into());
+                    }
+                    return Err(RepoError::InvalidSignature(key).into());
+                }
+                let path = entry.resolve()?;
+                let expires = entry.validity().min(now + ttl);
+                cache.lock().unwrap().insert(key, path.clone(), expires, generation);
+                return Ok(Some(path));
+            }
             let bytes = await!(data_store.get(Column::Ipns, key.as_bytes()))?;
             match bytes {
                 Some(ref bytes) => {
                     let string = String::from_utf8_lossy(bytes);
                     let path = IpfsPath::from_str(&string)?;
+                    cache.lock().unwrap().insert(key, path.clone(), now + ttl, generation);
                     Ok(Some(path))
                 }
                 None => Ok(None)
@@ -198,22 +1945,119 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     }
 
     /// Put an ipld path into the datastore.
+    ///
+    /// Fails with `RepoError::IpnsLoop` if `path` points at `ipns` itself.
     pub fn put_ipns(&self, ipns: &PeerId, path: &IpfsPath) ->
     impl Future<Output=Result<(), Error>>
     {
+        let data_store = self.available_data_store().map(Clone::clone);
+        let cache = self.ipns_cache.clone();
+        let ipns = ipns.to_owned();
         let string = path.to_string();
-        let value = string.as_bytes();
-        self.data_store.put(Column::Ipns, ipns.as_bytes(), value)
+        let is_loop = path.root().peer_id() == Some(&ipns);
+        async move {
+            if is_loop {
+                return Err(RepoError::IpnsLoop(ipns).into());
+            }
+            let res = await!(data_store?.put(Column::Ipns, ipns.as_bytes(), string.as_bytes()));
+            cache.lock().unwrap().invalidate(&ipns);
+            res
+        }
+    }
+
+    /// Put an ipld path signed by `keypair` into the datastore.
+    ///
+    /// The path is stored under the peer id of `keypair` and is valid for
+    /// `ipns_record_ttl`.
+    pub fn put_ipns_signed(&self, keypair: &Keypair, path: &IpfsPath) ->
+    impl Future<Output=Result<PeerId, Error>>
+    {
+        let data_store = self.available_data_store().map(Clone::clone);
+        let cache = self.ipns_cache.clone();
+        let validity = self.clock.now() + self.ipns_record_ttl;
+        let entry = IpnsEntry::from_path_signed(path, 0, validity, keypair);
+        let peer_id = entry.peer_id();
+        async move {
+            let data_store = data_store?;
+            let key = signed_ipns_key(&peer_id);
+            let seq = match await!(data_store.get(Column::Ipns, &key))? {
+                Some(bytes) => IpnsEntry::from_bytes(&bytes)?.seq() + 1,
+                None => 0,
+            };
+            let entry = entry.with_seq(seq);
+            let res = await!(data_store.put(Column::Ipns, &key, &entry.to_bytes()));
+            cache.lock().unwrap().invalidate(&peer_id);
+            res?;
+            Ok(peer_id)
+        }
+    }
+
+    /// Returns the peer ids of all stored ipns records.
+    ///
+    /// Keys that aren't valid peer ids are skipped.
+    pub fn ipns_keys_stream(&self) -> impl Stream<Item=Result<PeerId, Error>> {
+        let keys = match self.available_data_store() {
+            Ok(data_store) => data_store.list_keys(Column::Ipns),
+            Err(err) => FutureObj::new(Box::new(futures::future::err(err))),
+        };
+        futures::stream::once(keys).map(|keys| {
+            let peer_ids: Vec<Result<PeerId, Error>> = match keys {
+                Ok(keys) => {
+                    let mut seen = HashSet::new();
+                    keys.into_iter().filter_map(|key| {
+                        let bytes = if key.starts_with(SIGNED_IPNS_PREFIX) {
+                            key[SIGNED_IPNS_PREFIX.len()..].to_vec()
+                        } else {
+                            key
+                        };
+                        match PeerId::from_bytes(bytes) {
+                            Ok(peer_id) => Some(peer_id),
+                            Err(bytes) => {
+                                warn!("skipping malformed ipns key {:?}", bytes);
+                                None
+                            }
+                        }
+                    })
+                    // a peer can have a signed and a plain record
+                    .filter(|peer_id| seen.insert(peer_id.clone()))
+                    .map(Ok)
+                    .collect()
+                }
+                Err(err) => vec![Err(err)],
+            };
+            futures::stream::iter(peer_ids)
+        }).flatten()
     }
 
     /// Remove an ipld path from the datastore.
     pub fn remove_ipns(&self, ipns: &PeerId) ->
     impl Future<Output=Result<(), Error>>
     {
-        self.data_store.remove(Column::Ipns, ipns.as_bytes())
+        let data_store = self.available_data_store().map(Clone::clone);
+        let cache = self.ipns_cache.clone();
+        let ipns = ipns.to_owned();
+        async move {
+            let data_store = data_store?;
+            let f1 = data_store.remove(Column::Ipns, ipns.as_bytes());
+            let f2 = data_store.remove(Column::Ipns, &signed_ipns_key(&ipns));
+            let (r1, r2) = join!(f1, f2);
+            cache.lock().unwrap().invalidate(&ipns);
+            r1?;
+            r2
+        }
     }
 }
 
+/// Signed records are kept apart from the plain paths written by
+/// `put_ipns`.
+const SIGNED_IPNS_PREFIX: &[u8] = b"signed/";
+
+fn signed_ipns_key(peer_id: &PeerId) -> Vec<u8> {
+    let mut key = SIGNED_IPNS_PREFIX.to_vec();
+    key.extend(peer_id.as_bytes());
+    key
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -225,30 +2069,863 @@ pub(crate) mod tests {
     impl RepoTypes for Types {
         type TBlockStore = mem::MemBlockStore;
         type TDataStore = mem::MemDataStore;
+        type TPinStore = DataStorePinStore<mem::MemDataStore>;
     }
 
     pub fn create_mock_repo() -> Repo<Types> {
+        let (r, _) = create_mock_repo_with_events();
+        r
+    }
+
+    pub fn create_mock_repo_with_events() -> (Repo<Types>, Receiver<RepoEvent>) {
         let mut tmp = temp_dir();
         tmp.push("rust-ipfs-repo");
-        let options: RepoOptions<Types> = RepoOptions {
-            _marker: PhantomData,
-            path: tmp,
-        };
-        let (r, _) = Repo::new(options);
-        r
+        let options: RepoOptions<Types> = RepoOptions::new(tmp);
+        Repo::new(options)
     }
 
     #[test]
     fn test_repo() {
         let mut tmp = temp_dir();
         tmp.push("rust-ipfs-repo");
-        let options: RepoOptions<Types> = RepoOptions {
-            _marker: PhantomData,
-            path: tmp,
-        };
+        let options: RepoOptions<Types> = RepoOptions::new(tmp);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_init_columns() {
+        tokio::run_async(async move {
+            let initialized = Arc::new(Mutex::new(Vec::new()));
+            let recorded = initialized.clone();
+            await!(init_columns(Spawner::default(), move |col| {
+                recorded.lock().unwrap().push(col);
+                FutureObj::new(Box::new(futures::future::ok(())))
+            })).unwrap();
+            let mut initialized = initialized.lock().unwrap().clone();
+            initialized.sort_by_key(|col| col.name());
+            let mut all = Column::all().to_vec();
+            all.sort_by_key(|col| col.name());
+            assert_eq!(initialized, all);
+
+            let res = await!(init_columns(Spawner::default(), |col| {
+                match col {
+                    Column::Pin => FutureObj::new(Box::new(futures::future::err(
+                        format_err!("no space left")))),
+                    _ => FutureObj::new(Box::new(futures::future::ok(()))),
+                }
+            }));
+            let err = res.unwrap_err().to_string();
+            assert!(err.contains("pin: no space left"));
+            assert!(!err.contains("ipns"));
+        });
+    }
+
+    #[test]
+    fn test_put_block_provides_once() {
+        let (repo, events) = create_mock_repo_with_events();
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            let put = await!(repo.block_store.put_checked(block.clone())).unwrap();
+            assert_eq!(put, PutResult::new(block.cid().to_owned(), true));
+            let put = await!(repo.block_store.put_checked(block.clone())).unwrap();
+            assert_eq!(put, PutResult::new(block.cid().to_owned(), false));
+
+            let block = Block::from("2");
+            await!(repo.put_block(block.clone())).unwrap();
+            await!(repo.put_block(block.clone())).unwrap();
+            match events.try_recv() {
+                Ok(RepoEvent::ProvideBlock(provided)) => assert_eq!(&provided, block.cid()),
+                event => panic!("expected provide event, got {:?}", event),
+            }
+            assert!(events.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_dedup_stats() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let blocks = vec![Block::from("1"), Block::from("2"), Block::from("3")];
+            let size: usize = blocks.iter().map(|block| block.size()).sum();
+            for block in blocks.clone() {
+                await!(repo.put_block(block)).unwrap();
+            }
+            assert_eq!(repo.dedup_stats(), DedupStats {
+                unique: 3,
+                duplicate: 0,
+                bytes_saved: 0,
+            });
+            for block in blocks {
+                await!(repo.put_block(block)).unwrap();
+            }
+            assert_eq!(repo.dedup_stats(), DedupStats {
+                unique: 3,
+                duplicate: 3,
+                bytes_saved: size as u64,
+            });
+        });
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let repo = create_mock_repo();
+        let mut events = repo.event_stream();
+        tokio::run_async(async move {
+            let cid = await!(repo.put_block(Block::from("1"))).unwrap();
+            repo.fetch_blocks(&[cid.clone()]);
+            drop(repo);
+
+            match await!(events.next()) {
+                Some(RepoEvent::ProvideBlock(provided)) => assert_eq!(provided, cid),
+                event => panic!("unexpected event {:?}", event),
+            }
+            match await!(events.next()) {
+                Some(RepoEvent::WantBlock(wanted)) => assert_eq!(wanted, cid),
+                event => panic!("unexpected event {:?}", event),
+            }
+            assert!(await!(events.next()).is_none());
+        });
+    }
+
+    #[test]
+    fn test_ipns_cache() {
+        let repo = create_mock_repo();
+        let peer_id = PeerId::random();
+        let cid1 = Block::from("1").cid().to_owned();
+        let cid2 = Block::from("2").cid().to_owned();
+        tokio::run_async(async move {
+            await!(repo.put_ipns(&peer_id, &IpfsPath::from(cid1.clone()))).unwrap();
+            assert_eq!(await!(repo.get_ipns(&peer_id)).unwrap(), Some(cid1.clone().into()));
+
+            // bypass the invalidation to see the cached record
+            let path = IpfsPath::from(cid2.clone()).to_string();
+            await!(repo.data_store.put(Column::Ipns, peer_id.as_bytes(), path.as_bytes()))
+                .unwrap();
+            assert_eq!(await!(repo.get_ipns(&peer_id)).unwrap(), Some(cid1.clone().into()));
+
+            await!(repo.put_ipns(&peer_id, &IpfsPath::from(cid2.clone()))).unwrap();
+            assert_eq!(await!(repo.get_ipns(&peer_id)).unwrap(), Some(cid2.into()));
+            await!(repo.remove_ipns(&peer_id)).unwrap();
+            assert_eq!(await!(repo.get_ipns(&peer_id)).unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn test_ipns_cache_expiry() {
+        let clock = clock::FakeClock::new();
+        let options = RepoOptions::<Types>::new(temp_dir())
+            .clock(clock.clone())
+            .ipns_cache_ttl(Duration::from_secs(10));
+        let (repo, _) = Repo::new(options);
+        let peer_id = PeerId::random();
+        let cid1 = Block::from("1").cid().to_owned();
+        let cid2 = Block::from("2").cid().to_owned();
+        tokio::run_async(async move {
+            await!(repo.put_ipns(&peer_id, &IpfsPath::from(cid1.clone()))).unwrap();
+            assert_eq!(await!(repo.get_ipns(&peer_id)).unwrap(), Some(cid1.clone().into()));
+            // bypass the invalidation to see the cached record
+            let path = IpfsPath::from(cid2.clone()).to_string();
+            await!(repo.data_store.put(Column::Ipns, peer_id.as_bytes(), path.as_bytes()))
+                .unwrap();
+
+            clock.advance(Duration::from_secs(9));
+            assert_eq!(await!(repo.get_ipns(&peer_id)).unwrap(), Some(cid1.into()));
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(await!(repo.get_ipns(&peer_id)).unwrap(), Some(cid2.into()));
+        });
+    }
+
+    #[test]
+    fn test_ipns_signed() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let key_a = Keypair::generate(&mut rand::thread_rng());
+            let key_b = Keypair::generate(&mut rand::thread_rng());
+            let path = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8").unwrap();
+            let peer_a = await!(repo.put_ipns_signed(&key_a, &path)).unwrap();
+            let peer_b = await!(repo.put_ipns_signed(&key_b, &path)).unwrap();
+            assert_eq!(await!(repo.get_ipns(&peer_a)).unwrap(), Some(path.clone()));
+
+            // store the record of A under the peer id of B
+            let record = await!(repo.data_store.get(Column::Ipns, &signed_ipns_key(&peer_a)))
+                .unwrap().unwrap();
+            await!(repo.data_store.put(Column::Ipns, &signed_ipns_key(&peer_b), &record)).unwrap();
+            assert!(await!(repo.get_ipns(&peer_b)).is_err());
+        });
+    }
+
+    #[test]
+    fn test_ipns_signed_expiry() {
+        let clock = clock::FakeClock::new();
+        let options = RepoOptions::<Types>::new(temp_dir())
+            .clock(clock.clone())
+            .ipns_record_ttl(Duration::from_secs(10));
         let (repo, _) = Repo::new(options);
         tokio::run_async(async move {
+            let keypair = Keypair::generate(&mut rand::thread_rng());
+            let path = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8").unwrap();
+            let peer_id = await!(repo.put_ipns_signed(&keypair, &path)).unwrap();
+            clock.advance(Duration::from_secs(9));
+            assert_eq!(await!(repo.get_ipns(&peer_id)).unwrap(), Some(path));
+
+            clock.advance(Duration::from_secs(1));
+            let err = await!(repo.get_ipns(&peer_id)).unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::IpnsExpired(expired)) => assert_eq!(expired, &peer_id),
+                _ => panic!("expected expired record, got {}", err),
+            }
+        });
+    }
+
+    #[test]
+    fn test_ipns_keys_stream() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let path = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8").unwrap();
+            let mut peer_ids = vec![PeerId::random(), PeerId::random()];
+            for peer_id in &peer_ids {
+                await!(repo.put_ipns(peer_id, &path)).unwrap();
+            }
+            let keypair = Keypair::generate(&mut rand::thread_rng());
+            peer_ids.push(await!(repo.put_ipns_signed(&keypair, &path)).unwrap());
+            await!(repo.data_store.put(Column::Ipns, b"malformed", &[])).unwrap();
+
+            let mut stream = repo.ipns_keys_stream();
+            let mut keys = Vec::new();
+            while let Some(peer_id) = await!(stream.next()) {
+                keys.push(peer_id.unwrap());
+            }
+            keys.sort_by_key(|peer_id| peer_id.to_base58());
+            peer_ids.sort_by_key(|peer_id| peer_id.to_base58());
+            assert_eq!(keys, peer_ids);
+        });
+    }
+
+    /// Mem block store reporting `AVAILABLE` bytes of free space.
+    #[derive(Clone)]
+    struct LowDiskStore(mem::MemBlockStore);
+
+    const AVAILABLE: u64 = 100;
+
+    impl BlockStore for LowDiskStore {
+        fn new(path: PathBuf) -> Self {
+            LowDiskStore(mem::MemBlockStore::new(path))
+        }
+        fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.0.init()
+        }
+        fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.0.open()
+        }
+        fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+            self.0.contains(cid)
+        }
+        fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+            self.0.get(cid)
+        }
+        fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+            self.0.put(block)
+        }
+        fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+            self.0.remove(cid)
+        }
+        fn list_stream(&self) -> StoreStream<Cid> {
+            self.0.list_stream()
+        }
+        fn available_space(&self) -> FutureObj<'static, Result<Option<u64>, Error>> {
+            FutureObj::new(Box::new(futures::future::ok(Some(AVAILABLE))))
+        }
+    }
+
+    #[derive(Clone)]
+    struct LowDiskTypes;
+
+    impl RepoTypes for LowDiskTypes {
+        type TBlockStore = LowDiskStore;
+        type TDataStore = mem::MemDataStore;
+        type TPinStore = DataStorePinStore<mem::MemDataStore>;
+    }
+
+    #[test]
+    fn test_blocks_by_size() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            for data in &["1", "22", "333", "4444"] {
+                await!(repo.put_block(Block::from(*data))).unwrap();
+            }
+            let mut blocks = await!(repo.blocks_by_size(2, 3)).unwrap();
+            blocks.sort_by_key(|(_, size)| *size);
+            assert_eq!(blocks, vec![
+                (Block::from("22").cid().to_owned(), 2),
+                (Block::from("333").cid().to_owned(), 3),
+            ]);
+            assert!(await!(repo.blocks_by_size(5, u64::max_value())).unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_open_checks_version() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_open_version");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let (repo, _) = Repo::new(RepoOptions::<Types>::new(tmp.clone()));
+        let (newer, _) = Repo::new(RepoOptions::<Types>::new(tmp.clone()));
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+            assert_eq!(migrations::read_version(&tmp).unwrap(), Some(REPO_VERSION));
+            assert_eq!(await!(repo.migrate()).unwrap(), REPO_VERSION);
+            await!(repo.open()).unwrap();
+
+            migrations::write_version(&tmp, REPO_VERSION + 1).unwrap();
+            let err = await!(newer.open()).unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::UnsupportedVersion(_)) => {}
+                _ => panic!("expected unsupported version, got {}", err),
+            }
+            std::fs::remove_dir_all(tmp).ok();
+        });
+    }
+
+    #[test]
+    fn test_close_releases_lock() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_close_lock");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let (repo, _) = Repo::new(RepoOptions::<Types>::new(tmp.clone()));
+        tokio::run_async(async move {
+            let lock_path = tmp.join(lock::LOCK_FILE);
             await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            assert!(lock_path.exists());
+            await!(repo.close()).unwrap();
+            assert!(!lock_path.exists());
+
+            // opening again locks the repo again
+            await!(repo.open()).unwrap();
+            assert!(lock_path.exists());
+            await!(repo.close()).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_stat() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_stat");
+        let (repo, _) = Repo::new(RepoOptions::<Types>::new(tmp.clone()));
+        tokio::run_async(async move {
+            // quiet puts don't queue the blocks for providing
+            await!(repo.put_block_quiet(Block::from("1234"))).unwrap();
+            await!(repo.put_block_quiet(Block::from("56"))).unwrap();
+            let stat = await!(repo.stat()).unwrap();
+            assert_eq!(stat.num_objects, 2);
+            // the data store has no entries
+            assert_eq!(stat.repo_size, 6);
+            assert_eq!(stat.repo_path, tmp);
+            assert_eq!(stat.version, REPO_VERSION);
+
+            let path = IpfsPath::from(Block::from("1234").cid().to_owned());
+            await!(repo.put_ipns(&PeerId::random(), &path)).unwrap();
+            assert!(await!(repo.stat()).unwrap().repo_size > 6);
         });
     }
+
+    #[test]
+    fn test_can_store() {
+        let mut tmp = temp_dir();
+        tmp.push("rust-ipfs-repo-can-store");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let options = RepoOptions::<Types>::new(tmp.join("max-storage")).max_storage(10);
+        let (repo, _) = Repo::new(options);
+        let (low_disk, _) = Repo::new(RepoOptions::<LowDiskTypes>::new(tmp.join("low-disk")));
+        tokio::run_async(async move {
+            await!(repo.put_block(Block::from("1234"))).unwrap();
+            assert_eq!(await!(repo.repo_stat()).unwrap(), RepoStat {
+                num_blocks: 1,
+                total_size: 4,
+            });
+            assert!(await!(repo.can_store(6)).unwrap());
+            assert!(!await!(repo.can_store(7)).unwrap());
+
+            assert!(await!(low_disk.can_store(AVAILABLE)).unwrap());
+            assert!(!await!(low_disk.can_store(AVAILABLE + 1)).unwrap());
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[test]
+    fn test_get_block_data() {
+        let (repo, events) = create_mock_repo_with_events();
+        tokio::run_async(async move {
+            let cid = await!(repo.put_block_quiet(Block::from("1"))).unwrap();
+            let data = await!(repo.get_block_data(&cid)).unwrap().unwrap();
+            assert_eq!(&data, await!(repo.get_block(&cid)).unwrap().data());
+
+            let missing = Block::from("2").cid().to_owned();
+            assert_eq!(await!(repo.get_block_data(&missing)).unwrap(), None);
+            assert!(events.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_refs_local() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let mut cids = Vec::new();
+            for data in &["1", "2", "3"] {
+                cids.push(await!(repo.put_block_quiet(Block::from(*data))).unwrap());
+            }
+            let mut refs = Vec::new();
+            let mut stream = repo.refs_local();
+            while let Some(cid) = await!(stream.next()) {
+                refs.push(cid.unwrap());
+            }
+            refs.sort_by_key(|cid| cid.to_bytes());
+            cids.sort_by_key(|cid| cid.to_bytes());
+            assert_eq!(refs, cids);
+        });
+    }
+
+    #[test]
+    fn test_put_blocks() {
+        let (repo, events) = create_mock_repo_with_events();
+        tokio::run_async(async move {
+            await!(repo.put_block_quiet(Block::from("3"))).unwrap();
+            let blocks = vec![Block::from("1"), Block::from("2"), Block::from("1"), Block::from("3")];
+            let expected: Vec<Cid> = blocks.iter().map(|block| block.cid().to_owned()).collect();
+            assert_eq!(await!(repo.put_blocks(blocks)).unwrap(), expected);
+
+            let provided: Vec<RepoEvent> = events.try_iter().collect();
+            assert_eq!(provided.len(), 1);
+            match &provided[0] {
+                RepoEvent::ProvideBlocks(cids) => assert_eq!(cids, &expected[..2].to_vec()),
+                event => panic!("unexpected event {:?}", event),
+            }
+            // queued until they were provided
+            let mut queued = await!(repo.provide_queue()).unwrap();
+            queued.sort_by_key(|cid| cid.to_bytes());
+            let mut new = expected[..2].to_vec();
+            new.sort_by_key(|cid| cid.to_bytes());
+            assert_eq!(queued, new);
+            for cid in &new {
+                await!(repo.provided(cid)).unwrap();
+            }
+            assert!(await!(repo.provide_queue()).unwrap().is_empty());
+            for cid in &expected {
+                assert!(await!(repo.block_store.contains(cid)).unwrap());
+            }
+        });
+    }
+
+    #[test]
+    fn test_reprovide_all() {
+        let (repo, events) = create_mock_repo_with_events();
+        tokio::run_async(async move {
+            let mut cids = Vec::new();
+            for data in &["1", "2", "3"] {
+                cids.push(await!(repo.put_block_quiet(Block::from(*data))).unwrap());
+            }
+            assert_eq!(await!(repo.reprovide_all()).unwrap(), 3);
+            let mut provided: Vec<Cid> = events.try_iter().map(|event| match event {
+                RepoEvent::ProvideBlock(cid) => cid,
+                event => panic!("unexpected event {:?}", event),
+            }).collect();
+            provided.sort_by_key(|cid| cid.to_bytes());
+            cids.sort_by_key(|cid| cid.to_bytes());
+            assert_eq!(provided, cids);
+        });
+    }
+
+    #[test]
+    fn test_once() {
+        use futures::compat::Future01CompatExt;
+        use std::time::{Duration, Instant};
+        use tokio::timer::Delay;
+
+        let once = Once::default();
+        let runs = Arc::new(Mutex::new(0));
+        let run = |runs: Arc<Mutex<u32>>| async move {
+            *runs.lock().unwrap() += 1;
+            let delay = Instant::now() + Duration::from_millis(10);
+            await!(Delay::new(delay).compat()).unwrap();
+            Ok(())
+        };
+        let mut tmp = temp_dir();
+        tmp.push("rust-ipfs-repo-once");
+        std::fs::remove_dir_all(&tmp).ok();
+        let (repo, _) = Repo::new(RepoOptions::<crate::Types>::new(tmp.clone()));
+        tokio::run_async(async move {
+            let (r1, r2) = join!(once.run(run(runs.clone())), once.run(run(runs.clone())));
+            assert!(r1.is_ok() && r2.is_ok());
+            assert_eq!(*runs.lock().unwrap(), 1);
+            await!(once.run(run(runs.clone()))).unwrap();
+            assert_eq!(*runs.lock().unwrap(), 1);
+
+            // a dropped run doesn't block later runs
+            let dropped = Once::default();
+            {
+                let mut first = Box::pin(dropped.run(run(runs.clone())));
+                assert!(futures::poll!(first.as_mut()).is_pending());
+            }
+            await!(dropped.run(run(runs.clone()))).unwrap();
+            assert_eq!(*runs.lock().unwrap(), 3);
+
+            let (r1, r2) = join!(repo.init(), repo.init());
+            assert!(r1.is_ok() && r2.is_ok());
+            assert!(tmp.join("blockstore").is_dir());
+            await!(repo.init()).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_put_block_quiet() {
+        let (repo, events) = create_mock_repo_with_events();
+        tokio::run_async(async move {
+            let block = Block::from("quiet");
+            let cid = await!(repo.put_block_quiet(block.clone())).unwrap();
+            assert!(events.try_recv().is_err());
+            assert_eq!(await!(repo.get_block(&cid)).unwrap(), block);
+
+            repo.provide(&cid);
+            match events.try_recv() {
+                Ok(RepoEvent::ProvideBlock(provided)) => assert_eq!(provided, cid),
+                event => panic!("expected provide event, got {:?}", event),
+            }
+        });
+    }
+
+    #[test]
+    fn test_subscribe_events_filtered() {
+        let repo = create_mock_repo();
+        let events = repo.subscribe_events_filtered(|event| {
+            match event {
+                RepoEvent::ProvideBlock(cid) => cid.prefix().codec == cid::Codec::Raw,
+                _ => false,
+            }
+        });
+        tokio::run_async(async move {
+            let prefix = cid::Prefix {
+                version: cid::Version::V1,
+                codec: cid::Codec::Raw,
+                mh_type: multihash::Hash::SHA2256,
+                mh_len: 32,
+            };
+            let data = b"raw".to_vec();
+            let raw = Block::new(data.clone(), Cid::new_from_prefix(&prefix, &data));
+            let dag_pb = Block::from("dag-pb");
+
+            await!(repo.put_block(dag_pb)).unwrap();
+            let cid = await!(repo.put_block(raw)).unwrap();
+
+            match events.try_recv() {
+                Ok(RepoEvent::ProvideBlock(provided)) => assert_eq!(provided, cid),
+                event => panic!("expected provide event, got {:?}", event),
+            }
+            assert!(events.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_ipns_any_base() {
+        let mut tmp = temp_dir();
+        tmp.push("rust-ipfs-repo");
+        let options: RepoOptions<Types> = RepoOptions::new(tmp).cid_base(Base::Base32);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            let prefix = cid::Prefix {
+                version: cid::Version::V1,
+                codec: cid::Codec::Raw,
+                mh_type: multihash::Hash::SHA2256,
+                mh_len: 32,
+            };
+            let data = b"base".to_vec();
+            let block = Block::new(data.clone(), Cid::new_from_prefix(&prefix, &data));
+            let cid = await!(repo.put_block(block.clone())).unwrap();
+
+            let string = format!("/ipfs/{}", repo.format_cid(&cid));
+            assert!(string.starts_with("/ipfs/b"));
+            let base32 = IpfsPath::from_str(&string).unwrap();
+            let base58 = IpfsPath::from_str(&IpfsPath::from(cid.clone()).to_string()).unwrap();
+            assert_eq!(base32, base58);
+            let cid32 = base32.root().cid().unwrap();
+            assert_eq!(await!(repo.get_block(cid32)).unwrap(), block);
+
+            let peer_id = PeerId::random();
+            await!(repo.put_ipns(&peer_id, &base32)).unwrap();
+            let path = await!(repo.get_ipns(&peer_id)).unwrap().unwrap();
+            assert_eq!(path, base58);
+            assert_eq!(repo.format_path(&path), string);
+        });
+    }
+
+    #[test]
+    fn test_parse() {
+        let options = RepoOptions::<Types>::new(temp_dir()).cid_base(Base::Base32);
+        let (repo, _) = Repo::new(options);
+        let v0 = Block::from("1").cid().to_owned();
+        let cid = crate::block::to_v1(&v0);
+        assert!(repo.format_cid(&cid).starts_with("b"));
+        assert_eq!(repo.parse_cid(&repo.format_cid(&cid)).unwrap(), cid);
+        assert_eq!(repo.parse_cid(&cid.to_string()).unwrap(), cid);
+        assert_eq!(repo.format_cid(&v0), v0.to_string());
+        assert_eq!(repo.parse_cid(&repo.format_cid(&v0)).unwrap(), v0);
+        let err = repo.parse_cid("bogus").unwrap_err();
+        match err.downcast_ref::<RepoError>() {
+            Some(RepoError::InvalidCid(cid)) => assert_eq!(cid, "bogus"),
+            _ => panic!("expected invalid cid, got {}", err),
+        }
+
+        let path = IpfsPath::from(cid);
+        assert_eq!(repo.parse_path(&repo.format_path(&path)).unwrap(), path);
+        let path = IpfsPath::from(v0);
+        assert_eq!(repo.parse_path(&repo.format_path(&path)).unwrap(), path);
+        for invalid in &["/ipfs", "/ipfs/bogus", "/ipfs/QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG//a"] {
+            let err = repo.parse_path(invalid).unwrap_err();
+            match err.downcast_ref::<IpfsPathError>() {
+                Some(IpfsPathError::InvalidPath(path)) => assert_eq!(path, *invalid),
+                _ => panic!("expected invalid path, got {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_put_block_verified() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let block = Block::from("1");
+            let cid = await!(repo.put_block_verified(block.clone())).unwrap();
+            assert_eq!(await!(repo.get_block(&cid)).unwrap(), block);
+
+            let corrupted = Block::new("2", Block::from("3").cid().to_owned());
+            let err = await!(repo.put_block_verified(corrupted)).unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::BlockCorrupted(_)) => {}
+                _ => panic!("expected corrupted block, got {}", err),
+            }
+        });
+    }
+
+    #[test]
+    fn test_verify_block_offload() {
+        let offloaded = Arc::new(AtomicUsize::new(0));
+        let counter = offloaded.clone();
+        let spawner = Spawner::default().with_blocking(move |task| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(task);
+        });
+        let options = RepoOptions::<Types>::new(temp_dir())
+            .hash_offload_threshold(2)
+            .spawner(spawner);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            await!(repo.put_block_verified(Block::from("1"))).unwrap();
+            assert_eq!(offloaded.load(Ordering::SeqCst), 0);
+            await!(repo.put_block_verified(Block::from("12"))).unwrap();
+            assert_eq!(offloaded.load(Ordering::SeqCst), 1);
+
+            let corrupted = Block::new("23", Block::from("3").cid().to_owned());
+            assert!(await!(repo.put_block_verified(corrupted)).is_err());
+            assert_eq!(offloaded.load(Ordering::SeqCst), 2);
+        });
+    }
+
+    #[test]
+    fn test_verify_block_offload_responsive() {
+        use futures::compat::Future01CompatExt;
+        use tokio::timer::Delay;
+
+        // the hash only starts once the other future made progress, so
+        // that future can only finish first if the executor wasn't blocked
+        let (gate, gate_rx) = channel::<()>();
+        let gate_rx = Arc::new(Mutex::new(gate_rx));
+        let spawner = Spawner::default().with_blocking(move |task| {
+            let gate_rx = gate_rx.clone();
+            std::thread::spawn(move || {
+                gate_rx.lock().unwrap().recv().unwrap();
+                task();
+            });
+        });
+        let options = RepoOptions::<Types>::new(temp_dir()).spawner(spawner);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            // above the default offload threshold
+            let data = vec![7; 8 * 1024 * 1024];
+            let cid = Cid::new_from_prefix(&Block::from("1").cid().prefix(), &data);
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let verified = {
+                let order = order.clone();
+                let verify = repo.verify_block(Block::new(data, cid));
+                async move {
+                    let (_, valid) = await!(verify).unwrap();
+                    order.lock().unwrap().push("verified");
+                    valid
+                }
+            };
+            let ticks = {
+                let order = order.clone();
+                async move {
+                    for _ in 0..3 {
+                        let delay = Instant::now() + Duration::from_millis(1);
+                        await!(Delay::new(delay).compat()).unwrap();
+                        order.lock().unwrap().push("tick");
+                    }
+                    gate.send(()).unwrap();
+                }
+            };
+            // both futures are polled by the same task
+            let (valid, ()) = join!(verified, ticks);
+            assert!(valid);
+            assert_eq!(*order.lock().unwrap(), vec!["tick", "tick", "tick", "verified"]);
+        });
+    }
+
+    #[test]
+    fn test_get_block_with() {
+        let (repo, events) = create_mock_repo_with_events();
+        tokio::run_async(async move {
+            let missing = Block::from("missing");
+            let local_only = await!(repo.get_block_with(missing.cid(), FetchMode::LocalOnly));
+            assert_eq!(local_only.unwrap(), None);
+            assert!(events.try_recv().is_err());
+
+            let timeout = FetchMode::NetworkWithTimeout(Duration::from_millis(10));
+            let err = await!(repo.get_block_with(missing.cid(), timeout)).unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::FetchTimeout(cid)) => assert_eq!(cid, missing.cid()),
+                _ => panic!("expected timeout, got {}", err),
+            }
+            match events.try_recv() {
+                Ok(RepoEvent::WantBlock(cid)) => assert_eq!(&cid, missing.cid()),
+                event => panic!("expected want event, got {:?}", event),
+            }
+
+            let get = repo.get_block_with(missing.cid(), FetchMode::NetworkBlocking);
+            // the mem store writes eagerly, so delay the put until the
+            // get was polled.
+            let put = async { await!(repo.put_block_quiet(missing.clone())) };
+            let (get, put) = join!(get, put);
+            put.unwrap();
+            assert_eq!(get.unwrap(), Some(missing.clone()));
+            let local_only = await!(repo.get_block_with(missing.cid(), FetchMode::LocalOnly));
+            assert_eq!(local_only.unwrap(), Some(missing));
+        });
+    }
+
+    #[test]
+    fn test_get_block_traced() {
+        let (repo, events) = create_mock_repo_with_events();
+        tokio::run_async(async move {
+            let local = Block::from("local");
+            await!(repo.put_block(local.clone())).unwrap();
+            let _ = events.try_recv();
+            let (block, source) = await!(repo.get_block_traced(local.cid())).unwrap();
+            assert_eq!((block, source), (local, BlockSource::Local));
+            assert!(events.try_recv().is_err());
+
+            let remote = Block::from("remote");
+            let get = repo.get_block_traced(remote.cid());
+            // the mem store writes eagerly, so delay the put until the
+            // get was polled.
+            let put = async { await!(repo.put_block_quiet(remote.clone())) };
+            let (get, put) = join!(get, put);
+            put.unwrap();
+            assert_eq!(get.unwrap(), (remote.clone(), BlockSource::Network));
+            match events.try_recv() {
+                Ok(RepoEvent::WantBlock(cid)) => assert_eq!(&cid, remote.cid()),
+                event => panic!("expected want event, got {:?}", event),
+            }
+        });
+    }
+
+    #[derive(Clone)]
+    struct BrokenDataStore(mem::MemDataStore);
+
+    impl DataStore for BrokenDataStore {
+        fn new(path: PathBuf) -> Self {
+            BrokenDataStore(mem::MemDataStore::new(path))
+        }
+
+        fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+            self.0.init()
+        }
+
+        fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+            FutureObj::new(Box::new(futures::future::err(format_err!("corrupted"))))
+        }
+
+        fn contains(&self, col: Column, key: &[u8]) ->
+            FutureObj<'static, Result<bool, Error>>
+        {
+            self.0.contains(col, key)
+        }
+
+        fn get(&self, col: Column, key: &[u8]) ->
+            FutureObj<'static, Result<Option<Vec<u8>>, Error>>
+        {
+            self.0.get(col, key)
+        }
+
+        fn put(&self, col: Column, key: &[u8], value: &[u8]) ->
+            FutureObj<'static, Result<(), Error>>
+        {
+            self.0.put(col, key, value)
+        }
+
+        fn remove(&self, col: Column, key: &[u8]) ->
+            FutureObj<'static, Result<(), Error>>
+        {
+            self.0.remove(col, key)
+        }
+
+        fn list_keys(&self, col: Column) ->
+            FutureObj<'static, Result<Vec<Vec<u8>>, Error>>
+        {
+            self.0.list_keys(col)
+        }
+    }
+
+    #[derive(Clone)]
+    struct BrokenTypes;
+
+    impl RepoTypes for BrokenTypes {
+        type TBlockStore = mem::MemBlockStore;
+        type TDataStore = BrokenDataStore;
+        type TPinStore = DataStorePinStore<BrokenDataStore>;
+    }
+
+    #[test]
+    fn test_degraded() {
+        let mut tmp = temp_dir();
+        tmp.push("rust-ipfs-repo-degraded");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let (repo, _) = Repo::new(RepoOptions::<BrokenTypes>::new(tmp.clone()));
+        tokio::run_async(async move {
+            assert!(await!(repo.open()).is_err());
+        });
+
+        let options = RepoOptions::<BrokenTypes>::new(tmp.clone()).degraded(true);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            await!(repo.open()).unwrap();
+            let block = Block::from("1");
+            let cid = await!(repo.put_block(block.clone())).unwrap();
+            assert_eq!(await!(repo.get_block(&cid)).unwrap(), block);
+
+            let unavailable = |err: Error| match err.downcast_ref::<RepoError>() {
+                Some(RepoError::DatastoreUnavailable) => true,
+                _ => false,
+            };
+            let peer_id = PeerId::random();
+            assert!(unavailable(await!(repo.get_ipns(&peer_id)).unwrap_err()));
+            assert!(unavailable(await!(repo.pin_block(&cid)).unwrap_err()));
+            assert!(unavailable(await!(repo.remove_block(&cid)).unwrap_err()));
+            assert!(unavailable(await!(repo.mfs_root()).unwrap_err()));
+            assert!(unavailable(await!(repo.set_mfs_root(&cid)).unwrap_err()));
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
 }
@@ -0,0 +1,548 @@
+//! Pinning of blocks
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{block_links, AuditOp, BlockStore, CancellationToken, Column, DataStore, PinStore, Repo, RepoError, RepoEvent, RepoTypes};
+use core::future::Future;
+use futures::future::FutureObj;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Value of direct pins in the `Pin` column, recursive pins have an
+/// empty value.
+const DIRECT: &[u8] = b"direct";
+
+/// How a block is pinned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PinMode {
+    /// Pinned without the blocks it links to.
+    Direct,
+    /// Pinned together with the blocks it links to.
+    Recursive,
+    /// Reachable from a recursively pinned block.
+    Indirect,
+}
+
+/// Pin counts of a repo.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PinStat {
+    /// Number of blocks pinned without their links.
+    pub direct: u64,
+    /// Number of blocks pinned together with the blocks they link to.
+    pub recursive: u64,
+    /// Number of blocks kept alive by pins, including the pinned blocks.
+    pub pinned_blocks: u64,
+}
+
+/// The blocks reachable from the recursive pins, computed once and kept
+/// until the pins change or a block in it is put or removed.
+#[derive(Debug, Default)]
+pub(crate) struct PinCache {
+    /// Changed by every invalidation, so that a traversal running
+    /// concurrently doesn't store an outdated set.
+    generation: u64,
+    indirect: Option<Arc<HashSet<Cid>>>,
+}
+
+impl PinCache {
+    fn invalidate(&mut self) {
+        self.generation += 1;
+        self.indirect = None;
+    }
+}
+
+/// Pin store keeping the pins in the `Pin` column of a data store.
+#[derive(Clone, Debug)]
+pub struct DataStorePinStore<TDataStore: DataStore> {
+    data_store: TDataStore,
+}
+
+impl<TDataStore: DataStore> PinStore<TDataStore> for DataStorePinStore<TDataStore> {
+    fn new(data_store: TDataStore) -> Self {
+        DataStorePinStore {
+            data_store,
+        }
+    }
+
+    fn pin(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.data_store.put(Column::Pin, &cid.to_bytes(), &[])
+    }
+
+    fn pin_direct(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.data_store.put(Column::Pin, &cid.to_bytes(), DIRECT)
+    }
+
+    fn unpin(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        self.data_store.remove(Column::Pin, &cid.to_bytes())
+    }
+
+    fn is_pinned(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+        self.data_store.contains(Column::Pin, &cid.to_bytes())
+    }
+
+    fn list(&self) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        let keys = self.data_store.list_keys(Column::Pin);
+        FutureObj::new(Box::new(async move {
+            let keys = await!(keys)?;
+            let mut cids = Vec::with_capacity(keys.len());
+            for key in keys {
+                cids.push(Cid::from(key)?);
+            }
+            Ok(cids)
+        }))
+    }
+
+    fn list_direct(&self) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        let data_store = self.data_store.clone();
+        FutureObj::new(Box::new(async move {
+            let mut cids = Vec::new();
+            for key in await!(data_store.list_keys(Column::Pin))? {
+                // unpinned while listing
+                if await!(data_store.get(Column::Pin, &key))?.as_ref().map(|value| &value[..]) == Some(DIRECT) {
+                    cids.push(Cid::from(key)?);
+                }
+            }
+            Ok(cids)
+        }))
+    }
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Returns the pin store unless the repo was opened without its data
+    /// store.
+    fn available_pin_store(&self) -> Result<&TRepoTypes::TPinStore, Error> {
+        self.available_data_store()?;
+        Ok(&self.pin_store)
+    }
+
+    /// Pins a block and the blocks it links to.
+    pub fn pin_block(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let repo = self.clone();
+        let cid = cid.to_owned();
+        async move {
+            let _guard = await!(repo.gc_guard());
+            await!(repo.available_pin_store()?.pin(&cid))?;
+            repo.pin_cache.lock().unwrap().invalidate();
+            await!(repo.audit(AuditOp::Pin, &cid))
+        }
+    }
+
+    /// Pins a block without the blocks it links to.
+    ///
+    /// Fails with `RepoError::PinnedRecursively` if the block is pinned
+    /// recursively, the blocks it links to would lose their pin.
+    pub fn pin_block_direct(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let repo = self.clone();
+        let cid = cid.to_owned();
+        async move {
+            let _guard = await!(repo.gc_guard());
+            if await!(repo.is_pinned(&cid))? && !await!(repo.list_direct_pins())?.contains(&cid) {
+                return Err(RepoError::PinnedRecursively(cid).into());
+            }
+            await!(repo.available_pin_store()?.pin_direct(&cid))?;
+            repo.pin_cache.lock().unwrap().invalidate();
+            await!(repo.audit(AuditOp::Pin, &cid))
+        }
+    }
+
+    /// Unpins a block.
+    pub fn unpin_block(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let unpin = self.available_pin_store().map(|pins| pins.unpin(cid));
+        let audit = self.audit(AuditOp::Unpin, cid);
+        let pin_cache = self.pin_cache.clone();
+        async move {
+            await!(unpin?)?;
+            pin_cache.lock().unwrap().invalidate();
+            await!(audit)
+        }
+    }
+
+    /// Checks if a block is pinned.
+    pub fn is_pinned(&self, cid: &Cid) -> impl Future<Output=Result<bool, Error>> {
+        let is_pinned = self.available_pin_store().map(|pins| pins.is_pinned(cid));
+        async move {
+            await!(is_pinned?)
+        }
+    }
+
+    /// Checks if a block is reachable from a pinned block.
+    ///
+    /// Only blocks in the local block store are traversed. The reachable
+    /// blocks are cached, so that removing many blocks doesn't traverse
+    /// the pinned dags for each of them.
+    pub fn is_pinned_indirectly(&self, cid: &Cid) ->
+    impl Future<Output=Result<bool, Error>>
+    {
+        let indirect = self.indirect_pins();
+        let cid = cid.to_owned();
+        async move {
+            Ok(await!(indirect)?.contains(&cid))
+        }
+    }
+
+    /// Returns the blocks reachable from the recursive pins, including
+    /// the missing ones.
+    fn indirect_pins(&self) -> impl Future<Output=Result<Arc<HashSet<Cid>>, Error>> {
+        let repo = self.clone();
+        async move {
+            let generation = {
+                let cache = repo.pin_cache.lock().unwrap();
+                if let Some(indirect) = &cache.indirect {
+                    return Ok(indirect.clone());
+                }
+                cache.generation
+            };
+            let mut indirect = HashSet::new();
+            let mut stack = Vec::new();
+            for pin in await!(repo.list_recursive_pins())? {
+                if let Some(block) = await!(repo.block_store.get(&pin))? {
+                    stack.extend(block_links(&block)?.into_iter().map(|link| (link, 1)));
+                }
+            }
+            while let Some((link, depth)) = stack.pop() {
+                if depth > repo.max_depth {
+                    return Err(RepoError::DagTooDeep(repo.max_depth).into());
+                }
+                if !indirect.insert(link.clone()) {
+                    continue;
+                }
+                if let Some(block) = await!(repo.block_store.get(&link))? {
+                    stack.extend(block_links(&block)?.into_iter().map(|link| (link, depth + 1)));
+                }
+            }
+            let indirect = Arc::new(indirect);
+            let mut cache = repo.pin_cache.lock().unwrap();
+            if cache.generation == generation {
+                cache.indirect = Some(indirect.clone());
+            }
+            Ok(indirect)
+        }
+    }
+
+    /// Drops the cached indirect pins if `cid` may be one of them.
+    ///
+    /// Putting such a block makes the blocks it links to reachable,
+    /// removing it makes them unreachable.
+    pub(crate) fn touch_indirect_pin(&self, cid: &Cid) {
+        let mut cache = self.pin_cache.lock().unwrap();
+        // without a cached set a running traversal may have missed it
+        if cache.indirect.as_ref().map_or(true, |indirect| indirect.contains(cid)) {
+            cache.invalidate();
+        }
+    }
+
+    /// Pins a block, together with the blocks it links to if `recursive`.
+    pub fn pin_add(&self, cid: &Cid, recursive: bool) -> impl Future<Output=Result<(), Error>> {
+        if recursive {
+            FutureObj::new(Box::new(self.pin_block(cid)))
+        } else {
+            FutureObj::new(Box::new(self.pin_block_direct(cid)))
+        }
+    }
+
+    /// Removes a direct or recursive pin.
+    ///
+    /// Fails with `RepoError::NotPinned` if the block isn't pinned itself,
+    /// indirect pins can only be removed by removing the pins they are
+    /// reachable from.
+    pub fn pin_rm(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let repo = self.clone();
+        let cid = cid.to_owned();
+        async move {
+            if !await!(repo.is_pinned(&cid))? {
+                return Err(RepoError::NotPinned(cid).into());
+            }
+            await!(repo.unpin_block(&cid))
+        }
+    }
+
+    /// Lists the pinned blocks with the way they are pinned, or only the
+    /// blocks pinned with `mode`.
+    ///
+    /// A block that is pinned itself is listed with its own pin even if
+    /// it is also reachable from a recursive pin. Only blocks in the
+    /// local block store are traversed for indirect pins.
+    pub fn pin_ls(&self, mode: Option<PinMode>) ->
+    impl Future<Output=Result<Vec<(Cid, PinMode)>, Error>>
+    {
+        let repo = self.clone();
+        async move {
+            let wanted = |m| mode.map_or(true, |mode| mode == m);
+            let recursive = await!(repo.list_recursive_pins())?;
+            let direct = await!(repo.list_direct_pins())?;
+            let mut pins = Vec::new();
+            if wanted(PinMode::Indirect) {
+                let pinned: HashSet<&Cid> = recursive.iter().chain(direct.iter()).collect();
+                for cid in await!(repo.live_blocks(&CancellationToken::new()))? {
+                    if !pinned.contains(&cid) {
+                        pins.push((cid, PinMode::Indirect));
+                    }
+                }
+            }
+            if wanted(PinMode::Direct) {
+                pins.extend(direct.into_iter().map(|cid| (cid, PinMode::Direct)));
+            }
+            if wanted(PinMode::Recursive) {
+                pins.extend(recursive.into_iter().map(|cid| (cid, PinMode::Recursive)));
+            }
+            Ok(pins)
+        }
+    }
+
+    /// Lists all pinned blocks.
+    pub fn list_pins(&self) -> impl Future<Output=Result<Vec<Cid>, Error>> {
+        let list = self.available_pin_store().map(|pins| pins.list());
+        async move {
+            await!(list?)
+        }
+    }
+
+    /// Lists the blocks pinned with `pin_block_direct`.
+    pub(crate) fn list_direct_pins(&self) -> impl Future<Output=Result<Vec<Cid>, Error>> {
+        let list = self.available_pin_store().map(|pins| pins.list_direct());
+        async move {
+            await!(list?)
+        }
+    }
+
+    /// Lists the blocks pinned together with the blocks they link to.
+    pub(crate) fn list_recursive_pins(&self) -> impl Future<Output=Result<Vec<Cid>, Error>> {
+        let pins = self.list_pins();
+        let direct = self.list_direct_pins();
+        async move {
+            let direct: HashSet<Cid> = await!(direct)?.into_iter().collect();
+            Ok(await!(pins)?.into_iter().filter(|pin| !direct.contains(pin)).collect())
+        }
+    }
+
+    /// Returns the number of pinned blocks.
+    pub fn pin_count(&self) -> impl Future<Output=Result<u64, Error>> {
+        let count = self.available_pin_store().map(|pins| pins.pin_count());
+        async move {
+            await!(count?)
+        }
+    }
+
+    /// Counts the direct and recursive pins and the blocks they keep
+    /// alive.
+    ///
+    /// Only blocks in the local block store are traversed.
+    pub fn pin_stat(&self) -> impl Future<Output=Result<PinStat, Error>> {
+        let repo = self.clone();
+        async move {
+            let pins = await!(repo.pin_count())?;
+            let direct = await!(repo.list_direct_pins())?.len() as u64;
+            let live = await!(repo.live_blocks(&CancellationToken::new()))?;
+            Ok(PinStat {
+                direct,
+                recursive: pins - direct,
+                pinned_blocks: live.len() as u64,
+            })
+        }
+    }
+
+    /// Returns the pinned blocks that are missing from the block store.
+    ///
+    /// Use `fetch_blocks` to retrive them from the network or `unpin_block`
+    /// to drop the pins.
+    pub fn check_pins(&self) -> impl Future<Output=Result<Vec<Cid>, Error>> {
+        let pins = self.list_pins();
+        let block_store = self.block_store.clone();
+        async move {
+            let mut dangling = Vec::new();
+            for cid in await!(pins)? {
+                if !await!(block_store.contains(&cid))? {
+                    dangling.push(cid);
+                }
+            }
+            Ok(dangling)
+        }
+    }
+
+    /// Requests blocks that are missing from the block store from the
+    /// network.
+    pub fn fetch_blocks(&self, cids: &[Cid]) {
+        for cid in cids {
+            // sending only fails if no one is listening anymore
+            // and that is okay with us.
+            let _ = self.events.send(RepoEvent::WantBlock(cid.to_owned()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::ipld::Ipld;
+    use crate::repo::mem::{MemDataStore, MemPinStore};
+    use crate::repo::tests::create_mock_repo;
+    use std::env::temp_dir;
+
+    /// Pins two blocks, unpins one and reports what the pin store sees.
+    fn exercise_pin_store<P: PinStore<MemDataStore>>(store: P) ->
+    impl Future<Output=(Vec<bool>, Vec<Cid>, u64)>
+    {
+        async move {
+            let cid1 = Block::from("1").cid().to_owned();
+            let cid2 = Block::from("2").cid().to_owned();
+            await!(store.pin(&cid1)).unwrap();
+            await!(store.pin(&cid2)).unwrap();
+            await!(store.unpin(&cid1)).unwrap();
+            let pinned = vec![
+                await!(store.is_pinned(&cid1)).unwrap(),
+                await!(store.is_pinned(&cid2)).unwrap(),
+            ];
+            let list = await!(store.list()).unwrap();
+            let count = await!(store.pin_count()).unwrap();
+            (pinned, list, count)
+        }
+    }
+
+    #[test]
+    fn test_mem_pin_store_matches_data_store() {
+        let data_store = MemDataStore::new(temp_dir());
+        let mem: MemPinStore = PinStore::new(data_store.clone());
+        let default = DataStorePinStore::new(data_store);
+        tokio::run_async(async move {
+            let mem = await!(exercise_pin_store(mem));
+            let default = await!(exercise_pin_store(default));
+            assert_eq!(mem, default);
+            assert_eq!(mem.0, vec![false, true]);
+            assert_eq!(mem.2, 1);
+        });
+    }
+
+    #[test]
+    fn test_remove_pinned_block() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let child = await!(repo.put_block(Block::from("child"))).unwrap();
+            let parent = Ipld::from(vec![Ipld::from(child.clone())]).to_dag_cbor().unwrap();
+            let parent = await!(repo.put_block(parent)).unwrap();
+            let unpinned = await!(repo.put_block(Block::from("unpinned"))).unwrap();
+            await!(repo.pin_block(&parent)).unwrap();
+
+            assert!(await!(repo.remove_block(&parent)).is_err());
+            assert!(await!(repo.remove_block(&child)).is_err());
+            assert!(await!(repo.block_store.contains(&child)).unwrap());
+
+            await!(repo.remove_block(&unpinned)).unwrap();
+            assert!(!await!(repo.block_store.contains(&unpinned)).unwrap());
+
+            await!(repo.remove_block_force(&child)).unwrap();
+            assert!(!await!(repo.block_store.contains(&child)).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_pin_stat() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let child = await!(repo.put_block(Block::from("child"))).unwrap();
+            let parent = Ipld::from(vec![Ipld::from(child.clone())]).to_dag_cbor().unwrap();
+            let parent = await!(repo.put_block(parent)).unwrap();
+            let direct_child = await!(repo.put_block(Block::from("direct child"))).unwrap();
+            let direct = Ipld::from(vec![Ipld::from(direct_child.clone())]).to_dag_cbor().unwrap();
+            let direct = await!(repo.put_block(direct)).unwrap();
+            await!(repo.pin_block(&parent)).unwrap();
+            // also reachable from the recursive pin
+            await!(repo.pin_block_direct(&child)).unwrap();
+            await!(repo.pin_block_direct(&direct)).unwrap();
+
+            assert_eq!(await!(repo.pin_stat()).unwrap(), PinStat {
+                direct: 2,
+                recursive: 1,
+                pinned_blocks: 3,
+            });
+            assert!(!await!(repo.is_pinned_indirectly(&direct_child)).unwrap());
+
+            // pinning recursively replaces the direct pin
+            await!(repo.pin_block(&direct)).unwrap();
+            let stat = await!(repo.pin_stat()).unwrap();
+            assert_eq!((stat.direct, stat.recursive, stat.pinned_blocks), (1, 2, 4));
+        });
+    }
+
+    #[test]
+    fn test_pin_ls() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let child = await!(repo.put_block(Block::from("child"))).unwrap();
+            let parent = Ipld::from(vec![Ipld::from(child.clone())]).to_dag_cbor().unwrap();
+            let parent = await!(repo.put_block(parent)).unwrap();
+            let direct = await!(repo.put_block(Block::from("direct"))).unwrap();
+            await!(repo.pin_add(&parent, true)).unwrap();
+            await!(repo.pin_add(&direct, false)).unwrap();
+
+            let mut pins = await!(repo.pin_ls(None)).unwrap();
+            pins.sort_by_key(|(cid, _)| cid.to_bytes());
+            let mut expected = vec![
+                (child.clone(), PinMode::Indirect),
+                (parent.clone(), PinMode::Recursive),
+                (direct.clone(), PinMode::Direct),
+            ];
+            expected.sort_by_key(|(cid, _)| cid.to_bytes());
+            assert_eq!(pins, expected);
+            let indirect = await!(repo.pin_ls(Some(PinMode::Indirect))).unwrap();
+            assert_eq!(indirect, vec![(child.clone(), PinMode::Indirect)]);
+
+            let err = await!(repo.pin_rm(&child)).unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::NotPinned(cid)) => assert_eq!(cid, &child),
+                _ => panic!("expected not pinned, got {}", err),
+            }
+            // the recursive pin isn't downgraded
+            let err = await!(repo.pin_add(&parent, false)).unwrap_err();
+            match err.downcast_ref::<RepoError>() {
+                Some(RepoError::PinnedRecursively(cid)) => assert_eq!(cid, &parent),
+                _ => panic!("expected recursive pin, got {}", err),
+            }
+            assert_eq!(await!(repo.pin_ls(Some(PinMode::Recursive))).unwrap(),
+                       vec![(parent.clone(), PinMode::Recursive)]);
+
+            await!(repo.pin_rm(&parent)).unwrap();
+            assert_eq!(await!(repo.pin_ls(None)).unwrap(), vec![(direct, PinMode::Direct)]);
+        });
+    }
+
+    #[test]
+    fn test_indirect_pin_cache() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let grandchild = await!(repo.put_block(Block::from("grandchild"))).unwrap();
+            let child = Ipld::from(vec![Ipld::from(grandchild.clone())]).to_dag_cbor().unwrap();
+            let parent = Ipld::from(vec![Ipld::from(child.cid().to_owned())]).to_dag_cbor().unwrap();
+            let parent = await!(repo.put_block(parent)).unwrap();
+            await!(repo.pin_block(&parent)).unwrap();
+            assert!(await!(repo.is_pinned_indirectly(child.cid())).unwrap());
+            assert!(!await!(repo.is_pinned_indirectly(&grandchild)).unwrap());
+            assert!(repo.pin_cache.lock().unwrap().indirect.is_some());
+
+            // putting the missing child makes the grandchild reachable
+            await!(repo.put_block(child)).unwrap();
+            assert!(await!(repo.is_pinned_indirectly(&grandchild)).unwrap());
+
+            await!(repo.unpin_block(&parent)).unwrap();
+            assert!(!await!(repo.is_pinned_indirectly(&grandchild)).unwrap());
+            await!(repo.remove_block(&grandchild)).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_check_pins() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let block1 = Block::from("pinned1");
+            let block2 = Block::from("pinned2");
+            let cid1 = await!(repo.put_block(block1)).unwrap();
+            let cid2 = await!(repo.put_block(block2)).unwrap();
+            await!(repo.pin_block(&cid1)).unwrap();
+            await!(repo.pin_block(&cid2)).unwrap();
+            assert!(await!(repo.is_pinned(&cid1)).unwrap());
+            assert!(await!(repo.check_pins()).unwrap().is_empty());
+
+            await!(repo.block_store.remove(&cid1)).unwrap();
+            assert_eq!(await!(repo.check_pins()).unwrap(), vec![cid1]);
+        });
+    }
+}
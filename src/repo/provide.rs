@@ -0,0 +1,123 @@
+//! Persistent queue of blocks waiting to be announced
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{BlockStore, Column, DataStore, Repo, RepoEvent, RepoTypes};
+use core::future::Future;
+
+const QUEUE_PREFIX: &[u8] = b"provide/";
+
+fn queue_key(cid: &Cid) -> Vec<u8> {
+    let mut key = QUEUE_PREFIX.to_vec();
+    key.extend_from_slice(&cid.to_bytes());
+    key
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Records that `cid` needs to be announced.
+    pub(crate) fn enqueue_provide(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        self.data_store.put(Column::Config, &queue_key(cid), &[])
+    }
+
+    /// Records that `cid` was announced.
+    pub(crate) fn dequeue_provide(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        self.data_store.remove(Column::Config, &queue_key(cid))
+    }
+
+    /// Removes `cid` from the provide queue after it was announced.
+    pub(crate) fn provided(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        // the queue can't be kept without the data store
+        let dequeue = match self.available_data_store() {
+            Ok(_) => Some(self.dequeue_provide(cid)),
+            Err(_) => None,
+        };
+        async move {
+            if let Some(dequeue) = dequeue {
+                await!(dequeue)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Lists the blocks that were stored but not announced yet.
+    pub fn provide_queue(&self) -> impl Future<Output=Result<Vec<Cid>, Error>> {
+        let data_store = self.available_data_store().map(Clone::clone);
+        async move {
+            let data_store = data_store?;
+            let mut cids = Vec::new();
+            for key in await!(data_store.list_keys(Column::Config))? {
+                if key.starts_with(QUEUE_PREFIX) {
+                    cids.push(Cid::from(&key[QUEUE_PREFIX.len()..])?);
+                }
+            }
+            Ok(cids)
+        }
+    }
+
+    /// Announces the blocks left in the provide queue, returning the
+    /// number of announced blocks.
+    ///
+    /// The blocks stay queued until they were provided.
+    ///
+    /// Call this after opening the repo instead of `reprovide_all` to
+    /// resume providing where the last run stopped.
+    pub fn provide_queued(&self) -> impl Future<Output=Result<u64, Error>> {
+        let repo = self.clone();
+        async move {
+            let mut count = 0;
+            for cid in await!(repo.provide_queue())? {
+                // removed before it was announced
+                if !await!(repo.block_store.contains(&cid))? {
+                    await!(repo.dequeue_provide(&cid))?;
+                    continue;
+                }
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = repo.events.send(RepoEvent::ProvideBlock(cid));
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::repo::{Repo, RepoEvent, RepoOptions};
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_provide_queue() {
+        let mut tmp = temp_dir();
+        tmp.push("repo_provide_queue");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let (repo, _) = Repo::<crate::Types>::new(RepoOptions::new(tmp.clone()));
+        tokio::run_async(async move {
+            await!(repo.init()).unwrap();
+            await!(repo.open()).unwrap();
+            await!(repo.put_block(Block::from("1"))).unwrap();
+            // stopped before the block was announced
+            let cid = await!(repo.put_block_quiet(Block::from("2"))).unwrap();
+            await!(repo.enqueue_provide(&cid)).unwrap();
+        });
+
+        let (repo, events) = Repo::<crate::Types>::new(RepoOptions::new(tmp.clone()));
+        let cid = Block::from("2").cid().to_owned();
+        tokio::run_async(async move {
+            await!(repo.open()).unwrap();
+            assert_eq!(await!(repo.provide_queue()).unwrap(), vec![cid.clone()]);
+            assert_eq!(await!(repo.provide_queued()).unwrap(), 1);
+            match events.try_recv() {
+                Ok(RepoEvent::ProvideBlock(provided)) => assert_eq!(provided, cid),
+                event => panic!("expected provide event, got {:?}", event),
+            }
+            // queued until it was provided
+            assert_eq!(await!(repo.provide_queue()).unwrap(), vec![cid.clone()]);
+            await!(repo.provided(&cid)).unwrap();
+            assert!(await!(repo.provide_queue()).unwrap().is_empty());
+        });
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
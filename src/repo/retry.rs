@@ -0,0 +1,179 @@
+//! Retrying of transient store errors
+use crate::error::Error;
+use crate::repo::RepoError;
+use core::future::Future;
+use futures::compat::*;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+/// `EBUSY` is reported as `ErrorKind::Other`.
+const EBUSY: i32 = 16;
+
+/// `ENOSPC` is reported as `ErrorKind::Other`.
+const ENOSPC: i32 = 28;
+
+/// Configures how often an operation failing with a transient error
+/// is retried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every further retry.
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a new `RetryPolicy`.
+    pub fn new(max_retries: u32, base_delay: Duration) -> Self {
+        RetryPolicy {
+            max_retries,
+            base_delay,
+        }
+    }
+
+    /// A policy that never retries.
+    pub fn never() -> Self {
+        RetryPolicy::new(0, Duration::from_millis(0))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::never()
+    }
+}
+
+/// Returns `true` if the operation may succeed when it is retried.
+///
+/// Errors like `ENOSPC` or permission errors are not transient.
+pub fn is_transient(err: &Error) -> bool {
+    if let Some(err) = err.downcast_ref::<std::io::Error>() {
+        return match err.kind() {
+            ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut => true,
+            _ => err.raw_os_error() == Some(EBUSY),
+        };
+    }
+    if let Some(err) = err.downcast_ref::<rocksdb::Error>() {
+        let err = err.to_string();
+        return err.starts_with("Resource busy") || err.starts_with("Operation failed. Try again.");
+    }
+    false
+}
+
+/// Replaces errors caused by a full disk with `RepoError::OutOfSpace`.
+pub fn out_of_space(err: Error) -> Error {
+    let full = if let Some(err) = err.downcast_ref::<std::io::Error>() {
+        err.raw_os_error() == Some(ENOSPC)
+    } else if let Some(err) = err.downcast_ref::<rocksdb::Error>() {
+        err.to_string().contains("No space left on device")
+    } else {
+        false
+    };
+    if full {
+        RepoError::OutOfSpace.into()
+    } else {
+        err
+    }
+}
+
+/// Runs `op` until it succeeds, fails with a non transient error or the
+/// retries of the `policy` are exhausted.
+pub fn retry<T, F, R>(policy: RetryPolicy, mut op: F) ->
+impl Future<Output=Result<T, Error>>
+where
+    F: FnMut() -> R,
+    R: Future<Output=Result<T, Error>>,
+{
+    async move {
+        let mut delay = policy.base_delay;
+        let mut retries = 0;
+        loop {
+            match await!(op()) {
+                Err(ref err) if retries < policy.max_retries && is_transient(err) => {
+                    debug!("retrying after transient error: {}", err);
+                    await!(Delay::new(Instant::now() + delay).compat())?;
+                    delay *= 2;
+                    retries += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn failing_op(attempts: Arc<AtomicUsize>, failures: usize, kind: ErrorKind) ->
+    impl Future<Output=Result<usize, Error>>
+    {
+        let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+        async move {
+            if attempt < failures {
+                Err(std::io::Error::from(kind).into())
+            } else {
+                Ok(attempt)
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_out_of_space() {
+        use std::io::Write;
+
+        // writes to /dev/full fail with ENOSPC
+        let mut full = std::fs::OpenOptions::new().write(true).open("/dev/full").unwrap();
+        let err = full.write_all(b"1").and_then(|_| full.flush()).unwrap_err();
+        match out_of_space(err.into()).downcast_ref::<RepoError>() {
+            Some(RepoError::OutOfSpace) => {}
+            _ => panic!("expected out of space"),
+        }
+
+        let err = out_of_space(std::io::Error::from(ErrorKind::Interrupted).into());
+        assert!(err.downcast_ref::<RepoError>().is_none());
+    }
+
+    #[test]
+    fn test_retry_transient() {
+        tokio::run_async(async {
+            let attempts = Arc::new(AtomicUsize::new(0));
+            let policy = RetryPolicy::new(3, Duration::from_millis(1));
+            let res = await!(retry(policy, || {
+                failing_op(attempts.clone(), 2, ErrorKind::Interrupted)
+            }));
+            assert_eq!(res.unwrap(), 2);
+            assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        });
+    }
+
+    #[test]
+    fn test_retry_budget_exhausted() {
+        tokio::run_async(async {
+            let attempts = Arc::new(AtomicUsize::new(0));
+            let policy = RetryPolicy::new(1, Duration::from_millis(1));
+            let res = await!(retry(policy, || {
+                failing_op(attempts.clone(), 2, ErrorKind::Interrupted)
+            }));
+            assert!(res.is_err());
+            assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        });
+    }
+
+    #[test]
+    fn test_retry_permanent() {
+        tokio::run_async(async {
+            let attempts = Arc::new(AtomicUsize::new(0));
+            let policy = RetryPolicy::new(3, Duration::from_millis(1));
+            let res = await!(retry(policy, || {
+                failing_op(attempts.clone(), 2, ErrorKind::PermissionDenied)
+            }));
+            assert!(res.is_err());
+            assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        });
+    }
+}
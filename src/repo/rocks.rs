@@ -0,0 +1,279 @@
+//! Rocksdb backed block store
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::repo::{BlockStore, StoreStream};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use futures::compat::*;
+use futures::future::{self, FutureObj};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::fs;
+
+const BLOCKS: &str = "blocks";
+const META: &str = "meta";
+
+/// How rocksdb compacts its files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionStyle {
+    Level,
+    Universal,
+    Fifo,
+}
+
+/// Tuning of the rocksdb backed stores.
+///
+/// Large stores profit from larger write buffers, at the cost of memory
+/// and a longer recovery from the write-ahead log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RocksTuning {
+    /// Size of a single memtable in bytes.
+    pub write_buffer_size: usize,
+    /// Number of memtables kept in memory before writes stall.
+    pub max_write_buffers: i32,
+    /// Number of level zero files that trigger a compaction.
+    pub level_zero_compaction_trigger: i32,
+    /// Target size of the compacted files in bytes.
+    pub target_file_size: u64,
+    pub compaction_style: CompactionStyle,
+}
+
+impl Default for RocksTuning {
+    fn default() -> Self {
+        // the rocksdb defaults
+        RocksTuning {
+            write_buffer_size: 64 * 1024 * 1024,
+            max_write_buffers: 2,
+            level_zero_compaction_trigger: 4,
+            target_file_size: 64 * 1024 * 1024,
+            compaction_style: CompactionStyle::Level,
+        }
+    }
+}
+
+impl RocksTuning {
+    pub(crate) fn options(&self) -> rocksdb::Options {
+        let mut opts = rocksdb::Options::default();
+        opts.set_write_buffer_size(self.write_buffer_size);
+        opts.set_max_write_buffer_number(self.max_write_buffers);
+        opts.set_level_zero_file_num_compaction_trigger(self.level_zero_compaction_trigger);
+        opts.set_target_file_size_base(self.target_file_size);
+        opts.set_compaction_style(match self.compaction_style {
+            CompactionStyle::Level => rocksdb::DBCompactionStyle::Level,
+            CompactionStyle::Universal => rocksdb::DBCompactionStyle::Universal,
+            CompactionStyle::Fifo => rocksdb::DBCompactionStyle::Fifo,
+        });
+        opts
+    }
+}
+
+/// Block store keeping all blocks in a single rocksdb database.
+///
+/// The block data lives in the `blocks` column family and the size of
+/// every block in the `meta` column family, so that listing blocks and
+/// looking up their sizes doesn't read the data.
+#[derive(Clone, Debug)]
+pub struct RocksBlockStore {
+    path: PathBuf,
+    db: Arc<Mutex<Option<rocksdb::DB>>>,
+    tuning: RocksTuning,
+}
+
+impl RocksBlockStore {
+    fn with_db<T, F>(&self, f: F) -> Result<T, Error>
+    where F: FnOnce(&rocksdb::DB, rocksdb::ColumnFamily, rocksdb::ColumnFamily) -> Result<T, Error>
+    {
+        let db = self.db.lock().unwrap();
+        let db = match db.as_ref() {
+            Some(db) => db,
+            None => bail!("rocks block store is not open"),
+        };
+        let blocks = db.cf_handle(BLOCKS).ok_or_else(|| format_err!("missing column family {}", BLOCKS))?;
+        let meta = db.cf_handle(META).ok_or_else(|| format_err!("missing column family {}", META))?;
+        f(db, blocks, meta)
+    }
+
+    fn put_batch(&self, blocks: &[Block]) -> Result<(), Error> {
+        self.with_db(|db, blocks_cf, meta_cf| {
+            let mut batch = rocksdb::WriteBatch::default();
+            for block in blocks {
+                let key = block.cid().to_bytes();
+                let mut size = Vec::with_capacity(8);
+                size.write_u64::<BigEndian>(block.size() as u64)?;
+                batch.put_cf(blocks_cf, &key, block.data())?;
+                batch.put_cf(meta_cf, &key, &size)?;
+            }
+            db.write(batch)?;
+            Ok(())
+        })
+    }
+}
+
+impl BlockStore for RocksBlockStore {
+    fn new(path: PathBuf) -> Self {
+        RocksBlockStore {
+            path,
+            db: Arc::new(Mutex::new(None)),
+            tuning: RocksTuning::default(),
+        }
+    }
+
+    fn with_rocks_tuning(mut self, tuning: RocksTuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+
+    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+        let path = self.path.clone();
+        FutureObj::new(Box::new(async move {
+            await!(fs::create_dir_all(path).compat())?;
+            Ok(())
+        }))
+    }
+
+    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+        let db = self.db.clone();
+        let path = self.path.clone();
+        let tuning = self.tuning;
+        FutureObj::new(Box::new(async move {
+            let mut db_opts = tuning.options();
+            db_opts.create_missing_column_families(true);
+            db_opts.create_if_missing(true);
+
+            let cfs = vec![
+                rocksdb::ColumnFamilyDescriptor::new(BLOCKS, tuning.options()),
+                rocksdb::ColumnFamilyDescriptor::new(META, tuning.options()),
+            ];
+            let rdb = rocksdb::DB::open_cf_descriptors(&db_opts, &path, cfs)?;
+            *db.lock().unwrap() = Some(rdb);
+            Ok(())
+        }))
+    }
+
+    fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+        let key = cid.to_bytes();
+        let contains = self.with_db(|db, _, meta| {
+            Ok(db.get_cf(meta, &key)?.is_some())
+        });
+        FutureObj::new(Box::new(future::ready(contains)))
+    }
+
+    fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+        let cid = cid.to_owned();
+        let block = self.with_db(|db, blocks, _| {
+            Ok(db.get_cf(blocks, &cid.to_bytes())?
+                .map(|data| Block::new(data.to_vec(), cid)))
+        });
+        FutureObj::new(Box::new(future::ready(block)))
+    }
+
+    fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+        let cid = block.cid().to_owned();
+        let put = self.put_batch(&[block]).map(|_| cid);
+        FutureObj::new(Box::new(future::ready(put)))
+    }
+
+    fn put_many(&self, blocks: Vec<Block>) -> FutureObj<'static, Result<Vec<Cid>, Error>> {
+        let cids = blocks.iter().map(|block| block.cid().to_owned()).collect();
+        let put = self.put_batch(&blocks).map(|_| cids);
+        FutureObj::new(Box::new(future::ready(put)))
+    }
+
+    fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        let key = cid.to_bytes();
+        let remove = self.with_db(|db, blocks, meta| {
+            let mut batch = rocksdb::WriteBatch::default();
+            batch.delete_cf(blocks, &key)?;
+            batch.delete_cf(meta, &key)?;
+            db.write(batch)?;
+            Ok(())
+        });
+        FutureObj::new(Box::new(future::ready(remove)))
+    }
+
+    fn list_stream(&self) -> StoreStream<Cid> {
+        let keys = self.with_db(|db, _, meta| {
+            Ok(db.iterator_cf(meta, rocksdb::IteratorMode::Start)?
+                .map(|(key, _)| key.to_vec())
+                .collect::<Vec<_>>())
+        });
+        match keys {
+            Ok(keys) => Box::pin(futures::stream::iter(keys.into_iter()
+                .map(|key| Cid::from(key).map_err(Into::into)))),
+            Err(err) => Box::pin(futures::stream::iter(vec![Err(err)])),
+        }
+    }
+
+    fn block_size(&self, cid: &Cid) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        let key = cid.to_bytes();
+        let size = self.with_db(|db, _, meta| {
+            match db.get_cf(meta, &key)? {
+                Some(size) => Ok(Some((&size[..]).read_u64::<BigEndian>()?)),
+                None => Ok(None),
+            }
+        });
+        FutureObj::new(Box::new(future::ready(size)))
+    }
+
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        let flush = self.with_db(|db, blocks, meta| {
+            // `flush` only flushes the default column family
+            db.flush_cf(blocks)?;
+            Ok(db.flush_cf(meta)?)
+        });
+        FutureObj::new(Box::new(future::ready(flush)))
+    }
+
+    fn snapshot(&self, dest: PathBuf) -> FutureObj<'static, Result<(), Error>> {
+        let snapshot = self.with_db(|db, _, _| {
+            let checkpoint = rocksdb::checkpoint::Checkpoint::new(db)?;
+            checkpoint.create_checkpoint(&dest)?;
+            Ok(())
+        });
+        FutureObj::new(Box::new(future::ready(snapshot)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::testsuite::run_block_store_tests;
+    use std::env::temp_dir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_rocks_blockstore_suite() {
+        let count = AtomicUsize::new(0);
+        run_block_store_tests(|| {
+            let mut tmp = temp_dir();
+            tmp.push(format!("rocks-blockstore-suite-{}", count.fetch_add(1, Ordering::SeqCst)));
+            std::fs::remove_dir_all(tmp.clone()).ok();
+            RocksBlockStore::new(tmp)
+        });
+    }
+
+    #[test]
+    fn test_rocks_blockstore_tuning() {
+        let mut tmp = temp_dir();
+        tmp.push("rocks-blockstore-tuning");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let tuning = RocksTuning {
+            write_buffer_size: 1024 * 1024,
+            compaction_style: CompactionStyle::Universal,
+            ..RocksTuning::default()
+        };
+        let store = RocksBlockStore::new(tmp.clone()).with_rocks_tuning(tuning);
+        let block = Block::from("1");
+        let cid = block.cid().to_owned();
+        tokio::run_async(async move {
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+            await!(store.put(block)).unwrap();
+            assert_eq!(await!(store.block_size(&cid)).unwrap(), Some(1));
+            assert_eq!(await!(store.list()).unwrap(), vec![cid.clone()]);
+            await!(store.remove(&cid)).unwrap();
+            assert!(!await!(store.contains(&cid)).unwrap());
+            assert_eq!(await!(store.block_size(&cid)).unwrap(), None);
+        });
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
@@ -0,0 +1,267 @@
+//! S3 backed block store
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::repo::{BlockStore, StoreStream};
+use futures::compat::*;
+use futures::future::FutureObj;
+use futures::stream::StreamExt;
+use rusoto_core::Region;
+use rusoto_s3::{
+    DeleteObjectRequest, GetObjectError, GetObjectRequest, HeadObjectError,
+    HeadObjectRequest, ListObjectsV2Request, PutObjectRequest, S3, S3Client,
+};
+use rustc_serialize::hex::{FromHex, ToHex};
+use std::path::PathBuf;
+use tokio::prelude::Stream as OldStream;
+
+/// Environment variable with the endpoint of an S3 compatible service.
+pub const S3_ENDPOINT_VAR: &str = "AWS_S3_ENDPOINT";
+
+/// Block store keeping every block as an object in an S3 compatible
+/// bucket, so that multiple stateless nodes can share their blocks.
+///
+/// The path of the store is the bucket followed by an optional key
+/// prefix, like `my-bucket/blocks`. The region is read from the
+/// environment like in the aws cli, a custom endpoint can be set with
+/// `AWS_S3_ENDPOINT`.
+///
+/// Objects are keyed by the hex encoded cid of the block, not by its
+/// multihash. Listing a bucket only returns the keys, so this way
+/// `list_stream` returns the cids the blocks were stored with without
+/// reading every object, and removing a block doesn't remove blocks of
+/// other codecs with the same data. The downside is that the same data
+/// stored under several cids, e.g. as cid v0 and v1, is stored once per
+/// cid.
+#[derive(Clone)]
+pub struct S3BlockStore {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+}
+
+/// Splits a store path into the bucket and the key prefix.
+fn bucket_and_prefix(path: &PathBuf) -> (String, String) {
+    let mut components = path.iter().map(|c| c.to_string_lossy().into_owned());
+    let bucket = components.next().unwrap_or_default();
+    let prefix = components.map(|c| c + "/").collect();
+    (bucket, prefix)
+}
+
+fn region_from_env() -> Region {
+    match std::env::var(S3_ENDPOINT_VAR) {
+        Ok(endpoint) => Region::Custom {
+            name: Region::default().name().to_owned(),
+            endpoint,
+        },
+        Err(_) => Region::default(),
+    }
+}
+
+impl S3BlockStore {
+    /// Creates a store using the bucket in `region`.
+    pub fn with_region(bucket: &str, prefix: &str, region: Region) -> Self {
+        S3BlockStore {
+            client: S3Client::new(region),
+            bucket: bucket.to_owned(),
+            prefix: prefix.to_owned(),
+        }
+    }
+
+    fn key(&self, cid: &Cid) -> String {
+        format!("{}{}", self.prefix, cid.to_bytes().to_hex())
+    }
+
+    fn head(&self, key: String) -> FutureObj<'static, Result<bool, Error>> {
+        let request = HeadObjectRequest {
+            bucket: self.bucket.clone(),
+            key,
+            ..Default::default()
+        };
+        let head = self.client.head_object(request);
+        FutureObj::new(Box::new(async move {
+            match await!(head.compat()) {
+                Ok(_) => Ok(true),
+                // head responses have no body, so a missing object is
+                // only distinguished by the status
+                Err(HeadObjectError::NoSuchKey(_)) => Ok(false),
+                Err(HeadObjectError::Unknown(ref response)) if response.status.as_u16() == 404 =>
+                    Ok(false),
+                Err(err) => Err(err.into()),
+            }
+        }))
+    }
+}
+
+impl BlockStore for S3BlockStore {
+    fn new(path: PathBuf) -> Self {
+        let (bucket, prefix) = bucket_and_prefix(&path);
+        S3BlockStore::with_region(&bucket, &prefix, region_from_env())
+    }
+
+    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+        // the bucket is managed outside of the repo
+        self.open()
+    }
+
+    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+        let request = ListObjectsV2Request {
+            bucket: self.bucket.clone(),
+            prefix: Some(self.prefix.clone()),
+            max_keys: Some(1),
+            ..Default::default()
+        };
+        let list = self.client.list_objects_v2(request);
+        FutureObj::new(Box::new(async move {
+            await!(list.compat())?;
+            Ok(())
+        }))
+    }
+
+    fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+        self.head(self.key(cid))
+    }
+
+    fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+        let cid = cid.to_owned();
+        let request = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.key(&cid),
+            ..Default::default()
+        };
+        let get = self.client.get_object(request);
+        FutureObj::new(Box::new(async move {
+            let output = match await!(get.compat()) {
+                Ok(output) => output,
+                Err(GetObjectError::NoSuchKey(_)) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let data = match output.body {
+                Some(body) => await!(body.concat2().compat())?,
+                None => Vec::new(),
+            };
+            Ok(Some(Block::new(data, cid)))
+        }))
+    }
+
+    fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+        let cid = block.cid().to_owned();
+        let request = PutObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.key(&cid),
+            body: Some(block.data().to_vec().into()),
+            ..Default::default()
+        };
+        let put = self.client.put_object(request);
+        FutureObj::new(Box::new(async move {
+            await!(put.compat())?;
+            Ok(cid)
+        }))
+    }
+
+    fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+        let request = DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.key(cid),
+            ..Default::default()
+        };
+        let delete = self.client.delete_object(request);
+        FutureObj::new(Box::new(async move {
+            await!(delete.compat())?;
+            Ok(())
+        }))
+    }
+
+    fn block_size(&self, cid: &Cid) -> FutureObj<'static, Result<Option<u64>, Error>> {
+        let request = HeadObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.key(cid),
+            ..Default::default()
+        };
+        let head = self.client.head_object(request);
+        FutureObj::new(Box::new(async move {
+            match await!(head.compat()) {
+                Ok(output) => Ok(output.content_length.map(|len| len as u64)),
+                Err(HeadObjectError::NoSuchKey(_)) => Ok(None),
+                Err(HeadObjectError::Unknown(ref response)) if response.status.as_u16() == 404 =>
+                    Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        }))
+    }
+
+    fn list_stream(&self) -> StoreStream<Cid> {
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let prefix = self.prefix.clone();
+        // pages are requested one after the other with the continuation
+        // token of the previous page
+        let pages = futures::stream::unfold(Some(None), move |token: Option<Option<String>>| {
+            let client = client.clone();
+            let bucket = bucket.clone();
+            let prefix = prefix.clone();
+            async move {
+                let token = match token {
+                    Some(token) => token,
+                    None => return None,
+                };
+                let request = ListObjectsV2Request {
+                    bucket,
+                    prefix: Some(prefix.clone()),
+                    continuation_token: token,
+                    ..Default::default()
+                };
+                let output = match await!(client.list_objects_v2(request).compat()) {
+                    Ok(output) => output,
+                    Err(err) => return Some((vec![Err(err.into())], None)),
+                };
+                let next = match output.is_truncated {
+                    Some(true) => output.next_continuation_token.map(Some),
+                    _ => None,
+                };
+                let cids = output.contents.unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key)
+                    .map(|key| cid_from_key(&prefix, &key))
+                    .collect();
+                Some((cids, next))
+            }
+        });
+        Box::pin(pages.map(futures::stream::iter).flatten())
+    }
+}
+
+/// Returns the cid of the object `key`.
+fn cid_from_key(prefix: &str, key: &str) -> Result<Cid, Error> {
+    let bytes = key[prefix.len()..].from_hex()
+        .map_err(|_| format_err!("invalid block key {}", key))?;
+    Ok(Cid::from(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_and_prefix() {
+        assert_eq!(bucket_and_prefix(&PathBuf::from("bucket")),
+                   ("bucket".to_string(), "".to_string()));
+        assert_eq!(bucket_and_prefix(&PathBuf::from("bucket/repo/blocks")),
+                   ("bucket".to_string(), "repo/blocks/".to_string()));
+    }
+
+    #[test]
+    fn test_object_key() {
+        let store = S3BlockStore::with_region("bucket", "blocks/", Region::UsEast1);
+        let block = Block::from("1");
+        let key = store.key(block.cid());
+        assert!(key.starts_with("blocks/"));
+
+        assert_eq!(&cid_from_key("blocks/", &key).unwrap(), block.cid());
+        assert!(cid_from_key("blocks/", "blocks/zz").is_err());
+
+        // blocks of different codecs with the same data don't share objects
+        let raw = Cid::new(cid::Codec::Raw, cid::Version::V1, &block.cid().hash);
+        assert_ne!(store.key(&raw), key);
+        assert_eq!(cid_from_key("blocks/", &store.key(&raw)).unwrap(), raw);
+    }
+}
@@ -0,0 +1,114 @@
+//! Fetching the blocks of one dag together
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::repo::{FetchMode, Repo, RepoEvent, RepoEvents, RepoTypes};
+use core::future::Future;
+use std::sync::Arc;
+
+pub type SessionId = usize;
+
+/// Ends the session when the last clone of it is dropped.
+struct SessionGuard {
+    id: SessionId,
+    events: RepoEvents,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        // sending only fails if no one is listening anymore
+        // and that is okay with us.
+        let _ = self.events.send(RepoEvent::EndSession(self.id));
+    }
+}
+
+/// Fetches related blocks, e.g. all blocks of one file.
+///
+/// Missing blocks are wanted in the session, so that bitswap asks the
+/// peers that sent earlier blocks of the session instead of all peers.
+/// Clones share the session, it ends when the last one is dropped.
+#[derive(Clone)]
+pub struct Session<T: RepoTypes> {
+    repo: Repo<T>,
+    guard: Arc<SessionGuard>,
+}
+
+impl<T: RepoTypes> Session<T> {
+    pub(crate) fn new(repo: Repo<T>, id: SessionId) -> Self {
+        let guard = Arc::new(SessionGuard {
+            id,
+            events: repo.events.clone(),
+        });
+        Session {
+            repo,
+            guard,
+        }
+    }
+
+    /// Returns the id of the session.
+    pub fn id(&self) -> SessionId {
+        self.guard.id
+    }
+
+    /// Retrives a block, waiting for missing blocks to be fetched.
+    pub fn get_block(&self, cid: &Cid) -> impl Future<Output=Result<Block, Error>> {
+        let get = self.get_block_with(cid, FetchMode::NetworkBlocking);
+        async move {
+            Ok(await!(get)?.expect("blocking gets wait for the block"))
+        }
+    }
+
+    /// Retrives a block, fetching missing blocks according to `mode`.
+    pub fn get_block_with(&self, cid: &Cid, mode: FetchMode) ->
+    impl Future<Output=Result<Option<Block>, Error>>
+    {
+        self.repo.fetch_block(cid, mode, Some(self.id()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::tests::create_mock_repo;
+    use std::time::Duration;
+
+    #[test]
+    fn test_session() {
+        let repo = create_mock_repo();
+        let events = repo.subscribe_events();
+        tokio::run_async(async move {
+            let session = repo.session();
+            let other = repo.session();
+            assert_ne!(session.id(), other.id());
+            drop(other);
+
+            let local = Block::from("local");
+            await!(repo.put_block(local.clone())).unwrap();
+            assert_eq!(await!(session.get_block(local.cid())).unwrap(), local);
+            let missing = Block::from("missing");
+            let timeout = FetchMode::NetworkWithTimeout(Duration::from_millis(10));
+            assert!(await!(session.get_block_with(missing.cid(), timeout)).is_err());
+            let id = session.id();
+            drop(session.clone());
+            drop(session);
+
+            let events: Vec<RepoEvent> = events.try_iter().filter(|event| match event {
+                RepoEvent::WantBlock(_) | RepoEvent::WantBlockInSession(_, _) |
+                RepoEvent::EndSession(_) => true,
+                _ => false,
+            }).collect();
+            match &events[..] {
+                [
+                    RepoEvent::EndSession(other),
+                    RepoEvent::WantBlockInSession(cid, wanted),
+                    RepoEvent::EndSession(ended),
+                ] => {
+                    assert_ne!(*other, id);
+                    assert_eq!(cid, missing.cid());
+                    assert_eq!(*wanted, id);
+                    assert_eq!(*ended, id);
+                }
+                events => panic!("unexpected events {:?}", events),
+            }
+        });
+    }
+}
@@ -0,0 +1,207 @@
+//! Sled backed data store
+use crate::error::Error;
+use crate::repo::{Column, DataStore};
+use crate::repo::fs::{file_exists, remove_stream, write_stream};
+use futures::compat::*;
+use futures::future::{self, FutureObj};
+use rustc_serialize::hex::ToHex;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::fs;
+use tokio::io::AsyncRead;
+
+/// Data store keeping every column in a tree of an embedded sled
+/// database.
+///
+/// Like the `RocksDataStore`, streamed values are stored as files in the
+/// `streams` directory.
+#[derive(Clone, Debug)]
+pub struct SledDataStore {
+    path: PathBuf,
+    db: Arc<Mutex<Option<::sled::Db>>>,
+}
+
+impl SledDataStore {
+    fn tree(&self, col: Column) -> Result<Arc<::sled::Tree>, Error> {
+        let db = self.db.lock().unwrap();
+        match db.as_ref() {
+            Some(db) => Ok(db.open_tree(col.name().as_bytes().to_vec())?),
+            None => bail!("sled data store is not open"),
+        }
+    }
+
+    fn db_path(&self) -> PathBuf {
+        let mut path = self.path.clone();
+        path.push("db");
+        path
+    }
+
+    /// Streamed values are stored as files in the `streams` directory,
+    /// see `RocksDataStore`.
+    fn stream_path(&self, col: Column, key: &[u8]) -> PathBuf {
+        let mut path = self.path.clone();
+        path.push("streams");
+        path.push(col.name());
+        path.push(key.to_hex());
+        path
+    }
+}
+
+impl DataStore for SledDataStore {
+    fn new(path: PathBuf) -> Self {
+        SledDataStore {
+            path,
+            db: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+        let path = self.path.clone();
+        FutureObj::new(Box::new(async move {
+            await!(fs::create_dir_all(path).compat())?;
+            Ok(())
+        }))
+    }
+
+    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+        let db = self.db.clone();
+        let path = self.db_path();
+        FutureObj::new(Box::new(async move {
+            let sled_db = ::sled::Db::start_default(path)?;
+            *db.lock().unwrap() = Some(sled_db);
+            Ok(())
+        }))
+    }
+
+    fn contains(&self, col: Column, key: &[u8]) ->
+        FutureObj<'static, Result<bool, Error>>
+    {
+        let contains = self.tree(col)
+            .and_then(|tree| Ok(tree.contains_key(key)?));
+        let stream = self.stream_path(col, key);
+        FutureObj::new(Box::new(async move {
+            if contains? {
+                return Ok(true);
+            }
+            await!(file_exists(stream))
+        }))
+    }
+
+    fn get(&self, col: Column, key: &[u8]) ->
+        FutureObj<'static, Result<Option<Vec<u8>>, Error>>
+    {
+        let value = self.tree(col)
+            .and_then(|tree| Ok(tree.get(key)?.map(|value| value.to_vec())));
+        FutureObj::new(Box::new(future::ready(value)))
+    }
+
+    fn put(&self, col: Column, key: &[u8], value: &[u8]) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        let res = self.tree(col)
+            .and_then(|tree| Ok(tree.set(key, value.to_vec()).map(|_| ())?));
+        FutureObj::new(Box::new(future::ready(res)))
+    }
+
+    fn remove(&self, col: Column, key: &[u8]) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        let res = self.tree(col)
+            .and_then(|tree| Ok(tree.del(key).map(|_| ())?));
+        let stream = self.stream_path(col, key);
+        FutureObj::new(Box::new(async move {
+            res?;
+            await!(remove_stream(stream))
+        }))
+    }
+
+    fn list_keys(&self, col: Column) ->
+        FutureObj<'static, Result<Vec<Vec<u8>>, Error>>
+    {
+        let keys = self.tree(col).and_then(|tree| {
+            let mut keys = Vec::new();
+            for entry in tree.iter() {
+                let (key, _) = entry?;
+                keys.push(key.to_vec());
+            }
+            Ok(keys)
+        });
+        FutureObj::new(Box::new(future::ready(keys)))
+    }
+
+    fn flush(&self) -> FutureObj<'static, Result<(), Error>> {
+        let db = self.db.clone();
+        FutureObj::new(Box::new(async move {
+            if let Some(db) = db.lock().unwrap().as_ref() {
+                db.flush()?;
+            }
+            Ok(())
+        }))
+    }
+
+    fn get_stream(&self, col: Column, key: &[u8]) ->
+        FutureObj<'static, Result<Option<Box<dyn AsyncRead + Send>>, Error>>
+    {
+        let path = self.stream_path(col, key);
+        FutureObj::new(Box::new(async move {
+            match await!(fs::File::open(path).compat()) {
+                Ok(file) => {
+                    let reader: Box<dyn AsyncRead + Send> = Box::new(file);
+                    Ok(Some(reader))
+                }
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        }))
+    }
+
+    fn put_stream(&self, col: Column, key: &[u8], value: Box<dyn AsyncRead + Send>) ->
+        FutureObj<'static, Result<(), Error>>
+    {
+        let path = self.stream_path(col, key);
+        let mut tmp_path = path.clone();
+        // concurrent writes of the same key need their own temp files
+        tmp_path.set_extension(format!("{:x}.tmp", rand::random::<u64>()));
+        FutureObj::new(Box::new(write_stream(path, tmp_path, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::testsuite::run_data_store_tests;
+    use std::env::temp_dir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_sled_datastore_suite() {
+        let count = AtomicUsize::new(0);
+        run_data_store_tests(|| {
+            let mut tmp = temp_dir();
+            tmp.push(format!("sled-suite-{}", count.fetch_add(1, Ordering::SeqCst)));
+            std::fs::remove_dir_all(tmp.clone()).ok();
+            SledDataStore::new(tmp)
+        });
+    }
+
+    #[test]
+    fn test_sled_datastore_reopen() {
+        let mut tmp = temp_dir();
+        tmp.push("sled-reopen");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = SledDataStore::new(tmp.clone());
+        tokio::run_async(async move {
+            await!(store.init()).unwrap();
+            await!(store.open()).unwrap();
+            await!(store.put(Column::Ipns, b"key", b"value")).unwrap();
+            await!(store.flush()).unwrap();
+        });
+
+        let store = SledDataStore::new(tmp.clone());
+        tokio::run_async(async move {
+            await!(store.open()).unwrap();
+            assert_eq!(await!(store.get(Column::Ipns, b"key")).unwrap(), Some(b"value".to_vec()));
+        });
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
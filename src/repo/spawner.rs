@@ -0,0 +1,71 @@
+//! Spawning of background tasks
+use crate::error::Error;
+use core::future::Future;
+use futures::channel::oneshot;
+use futures::future::FutureObj;
+use std::sync::Arc;
+
+/// Spawns the background tasks of the repo, like flushing buffered
+/// writes, and runs blocking work like hashing large blocks.
+///
+/// Defaults to spawning on the tokio runtime and running blocking work
+/// on a new thread.
+#[derive(Clone)]
+pub struct Spawner {
+    spawn: Arc<dyn Fn(FutureObj<'static, ()>) + Send + Sync>,
+    blocking: Arc<dyn Fn(Box<dyn FnOnce() + Send>) + Send + Sync>,
+}
+
+impl Spawner {
+    /// Creates a spawner that hands the tasks to `spawn`.
+    pub fn new<F>(spawn: F) -> Self
+    where F: Fn(FutureObj<'static, ()>) + Send + Sync + 'static
+    {
+        Spawner {
+            spawn: Arc::new(spawn),
+            blocking: Arc::new(|task| {
+                std::thread::spawn(task);
+            }),
+        }
+    }
+
+    /// Hands blocking work to `blocking`, for example to run it on a
+    /// thread pool.
+    pub fn with_blocking<F>(mut self, blocking: F) -> Self
+    where F: Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static
+    {
+        self.blocking = Arc::new(blocking);
+        self
+    }
+
+    /// Spawns `future` as a background task.
+    pub fn spawn<F: Future<Output=()> + Send + 'static>(&self, future: F) {
+        (self.spawn)(FutureObj::new(Box::new(future)))
+    }
+
+    /// Runs `task` off the async executor and returns its result.
+    pub fn spawn_blocking<T, F>(&self, task: F) -> impl Future<Output=Result<T, Error>>
+    where T: Send + 'static, F: FnOnce() -> T + Send + 'static
+    {
+        let (tx, rx) = oneshot::channel();
+        (self.blocking)(Box::new(move || {
+            // sending only fails if the result isn't awaited anymore
+            let _ = tx.send(task());
+        }));
+        async move {
+            await!(rx).map_err(|_| format_err!("blocking task was dropped"))
+        }
+    }
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Spawner::new(|future| tokio::spawn_async(future))
+    }
+}
+
+impl std::fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Spawner")
+    }
+}
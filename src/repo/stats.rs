@@ -0,0 +1,21 @@
+//! Per operation block store statistics
+use std::time::Duration;
+
+/// Statistics collected while executing a single block store operation.
+///
+/// Only available with the `metrics` feature.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OpStats {
+    /// Time spent executing the operation.
+    pub elapsed: Duration,
+    /// Number of lookups that found the block.
+    pub hits: u64,
+    /// Number of lookups that didn't find the block.
+    pub misses: u64,
+    /// Number of reads that went to disk.
+    pub disk_reads: u64,
+    /// Number of bytes read.
+    pub bytes_read: u64,
+    /// Number of bytes written.
+    pub bytes_written: u64,
+}
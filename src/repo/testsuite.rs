@@ -0,0 +1,131 @@
+//! Conformance tests shared by all store implementations
+use crate::block::Block;
+use crate::repo::{BlockStore, Column, DataStore, PutResult};
+
+/// Runs the block store tests against stores created by `make`.
+///
+/// Every call to `make` has to return a new empty store.
+pub(crate) fn run_block_store_tests<S: BlockStore>(make: impl Fn() -> S) {
+    let store = make();
+    tokio::run_async(async move {
+        await!(store.init()).unwrap();
+        await!(store.open()).unwrap();
+        let block = Block::from("1");
+        let cid = block.cid().to_owned();
+
+        assert!(!await!(store.contains(&cid)).unwrap());
+        assert_eq!(await!(store.get(&cid)).unwrap(), None);
+        assert_eq!(await!(store.block_size(&cid)).unwrap(), None);
+        assert!(await!(store.list()).unwrap().is_empty());
+        // removing a missing block is not an error
+        await!(store.remove(&cid)).unwrap();
+
+        assert_eq!(await!(store.put(block.clone())).unwrap(), cid);
+        assert!(await!(store.contains(&cid)).unwrap());
+        assert_eq!(await!(store.get(&cid)).unwrap(), Some(block.clone()));
+        assert_eq!(await!(store.get_data(&cid)).unwrap().as_ref(), Some(block.data()));
+        assert_eq!(await!(store.block_size(&cid)).unwrap(), Some(1));
+        assert_eq!(await!(store.list()).unwrap(), vec![cid.clone()]);
+
+        await!(store.remove(&cid)).unwrap();
+        assert!(!await!(store.contains(&cid)).unwrap());
+        assert_eq!(await!(store.get(&cid)).unwrap(), None);
+        assert!(await!(store.list()).unwrap().is_empty());
+    });
+
+    let store = make();
+    tokio::run_async(async move {
+        await!(store.init()).unwrap();
+        await!(store.open()).unwrap();
+
+        // putting a block twice stores it once
+        let block = Block::from("1");
+        let cid = block.cid().to_owned();
+        assert_eq!(await!(store.put_checked(block.clone())).unwrap(), PutResult::new(cid.clone(), true));
+        assert_eq!(await!(store.put_checked(block.clone())).unwrap(), PutResult::new(cid, false));
+        assert_eq!(await!(store.list()).unwrap().len(), 1);
+
+        let empty = Block::from("");
+        await!(store.put(empty.clone())).unwrap();
+        assert_eq!(await!(store.get(empty.cid())).unwrap(), Some(empty.clone()));
+        assert_eq!(await!(store.block_size(empty.cid())).unwrap(), Some(0));
+        assert_eq!(await!(store.list()).unwrap().len(), 2);
+
+        // sorted listing is stable
+        for data in &["2", "3", "4", "5"] {
+            await!(store.put(Block::from(*data))).unwrap();
+        }
+        let sorted = await!(store.list_sorted()).unwrap();
+        assert_eq!(sorted.len(), 6);
+        assert!(sorted.windows(2).all(|pair| pair[0].to_bytes() < pair[1].to_bytes()));
+        assert_eq!(await!(store.list_sorted()).unwrap(), sorted);
+    });
+}
+
+/// Runs the data store tests against stores created by `make`.
+///
+/// Every call to `make` has to return a new empty store.
+pub(crate) fn run_data_store_tests<S: DataStore>(make: impl Fn() -> S) {
+    let store = make();
+    tokio::run_async(async move {
+        await!(store.init()).unwrap();
+        await!(store.open()).unwrap();
+        let key = [1, 2, 3, 4];
+        let value = [5, 6, 7, 8];
+
+        for &col in Column::all() {
+            assert!(!await!(store.contains(col, &key)).unwrap());
+            assert_eq!(await!(store.get(col, &key)).unwrap(), None);
+            assert!(await!(store.list_keys(col)).unwrap().is_empty());
+            // removing a missing key is not an error
+            await!(store.remove(col, &key)).unwrap();
+        }
+
+        await!(store.put(Column::Ipns, &key, &value)).unwrap();
+        assert!(await!(store.contains(Column::Ipns, &key)).unwrap());
+        assert_eq!(await!(store.get(Column::Ipns, &key)).unwrap(), Some(value.to_vec()));
+        assert_eq!(await!(store.list_keys(Column::Ipns)).unwrap(), vec![key.to_vec()]);
+        // columns don't share keys
+        assert!(!await!(store.contains(Column::Pin, &key)).unwrap());
+
+        await!(store.remove(Column::Ipns, &key)).unwrap();
+        assert!(!await!(store.contains(Column::Ipns, &key)).unwrap());
+        assert_eq!(await!(store.get(Column::Ipns, &key)).unwrap(), None);
+    });
+
+    let store = make();
+    tokio::run_async(async move {
+        await!(store.init()).unwrap();
+        await!(store.open()).unwrap();
+        let key = [1, 2, 3, 4];
+
+        // puts overwrite existing values
+        await!(store.put(Column::Pin, &key, &[5])).unwrap();
+        await!(store.put(Column::Pin, &key, &[6])).unwrap();
+        assert_eq!(await!(store.get(Column::Pin, &key)).unwrap(), Some(vec![6]));
+        assert_eq!(await!(store.list_keys(Column::Pin)).unwrap().len(), 1);
+
+        await!(store.put(Column::Pin, &key, &[])).unwrap();
+        assert_eq!(await!(store.get(Column::Pin, &key)).unwrap(), Some(vec![]));
+    });
+
+    let store = make();
+    tokio::run_async(async move {
+        await!(store.init()).unwrap();
+        await!(store.open()).unwrap();
+        let key = [1, 2, 3, 4];
+
+        // streamed values are stored values as well
+        let reader = Box::new(std::io::Cursor::new(vec![5, 6]));
+        await!(store.put_stream(Column::Config, &key, reader)).unwrap();
+        assert!(await!(store.contains(Column::Config, &key)).unwrap());
+        await!(store.remove(Column::Config, &key)).unwrap();
+        assert!(!await!(store.contains(Column::Config, &key)).unwrap());
+        assert!(await!(store.get_stream(Column::Config, &key)).unwrap().is_none());
+
+        let reader = Box::new(std::io::Cursor::new(vec![5, 6]));
+        await!(store.put_stream(Column::Config, &key, reader)).unwrap();
+        await!(store.remove_many(Column::Config, vec![key.to_vec()])).unwrap();
+        assert!(!await!(store.contains(Column::Config, &key)).unwrap());
+    });
+}
@@ -0,0 +1,142 @@
+//! Deletion markers for removed blocks
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{Column, DataStore, Repo, RepoTypes};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use core::future::Future;
+use std::time::{Duration, SystemTime};
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Records that `cid` was removed at `time`.
+    pub(crate) fn put_tombstone(&self, cid: &Cid, time: SystemTime) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let data_store = self.available_data_store().map(Clone::clone);
+        let key = cid.to_bytes();
+        async move {
+            let data_store = data_store?;
+            let secs = time.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+            let mut value = Vec::with_capacity(8);
+            value.write_u64::<BigEndian>(secs)?;
+            await!(data_store.put(Column::Tombstone, &key, &value))
+        }
+    }
+
+    /// Removes the tombstone of a block that was stored again.
+    pub(crate) fn clear_tombstone(&self, cid: &Cid) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let data_store = self.available_data_store().map(Clone::clone);
+        let key = cid.to_bytes();
+        async move {
+            let data_store = data_store?;
+            if await!(data_store.contains(Column::Tombstone, &key))? {
+                await!(data_store.remove(Column::Tombstone, &key))?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Checks if the block was removed while tombstones were enabled.
+    pub fn is_tombstoned(&self, cid: &Cid) -> impl Future<Output=Result<bool, Error>> {
+        let contains = self.available_data_store()
+            .map(|data_store| data_store.contains(Column::Tombstone, &cid.to_bytes()));
+        async move {
+            await!(contains?)
+        }
+    }
+
+    /// Lists the removed blocks together with the time of removal.
+    pub fn list_tombstones(&self) ->
+    impl Future<Output=Result<Vec<(Cid, SystemTime)>, Error>>
+    {
+        let data_store = self.available_data_store().map(Clone::clone);
+        async move {
+            let data_store = data_store?;
+            let keys = await!(data_store.list_keys(Column::Tombstone))?;
+            let mut tombstones = Vec::with_capacity(keys.len());
+            for key in keys {
+                // reaped while listing
+                let value = match await!(data_store.get(Column::Tombstone, &key))? {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let secs = (&value[..]).read_u64::<BigEndian>()?;
+                let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+                tombstones.push((Cid::from(key)?, time));
+            }
+            Ok(tombstones)
+        }
+    }
+
+    /// Removes tombstones older than `grace`, returning the number of
+    /// removed tombstones.
+    pub fn reap_tombstones(&self, grace: Duration) -> impl Future<Output=Result<u64, Error>> {
+        let repo = self.clone();
+        async move {
+            let now = repo.clock.now();
+            let mut reaped = 0;
+            for (cid, time) in await!(repo.list_tombstones())? {
+                if time + grace <= now {
+                    await!(repo.data_store.remove(Column::Tombstone, &cid.to_bytes()))?;
+                    reaped += 1;
+                }
+            }
+            Ok(reaped)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::repo::{BlockStore, Repo, RepoOptions};
+    use crate::repo::clock::FakeClock;
+    use crate::repo::tests::Types;
+    use std::env::temp_dir;
+    use std::time::Duration;
+
+    #[test]
+    fn test_tombstones() {
+        let options = RepoOptions::<Types>::new(temp_dir()).tombstones(true);
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            let cid = await!(repo.put_block(Block::from("1"))).unwrap();
+            await!(repo.remove_block(&cid)).unwrap();
+            assert!(!await!(repo.block_store.contains(&cid)).unwrap());
+            assert!(await!(repo.block_store.get(&cid)).unwrap().is_none());
+            let tombstones = await!(repo.list_tombstones()).unwrap();
+            assert_eq!(tombstones.len(), 1);
+            assert_eq!(tombstones[0].0, cid);
+
+            // a long grace period keeps the tombstone
+            assert_eq!(await!(repo.reap_tombstones(Duration::from_secs(3600))).unwrap(), 0);
+            assert_eq!(await!(repo.reap_tombstones(Duration::from_secs(0))).unwrap(), 1);
+            assert!(await!(repo.list_tombstones()).unwrap().is_empty());
+
+            // storing the block again clears its tombstone
+            await!(repo.remove_block(&cid)).unwrap();
+            await!(repo.put_block(Block::from("1"))).unwrap();
+            assert!(!await!(repo.is_tombstoned(&cid)).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_reap_tombstones_grace_period() {
+        let clock = FakeClock::new();
+        let options = RepoOptions::<Types>::new(temp_dir())
+            .tombstones(true)
+            .clock(clock.clone());
+        let (repo, _) = Repo::new(options);
+        tokio::run_async(async move {
+            let cid = await!(repo.put_block(Block::from("1"))).unwrap();
+            await!(repo.remove_block(&cid)).unwrap();
+            let grace = Duration::from_secs(60);
+
+            clock.advance(Duration::from_secs(59));
+            assert_eq!(await!(repo.reap_tombstones(grace)).unwrap(), 0);
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(await!(repo.reap_tombstones(grace)).unwrap(), 1);
+        });
+    }
+}
@@ -0,0 +1,187 @@
+//! Verification of the stored blocks
+use crate::block::Block;
+use crate::error::Error;
+use crate::repo::{BlockStore, Repo, RepoError, RepoTypes};
+use crate::repo::fs::write_stream;
+use core::future::Future;
+use futures::channel::mpsc;
+use futures::stream::{Stream, StreamExt};
+use std::io::Cursor;
+use std::path::PathBuf;
+
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// What `Repo::verify` does with blocks whose data doesn't match their
+/// cid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepairPolicy {
+    /// Only report the block.
+    Report,
+    /// Remove the block, even if it is pinned.
+    Remove,
+    /// Move the data of the block into the `quarantine` directory of the
+    /// repo, named by its cid, and remove the block.
+    Quarantine,
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Hashes the data of every stored block, streaming a
+    /// `RepoError::BlockCorrupted` for every block that doesn't match its
+    /// cid, including truncated blocks.
+    ///
+    /// Bad blocks are handled according to `policy`. Blocks whose hash
+    /// can't be computed are kept and streamed as
+    /// `RepoError::UnsupportedHash`. Any other error is the last item of
+    /// the stream.
+    pub fn verify(&self, policy: RepairPolicy) -> impl Stream<Item=Error> {
+        let (sender, errors) = mpsc::unbounded();
+        let verify = self.verify_blocks(policy, sender.clone());
+        self.spawner.spawn(async move {
+            if let Err(err) = await!(verify) {
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = sender.unbounded_send(err);
+            }
+        });
+        errors
+    }
+
+    fn verify_blocks(&self, policy: RepairPolicy, errors: mpsc::UnboundedSender<Error>) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let repo = self.clone();
+        async move {
+            let mut cids = repo.block_store.list_stream();
+            while let Some(cid) = await!(cids.next()) {
+                let cid = cid?;
+                let block = match await!(repo.block_store.get(&cid))? {
+                    Some(block) => block,
+                    // removed while verifying
+                    None => continue,
+                };
+                let (block, valid) = match await!(repo.verify_block(block)) {
+                    Ok(verified) => verified,
+                    Err(err) => match err.downcast::<RepoError>() {
+                        Ok(err @ RepoError::UnsupportedHash(_)) => {
+                            // sending only fails if no one is listening anymore
+                            // and that is okay with us.
+                            let _ = errors.unbounded_send(err.into());
+                            continue;
+                        }
+                        Ok(err) => return Err(err.into()),
+                        Err(err) => return Err(err),
+                    },
+                };
+                if valid {
+                    continue;
+                }
+                match policy {
+                    RepairPolicy::Report => {}
+                    RepairPolicy::Remove => await!(repo.remove_block_force(&cid))?,
+                    RepairPolicy::Quarantine => {
+                        await!(quarantine(repo.quarantine_path(), block))?;
+                        await!(repo.remove_block_force(&cid))?;
+                    }
+                }
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = errors.unbounded_send(RepoError::BlockCorrupted(cid).into());
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns the directory of quarantined blocks.
+    pub fn quarantine_path(&self) -> PathBuf {
+        self.path().join(QUARANTINE_DIR)
+    }
+}
+
+fn quarantine(dir: PathBuf, block: Block) -> impl Future<Output=Result<(), Error>> {
+    let path = dir.join(block.cid().to_string());
+    let mut tmp_path = path.clone();
+    tmp_path.set_extension("tmp");
+    let data = Box::new(Cursor::new(block.data().to_vec()));
+    write_stream(path, tmp_path, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Cid;
+    use crate::repo::RepoOptions;
+    use crate::repo::tests::Types;
+    use std::env::temp_dir;
+
+    fn verify_corrupted(name: &str, policy: RepairPolicy) -> (bool, PathBuf) {
+        let mut tmp = temp_dir();
+        tmp.push(name);
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let (repo, _) = Repo::new(RepoOptions::<Types>::new(tmp));
+        let quarantined = repo.quarantine_path();
+        let corrupted = Block::new("2", Block::from("3").cid().to_owned());
+        let cid = corrupted.cid().to_owned();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        tokio::run_async(async move {
+            await!(repo.put_block(Block::from("1"))).unwrap();
+            await!(repo.block_store.put(corrupted)).unwrap();
+            let errors = await!(repo.verify(policy).collect::<Vec<_>>());
+            assert_eq!(errors.len(), 1);
+            match errors[0].downcast_ref::<RepoError>() {
+                Some(RepoError::BlockCorrupted(corrupted)) => assert_eq!(corrupted, &cid),
+                _ => panic!("expected corrupted block, got {}", errors[0]),
+            }
+            assert!(await!(repo.block_store.contains(Block::from("1").cid())).unwrap());
+            sender.send(await!(repo.block_store.contains(&cid)).unwrap()).unwrap();
+        });
+        let kept = receiver.recv().unwrap();
+        (kept, quarantined.join(Block::from("3").cid().to_string()))
+    }
+
+    #[test]
+    fn test_verify_report() {
+        let (kept, quarantined) = verify_corrupted("repo_verify_report", RepairPolicy::Report);
+        assert!(kept);
+        assert!(!quarantined.exists());
+    }
+
+    #[test]
+    fn test_verify_remove() {
+        let (kept, quarantined) = verify_corrupted("repo_verify_remove", RepairPolicy::Remove);
+        assert!(!kept);
+        assert!(!quarantined.exists());
+    }
+
+    #[test]
+    fn test_verify_quarantine() {
+        let (kept, quarantined) = verify_corrupted("repo_verify_quarantine", RepairPolicy::Quarantine);
+        assert!(!kept);
+        assert_eq!(std::fs::read(quarantined).unwrap(), b"2".to_vec());
+    }
+
+    #[test]
+    fn test_verify_unsupported_hash() {
+        use multihash::Hash;
+        let repo = crate::repo::tests::create_mock_repo();
+        // multihash can't compute blake2b, so the cid is made by hand.
+        let mut hash = vec![Hash::Blake2b.code(), Hash::Blake2b.size()];
+        hash.extend(vec![0; Hash::Blake2b.size() as usize]);
+        let unsupported = Cid::new(cid::Codec::Raw, cid::Version::V1, &hash);
+        let corrupted = Block::new("2", Block::from("3").cid().to_owned());
+        tokio::run_async(async move {
+            await!(repo.block_store.put(Block::new("1", unsupported.clone()))).unwrap();
+            await!(repo.block_store.put(corrupted.clone())).unwrap();
+            let errors = await!(repo.verify(RepairPolicy::Report).collect::<Vec<_>>());
+            // the other blocks are still verified
+            assert_eq!(errors.len(), 2);
+            for err in &errors {
+                match err.downcast_ref::<RepoError>() {
+                    Some(RepoError::UnsupportedHash(code)) => assert_eq!(*code, Hash::Blake2b.code()),
+                    Some(RepoError::BlockCorrupted(cid)) => assert_eq!(cid, corrupted.cid()),
+                    _ => panic!("unexpected error {}", err),
+                }
+            }
+            assert!(await!(repo.block_store.contains(&unsupported)).unwrap());
+        });
+    }
+}
@@ -0,0 +1,130 @@
+//! Reading blocks without them being collected
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::repo::{BlockStore, Repo, RepoTypes};
+use core::future::Future;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Blocks read through a `ReadView` and blocks being removed by garbage
+/// collection.
+#[derive(Debug, Default)]
+pub(crate) struct Holds {
+    held: HashMap<Cid, usize>,
+    sweeping: HashSet<Cid>,
+}
+
+impl Holds {
+    /// Marks `cid` as being removed, returning false if it is held.
+    pub(crate) fn start_sweep(&mut self, cid: &Cid) -> bool {
+        if self.held.contains_key(cid) {
+            return false;
+        }
+        self.sweeping.insert(cid.to_owned());
+        true
+    }
+
+    /// Marks `cid` as removed.
+    pub(crate) fn finish_sweep(&mut self, cid: &Cid) {
+        self.sweeping.remove(cid);
+    }
+}
+
+/// View of the local blocks that keeps garbage collection from removing
+/// the blocks read through it.
+///
+/// A block returned by the view stays stored until the view is dropped.
+/// Garbage collection skips held blocks, they are collected by the next
+/// run after the view is dropped.
+#[derive(Debug)]
+pub struct ReadView<TRepoTypes: RepoTypes> {
+    repo: Repo<TRepoTypes>,
+    held: Arc<Mutex<HashSet<Cid>>>,
+}
+
+impl<TRepoTypes: RepoTypes> ReadView<TRepoTypes> {
+    /// Returns a block from the local block store and holds it.
+    ///
+    /// Blocks that are being removed by garbage collection are treated
+    /// as missing.
+    pub fn get_block(&self, cid: &Cid) -> impl Future<Output=Result<Option<Block>, Error>> {
+        let block_store = self.repo.block_store.clone();
+        let holds = self.repo.holds.clone();
+        let held = self.held.clone();
+        let cid = cid.to_owned();
+        async move {
+            {
+                let mut holds = holds.lock().unwrap();
+                if holds.sweeping.contains(&cid) {
+                    return Ok(None);
+                }
+                let mut held = held.lock().unwrap();
+                if held.insert(cid.clone()) {
+                    *holds.held.entry(cid.clone()).or_insert(0) += 1;
+                }
+            }
+            await!(block_store.get(&cid))
+        }
+    }
+}
+
+impl<TRepoTypes: RepoTypes> Drop for ReadView<TRepoTypes> {
+    fn drop(&mut self) {
+        let mut holds = self.repo.holds.lock().unwrap();
+        for cid in self.held.lock().unwrap().drain() {
+            let last = match holds.held.get_mut(&cid) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                }
+                None => false,
+            };
+            if last {
+                holds.held.remove(&cid);
+            }
+        }
+    }
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Creates a view whose blocks aren't removed by garbage collection
+    /// while it is alive.
+    pub fn read_view(&self) -> ReadView<TRepoTypes> {
+        ReadView {
+            repo: self.clone(),
+            held: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::repo::tests::create_mock_repo;
+    use futures::join;
+
+    #[test]
+    fn test_read_view_during_gc() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let unpinned = await!(repo.put_block(Block::from("unpinned"))).unwrap();
+            let pinned = await!(repo.put_block(Block::from("pinned"))).unwrap();
+            await!(repo.pin_block(&pinned)).unwrap();
+
+            let view = repo.read_view();
+            assert!(await!(view.get_block(&unpinned)).unwrap().is_some());
+            let reads = async {
+                for _ in 0..10 {
+                    assert!(await!(view.get_block(&unpinned)).unwrap().is_some());
+                    assert!(await!(view.get_block(&pinned)).unwrap().is_some());
+                }
+            };
+            let (stats, ()) = join!(repo.garbage_collect(), reads);
+            assert_eq!(stats.unwrap().removed, 0);
+
+            drop(view);
+            assert_eq!(await!(repo.garbage_collect()).unwrap().removed, 1);
+            assert_eq!(await!(repo.read_view().get_block(&unpinned)).unwrap(), None);
+        });
+    }
+}
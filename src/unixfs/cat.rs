@@ -0,0 +1,199 @@
+//! Reading unixfs files
+use crate::block::{Block, Bytes, Cid};
+use crate::error::Error;
+use crate::ipld::{Ipld, formats::pb::PbNode};
+use crate::path::IpfsPath;
+use crate::repo::{Repo, RepoError, RepoTypes, Session};
+use crate::repo::content::{unixfs_node, Node};
+use crate::unixfs::dir::resolve;
+use cid::Codec;
+use futures::prelude::*;
+use std::convert::TryInto;
+
+/// Block of a file that is still to be read.
+struct Pending {
+    cid: Cid,
+    depth: usize,
+    /// Number of file bytes below the block if the parent recorded it.
+    size: Option<u64>,
+}
+
+struct Cat<T: RepoTypes> {
+    repo: Repo<T>,
+    // fetches the blocks of the file from the peers that sent earlier ones
+    session: Session<T>,
+    // resolved when the stream is first polled
+    path: Option<IpfsPath>,
+    // blocks in reverse order of their data
+    stack: Vec<Pending>,
+    skip: u64,
+    remaining: Option<u64>,
+}
+
+impl<T: RepoTypes> Cat<T> {
+    /// Cuts `data` to the requested range.
+    fn take(&mut self, mut data: Bytes) -> Bytes {
+        let skip = self.skip.min(data.len() as u64);
+        self.skip -= skip;
+        let _ = data.split_to(skip as usize);
+        if let Some(remaining) = &mut self.remaining {
+            let len = (*remaining).min(data.len() as u64);
+            data.truncate(len as usize);
+            *remaining -= len;
+        }
+        data
+    }
+}
+
+/// Returns the data of a unixfs file block and queues its children.
+fn read_node(block: &Block, depth: usize, stack: &mut Vec<Pending>) -> Result<Bytes, Error> {
+    if block.cid().prefix().codec != Codec::DagProtobuf {
+        return Ok(block.data().to_owned());
+    }
+    let pb_node: PbNode = match Ipld::from(block)?.try_into() {
+        Ok(pb_node) => pb_node,
+        Err(_) => bail!("invalid dag_pb node"),
+    };
+    let (content, blocksizes) = match unixfs_node(&pb_node.data) {
+        Some(Node::File(content, blocksizes)) => (content, blocksizes),
+        Some(Node::Directory) | Some(Node::HamtShard { .. }) => bail!("can't cat a directory"),
+        Some(Node::Symlink(_)) => bail!("can't cat a symlink"),
+        None => bail!("invalid unixfs node"),
+    };
+    // the dag-pb `Tsize` includes the encoding of the blocks, so only the
+    // blocksizes allow skipping children without reading them
+    let sizes: Vec<Option<u64>> = if blocksizes.len() == pb_node.links.len() {
+        blocksizes.into_iter().map(Some).collect()
+    } else {
+        vec![None; pb_node.links.len()]
+    };
+    for (link, size) in pb_node.links.iter().zip(sizes).rev() {
+        let cid = match link.cid.cid() {
+            Some(cid) => cid.to_owned(),
+            None => bail!("expected cid"),
+        };
+        stack.push(Pending {
+            cid,
+            depth: depth + 1,
+            size,
+        });
+    }
+    Ok(Bytes::from(content))
+}
+
+/// Streams the data of the unixfs file at `path`, starting at `offset`
+/// and ending after `length` bytes if given.
+///
+/// The blocks of the file are read in order, missing blocks are fetched
+/// from the network in one session. Subtrees before `offset` are skipped without
+/// reading them if their size is recorded in the parent.
+pub fn cat<T: RepoTypes>(repo: &Repo<T>, path: IpfsPath, offset: u64, length: Option<u64>) ->
+impl Stream<Item=Result<Bytes, Error>>
+{
+    let cat = Cat {
+        repo: repo.clone(),
+        session: repo.session(),
+        path: Some(path),
+        stack: Vec::new(),
+        skip: offset,
+        remaining: length,
+    };
+    stream::unfold(Some(cat), |state: Option<Cat<T>>| {
+        async move {
+            let mut cat = match state {
+                Some(cat) => cat,
+                None => return None,
+            };
+            if let Some(path) = cat.path.take() {
+                match await!(resolve(&cat.repo, path)) {
+                    Ok(cid) => cat.stack.push(Pending {
+                        cid,
+                        depth: 0,
+                        size: None,
+                    }),
+                    Err(err) => return Some((Err(err), None)),
+                }
+            }
+            loop {
+                if cat.remaining == Some(0) {
+                    return None;
+                }
+                let pending = match cat.stack.pop() {
+                    Some(pending) => pending,
+                    None => return None,
+                };
+                if let Some(size) = pending.size {
+                    if cat.skip >= size {
+                        cat.skip -= size;
+                        continue;
+                    }
+                }
+                let max_depth = cat.repo.max_depth();
+                if pending.depth > max_depth {
+                    return Some((Err(RepoError::DagTooDeep(max_depth).into()), None));
+                }
+                let block = match await!(cat.session.get_block(&pending.cid)) {
+                    Ok(block) => block,
+                    Err(err) => return Some((Err(err), None)),
+                };
+                let data = match read_node(&block, pending.depth, &mut cat.stack) {
+                    Ok(data) => data,
+                    Err(err) => return Some((Err(err), None)),
+                };
+                let data = cat.take(data);
+                if !data.is_empty() {
+                    return Some((Ok(data), Some(cat)));
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::PathRoot;
+    use crate::repo::tests::create_mock_repo;
+    use std::io::Cursor;
+
+    fn raw_cid(data: &[u8]) -> Cid {
+        let prefix = cid::Prefix {
+            version: cid::Version::V1,
+            codec: Codec::Raw,
+            mh_type: multihash::Hash::SHA2256,
+            mh_len: 32,
+        };
+        Cid::new_from_prefix(&prefix, data)
+    }
+
+    fn read<T: RepoTypes>(repo: &Repo<T>, root: &Cid, offset: u64, length: Option<u64>) ->
+    impl Future<Output=Result<Vec<u8>, Error>>
+    {
+        let path = IpfsPath::new(PathRoot::Ipld(root.to_owned()));
+        let mut data = cat(repo, path, offset, length);
+        async move {
+            let mut file = Vec::new();
+            while let Some(bytes) = await!(data.next()) {
+                file.extend_from_slice(&bytes?);
+            }
+            Ok(file)
+        }
+    }
+
+    #[test]
+    fn test_cat() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let data = b"aaaabbbbccccdd".to_vec();
+            let (root, _) = await!(repo.add_reader(Cursor::new(data.clone()), 4)).unwrap();
+            assert_eq!(await!(read(&repo, &root, 0, None)).unwrap(), data);
+            assert_eq!(await!(read(&repo, &root, 5, None)).unwrap(), b"bbbccccdd".to_vec());
+            assert_eq!(await!(read(&repo, &root, 3, Some(6))).unwrap(), b"abbbbc".to_vec());
+            assert_eq!(await!(read(&repo, &root, 20, None)).unwrap(), Vec::<u8>::new());
+
+            // skipped leaves aren't read, so they don't need to be fetched
+            await!(repo.remove_block(&raw_cid(b"aaaa"))).unwrap();
+            assert_eq!(await!(read(&repo, &root, 4, Some(4))).unwrap(), b"bbbb".to_vec());
+        });
+    }
+}
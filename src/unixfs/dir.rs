@@ -0,0 +1,252 @@
+//! Building and resolving unixfs directories
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::ipld::{Ipld, formats::pb::{PbLink, PbNode}};
+use crate::path::{IpfsPath, SubPath};
+use crate::repo::{Metadata, Repo, RepoError, RepoTypes};
+use crate::repo::content::{unixfs_node, Node};
+use crate::unixfs::hamt::{build_shard, check_shard, shard_entries, shard_lookup};
+use cid::Codec;
+use core::future::Future;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+/// Number of entries above which directories are sharded.
+pub const DEFAULT_SHARD_THRESHOLD: usize = 1000;
+
+/// Builds a unixfs directory from its entries.
+///
+/// Directories with more entries than the shard threshold are stored as
+/// HAMT shards like in go-ipfs, so that no single block grows too large.
+#[derive(Clone, Debug)]
+pub struct DirBuilder {
+    // name to the cid and the cumulative size of the entry
+    entries: BTreeMap<String, (Cid, u64)>,
+    shard_threshold: usize,
+    metadata: Metadata,
+}
+
+impl Default for DirBuilder {
+    fn default() -> Self {
+        DirBuilder {
+            entries: BTreeMap::new(),
+            shard_threshold: DEFAULT_SHARD_THRESHOLD,
+            metadata: Metadata::default(),
+        }
+    }
+}
+
+impl DirBuilder {
+    pub fn new() -> Self {
+        DirBuilder::default()
+    }
+
+    /// Shards the directory if it has more than `threshold` entries.
+    pub fn shard_threshold(mut self, threshold: usize) -> Self {
+        self.shard_threshold = threshold;
+        self
+    }
+
+    /// Records `metadata` in the root of the directory.
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Adds the entry `name` linking to `cid`, replacing an entry of the
+    /// same name. `size` is the cumulative size of the blocks of the
+    /// entry, the dag-pb `Tsize`.
+    pub fn insert(&mut self, name: &str, cid: Cid, size: u64) -> Result<(), Error> {
+        if name.is_empty() || name.contains('/') {
+            bail!("invalid directory entry name {:?}", name);
+        }
+        self.entries.insert(name.to_owned(), (cid, size));
+        Ok(())
+    }
+
+    /// Encodes the blocks of the directory, the root is the last one.
+    fn build(self) -> Result<Vec<Block>, Error> {
+        let links: Vec<PbLink> = self.entries.into_iter().map(|(name, (cid, size))| PbLink {
+            cid: cid.into(),
+            name,
+            size,
+        }).collect();
+        if links.len() > self.shard_threshold {
+            let mut blocks = build_shard(links)?;
+            let root = blocks.pop().expect("the root is always built");
+            blocks.push(self.metadata.apply(root)?);
+            return Ok(blocks);
+        }
+        let mut data = vec![0x08, 0x01];
+        self.metadata.encode(&mut data);
+        let node: Ipld = PbNode {
+            links,
+            data,
+        }.into();
+        Ok(vec![node.to_dag_pb()?])
+    }
+
+    /// Stores the directory in `repo`, returning its cid.
+    pub fn put<T: RepoTypes>(self, repo: &Repo<T>) -> impl Future<Output=Result<Cid, Error>> {
+        let repo = repo.clone();
+        let blocks = self.build();
+        async move {
+            let cids = await!(repo.put_blocks(blocks?))?;
+            Ok(cids.last().expect("the root is always built").to_owned())
+        }
+    }
+}
+
+/// Decodes the unixfs directory `block` if it is one.
+pub(crate) fn directory(block: &Block) -> Result<Option<PbNode>, Error> {
+    if block.cid().prefix().codec != Codec::DagProtobuf {
+        return Ok(None);
+    }
+    let pb_node: PbNode = match Ipld::from(block)?.try_into() {
+        Ok(pb_node) => pb_node,
+        Err(_) => bail!("invalid dag_pb node"),
+    };
+    match unixfs_node(&pb_node.data) {
+        Some(Node::Directory) | Some(Node::HamtShard { .. }) => Ok(Some(pb_node)),
+        Some(_) => Ok(None),
+        None => bail!("invalid unixfs node"),
+    }
+}
+
+/// Lists the names, cids and cumulative sizes of the entries of the
+/// unixfs directory `cid`, reading all shards of sharded directories.
+pub(crate) fn dir_entries<T: RepoTypes>(repo: &Repo<T>, cid: &Cid) ->
+impl Future<Output=Result<Vec<(String, Cid, u64)>, Error>>
+{
+    let repo = repo.clone();
+    let cid = cid.to_owned();
+    async move {
+        let pb_node = match directory(&await!(repo.get_block(&cid))?)? {
+            Some(pb_node) => pb_node,
+            None => bail!("{} is not a directory", cid.to_string()),
+        };
+        if let Some(Node::HamtShard { hash_type, fanout }) = unixfs_node(&pb_node.data) {
+            check_shard(hash_type, fanout)?;
+            return await!(shard_entries(&repo, pb_node));
+        }
+        let mut entries = Vec::with_capacity(pb_node.links.len());
+        for link in pb_node.links {
+            match link.cid.cid() {
+                Some(cid) => entries.push((link.name, cid.to_owned(), link.size)),
+                None => bail!("expected cid"),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Finds the entry `name` of the unixfs directory `cid`.
+fn lookup<T: RepoTypes>(repo: &Repo<T>, cid: &Cid, name: String) ->
+impl Future<Output=Result<Option<Cid>, Error>>
+{
+    let repo = repo.clone();
+    let cid = cid.to_owned();
+    async move {
+        let pb_node = match directory(&await!(repo.get_block(&cid))?)? {
+            Some(pb_node) => pb_node,
+            None => bail!("{} is not a directory", cid.to_string()),
+        };
+        if let Some(Node::HamtShard { hash_type, fanout }) = unixfs_node(&pb_node.data) {
+            check_shard(hash_type, fanout)?;
+            return await!(shard_lookup(&repo, pb_node, &name));
+        }
+        Ok(pb_node.links.iter()
+            .find(|link| link.name == name)
+            .and_then(|link| link.cid.cid())
+            .map(|cid| cid.to_owned()))
+    }
+}
+
+/// Resolves `path` through unixfs directories by the names of their
+/// entries, looking up entries of sharded directories in their shards.
+pub fn resolve<T: RepoTypes>(repo: &Repo<T>, path: IpfsPath) ->
+impl Future<Output=Result<Cid, Error>>
+{
+    let repo = repo.clone();
+    async move {
+        let mut cid = match path.root().cid() {
+            Some(cid) => cid.to_owned(),
+            None => bail!("expected cid"),
+        };
+        for (depth, sub_path) in path.iter().enumerate() {
+            if depth >= repo.max_depth() {
+                return Err(RepoError::DagTooDeep(repo.max_depth()).into());
+            }
+            let name = match sub_path {
+                SubPath::Key(key) => key.to_owned(),
+                SubPath::Index(index) => index.to_string(),
+            };
+            cid = match await!(lookup(&repo, &cid, name.clone()))? {
+                Some(entry) => entry,
+                None => bail!("no entry {:?} in directory {}", name, cid.to_string()),
+            };
+        }
+        Ok(cid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::PathRoot;
+    use crate::repo::tests::create_mock_repo;
+
+    #[test]
+    fn test_dir_builder() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let file = await!(repo.put_block(Block::from("file"))).unwrap();
+            let mut builder = DirBuilder::new();
+            builder.insert("b", file.clone(), 4).unwrap();
+            builder.insert("a", file.clone(), 4).unwrap();
+            assert!(builder.insert("a/b", file.clone(), 4).is_err());
+            let dir = await!(builder.put(&repo)).unwrap();
+
+            let entries = await!(dir_entries(&repo, &dir)).unwrap();
+            assert_eq!(entries, vec![
+                ("a".to_string(), file.clone(), 4),
+                ("b".to_string(), file.clone(), 4),
+            ]);
+            let path = IpfsPath::new(PathRoot::Ipld(dir)).sub_path("b").unwrap();
+            assert_eq!(await!(resolve(&repo, path.clone())).unwrap(), file);
+            assert!(await!(resolve(&repo, path.sub_path("c").unwrap())).is_err());
+        });
+    }
+
+    #[test]
+    fn test_sharded_dir() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let mut builder = DirBuilder::new().shard_threshold(100);
+            let mut files = Vec::new();
+            for i in 0..1000 {
+                let name = format!("file-{}", i);
+                let file = await!(repo.put_block(Block::from(name.as_str()))).unwrap();
+                let size = name.len() as u64;
+                builder.insert(&name, file.clone(), size).unwrap();
+                files.push((name, file, size));
+            }
+            let dir = await!(builder.put(&repo)).unwrap();
+            let root = await!(repo.get_block(&dir)).unwrap();
+            let pb_node = directory(&root).unwrap().unwrap();
+            assert!(pb_node.links.len() <= 256);
+            assert!(pb_node.links.iter().all(|link| link.name.len() >= 2));
+
+            let mut entries = await!(dir_entries(&repo, &dir)).unwrap();
+            entries.sort();
+            files.sort();
+            assert_eq!(entries, files);
+            for (name, file, _) in files.iter().step_by(97) {
+                let path = IpfsPath::new(PathRoot::Ipld(dir.clone())).sub_path(name).unwrap();
+                assert_eq!(&await!(resolve(&repo, path)).unwrap(), file);
+            }
+            let path = IpfsPath::new(PathRoot::Ipld(dir)).sub_path("missing").unwrap();
+            assert!(await!(resolve(&repo, path)).is_err());
+        });
+    }
+}
@@ -0,0 +1,241 @@
+//! HAMT sharded unixfs directories, compatible with go-ipfs
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::ipld::{Ipld, formats::pb::{PbLink, PbNode}};
+use crate::repo::{Repo, RepoTypes};
+use core::future::Future;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+/// Number of buckets of every shard, the same as in go-ipfs.
+pub const HAMT_FANOUT: u64 = 256;
+
+/// Multicodec of the murmur3-x64-64 hash placing the entries.
+const MURMUR3_X64_64: u64 = 0x22;
+
+/// Number of levels until the 64 bit hash is used up.
+const MAX_LEVELS: usize = 8;
+
+fn fmix(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+    k
+}
+
+/// Returns the first half of the x64 128 bit murmur3 hash of `data` in
+/// big endian, which is how go-ipfs hashes the names of shard entries.
+pub(crate) fn murmur3_x64_64(data: &[u8]) -> [u8; 8] {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+    let mut h1: u64 = 0;
+    let mut h2: u64 = 0;
+    let blocks = data.len() / 16;
+    let read = |bytes: &[u8]| {
+        let mut k = 0u64;
+        for (i, byte) in bytes.iter().enumerate() {
+            k |= u64::from(*byte) << (8 * i);
+        }
+        k
+    };
+    for block in data.chunks(16).take(blocks) {
+        let k1 = read(&block[..8]).wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(27).wrapping_add(h2).wrapping_mul(5).wrapping_add(0x52dc_e729);
+        let k2 = read(&block[8..]).wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 ^= k2;
+        h2 = h2.rotate_left(31).wrapping_add(h1).wrapping_mul(5).wrapping_add(0x3849_5ab5);
+    }
+    let tail = &data[blocks * 16..];
+    if tail.len() > 8 {
+        h2 ^= read(&tail[8..]).wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        h1 ^= read(&tail[..tail.len().min(8)]).wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix(h1);
+    h2 = fmix(h2);
+    h1 = h1.wrapping_add(h2);
+    h1.to_be_bytes()
+}
+
+fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Encodes the unixfs message of a shard using the buckets in `bitfield`.
+fn shard_data(bitfield: &[u8]) -> Vec<u8> {
+    let mut data = vec![0x08, 0x05, 0x12];
+    encode_varint(bitfield.len() as u64, &mut data);
+    data.extend_from_slice(bitfield);
+    data.push(0x28);
+    encode_varint(MURMUR3_X64_64, &mut data);
+    data.push(0x30);
+    encode_varint(HAMT_FANOUT, &mut data);
+    data
+}
+
+/// Fails for shards that weren't built like the ones of go-ipfs.
+pub(crate) fn check_shard(hash_type: u64, fanout: u64) -> Result<(), Error> {
+    if hash_type != MURMUR3_X64_64 || fanout != HAMT_FANOUT {
+        bail!("unsupported hamt shard with hash {:#x} and fanout {}", hash_type, fanout);
+    }
+    Ok(())
+}
+
+/// Prefix of the links of the bucket `index`.
+fn bucket_name(index: u8) -> String {
+    format!("{:02X}", index)
+}
+
+fn shard_rec(entries: Vec<([u8; 8], PbLink)>, level: usize, blocks: &mut Vec<Block>) ->
+Result<(Cid, u64), Error>
+{
+    if level == MAX_LEVELS {
+        bail!("names of directory entries have the same hash");
+    }
+    let mut buckets: BTreeMap<u8, Vec<([u8; 8], PbLink)>> = BTreeMap::new();
+    for (hash, link) in entries {
+        buckets.entry(hash[level]).or_insert_with(Vec::new).push((hash, link));
+    }
+    // bit `index` is set for every used bucket, the big endian bytes are
+    // stored without leading zeros
+    let mut bitfield = vec![0u8; HAMT_FANOUT as usize / 8];
+    let mut links = Vec::with_capacity(buckets.len());
+    for (index, mut bucket) in buckets {
+        let last = bitfield.len() - 1;
+        bitfield[last - index as usize / 8] |= 1 << (index % 8);
+        if bucket.len() == 1 {
+            let (_, link) = bucket.pop().unwrap();
+            links.push(PbLink {
+                name: bucket_name(index) + &link.name,
+                ..link
+            });
+        } else {
+            let (cid, size) = shard_rec(bucket, level + 1, blocks)?;
+            links.push(PbLink {
+                cid: cid.into(),
+                name: bucket_name(index),
+                size,
+            });
+        }
+    }
+    let first = bitfield.iter().position(|byte| *byte != 0).unwrap_or(bitfield.len());
+    let children: u64 = links.iter().map(|link| link.size).sum();
+    let node: Ipld = PbNode {
+        links,
+        data: shard_data(&bitfield[first..]),
+    }.into();
+    let block = node.to_dag_pb()?;
+    let cid = block.cid().to_owned();
+    let size = block.size() as u64 + children;
+    blocks.push(block);
+    Ok((cid, size))
+}
+
+/// Builds a sharded directory of `entries`, returning the blocks with
+/// the root shard last.
+pub(crate) fn build_shard(entries: Vec<PbLink>) -> Result<Vec<Block>, Error> {
+    let entries = entries.into_iter()
+        .map(|link| (murmur3_x64_64(link.name.as_bytes()), link))
+        .collect();
+    let mut blocks = Vec::new();
+    shard_rec(entries, 0, &mut blocks)?;
+    Ok(blocks)
+}
+
+fn decode_shard(block: &Block) -> Result<PbNode, Error> {
+    match Ipld::from(block)?.try_into() {
+        Ok(pb_node) => Ok(pb_node),
+        Err(_) => bail!("invalid dag_pb node"),
+    }
+}
+
+fn link_cid(link: &PbLink) -> Result<Cid, Error> {
+    match link.cid.cid() {
+        Some(cid) => Ok(cid.to_owned()),
+        None => bail!("expected cid"),
+    }
+}
+
+/// Finds the entry `name` in the sharded directory with the root `shard`,
+/// reading only the shards of its bucket.
+pub(crate) fn shard_lookup<T: RepoTypes>(repo: &Repo<T>, shard: PbNode, name: &str) ->
+impl Future<Output=Result<Option<Cid>, Error>>
+{
+    let repo = repo.clone();
+    let hash = murmur3_x64_64(name.as_bytes());
+    let name = name.to_owned();
+    async move {
+        let mut shard = shard;
+        for index in hash.iter() {
+            let prefix = bucket_name(*index);
+            let link = match shard.links.iter().find(|link| link.name.starts_with(&prefix)) {
+                Some(link) => link,
+                None => return Ok(None),
+            };
+            if link.name.len() > prefix.len() {
+                if link.name[prefix.len()..] == name[..] {
+                    return Ok(Some(link_cid(link)?));
+                }
+                return Ok(None);
+            }
+            let cid = link_cid(link)?;
+            shard = decode_shard(&await!(repo.get_block(&cid))?)?;
+        }
+        bail!("sharded directory is deeper than its hash");
+    }
+}
+
+/// Lists the names, cids and cumulative sizes of the entries of the
+/// sharded directory with the root `shard` in the order of their buckets.
+pub(crate) fn shard_entries<T: RepoTypes>(repo: &Repo<T>, shard: PbNode) ->
+impl Future<Output=Result<Vec<(String, Cid, u64)>, Error>>
+{
+    let repo = repo.clone();
+    async move {
+        let mut entries = Vec::new();
+        // links of the shards still to be listed, in reverse order
+        let mut stack: Vec<(PbLink, usize)> = shard.links.into_iter().rev()
+            .map(|link| (link, 0))
+            .collect();
+        while let Some((link, level)) = stack.pop() {
+            let cid = link_cid(&link)?;
+            if link.name.len() > 2 {
+                entries.push((link.name[2..].to_owned(), cid, link.size));
+                continue;
+            }
+            if level + 1 >= MAX_LEVELS {
+                bail!("sharded directory is deeper than its hash");
+            }
+            let child = decode_shard(&await!(repo.get_block(&cid))?)?;
+            stack.extend(child.links.into_iter().rev().map(|link| (link, level + 1)));
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_murmur3() {
+        assert_eq!(murmur3_x64_64(b""), [0; 8]);
+        assert_eq!(murmur3_x64_64(b"hello"), 0xcbd8_a7b3_41bd_9b02u64.to_be_bytes());
+        assert_eq!(
+            murmur3_x64_64(b"The quick brown fox jumps over the lazy dog"),
+            0xe34b_bc7b_bc07_1b6cu64.to_be_bytes(),
+        );
+    }
+}
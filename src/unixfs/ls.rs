@@ -0,0 +1,159 @@
+//! Listing unixfs directories
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::ipld::{Ipld, formats::pb::PbNode};
+use crate::path::IpfsPath;
+use crate::repo::{Metadata, Repo, RepoTypes};
+use crate::repo::content::{unixfs_metadata, unixfs_node, Node};
+use crate::unixfs::dir::{dir_entries, resolve};
+use cid::Codec;
+use core::future::Future;
+use std::convert::TryInto;
+
+/// Kind of a directory entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// Entry of a unixfs directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub cid: Cid,
+    /// Number of bytes of a file or of the target of a symlink, zero for
+    /// directories.
+    pub size: u64,
+    pub kind: EntryType,
+    /// Mode and modification time if they were recorded.
+    pub metadata: Metadata,
+}
+
+fn decode_pb(block: &Block) -> Result<PbNode, Error> {
+    match Ipld::from(block)?.try_into() {
+        Ok(pb_node) => Ok(pb_node),
+        Err(_) => bail!("invalid dag_pb node"),
+    }
+}
+
+/// Returns the kind, the size and the metadata of the unixfs node in
+/// `block`.
+pub(crate) fn entry_type(block: &Block) -> Result<(EntryType, u64, Metadata), Error> {
+    if block.cid().prefix().codec != Codec::DagProtobuf {
+        return Ok((EntryType::File, block.data().len() as u64, Metadata::default()));
+    }
+    let pb_node = decode_pb(block)?;
+    let metadata = match unixfs_metadata(&pb_node.data) {
+        Some(metadata) => metadata,
+        None => bail!("invalid unixfs node"),
+    };
+    match unixfs_node(&pb_node.data) {
+        Some(Node::Directory) | Some(Node::HamtShard { .. }) => {
+            Ok((EntryType::Directory, 0, metadata))
+        }
+        Some(Node::File(content, blocksizes)) => {
+            let size = content.len() as u64 + blocksizes.iter().sum::<u64>();
+            Ok((EntryType::File, size, metadata))
+        }
+        Some(Node::Symlink(target)) => Ok((EntryType::Symlink, target.len() as u64, metadata)),
+        None => bail!("invalid unixfs node"),
+    }
+}
+
+/// Returns the mode and the modification time recorded for the unixfs
+/// file or directory at `path`.
+pub fn metadata<T: RepoTypes>(repo: &Repo<T>, path: IpfsPath) ->
+impl Future<Output=Result<Metadata, Error>>
+{
+    let repo = repo.clone();
+    async move {
+        let cid = await!(resolve(&repo, path))?;
+        let (_, _, metadata) = entry_type(&await!(repo.get_block(&cid))?)?;
+        Ok(metadata)
+    }
+}
+
+/// Lists the entries of the unixfs directory at `path`.
+///
+/// The blocks of the entries are read to find out their kind and size,
+/// missing blocks are fetched from the network. Sharded directories are
+/// listed in the order of the buckets of their entries.
+pub fn ls<T: RepoTypes>(repo: &Repo<T>, path: IpfsPath) ->
+impl Future<Output=Result<Vec<Entry>, Error>>
+{
+    let repo = repo.clone();
+    async move {
+        let cid = await!(resolve(&repo, path))?;
+        let links = await!(dir_entries(&repo, &cid))?;
+        let mut entries = Vec::with_capacity(links.len());
+        for (name, cid, _) in links {
+            let (kind, size, metadata) = entry_type(&await!(repo.get_block(&cid))?)?;
+            entries.push(Entry {
+                name,
+                cid,
+                size,
+                kind,
+                metadata,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipld::formats::pb::PbLink;
+    use crate::path::PathRoot;
+    use crate::repo::tests::create_mock_repo;
+    use std::io::Cursor;
+
+    fn link(cid: &Cid, name: &str) -> PbLink {
+        PbLink {
+            cid: cid.to_owned().into(),
+            name: name.to_string(),
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn test_ls() {
+        let repo = create_mock_repo();
+        tokio::run_async(async move {
+            let (file, _) = await!(repo.add_reader(Cursor::new(b"aaaabb".to_vec()), 4)).unwrap();
+            let empty: Ipld = PbNode {
+                links: vec![],
+                data: vec![0x08, 0x01],
+            }.into();
+            let empty = await!(repo.put_block(empty.to_dag_pb().unwrap())).unwrap();
+            let symlink: Ipld = PbNode {
+                links: vec![],
+                data: vec![0x08, 0x04, 0x12, 0x04, b'f', b'i', b'l', b'e'],
+            }.into();
+            let symlink = await!(repo.put_block(symlink.to_dag_pb().unwrap())).unwrap();
+            let dir: Ipld = PbNode {
+                links: vec![link(&file, "file"), link(&empty, "empty"), link(&symlink, "link")],
+                data: vec![0x08, 0x01],
+            }.into();
+            let dir = await!(repo.put_block(dir.to_dag_pb().unwrap())).unwrap();
+
+            let path = IpfsPath::new(PathRoot::Ipld(dir));
+            let entries = await!(ls(&repo, path)).unwrap();
+            let summary: Vec<_> = entries.iter()
+                .map(|entry| (entry.name.as_str(), entry.kind, entry.size))
+                .collect();
+            assert_eq!(summary, vec![
+                ("file", EntryType::File, 6),
+                ("empty", EntryType::Directory, 0),
+                ("link", EntryType::Symlink, 4),
+            ]);
+            assert_eq!(entries[0].cid, file);
+            assert!(entries[0].metadata.is_empty());
+
+            let path = IpfsPath::new(PathRoot::Ipld(file));
+            assert!(await!(ls(&repo, path)).is_err());
+        });
+    }
+}
@@ -1,12 +1,37 @@
+use crate::block::Cid;
 use crate::error::Error;
 use crate::ipld::{Ipld, IpldDag, formats::pb::PbNode};
 use crate::path::IpfsPath;
-use crate::repo::RepoTypes;
+use crate::repo::{Repo, RepoTypes, DEFAULT_CHUNK_SIZE};
 use core::future::Future;
 use futures::compat::*;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::path::PathBuf;
+use tokio::io::AsyncRead;
+
+mod cat;
+pub(crate) mod dir;
+mod hamt;
+pub(crate) mod ls;
+
+pub use self::cat::cat;
+pub use self::dir::{resolve, DirBuilder, DEFAULT_SHARD_THRESHOLD};
+pub use self::hamt::HAMT_FANOUT;
+pub use self::ls::{ls, metadata, Entry, EntryType};
+
+/// Adds the data of `reader` as a chunked unixfs file, returning the cid
+/// of the file.
+///
+/// See `Repo::add_reader` for the layout of the file.
+pub fn add<T: RepoTypes, R: AsyncRead + Send + 'static>(repo: &Repo<T>, reader: R) ->
+impl Future<Output=Result<Cid, Error>>
+{
+    let add = repo.add_reader(reader, DEFAULT_CHUNK_SIZE);
+    async move {
+        Ok(await!(add)?.0)
+    }
+}
 
 pub struct File {
     data: Vec<u8>,